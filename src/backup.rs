@@ -0,0 +1,232 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, CachedKey};
+use crate::vault;
+
+// ---------------------------------------------------------------------------
+// Rotating backups  (<vault>/backups/vault-<unix seconds>.json)
+// ---------------------------------------------------------------------------
+//
+// When a save finds the newest backup more than an hour old, it first copies
+// the vault as it is on disk, before that save overwrites it, into `backups/`
+// as a single encrypted file with the notes inline, so a backup stands on its
+// own even though vault.json only holds a manifest. Old generations are thinned
+// to one per hour, day and week, newest first, as many as the config keeps.
+//
+// A backup carries the key slots of its time. When the passphrase or recovery
+// code changes, backups under the same data key are given the new slots, so the
+// old secrets stop opening them. Backups from before the data key itself was
+// replaced (upgrading a legacy vault) still need the passphrase of their time.
+
+/// Minimum time between two automatic backups.
+const BACKUP_INTERVAL_SECS: u64 = 60 * 60;
+
+/// How many generations to keep per bucket size.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Retention {
+    pub hourly: usize,
+    pub daily: usize,
+    pub weekly: usize,
+}
+
+pub const RETENTION: Retention = Retention { hourly: 24, daily: 7, weekly: 8 };
+
+impl Default for Retention {
+    fn default() -> Self {
+        RETENTION
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// Creation time in Unix seconds.
+    pub created: u64,
+    pub size: u64,
+}
+
+pub fn backups_dir(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("backups")
+}
+
+fn parse_backup_name(name: &str) -> Option<u64> {
+    name.strip_prefix("vault-")?.strip_suffix(".json")?.parse().ok()
+}
+
+fn unix_now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// All backups, newest first.
+pub fn list_backups(vault_folder: &str) -> io::Result<Vec<Backup>> {
+    let dir = backups_dir(vault_folder);
+    let mut backups = Vec::new();
+    if !dir.is_dir() {
+        return Ok(backups);
+    }
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let Some(created) = parse_backup_name(&entry.file_name().to_string_lossy()) else {
+            continue;
+        };
+        backups.push(Backup { path: entry.path(), created, size: entry.metadata()?.len() });
+    }
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created));
+    Ok(backups)
+}
+
+/// Store `encrypted`, a whole vault encrypted as a single file, as a new
+/// backup generation.
+pub fn create_backup(vault_folder: &str, encrypted: &str) -> io::Result<Backup> {
+    let dir = backups_dir(vault_folder);
+    fs::create_dir_all(&dir)?;
+    let created = unix_now_secs();
+    let path = dir.join(format!("vault-{created}.json"));
+    vault::atomic_write(&path, encrypted.as_bytes())?;
+    Ok(Backup { path, created, size: encrypted.len() as u64 })
+}
+
+/// Called on every save before it writes: if the newest backup is older than
+/// `BACKUP_INTERVAL_SECS`, back up what `encrypt` returns, the vault as it is
+/// on disk (`None` if there is none yet), and thin old generations. `encrypt`
+/// only runs when a backup is due.
+pub fn backup_before_save(
+    vault_folder: &str,
+    retention: Retention,
+    encrypt: impl FnOnce() -> io::Result<Option<String>>,
+) -> io::Result<()> {
+    let newest = list_backups(vault_folder)?.first().map(|b| b.created);
+    if newest.is_some_and(|t| unix_now_secs().saturating_sub(t) < BACKUP_INTERVAL_SECS) {
+        return Ok(());
+    }
+    let Some(encrypted) = encrypt()? else {
+        return Ok(());
+    };
+    create_backup(vault_folder, &encrypted)?;
+    thin_backups(vault_folder, retention)?;
+    Ok(())
+}
+
+/// Give every backup under `key`'s data key the current key slots of `key`,
+/// after the passphrase or recovery code changed. Returns how many were
+/// rewritten.
+pub fn rewrap_backups(vault_folder: &str, key: &CachedKey) -> io::Result<usize> {
+    let mut rewritten = 0;
+    for backup in list_backups(vault_folder)? {
+        let raw = fs::read_to_string(&backup.path)?;
+        match crypto::rewrap_vault_envelope(&raw, key) {
+            Ok(Some(rewrapped)) => {
+                vault::atomic_write(&backup.path, rewrapped.as_bytes())?;
+                rewritten += 1;
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Warning: could not re-wrap backup {}: {e}", backup.path.display()),
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Which creation times survive thinning: the newest backup in each of the
+/// most recent `hourly` hours, `daily` days and `weekly` weeks that have one.
+pub fn backups_to_keep(created: &[u64], retention: Retention) -> HashSet<u64> {
+    let mut sorted = created.to_vec();
+    sorted.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut keep = HashSet::new();
+    for (bucket_secs, count) in [
+        (60 * 60, retention.hourly),
+        (24 * 60 * 60, retention.daily),
+        (7 * 24 * 60 * 60, retention.weekly),
+    ] {
+        let mut last_bucket = None;
+        let mut kept = 0;
+        for &t in &sorted {
+            if kept == count {
+                break;
+            }
+            let bucket = t / bucket_secs;
+            if last_bucket != Some(bucket) {
+                last_bucket = Some(bucket);
+                keep.insert(t);
+                kept += 1;
+            }
+        }
+    }
+    keep
+}
+
+/// Delete backups that fall outside the retention policy. Returns the number deleted.
+pub fn thin_backups(vault_folder: &str, retention: Retention) -> io::Result<usize> {
+    let backups = list_backups(vault_folder)?;
+    let created: Vec<u64> = backups.iter().map(|b| b.created).collect();
+    let keep = backups_to_keep(&created, retention);
+    let mut deleted = 0;
+    for backup in backups.iter().filter(|b| !keep.contains(&b.created)) {
+        fs::remove_file(&backup.path)?;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::test_fixtures::temp_vault_dir;
+
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+
+    #[test]
+    fn test_backups_to_keep() {
+        // One backup every hour for 60 days
+        let now = 1000 * DAY;
+        let created: Vec<u64> = (0..60 * 24).map(|h| now - h * HOUR).collect();
+        let keep = backups_to_keep(&created, RETENTION);
+
+        // The last 24 hours are all kept, older days only by their newest backup
+        assert!(keep.contains(&now));
+        assert!(keep.contains(&(now - 23 * HOUR)));
+        assert!(keep.contains(&(now - 25 * HOUR)));
+        assert!(!keep.contains(&(now - 26 * HOUR)));
+        assert_eq!(keep.iter().filter(|&&t| t > now - DAY).count(), 24);
+        assert!(keep.len() <= RETENTION.hourly + RETENTION.daily + RETENTION.weekly);
+        assert!(keep.len() >= RETENTION.hourly + RETENTION.weekly);
+        let oldest = *keep.iter().min().unwrap();
+        assert!(now - oldest > 6 * 7 * DAY);
+    }
+
+    #[test]
+    fn test_backup_before_save() {
+        let dir = temp_vault_dir("backup");
+        let folder = dir.to_str().unwrap();
+
+        // No backup folder yet: nothing listed
+        assert!(list_backups(folder).unwrap().is_empty());
+
+        // Nothing on disk yet: nothing to back up
+        backup_before_save(folder, RETENTION, || Ok(None)).unwrap();
+        assert!(list_backups(folder).unwrap().is_empty());
+
+        backup_before_save(folder, RETENTION, || Ok(Some("first".to_string()))).unwrap();
+        let backups = list_backups(folder).unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(fs::read_to_string(&backups[0].path).unwrap(), "first");
+
+        // Within the interval no new generation is made, nor encrypted
+        backup_before_save(folder, RETENTION, || panic!("backup not due")).unwrap();
+        assert_eq!(list_backups(folder).unwrap().len(), 1);
+
+        // Stray files are ignored
+        fs::write(backups_dir(folder).join("notes.txt"), "x").unwrap();
+        assert_eq!(list_backups(folder).unwrap().len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
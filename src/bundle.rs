@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::{fs, io};
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, CachedKey, Keyfile, PlaintextPolicy};
+use crate::integrity;
+use crate::state::file_name_stem;
+use crate::vault::{self, AssetMeta, VaultData};
+
+// ---------------------------------------------------------------------------
+// Vault bundles  (<name>.mdnbundle)
+// ---------------------------------------------------------------------------
+//
+// A bundle is one JSON file holding a whole vault: the vault encrypted as a
+// single file with the notes inline, as backups are, and every asset the notes
+// refer to, still encrypted. By default the bundle keeps the vault's own key,
+// so the asset files are copied as they are. Exported under a new passphrase
+// it gets a new random key, and the assets are re-encrypted for it.
+//
+// A bundle opens like a vault: its envelope carries the key slots, so the
+// passphrase (and keyfile, if the vault uses one) it was exported with unlock
+// it. Importing writes it out as a new vault folder, or merges its notes and
+// images into the open vault.
+
+pub const BUNDLE_EXTENSION: &str = "mdnbundle";
+const BUNDLE_FORMAT: &str = "mdnotebook-bundle";
+const BUNDLE_VERSION: u32 = 1;
+
+/// A bundle as stored on disk; nothing in it is readable without the key.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleFile {
+    format: String,
+    version: u32,
+    /// Unix seconds.
+    pub created: i64,
+    /// The vault envelope, notes inline.
+    vault: String,
+    /// Encrypted asset files by asset ID, base64.
+    assets: BTreeMap<String, String>,
+}
+
+/// What an export put in the bundle.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub notes: usize,
+    pub assets: usize,
+    /// Referenced assets that have no file in the vault folder.
+    pub missing: Vec<String>,
+}
+
+fn count_notes(items: &[vault::TreeItem]) -> usize {
+    items
+        .iter()
+        .map(|item| match &item.children {
+            Some(children) if item.item_type == "folder" => count_notes(children),
+            _ => 1,
+        })
+        .sum()
+}
+
+fn reencrypt_asset(
+    raw: &[u8],
+    id: &str,
+    from: &CachedKey,
+    to: &CachedKey,
+    policy: PlaintextPolicy,
+    meta: &HashMap<String, AssetMeta>,
+) -> io::Result<Vec<u8>> {
+    let failed = |e: crypto::CryptoError| io::Error::other(format!("Image {id}: {e}"));
+    let plain = zeroize::Zeroizing::new(crypto::decrypt_asset(raw, id, from, policy).map_err(failed)?);
+    let mime_type = vault::asset_mime_type(raw, id, meta);
+    crypto::encrypt_asset(&plain, id, &mime_type, to).map_err(failed)
+}
+
+/// Bundle `data` and the assets it refers to. `key` is the vault's key; with
+/// `new_key` the bundle is encrypted under that instead. `policy` decides
+/// whether unencrypted images are taken in.
+pub fn export_bundle(
+    vault_folder: &str,
+    data: &VaultData,
+    key: &CachedKey,
+    new_key: Option<&CachedKey>,
+    policy: PlaintextPolicy,
+) -> io::Result<(Vec<u8>, ExportSummary)> {
+    let referenced = integrity::referenced_asset_ids(data);
+    let mut data = data.clone();
+    data.assets.retain(|id, _| referenced.contains(id));
+
+    let mut summary = ExportSummary { notes: count_notes(&data.tree), ..ExportSummary::default() };
+    let mut assets = BTreeMap::new();
+    let mut ids: Vec<&String> = referenced.iter().collect();
+    ids.sort();
+    for id in ids {
+        let raw = match fs::read(vault::assets_dir(vault_folder).join(id)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                summary.missing.push(id.clone());
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let encrypted = match new_key {
+            Some(new_key) => reencrypt_asset(&raw, id, key, new_key, policy, &data.assets)?,
+            None => raw,
+        };
+        assets.insert(id.clone(), base64::engine::general_purpose::STANDARD.encode(encrypted));
+        summary.assets += 1;
+    }
+
+    let bundle = BundleFile {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        created: crate::state::unix_now(),
+        vault: vault::encrypt_vault_data(&data, new_key.unwrap_or(key))?,
+        assets,
+    };
+    let json = serde_json::to_vec(&bundle).map_err(io::Error::other)?;
+    Ok((json, summary))
+}
+
+pub fn parse_bundle(bytes: &[u8]) -> io::Result<BundleFile> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Not an MDNotebook vault bundle");
+    let bundle: BundleFile = serde_json::from_slice(bytes).map_err(|_| invalid())?;
+    if bundle.format != BUNDLE_FORMAT {
+        return Err(invalid());
+    }
+    if bundle.version > BUNDLE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "This bundle was made by a newer version of MDNotebook",
+        ));
+    }
+    Ok(bundle)
+}
+
+/// A bundle unlocked with its passphrase.
+pub struct Bundle {
+    pub data: VaultData,
+    pub key: CachedKey,
+    vault: String,
+    assets: BTreeMap<String, Vec<u8>>,
+}
+
+impl BundleFile {
+    /// Decrypt the vault inside (expensive — runs the KDF).
+    pub fn open(self, passphrase: &str, keyfile: Option<&Keyfile>) -> Result<Bundle, crypto::CryptoError> {
+        let (json, key) = crypto::decrypt_vault_returning_key(&self.vault, passphrase, keyfile, PlaintextPolicy::Refuse)?;
+        let json = zeroize::Zeroizing::new(json);
+        let data: VaultData = serde_json::from_str(&json)
+            .map_err(|e| crypto::CryptoError::InvalidData(format!("Corrupt bundle: {e}")))?;
+        let mut assets = BTreeMap::new();
+        for (id, encoded) in self.assets {
+            let raw = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| crypto::CryptoError::InvalidData(format!("Image {id}: {e}")))?;
+            assets.insert(id, raw);
+        }
+        Ok(Bundle { data, key, vault: self.vault, assets })
+    }
+}
+
+/// Asset IDs end up as file names, so only the characters `generate_asset_id`
+/// and `vault://` references use are accepted.
+fn is_safe_asset_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Bundle {
+    /// Write the bundle out as a new vault in `vault_folder`, which must not
+    /// hold one already. The new vault opens with the bundle's passphrase.
+    pub fn write_as_vault(&self, vault_folder: &str) -> io::Result<()> {
+        if vault::vault_file_path(vault_folder).exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "This folder already holds a vault"));
+        }
+        for (id, raw) in &self.assets {
+            if is_safe_asset_id(id) {
+                vault::write_asset(vault_folder, id, raw)?;
+            }
+        }
+        if !self.assets.is_empty() {
+            vault::sync_dir(&vault::assets_dir(vault_folder))?;
+        }
+        // The vault file last, so a folder with one is complete
+        vault::write_vault_raw(vault_folder, &self.vault)
+    }
+
+    /// Store the bundle's images in another vault, encrypted under that
+    /// vault's `key`. Images the vault already has are skipped. Returns the
+    /// number written.
+    pub fn import_assets(&self, vault_folder: &str, key: &CachedKey) -> io::Result<usize> {
+        let mut written = 0;
+        for (id, raw) in &self.assets {
+            if !is_safe_asset_id(id) || vault::assets_dir(vault_folder).join(id).exists() {
+                continue;
+            }
+            let encrypted = reencrypt_asset(raw, id, &self.key, key, PlaintextPolicy::Refuse, &self.data.assets)?;
+            vault::write_asset(vault_folder, id, &encrypted)?;
+            written += 1;
+        }
+        if written > 0 {
+            vault::sync_dir(&vault::assets_dir(vault_folder))?;
+        }
+        Ok(written)
+    }
+}
+
+/// Suggested file name for a bundle of the vault called `vault_name`.
+pub fn bundle_file_name(vault_name: &str) -> String {
+    format!("{}.{BUNDLE_EXTENSION}", file_name_stem(vault_name))
+}
+
+/// Whether `path` has the bundle extension.
+pub fn is_bundle_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == BUNDLE_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::test_fixtures::{asset_meta, note, temp_vault_dir};
+
+    #[test]
+    fn test_export_and_import_bundle() {
+        let dir = temp_vault_dir("bundle");
+        let folder = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let source = folder("source");
+        let key = CachedKey::generate("bundle test", None).unwrap();
+
+        let mut data = VaultData {
+            tree: vec![note("a", "A", "![x](vault://used) ![y](vault://gone)"), note("b", "B", "plain")],
+            ..VaultData::default()
+        };
+        for id in ["used", "gone", "unused"] {
+            data.assets.insert(id.into(), asset_meta(id));
+        }
+        for id in ["used", "unused"] {
+            let encrypted = crypto::encrypt_asset(b"png", id, "image/png", &key).unwrap();
+            vault::write_asset(&source, id, &encrypted).unwrap();
+        }
+
+        // Only referenced assets go in; a missing file is reported, not fatal
+        let (bytes, summary) = export_bundle(&source, &data, &key, None, PlaintextPolicy::Refuse).unwrap();
+        assert_eq!(summary, ExportSummary { notes: 2, assets: 1, missing: vec!["gone".into()] });
+        assert!(!String::from_utf8_lossy(&bytes).contains("plain"));
+
+        let bundle = parse_bundle(&bytes).unwrap().open("bundle test", None).unwrap();
+        assert_eq!(bundle.assets.len(), 1);
+        assert!(!bundle.data.assets.contains_key("unused"));
+        assert_eq!(bundle.data.tree[1].content.as_deref(), Some("plain"));
+
+        // Restored as a new vault, it opens with the same passphrase
+        let restored = folder("restored");
+        bundle.write_as_vault(&restored).unwrap();
+        assert!(bundle.write_as_vault(&restored).is_err());
+        let raw = vault::read_vault_raw(&restored).unwrap().unwrap();
+        assert!(crypto::decrypt_vault_returning_key(&raw, "bundle test", None, PlaintextPolicy::Refuse).is_ok());
+        assert!(vault::assets_dir(&restored).join("used").exists());
+
+        // Under a new passphrase the old one no longer opens it, and merged
+        // images are re-encrypted under the receiving vault's key
+        let new_key = CachedKey::generate("another passphrase", None).unwrap();
+        let (bytes, _) = export_bundle(&source, &data, &key, Some(&new_key), PlaintextPolicy::Refuse).unwrap();
+        assert!(parse_bundle(&bytes).unwrap().open("bundle test", None).is_err());
+        let bundle = parse_bundle(&bytes).unwrap().open("another passphrase", None).unwrap();
+        let target = folder("target");
+        let target_key = CachedKey::generate("target", None).unwrap();
+        assert_eq!(bundle.import_assets(&target, &target_key).unwrap(), 1);
+        assert_eq!(bundle.import_assets(&target, &target_key).unwrap(), 0);
+        let stored = fs::read(vault::assets_dir(&target).join("used")).unwrap();
+        let plain = crypto::decrypt_asset(&stored, "used", &target_key, PlaintextPolicy::Refuse).unwrap();
+        assert_eq!(plain, b"png");
+
+        assert!(parse_bundle(b"{\"not\": \"a bundle\"}").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,22 +1,66 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     Aes256Gcm, Nonce,
 };
 use base64::Engine as _;
 use rand::RngCore;
-use sha2::Sha256;
-use zeroize::ZeroizeOnDrop;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use zeroize::{ZeroizeOnDrop, Zeroizing};
 
-const PBKDF2_ITERATIONS: u32 = 600_000;
+/// Current vault envelope version.
+/// - 1: `{"encrypted":true,"data":...}`, key derived from the passphrase with PBKDF2
+/// - 2: adds `kdf`; the passphrase-derived key still encrypts the data directly
+/// - 3: a random data key encrypts the data and is wrapped by each of `keySlots`
+/// - 4: same as 3, but the data may be a manifest whose notes live in `notes/`
+/// - 5: adds `compression`, applied to the plaintext before encryption
+/// - 6: adds `padding`, applied after compression
+/// - 7: the compression and padding are authenticated as AAD
+/// - 8: `keySlots` may hold `device` slots, opened by a key kept in a device's keyring
+const ENVELOPE_VERSION: u32 = 8;
+/// Current asset envelope version.
+/// - 1: `{"encrypted":true,"data":...}` with `salt || IV || ciphertext`, no associated data
+/// - 2: adds `mimeType`; the asset ID and MIME type are authenticated as AAD
+/// - 3: binary, see `encrypt_asset`; the compression is authenticated too
+/// - 4: adds a padding byte after the compression byte, also authenticated
+const ASSET_ENVELOPE_VERSION: u8 = 4;
+/// Leading bytes of a binary (version 3+) asset envelope.
+const ASSET_MAGIC: &[u8; 4] = b"MDNA";
+/// Current note file envelope version.
+/// - 1: `{"encrypted":true,"version":1,"data":...}`; file name and note ID are AAD
+/// - 2: adds `compression`
+/// - 3: adds `padding`
+/// - 4: the compression and padding are authenticated too
+const NOTE_FILE_VERSION: u32 = 4;
+const LEGACY_PBKDF2_ITERATIONS: u32 = 600_000;
 const SALT_LEN: usize = 16;
 const IV_LEN: usize = 12;
 const KEY_LEN: usize = 32;
+const RECOVERY_CODE_BYTES: usize = 20;
+const RECOVERY_CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ"; // Crockford base32
+
+// Upper bounds for KDF parameters read from disk, so a tampered envelope
+// cannot make unlock allocate unbounded memory or spin forever.
+const MAX_ARGON2_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+/// Largest payload that is compressed, and so the most decompression may
+/// produce; anything longer in a tampered file is refused rather than
+/// inflated without bound.
+const MAX_PLAINTEXT: usize = 1024 * 1024 * 1024;
 
 #[derive(Debug)]
 pub enum CryptoError {
     EncryptionFailed(String),
     DecryptionFailed(String),
     InvalidData(String),
+    /// The vault's passphrase slot requires a keyfile and none was given.
+    KeyfileRequired,
+    /// The data is not encrypted and the caller refuses plaintext.
+    Unencrypted,
+    /// A device key that no slot of the vault accepts (any more).
+    DeviceKeyRevoked,
 }
 
 impl std::fmt::Display for CryptoError {
@@ -25,23 +69,458 @@ impl std::fmt::Display for CryptoError {
             Self::EncryptionFailed(m) => write!(f, "Encryption failed: {m}"),
             Self::DecryptionFailed(m) => write!(f, "Decryption failed: {m}"),
             Self::InvalidData(m) => write!(f, "Invalid data: {m}"),
+            Self::KeyfileRequired => write!(f, "This vault requires a keyfile"),
+            Self::Unencrypted => write!(f, "Refusing unencrypted data"),
+            Self::DeviceKeyRevoked => write!(f, "The key remembered on this device no longer opens the vault"),
         }
     }
 }
 
-fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; KEY_LEN] {
-    let mut key = [0u8; KEY_LEN];
-    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ITERATIONS, &mut key);
-    key
+impl std::error::Error for CryptoError {}
+
+/// What to do with vault or asset files that are not encrypted at all.
+/// `Refuse` keeps a tampered vault folder from feeding plaintext content in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaintextPolicy {
+    Allow,
+    Refuse,
+}
+
+/// Compression applied to a payload before encryption.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+}
+
+impl Compression {
+    fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Deflate => "deflate",
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, CryptoError> {
+        match byte {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            _ => Err(CryptoError::InvalidData(format!("Unknown compression {byte}"))),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+        }
+    }
+}
+
+/// `data` deflated, if compression is enabled and it comes out smaller.
+fn compress(data: &[u8], enabled: bool) -> Result<Option<Zeroizing<Vec<u8>>>, CryptoError> {
+    if !enabled || data.len() > MAX_PLAINTEXT {
+        return Ok(None);
+    }
+    let mut compressed = Zeroizing::new(Vec::with_capacity(data.len() / 2));
+    flate2::read::DeflateEncoder::new(data, flate2::Compression::fast())
+        .read_to_end(&mut compressed)
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+    Ok((compressed.len() < data.len()).then_some(compressed))
+}
+
+/// Undo `compress` on a decrypted payload.
+fn decompress(data: Vec<u8>, compression: Compression) -> Result<Vec<u8>, CryptoError> {
+    match compression {
+        Compression::None => Ok(data),
+        Compression::Deflate => inflate(&Zeroizing::new(data), MAX_PLAINTEXT),
+    }
 }
 
-/// A cached encryption key — derives the expensive PBKDF2 key once, reuses for all saves.
-/// The salt is fixed per session; a fresh random IV is generated for each encryption.
+/// Inflate `data`, refusing output longer than `limit`.
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, CryptoError> {
+    let mut out = Zeroizing::new(Vec::with_capacity((data.len() * 3).min(limit)));
+    flate2::read::DeflateDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| CryptoError::DecryptionFailed(format!("Decompression failed: {e}")))?;
+    if out.len() > limit {
+        return Err(CryptoError::InvalidData("Decompressed data is too large".into()));
+    }
+    Ok(std::mem::take(&mut *out))
+}
+
+/// Length-hiding padding applied to a payload, after compression.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Padding {
+    #[default]
+    None,
+    /// A 0x80 byte and zeros up to the next Padmé bucket (at least
+    /// `MIN_PADDED_LEN`), so at most about 12% is added and only the rough
+    /// magnitude of the length shows.
+    Padme,
+}
+
+impl Padding {
+    fn is_none(&self) -> bool {
+        *self == Padding::None
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Padding::None => "none",
+            Padding::Padme => "padme",
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, CryptoError> {
+        match byte {
+            0 => Ok(Padding::None),
+            1 => Ok(Padding::Padme),
+            _ => Err(CryptoError::InvalidData(format!("Unknown padding {byte}"))),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Padding::None => 0,
+            Padding::Padme => 1,
+        }
+    }
+}
+
+/// Smallest padded payload, so short notes all look alike.
+const MIN_PADDED_LEN: usize = 512;
+
+/// The Padmé bucket for `len`: only the top bits of the length are kept, the
+/// lower ones are rounded up.
+fn padme_len(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros(); // floor(log2(len))
+    let exponent_bits = u32::BITS - exponent.leading_zeros(); // floor(log2(exponent)) + 1
+    let mask = (1usize << (exponent - exponent_bits)) - 1;
+    (len + mask) & !mask
+}
+
+fn padded_len(len: usize) -> usize {
+    padme_len(len + 1).max(MIN_PADDED_LEN)
+}
+
+/// Strip padding from a decrypted payload.
+fn unpad(mut data: Vec<u8>, padding: Padding) -> Result<Vec<u8>, CryptoError> {
+    if padding == Padding::Padme {
+        let end = data
+            .iter()
+            .rposition(|&b| b != 0)
+            .filter(|&i| data[i] == 0x80)
+            .ok_or_else(|| CryptoError::DecryptionFailed("Invalid padding".into()))?;
+        data.truncate(end);
+    }
+    Ok(data)
+}
+
+/// A payload ready for encryption: compressed and padded as asked, or, with
+/// neither applied, `None` to encrypt the input as it is.
+struct Encoded {
+    compression: Compression,
+    padding: Padding,
+    payload: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Encoded {
+    fn new(data: &[u8], compress_it: bool, pad: bool) -> Result<Self, CryptoError> {
+        let compressed = compress(data, compress_it)?;
+        let compression = if compressed.is_some() { Compression::Deflate } else { Compression::None };
+        if !pad {
+            return Ok(Encoded { compression, padding: Padding::None, payload: compressed });
+        }
+        let body = compressed.as_deref().map_or(data, Vec::as_slice);
+        let mut padded = Zeroizing::new(Vec::with_capacity(padded_len(body.len())));
+        padded.extend_from_slice(body);
+        padded.push(0x80);
+        padded.resize(padded_len(body.len()), 0);
+        Ok(Encoded { compression, padding: Padding::Padme, payload: Some(padded) })
+    }
+
+    fn bytes<'a>(&'a self, data: &'a [u8]) -> &'a [u8] {
+        self.payload.as_deref().map_or(data, Vec::as_slice)
+    }
+}
+
+/// Undo `Encoded::new` on a decrypted payload.
+fn decode_payload(data: Vec<u8>, compression: Compression, padding: Padding) -> Result<Vec<u8>, CryptoError> {
+    decompress(unpad(data, padding)?, compression)
+}
+
+/// Whether `data` is an encrypted vault or asset envelope.
+pub fn is_encrypted_envelope(data: &[u8]) -> bool {
+    if data.starts_with(ASSET_MAGIC) {
+        return true;
+    }
+    serde_json::from_slice::<serde_json::Value>(data)
+        .ok()
+        .and_then(|envelope| envelope.get("encrypted").and_then(|v| v.as_bool()))
+        .unwrap_or(false)
+}
+
+/// Key-derivation function and its cost parameters. Recorded in the vault
+/// envelope so the defaults can be raised without breaking existing vaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "algorithm")]
+pub enum Kdf {
+    #[serde(rename = "pbkdf2-sha256")]
+    Pbkdf2Sha256 { iterations: u32 },
+    #[serde(rename = "argon2id", rename_all = "camelCase")]
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+}
+
+impl Kdf {
+    /// The KDF implied by version-1 envelopes.
+    pub const LEGACY: Kdf = Kdf::Pbkdf2Sha256 {
+        iterations: LEGACY_PBKDF2_ITERATIONS,
+    };
+
+    /// The KDF used for new vaults and for upgrading old ones.
+    pub const RECOMMENDED: Kdf = Kdf::Argon2id {
+        memory_kib: 64 * 1024,
+        iterations: 3,
+        parallelism: 1,
+    };
+
+    fn validate(&self) -> Result<(), CryptoError> {
+        let ok = match *self {
+            Kdf::Pbkdf2Sha256 { iterations } => (1..=MAX_KDF_ITERATIONS).contains(&iterations),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                (8..=MAX_ARGON2_MEMORY_KIB).contains(&memory_kib)
+                    && (1..=MAX_KDF_ITERATIONS).contains(&iterations)
+                    && (1..=MAX_ARGON2_PARALLELISM).contains(&parallelism)
+            }
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(CryptoError::InvalidData("Unsupported key derivation parameters".into()))
+        }
+    }
+
+    fn derive(&self, secret: &[u8], salt: &[u8]) -> Result<[u8; KEY_LEN], CryptoError> {
+        self.validate()?;
+        let mut key = [0u8; KEY_LEN];
+        match *self {
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(secret, salt, iterations, &mut key);
+            }
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                let params = argon2::Params::new(memory_kib, iterations, parallelism, Some(KEY_LEN))
+                    .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(secret, salt, &mut key)
+                    .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// On-disk vault envelope. Version 1 files only carry `encrypted` and `data`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VaultEnvelope {
+    encrypted: bool,
+    #[serde(default = "legacy_envelope_version")]
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kdf: Option<Kdf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    key_slots: Vec<KeySlot>,
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    compression: Compression,
+    #[serde(default, skip_serializing_if = "Padding::is_none")]
+    padding: Padding,
+    #[serde(default)]
+    data: Option<String>,
+}
+
+fn legacy_envelope_version() -> u32 {
+    1
+}
+
+/// SHA-256 digest of a keyfile, used as a second factor next to the passphrase.
+/// Any file can serve as a keyfile; only its digest is kept in memory.
+#[derive(Clone, ZeroizeOnDrop)]
+pub struct Keyfile([u8; 32]);
+
+impl Keyfile {
+    const GENERATED_LEN: usize = 64;
+
+    pub fn from_reader(mut reader: impl std::io::Read) -> std::io::Result<Self> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut reader, &mut hasher)?;
+        Ok(Keyfile(hasher.finalize().into()))
+    }
+
+    /// Random contents for a new keyfile, to be written wherever the user chooses.
+    pub fn generate_contents() -> Vec<u8> {
+        let mut bytes = vec![0u8; Self::GENERATED_LEN];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        bytes
+    }
+}
+
+impl std::fmt::Debug for Keyfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Keyfile([REDACTED])")
+    }
+}
+
+/// KDF input for a passphrase slot: the passphrase, followed by the keyfile
+/// digest when the slot requires one.
+fn passphrase_secret(passphrase: &str, keyfile: Option<&Keyfile>) -> Zeroizing<Vec<u8>> {
+    let mut secret = Zeroizing::new(passphrase.as_bytes().to_vec());
+    if let Some(keyfile) = keyfile {
+        secret.push(0);
+        secret.extend_from_slice(&keyfile.0);
+    }
+    secret
+}
+
+/// What unlocks a key slot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotKind {
+    Passphrase,
+    Recovery,
+    /// Opened by a random key kept in one device's keyring.
+    Device,
+}
+
+impl SlotKind {
+    fn aad(self) -> &'static [u8] {
+        match self {
+            SlotKind::Passphrase => b"mdnotebook-slot:passphrase",
+            SlotKind::Recovery => b"mdnotebook-slot:recovery",
+            SlotKind::Device => b"mdnotebook-slot:device",
+        }
+    }
+}
+
+/// Identifies the device slot a device key opens, without revealing the key.
+fn device_id(device_key: &[u8]) -> String {
+    let digest = Sha256::new().chain_update(b"mdnotebook-device:").chain_update(device_key).finalize();
+    digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The vault data key encrypted under a key-encryption key derived from a secret.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct KeySlot {
+    #[serde(rename = "type")]
+    kind: SlotKind,
+    kdf: Kdf,
+    salt: String,
+    /// base64(iv + AES-GCM(data key))
+    wrapped_key: String,
+    /// The KDF input also includes a keyfile digest.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    keyfile: bool,
+    /// For device slots, which device's key opens it (see `device_id`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+}
+
+impl KeySlot {
+    fn wrap(
+        kind: SlotKind,
+        secret: &[u8],
+        keyfile: bool,
+        data_key: &[u8; KEY_LEN],
+    ) -> Result<Self, CryptoError> {
+        let kdf = Kdf::RECOMMENDED;
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let kek = Zeroizing::new(kdf.derive(secret, &salt)?);
+
+        let mut iv = [0u8; IV_LEN];
+        rand::thread_rng().fill_bytes(&mut iv);
+        let cipher = Aes256Gcm::new_from_slice(kek.as_slice())
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&iv), Payload { msg: data_key, aad: kind.aad() })
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+
+        let mut combined = Vec::with_capacity(IV_LEN + ciphertext.len());
+        combined.extend_from_slice(&iv);
+        combined.extend_from_slice(&ciphertext);
+        let b64 = base64::engine::general_purpose::STANDARD;
+        Ok(KeySlot {
+            kind,
+            kdf,
+            salt: b64.encode(salt),
+            wrapped_key: b64.encode(&combined),
+            keyfile,
+            device: None,
+        })
+    }
+
+    fn unwrap(&self, secret: &[u8]) -> Result<[u8; KEY_LEN], CryptoError> {
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let salt = b64.decode(&self.salt).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+        let combined = b64
+            .decode(&self.wrapped_key)
+            .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+        if salt.len() != SALT_LEN || combined.len() != IV_LEN + KEY_LEN + 16 {
+            return Err(CryptoError::InvalidData("Malformed key slot".into()));
+        }
+
+        let kek = Zeroizing::new(self.kdf.derive(secret, &salt)?);
+        let cipher = Aes256Gcm::new_from_slice(kek.as_slice())
+            .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+        let (iv, ciphertext) = combined.split_at(IV_LEN);
+        let plain = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(iv), Payload { msg: ciphertext, aad: self.kind.aad() })
+                .map_err(|_| CryptoError::DecryptionFailed("Wrong passphrase or corrupted data".into()))?,
+        );
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&plain);
+        Ok(key)
+    }
+}
+
+/// The key used for all vault and asset encryption during a session. For
+/// current vaults this is a random data key together with the key slots that
+/// wrap it, so changing the passphrase only re-wraps the key. Legacy vaults
+/// (envelope v1/v2) use the passphrase-derived key directly and have no slots.
+/// A fresh random IV is generated for each encryption.
 /// Key material is securely zeroed when dropped.
 #[derive(Clone, ZeroizeOnDrop)]
 pub struct CachedKey {
     #[zeroize(skip)]
     salt: [u8; SALT_LEN],
+    #[zeroize(skip)]
+    kdf: Kdf,
+    #[zeroize(skip)]
+    slots: Vec<KeySlot>,
+    /// Compress payloads before encrypting them. A session preference, not
+    /// part of the key; every file records whether it was compressed.
+    #[zeroize(skip)]
+    compress: bool,
+    /// Pad payloads to hide their exact length. Also a session preference.
+    #[zeroize(skip)]
+    pad: bool,
     key: [u8; KEY_LEN],
 }
 
@@ -50,61 +529,259 @@ impl std::fmt::Debug for CachedKey {
         f.debug_struct("CachedKey")
             .field("key", &"[REDACTED]")
             .field("salt", &self.salt)
+            .field("kdf", &self.kdf)
+            .field("slots", &self.slots.len())
+            .field("compress", &self.compress)
+            .field("pad", &self.pad)
             .finish()
     }
 }
 
 impl CachedKey {
-    /// Derive and cache the key from a passphrase (expensive — call once at unlock/create time).
-    pub fn derive(passphrase: &str) -> Self {
+    /// Generate a random data key wrapped by `passphrase` and, optionally, a
+    /// keyfile (expensive — runs the KDF).
+    pub fn generate(passphrase: &str, keyfile: Option<&Keyfile>) -> Result<Self, CryptoError> {
+        let mut key = [0u8; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let secret = passphrase_secret(passphrase, keyfile);
+        let slot = KeySlot::wrap(SlotKind::Passphrase, &secret, keyfile.is_some(), &key)?;
+        Ok(CachedKey { key, salt, kdf: slot.kdf, slots: vec![slot], compress: true, pad: false })
+    }
+
+    /// Derive a legacy (unwrapped) key with explicit KDF parameters and a fresh salt.
+    #[cfg(test)]
+    fn derive_legacy(passphrase: &str, kdf: Kdf) -> Result<Self, CryptoError> {
         let mut salt = [0u8; SALT_LEN];
         rand::thread_rng().fill_bytes(&mut salt);
-        let key = derive_key(passphrase, &salt);
-        CachedKey { key, salt }
+        let key = kdf.derive(passphrase.as_bytes(), &salt)?;
+        Ok(CachedKey { key, salt, kdf, slots: Vec::new(), compress: true, pad: false })
+    }
+
+    /// Turn compression of newly encrypted payloads on or off.
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.compress = enabled;
+    }
+
+    /// Turn length-hiding padding of newly encrypted payloads on or off.
+    pub fn set_padding(&mut self, enabled: bool) {
+        self.pad = enabled;
+    }
+
+    /// True for keys from the current wrapped-key format.
+    pub fn is_wrapped(&self) -> bool {
+        !self.slots.is_empty()
+    }
+
+    /// True if unlocking with the passphrase also needs the keyfile.
+    pub fn requires_keyfile(&self) -> bool {
+        self.slots.iter().any(|s| s.kind == SlotKind::Passphrase && s.keyfile)
+    }
+
+    pub fn has_recovery_code(&self) -> bool {
+        self.slots.iter().any(|s| s.kind == SlotKind::Recovery)
+    }
+
+    /// True if the vault should be upgraded: it predates wrapped keys, or its
+    /// passphrase slot uses weaker-than-current KDF settings.
+    pub fn needs_upgrade(&self) -> bool {
+        !self.is_wrapped()
+            || self
+                .slots
+                .iter()
+                .any(|s| s.kind == SlotKind::Passphrase && s.kdf != Kdf::RECOMMENDED)
+    }
+
+    /// The same data key with its passphrase slot replaced (expensive — runs the KDF).
+    /// The recovery code slot is kept; device slots are dropped, so devices
+    /// that remembered the key have to be unlocked with the new passphrase.
+    pub fn with_passphrase(
+        &self,
+        passphrase: &str,
+        keyfile: Option<&Keyfile>,
+    ) -> Result<Self, CryptoError> {
+        if !self.is_wrapped() {
+            return Err(CryptoError::InvalidData("Legacy vault key cannot be re-wrapped".into()));
+        }
+        let secret = passphrase_secret(passphrase, keyfile);
+        let slot = KeySlot::wrap(SlotKind::Passphrase, &secret, keyfile.is_some(), &self.key)?;
+        let mut next = self.clone();
+        next.kdf = slot.kdf;
+        next.slots.retain(|s| !matches!(s.kind, SlotKind::Passphrase | SlotKind::Device));
+        next.slots.insert(0, slot);
+        Ok(next)
+    }
+
+    /// Wrap the data key with a new random device key and return that key,
+    /// for keeping in the system keyring so this device can unlock without
+    /// the passphrase (expensive — runs the KDF). The data key itself never
+    /// leaves the vault file.
+    pub fn add_device_slot(&mut self) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        if !self.is_wrapped() {
+            return Err(CryptoError::InvalidData("Legacy vault key cannot be re-wrapped".into()));
+        }
+        let mut device_key = Zeroizing::new(vec![0u8; KEY_LEN]);
+        rand::thread_rng().fill_bytes(device_key.as_mut_slice());
+        let mut slot = KeySlot::wrap(SlotKind::Device, &device_key, false, &self.key)?;
+        slot.device = Some(device_id(&device_key));
+        self.slots.push(slot);
+        Ok(device_key)
+    }
+
+    /// Drop the slot `device_key` opens. Returns whether there was one.
+    pub fn remove_device_slot(&mut self, device_key: &[u8]) -> bool {
+        let id = device_id(device_key);
+        let before = self.slots.len();
+        self.slots.retain(|s| s.kind != SlotKind::Device || s.device.as_deref() != Some(id.as_str()));
+        self.slots.len() != before
     }
 
-    /// Construct from already-derived key material (zero cost).
-    pub fn from_raw(key: [u8; KEY_LEN], salt: [u8; SALT_LEN]) -> Self {
-        CachedKey { key, salt }
+    /// Wrap the data key with a new random recovery code, replacing any previous
+    /// one, and return the code formatted for display (expensive — runs the KDF).
+    /// Device slots are dropped as on a passphrase change.
+    pub fn add_recovery_code(&mut self) -> Result<String, CryptoError> {
+        if !self.is_wrapped() {
+            return Err(CryptoError::InvalidData("Legacy vault key cannot be re-wrapped".into()));
+        }
+        let mut bytes = Zeroizing::new([0u8; RECOVERY_CODE_BYTES]);
+        rand::thread_rng().fill_bytes(bytes.as_mut_slice());
+        let code = format_recovery_code(bytes.as_slice());
+        let normalized = normalize_recovery_code(&code)?;
+        let slot = KeySlot::wrap(SlotKind::Recovery, normalized.as_bytes(), false, &self.key)?;
+        self.slots.retain(|s| !matches!(s.kind, SlotKind::Recovery | SlotKind::Device));
+        self.slots.push(slot);
+        Ok(code)
     }
 }
 
+/// Encode bytes as Crockford base32 in dash-separated groups of four.
+fn format_recovery_code(bytes: &[u8]) -> String {
+    let mut chars = Vec::with_capacity(bytes.len() * 8 / 5 + 1);
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for &b in bytes {
+        acc = (acc << 8) | u32::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            chars.push(RECOVERY_CODE_ALPHABET[((acc >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        chars.push(RECOVERY_CODE_ALPHABET[((acc << (5 - bits)) & 31) as usize] as char);
+    }
+    chars
+        .chunks(4)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
 
+/// Canonical form of a typed recovery code: case, separators and the
+/// commonly confused letters O/I/L are forgiven.
+fn normalize_recovery_code(input: &str) -> Result<String, CryptoError> {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        let c = match c.to_ascii_uppercase() {
+            '-' | ' ' | '\t' => continue,
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        if !RECOVERY_CODE_ALPHABET.contains(&(c as u8)) || !c.is_ascii() {
+            return Err(CryptoError::InvalidData("Recovery code contains invalid characters".into()));
+        }
+        out.push(c);
+    }
+    if out.len() != (RECOVERY_CODE_BYTES * 8).div_ceil(5) {
+        return Err(CryptoError::InvalidData("Recovery code is incomplete".into()));
+    }
+    Ok(out)
+}
 
-/// Fast encryption using a pre-derived cached key. No PBKDF2 on each call.
+/// Fast encryption using a pre-derived cached key. No KDF on each call.
 pub fn encrypt_vault_fast(plaintext: &str, cached: &CachedKey) -> Result<String, CryptoError> {
-    encrypt_with_key(plaintext, &cached.key, &cached.salt)
+    encrypt_with_key(plaintext, cached)
 }
 
-fn encrypt_with_key(
-    plaintext: &str,
-    key: &[u8; KEY_LEN],
-    salt: &[u8; SALT_LEN],
-) -> Result<String, CryptoError> {
+fn encrypt_with_key(plaintext: &str, cached: &CachedKey) -> Result<String, CryptoError> {
     let mut iv = [0u8; IV_LEN];
     rand::thread_rng().fill_bytes(&mut iv);
 
-    let cipher =
-        Aes256Gcm::new_from_slice(key).map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+    // Legacy keys keep writing v2 so the passphrase still derives the data key
+    let legacy = !cached.is_wrapped();
+    let encoded = Encoded::new(plaintext.as_bytes(), cached.compress && !legacy, cached.pad && !legacy)?;
+    let payload = encoded.bytes(plaintext.as_bytes());
+
+    let cipher = Aes256Gcm::new_from_slice(&cached.key)
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
     let nonce = Nonce::from_slice(&iv);
+    let version = if legacy { 2 } else { ENVELOPE_VERSION };
+    let aad = vault_aad(version, encoded.compression, encoded.padding);
     let ciphertext = cipher
-        .encrypt(nonce, plaintext.as_bytes())
+        .encrypt(nonce, Payload { msg: payload, aad: &aad })
         .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
 
     let mut combined = Vec::with_capacity(SALT_LEN + IV_LEN + ciphertext.len());
-    combined.extend_from_slice(salt);
+    if legacy {
+        combined.extend_from_slice(&cached.salt);
+    }
     combined.extend_from_slice(&iv);
     combined.extend_from_slice(&ciphertext);
 
-    let b64 = base64::engine::general_purpose::STANDARD.encode(&combined);
-    Ok(format!(r#"{{"encrypted":true,"data":"{b64}"}}"#))
+    let envelope = VaultEnvelope {
+        encrypted: true,
+        version,
+        kdf: legacy.then_some(cached.kdf),
+        key_slots: cached.slots.clone(),
+        compression: encoded.compression,
+        padding: encoded.padding,
+        data: Some(base64::engine::general_purpose::STANDARD.encode(&combined)),
+    };
+    serde_json::to_string(&envelope).map_err(|e| CryptoError::EncryptionFailed(e.to_string()))
+}
+
+/// Associated data binding a vault's compression and padding to its
+/// ciphertext, so neither can be switched off or swapped in the envelope.
+/// Empty before version 7.
+fn vault_aad(version: u32, compression: Compression, padding: Padding) -> Vec<u8> {
+    if version < 7 {
+        return Vec::new();
+    }
+    let mut aad = b"mdnotebook-vault:".to_vec();
+    aad.extend_from_slice(compression.name().as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(padding.name().as_bytes());
+    aad
 }
 
 /// Decrypt vault and return the derived CachedKey for reuse in future encryptions.
-/// Avoids a second PBKDF2 derivation after unlock.
+/// Avoids a second KDF derivation after unlock. Reads every envelope version;
+/// check `CachedKey::needs_upgrade` to find vaults that should be upgraded.
 pub fn decrypt_vault_returning_key(
     encrypted_json: &str,
     passphrase: &str,
+    keyfile: Option<&Keyfile>,
+    plaintext: PlaintextPolicy,
+) -> Result<(String, CachedKey), CryptoError> {
+    decrypt_vault_with(encrypted_json, passphrase, keyfile, SlotKind::Passphrase, plaintext)
+}
+
+/// Decrypt a vault using its recovery code instead of the passphrase.
+pub fn decrypt_vault_with_recovery_code(
+    encrypted_json: &str,
+    code: &str,
+) -> Result<(String, CachedKey), CryptoError> {
+    let code = normalize_recovery_code(code)?;
+    decrypt_vault_with(encrypted_json, &code, None, SlotKind::Recovery, PlaintextPolicy::Refuse)
+}
+
+fn decrypt_vault_with(
+    encrypted_json: &str,
+    secret: &str,
+    keyfile: Option<&Keyfile>,
+    kind: SlotKind,
+    plaintext: PlaintextPolicy,
 ) -> Result<(String, CachedKey), CryptoError> {
     let envelope: serde_json::Value =
         serde_json::from_str(encrypted_json).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
@@ -115,94 +792,396 @@ pub fn decrypt_vault_returning_key(
         .unwrap_or(false);
 
     if !is_encrypted {
+        if kind != SlotKind::Passphrase {
+            return Err(CryptoError::InvalidData("This vault has no recovery code".into()));
+        }
+        if plaintext == PlaintextPolicy::Refuse {
+            return Err(CryptoError::Unencrypted);
+        }
         let plaintext = envelope
             .get("data")
             .and_then(|v| v.as_str())
             .map(|s| s.to_string())
             .or_else(|| serde_json::to_string(&envelope).ok())
             .ok_or_else(|| CryptoError::InvalidData("No data field".into()))?;
-        // Unencrypted vault — derive a fresh key for future saves
-        let cached = CachedKey::derive(passphrase);
+        // Unencrypted vault — generate a fresh key for future saves
+        let cached = CachedKey::generate(secret, keyfile)?;
         return Ok((plaintext, cached));
     }
 
+    let envelope: VaultEnvelope =
+        serde_json::from_value(envelope).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if envelope.version > ENVELOPE_VERSION {
+        return Err(CryptoError::InvalidData(format!(
+            "Vault format version {} was created by a newer version of MDNotebook",
+            envelope.version
+        )));
+    }
+
     let data_b64 = envelope
-        .get("data")
-        .and_then(|v| v.as_str())
+        .data
+        .as_deref()
         .ok_or_else(|| CryptoError::InvalidData("Missing 'data' field".into()))?;
 
     let combined = base64::engine::general_purpose::STANDARD
         .decode(data_b64)
         .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
 
-    if combined.len() < SALT_LEN + IV_LEN + 1 {
-        return Err(CryptoError::InvalidData("Data too short".into()));
-    }
-
-    let salt = &combined[..SALT_LEN];
-    let iv = &combined[SALT_LEN..SALT_LEN + IV_LEN];
-    let ciphertext = &combined[SALT_LEN + IV_LEN..];
+    let (cached, iv, ciphertext) = if envelope.version >= 3 {
+        if combined.len() < IV_LEN + 1 {
+            return Err(CryptoError::InvalidData("Data too short".into()));
+        }
+        let mut candidates = envelope.key_slots.iter().filter(|s| s.kind == kind).peekable();
+        if candidates.peek().is_none() {
+            return Err(CryptoError::InvalidData(match kind {
+                SlotKind::Passphrase => "Vault has no passphrase slot".into(),
+                SlotKind::Recovery => "This vault has no recovery code".into(),
+                SlotKind::Device => "This vault has no device slot".into(),
+            }));
+        }
+        let mut result = Err(CryptoError::DecryptionFailed("Wrong passphrase or corrupted data".into()));
+        for slot in candidates {
+            result = match (slot.keyfile, keyfile) {
+                (true, None) => Err(CryptoError::KeyfileRequired),
+                (true, Some(keyfile)) => slot.unwrap(&passphrase_secret(secret, Some(keyfile))),
+                (false, _) => slot.unwrap(secret.as_bytes()),
+            };
+            if result.is_ok() {
+                break;
+            }
+        }
+        let key = result?;
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let kdf = envelope
+            .key_slots
+            .iter()
+            .find(|s| s.kind == SlotKind::Passphrase)
+            .map_or(Kdf::RECOMMENDED, |s| s.kdf);
+        let cached = CachedKey { key, salt, kdf, slots: envelope.key_slots.clone(), compress: true, pad: false };
+        (cached, &combined[..IV_LEN], &combined[IV_LEN..])
+    } else {
+        if kind != SlotKind::Passphrase {
+            return Err(CryptoError::InvalidData("This vault has no recovery code".into()));
+        }
+        let kdf = match (envelope.version, envelope.kdf) {
+            (1, _) => Kdf::LEGACY,
+            (_, Some(kdf)) => kdf,
+            (_, None) => return Err(CryptoError::InvalidData("Missing 'kdf' field".into())),
+        };
+        if combined.len() < SALT_LEN + IV_LEN + 1 {
+            return Err(CryptoError::InvalidData("Data too short".into()));
+        }
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&combined[..SALT_LEN]);
+        let key = kdf.derive(secret.as_bytes(), &salt)?;
+        // Reuse the same salt+key for future encryptions
+        let cached = CachedKey { key, salt, kdf, slots: Vec::new(), compress: true, pad: false };
+        (cached, &combined[SALT_LEN..SALT_LEN + IV_LEN], &combined[SALT_LEN + IV_LEN..])
+    };
 
-    let key = derive_key(passphrase, salt);
-    let cipher =
-        Aes256Gcm::new_from_slice(&key).map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+    let cipher = Aes256Gcm::new_from_slice(&cached.key)
+        .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
     let nonce = Nonce::from_slice(iv);
+    let aad = vault_aad(envelope.version, envelope.compression, envelope.padding);
     let plaintext = cipher
-        .decrypt(nonce, ciphertext)
+        .decrypt(nonce, Payload { msg: ciphertext, aad: &aad })
         .map_err(|_| CryptoError::DecryptionFailed("Wrong passphrase or corrupted data".into()))?;
+    let plaintext = decode_payload(plaintext, envelope.compression, envelope.padding)?;
 
     let plaintext_str =
         String::from_utf8(plaintext).map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
 
-    // Reuse the same salt+key for future encryptions
-    let mut salt_arr = [0u8; SALT_LEN];
-    salt_arr.copy_from_slice(salt);
-    let cached = CachedKey::from_raw(key, salt_arr);
-
     Ok((plaintext_str, cached))
 }
 
-/// Encrypt binary asset data using a pre-derived cached key.
-/// Format: `{"encrypted":true,"data":"<base64(salt+iv+ciphertext)>"}` — identical to web app.
-pub fn encrypt_asset(data: &[u8], cached: &CachedKey) -> Result<String, CryptoError> {
+/// Unlock a vault with a key `CachedKey::add_device_slot` returned. Fails
+/// with `DeviceKeyRevoked`, and only then, if the vault has no slot the key
+/// opens; a damaged or newer vault file gives other errors. The key slots
+/// are taken from the envelope, so later saves keep them.
+pub fn decrypt_vault_with_device_key(
+    encrypted_json: &str,
+    device_key: &[u8],
+) -> Result<(String, CachedKey), CryptoError> {
+    let envelope: VaultEnvelope =
+        serde_json::from_str(encrypted_json).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if !envelope.encrypted {
+        return Err(CryptoError::Unencrypted);
+    }
+    if envelope.version > ENVELOPE_VERSION {
+        return Err(CryptoError::InvalidData(format!(
+            "Vault format version {} was created by a newer version of MDNotebook",
+            envelope.version
+        )));
+    }
+    let id = device_id(device_key);
+    let slot = envelope
+        .key_slots
+        .iter()
+        .find(|s| s.kind == SlotKind::Device && s.device.as_deref() == Some(id.as_str()))
+        .ok_or(CryptoError::DeviceKeyRevoked)?;
+    let key = match slot.unwrap(device_key) {
+        Ok(key) => key,
+        Err(CryptoError::DecryptionFailed(_)) => return Err(CryptoError::DeviceKeyRevoked),
+        Err(e) => return Err(e),
+    };
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let kdf = envelope
+        .key_slots
+        .iter()
+        .find(|s| s.kind == SlotKind::Passphrase)
+        .map_or(Kdf::RECOMMENDED, |s| s.kdf);
+    let cached = CachedKey { key, salt, kdf, slots: envelope.key_slots, compress: true, pad: false };
+    let json = decrypt_vault_with_key(encrypted_json, &cached, PlaintextPolicy::Refuse)?;
+    Ok((json, cached))
+}
+
+/// Decrypt a vault envelope with an already-unlocked key instead of a secret,
+/// e.g. a backup written under an earlier passphrase but the same data key.
+pub fn decrypt_vault_with_key(
+    encrypted_json: &str,
+    cached: &CachedKey,
+    plaintext: PlaintextPolicy,
+) -> Result<String, CryptoError> {
+    let envelope: serde_json::Value =
+        serde_json::from_str(encrypted_json).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if !envelope.get("encrypted").and_then(|v| v.as_bool()).unwrap_or(false) {
+        if plaintext == PlaintextPolicy::Refuse {
+            return Err(CryptoError::Unencrypted);
+        }
+        return envelope
+            .get("data")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .or_else(|| serde_json::to_string(&envelope).ok())
+            .ok_or_else(|| CryptoError::InvalidData("No data field".into()));
+    }
+
+    let envelope: VaultEnvelope =
+        serde_json::from_value(envelope).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if envelope.version > ENVELOPE_VERSION {
+        return Err(CryptoError::InvalidData(format!(
+            "Vault format version {} was created by a newer version of MDNotebook",
+            envelope.version
+        )));
+    }
+    let combined = base64::engine::general_purpose::STANDARD
+        .decode(envelope.data.as_deref().unwrap_or_default())
+        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    let prefix = if envelope.version >= 3 { IV_LEN } else { SALT_LEN + IV_LEN };
+    if combined.len() < prefix + 1 {
+        return Err(CryptoError::InvalidData("Data too short".into()));
+    }
+    let (iv, ciphertext) = (&combined[prefix - IV_LEN..prefix], &combined[prefix..]);
+
+    let cipher = Aes256Gcm::new_from_slice(&cached.key)
+        .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+    let aad = vault_aad(envelope.version, envelope.compression, envelope.padding);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(iv), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| CryptoError::DecryptionFailed("Encrypted with a different key".into()))?;
+    let plaintext = decode_payload(plaintext, envelope.compression, envelope.padding)?;
+    String::from_utf8(plaintext).map_err(|e| CryptoError::DecryptionFailed(e.to_string()))
+}
+
+/// `encrypted_json` with its key slots replaced by those of `cached` and the
+/// payload left as it is, or `None` if it is not encrypted under `cached`'s
+/// data key (or predates key slots).
+pub fn rewrap_vault_envelope(encrypted_json: &str, cached: &CachedKey) -> Result<Option<String>, CryptoError> {
+    let mut envelope: VaultEnvelope =
+        serde_json::from_str(encrypted_json).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if !envelope.encrypted || envelope.version < 3 || !cached.is_wrapped() {
+        return Ok(None);
+    }
+    match decrypt_vault_with_key(encrypted_json, cached, PlaintextPolicy::Refuse) {
+        Ok(_) => {}
+        Err(CryptoError::DecryptionFailed(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    envelope.key_slots = cached.slots.clone();
+    if envelope.version >= 7 {
+        // Version 8 only adds device slots, so readers of 7 are told to update
+        envelope.version = ENVELOPE_VERSION;
+    }
+    serde_json::to_string(&envelope)
+        .map(Some)
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))
+}
+
+/// Associated data binding an asset's ciphertext to its ID and MIME type, so
+/// a file swapped or renamed inside `assets/` fails to decrypt.
+fn asset_aad(asset_id: &str, mime_type: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(17 + asset_id.len() + 1 + mime_type.len());
+    aad.extend_from_slice(b"mdnotebook-asset:");
+    aad.extend_from_slice(asset_id.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(mime_type.as_bytes());
+    aad
+}
+
+/// Associated data of a binary asset envelope, which also covers the
+/// compression and, from version 4, the padding.
+fn binary_asset_aad(asset_id: &str, asset: &BinaryAsset) -> Vec<u8> {
+    let mut aad = asset_aad(asset_id, asset.mime_type);
+    aad.push(0);
+    aad.extend_from_slice(asset.compression.name().as_bytes());
+    if asset.version >= 4 {
+        aad.push(0);
+        aad.extend_from_slice(asset.padding.name().as_bytes());
+    }
+    aad
+}
+
+/// Image formats that are compressed already; deflating them again only costs time.
+fn is_precompressed(mime_type: &str) -> bool {
+    matches!(mime_type, "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/avif")
+}
+
+/// Encrypt binary asset data using a pre-derived cached key. Format:
+/// `MDNA || version (1) || compression (1) || padding (1) || MIME length (1) || MIME type || IV || ciphertext`.
+pub fn encrypt_asset(
+    data: &[u8],
+    asset_id: &str,
+    mime_type: &str,
+    cached: &CachedKey,
+) -> Result<Vec<u8>, CryptoError> {
+    let mime_len = u8::try_from(mime_type.len())
+        .map_err(|_| CryptoError::EncryptionFailed("MIME type too long".into()))?;
+    let encoded = Encoded::new(data, cached.compress && !is_precompressed(mime_type), cached.pad)?;
+    let header = BinaryAsset {
+        version: ASSET_ENVELOPE_VERSION,
+        compression: encoded.compression,
+        padding: encoded.padding,
+        mime_type,
+        combined: &[],
+    };
+
     let mut iv = [0u8; IV_LEN];
     rand::thread_rng().fill_bytes(&mut iv);
 
     let cipher = Aes256Gcm::new_from_slice(&cached.key)
         .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
     let nonce = Nonce::from_slice(&iv);
+    let aad = binary_asset_aad(asset_id, &header);
     let ciphertext = cipher
-        .encrypt(nonce, data)
+        .encrypt(nonce, Payload { msg: encoded.bytes(data), aad: &aad })
         .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
 
-    let mut combined = Vec::with_capacity(SALT_LEN + IV_LEN + ciphertext.len());
-    combined.extend_from_slice(&cached.salt);
-    combined.extend_from_slice(&iv);
-    combined.extend_from_slice(&ciphertext);
+    let mut out = Vec::with_capacity(ASSET_MAGIC.len() + 4 + mime_type.len() + IV_LEN + ciphertext.len());
+    out.extend_from_slice(ASSET_MAGIC);
+    out.push(ASSET_ENVELOPE_VERSION);
+    out.push(header.compression.to_byte());
+    out.push(header.padding.to_byte());
+    out.push(mime_len);
+    out.extend_from_slice(mime_type.as_bytes());
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
 
-    let b64 = base64::engine::general_purpose::STANDARD.encode(&combined);
-    Ok(format!(r#"{{"encrypted":true,"data":"{b64}"}}"#))
+/// A binary asset envelope split into its fields.
+struct BinaryAsset<'a> {
+    version: u8,
+    compression: Compression,
+    padding: Padding,
+    mime_type: &'a str,
+    /// `IV || ciphertext`
+    combined: &'a [u8],
 }
 
-/// Decrypt binary asset bytes using a cached key.
-/// If bytes are not in encrypted envelope format, returns the original bytes.
-pub fn decrypt_asset(data: &[u8], cached: &CachedKey) -> Result<Vec<u8>, CryptoError> {
-    let Ok(as_text) = std::str::from_utf8(data) else {
-        // Raw binary asset (unencrypted fallback)
-        return Ok(data.to_vec());
+fn parse_binary_asset(data: &[u8]) -> Result<BinaryAsset<'_>, CryptoError> {
+    let too_short = || CryptoError::InvalidData("Encrypted asset data too short".into());
+    let header = data.strip_prefix(ASSET_MAGIC.as_slice()).ok_or_else(too_short)?;
+    let [version, compression, rest @ ..] = header else {
+        return Err(too_short());
+    };
+    let (padding, rest) = match *version {
+        3 => (Padding::None, rest),
+        4 => {
+            let [padding, rest @ ..] = rest else {
+                return Err(too_short());
+            };
+            (Padding::from_byte(*padding)?, rest)
+        }
+        v => {
+            return Err(CryptoError::InvalidData(format!(
+                "Asset format version {v} is not supported by this app"
+            )))
+        }
+    };
+    let [mime_len, rest @ ..] = rest else {
+        return Err(too_short());
     };
+    let mime_len = usize::from(*mime_len);
+    if rest.len() < mime_len {
+        return Err(too_short());
+    }
+    let (mime_type, combined) = rest.split_at(mime_len);
+    Ok(BinaryAsset {
+        version: *version,
+        compression: Compression::from_byte(*compression)?,
+        padding,
+        mime_type: std::str::from_utf8(mime_type).map_err(|e| CryptoError::InvalidData(e.to_string()))?,
+        combined,
+    })
+}
+
+/// The MIME type recorded in an asset envelope, if any. Legacy and unencrypted
+/// assets have none. Only authenticated once the asset is decrypted.
+pub fn asset_mime_type(data: &[u8]) -> Option<String> {
+    if data.starts_with(ASSET_MAGIC) {
+        return parse_binary_asset(data).ok().map(|asset| asset.mime_type.to_string());
+    }
+    let envelope: serde_json::Value = serde_json::from_slice(data).ok()?;
+    envelope.get("mimeType")?.as_str().map(str::to_string)
+}
 
-    let Ok(envelope) = serde_json::from_str::<serde_json::Value>(as_text) else {
+/// Decrypt binary asset bytes using a cached key. `asset_id` is the name the
+/// asset is stored under; an asset (version 2+) encrypted for another ID fails.
+/// If bytes are not in encrypted envelope format, returns the original bytes
+/// unless `plaintext` is `Refuse`.
+pub fn decrypt_asset(
+    data: &[u8],
+    asset_id: &str,
+    cached: &CachedKey,
+    plaintext: PlaintextPolicy,
+) -> Result<Vec<u8>, CryptoError> {
+    if !is_encrypted_envelope(data) {
         // Raw binary asset (unencrypted fallback)
-        return Ok(data.to_vec());
+        return match plaintext {
+            PlaintextPolicy::Allow => Ok(data.to_vec()),
+            PlaintextPolicy::Refuse => Err(CryptoError::Unencrypted),
+        };
+    }
+    let cipher = Aes256Gcm::new_from_slice(&cached.key)
+        .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+    let failed = || {
+        CryptoError::DecryptionFailed(format!(
+            "Asset {asset_id} failed verification (wrong key, corrupted, or substituted)"
+        ))
     };
 
-    let is_encrypted = envelope
-        .get("encrypted")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false);
-    if !is_encrypted {
-        return Ok(data.to_vec());
+    if data.starts_with(ASSET_MAGIC) {
+        let asset = parse_binary_asset(data)?;
+        if asset.combined.len() < IV_LEN + 1 {
+            return Err(CryptoError::InvalidData("Encrypted asset data too short".into()));
+        }
+        let (iv, ciphertext) = asset.combined.split_at(IV_LEN);
+        let aad = binary_asset_aad(asset_id, &asset);
+        let plain = cipher
+            .decrypt(Nonce::from_slice(iv), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| failed())?;
+        return decode_payload(plain, asset.compression, asset.padding);
+    }
+
+    let envelope: serde_json::Value =
+        serde_json::from_slice(data).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    let version = envelope.get("version").and_then(|v| v.as_u64()).unwrap_or(1);
+    if version > 2 {
+        return Err(CryptoError::InvalidData(format!(
+            "Asset format version {version} is newer than this app supports"
+        )));
     }
 
     let data_b64 = envelope
@@ -214,19 +1193,184 @@ pub fn decrypt_asset(data: &[u8], cached: &CachedKey) -> Result<Vec<u8>, CryptoE
         .decode(data_b64)
         .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
 
-    if combined.len() < SALT_LEN + IV_LEN + 1 {
+    if version == 1 {
+        // Legacy: salt prefix, no associated data
+        if combined.len() < SALT_LEN + IV_LEN + 1 {
+            return Err(CryptoError::InvalidData("Encrypted asset data too short".into()));
+        }
+        let iv = &combined[SALT_LEN..SALT_LEN + IV_LEN];
+        let ciphertext = &combined[SALT_LEN + IV_LEN..];
+        return cipher
+            .decrypt(Nonce::from_slice(iv), ciphertext)
+            .map_err(|_| CryptoError::DecryptionFailed("Wrong key or corrupted asset".into()));
+    }
+
+    // Version 2: JSON with base64 data, ID and MIME type as AAD
+    let mime_type = envelope
+        .get("mimeType")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| CryptoError::InvalidData("Missing asset MIME type".into()))?;
+    if combined.len() < IV_LEN + 1 {
         return Err(CryptoError::InvalidData("Encrypted asset data too short".into()));
     }
+    let (iv, ciphertext) = combined.split_at(IV_LEN);
+    let aad = asset_aad(asset_id, mime_type);
+    cipher
+        .decrypt(Nonce::from_slice(iv), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| failed())
+}
+
+/// Associated data binding a note file to its file name and note ID, so a
+/// note file can't be swapped for another one or attached to another note.
+/// From version 4 on its compression and padding are bound as well.
+fn note_file_aad(
+    version: u64,
+    file_name: &str,
+    note_id: &str,
+    compression: Compression,
+    padding: Padding,
+) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(16 + file_name.len() + 1 + note_id.len());
+    aad.extend_from_slice(b"mdnotebook-note:");
+    aad.extend_from_slice(file_name.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(note_id.as_bytes());
+    if version >= 4 {
+        aad.push(0);
+        aad.extend_from_slice(compression.name().as_bytes());
+        aad.push(0);
+        aad.extend_from_slice(padding.name().as_bytes());
+    }
+    aad
+}
+
+/// Encrypt the contents of one note file (see `note_files.rs`).
+pub fn encrypt_note_file(
+    data: &[u8],
+    file_name: &str,
+    note_id: &str,
+    cached: &CachedKey,
+) -> Result<String, CryptoError> {
+    let encoded = Encoded::new(data, cached.compress, cached.pad)?;
+    let payload = encoded.bytes(data);
+
+    let mut iv = [0u8; IV_LEN];
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let cipher = Aes256Gcm::new_from_slice(&cached.key)
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+    let aad = note_file_aad(
+        NOTE_FILE_VERSION.into(),
+        file_name,
+        note_id,
+        encoded.compression,
+        encoded.padding,
+    );
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&iv), Payload { msg: payload, aad: &aad })
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+
+    let mut combined = Vec::with_capacity(IV_LEN + ciphertext.len());
+    combined.extend_from_slice(&iv);
+    combined.extend_from_slice(&ciphertext);
+
+    let mut envelope = serde_json::json!({
+        "encrypted": true,
+        "version": NOTE_FILE_VERSION,
+        "data": base64::engine::general_purpose::STANDARD.encode(&combined),
+    });
+    if !encoded.compression.is_none() {
+        envelope["compression"] = encoded.compression.name().into();
+    }
+    if !encoded.padding.is_none() {
+        envelope["padding"] = encoded.padding.name().into();
+    }
+    Ok(envelope.to_string())
+}
 
-    let iv = &combined[SALT_LEN..SALT_LEN + IV_LEN];
-    let ciphertext = &combined[SALT_LEN + IV_LEN..];
+/// An envelope field that defaults when absent.
+fn optional_field<T: serde::de::DeserializeOwned + Default>(
+    envelope: &serde_json::Value,
+    name: &str,
+) -> Result<T, CryptoError> {
+    envelope.get(name).map_or(Ok(T::default()), |v| {
+        serde_json::from_value(v.clone()).map_err(|e| CryptoError::InvalidData(e.to_string()))
+    })
+}
 
+/// Decrypt a note file stored as `file_name` for the note `note_id`.
+pub fn decrypt_note_file(
+    data: &[u8],
+    file_name: &str,
+    note_id: &str,
+    cached: &CachedKey,
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let envelope: serde_json::Value =
+        serde_json::from_slice(data).map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    let version = envelope.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version > u64::from(NOTE_FILE_VERSION) {
+        return Err(CryptoError::InvalidData(format!(
+            "Note file format version {version} is newer than this app supports"
+        )));
+    }
+    let combined = base64::engine::general_purpose::STANDARD
+        .decode(envelope.get("data").and_then(|v| v.as_str()).unwrap_or_default())
+        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if combined.len() < IV_LEN + 1 {
+        return Err(CryptoError::InvalidData("Note file too short".into()));
+    }
+    let (iv, ciphertext) = combined.split_at(IV_LEN);
+    let cipher = Aes256Gcm::new_from_slice(&cached.key)
+        .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+    let compression: Compression = optional_field(&envelope, "compression")?;
+    let padding: Padding = optional_field(&envelope, "padding")?;
+    let aad = note_file_aad(version, file_name, note_id, compression, padding);
+    let plain = cipher
+        .decrypt(Nonce::from_slice(iv), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| {
+            CryptoError::DecryptionFailed(format!(
+                "Note file {file_name} failed verification (wrong key, corrupted, or substituted)"
+            ))
+        })?;
+    decode_payload(plain, compression, padding).map(Zeroizing::new)
+}
+
+/// Associated data for crash-journal records, so a record can't be passed off
+/// as any other ciphertext made with the vault key.
+const JOURNAL_AAD: &[u8] = b"mdnotebook-journal";
+
+/// Encrypt one crash-journal record as `base64(iv || ciphertext)`, a single line.
+pub fn encrypt_journal_record(data: &[u8], cached: &CachedKey) -> Result<String, CryptoError> {
+    let mut iv = [0u8; IV_LEN];
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let cipher = Aes256Gcm::new_from_slice(&cached.key)
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&iv), Payload { msg: data, aad: JOURNAL_AAD })
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+
+    let mut combined = Vec::with_capacity(IV_LEN + ciphertext.len());
+    combined.extend_from_slice(&iv);
+    combined.extend_from_slice(&ciphertext);
+    Ok(base64::engine::general_purpose::STANDARD.encode(&combined))
+}
+
+/// Decrypt a record made by `encrypt_journal_record`.
+pub fn decrypt_journal_record(record: &str, cached: &CachedKey) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let combined = base64::engine::general_purpose::STANDARD
+        .decode(record.trim())
+        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if combined.len() < IV_LEN + 1 {
+        return Err(CryptoError::InvalidData("Journal record too short".into()));
+    }
+    let (iv, ciphertext) = combined.split_at(IV_LEN);
     let cipher = Aes256Gcm::new_from_slice(&cached.key)
         .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
-    let nonce = Nonce::from_slice(iv);
     cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|_| CryptoError::DecryptionFailed("Wrong key or corrupted asset".into()))
+        .decrypt(Nonce::from_slice(iv), Payload { msg: ciphertext, aad: JOURNAL_AAD })
+        .map(Zeroizing::new)
+        .map_err(|_| CryptoError::DecryptionFailed("Wrong key or corrupted journal record".into()))
 }
 
 
@@ -235,21 +1379,219 @@ pub fn decrypt_asset(data: &[u8], cached: &CachedKey) -> Result<Vec<u8>, CryptoE
 mod tests {
     use super::*;
 
+    const STRICT: PlaintextPolicy = PlaintextPolicy::Refuse;
+
+    #[test]
+    fn asset_is_bound_to_id_and_mime() {
+        let key = CachedKey::generate("passphrase", None).expect("key");
+        let a = encrypt_asset(b"png-bytes", "aaaa", "image/png", &key).expect("encrypt");
+        assert!(is_encrypted_envelope(&a));
+        assert_eq!(asset_mime_type(&a).as_deref(), Some("image/png"));
+        assert_eq!(decrypt_asset(&a, "aaaa", &key, STRICT).unwrap(), b"png-bytes");
+
+        // Substituted file: stored under another asset's ID
+        assert!(matches!(
+            decrypt_asset(&a, "bbbb", &key, STRICT),
+            Err(CryptoError::DecryptionFailed(_))
+        ));
+        // Relabelled MIME type
+        let relabelled: Vec<u8> = [&a[..8], b"image/gif", &a[17..]].concat();
+        assert_eq!(asset_mime_type(&relabelled).as_deref(), Some("image/gif"));
+        assert!(decrypt_asset(&relabelled, "aaaa", &key, STRICT).is_err());
+        // Flipped compression flag
+        let mut flipped = a.clone();
+        flipped[5] = Compression::Deflate.to_byte();
+        assert!(decrypt_asset(&flipped, "aaaa", &key, STRICT).is_err());
+        // Downgraded to the JSON format
+        let parsed = parse_binary_asset(&a).unwrap();
+        let b64 = base64::engine::general_purpose::STANDARD.encode(parsed.combined);
+        let downgraded = format!(r#"{{"encrypted":true,"version":2,"mimeType":"image/png","data":"{b64}"}}"#);
+        assert!(decrypt_asset(downgraded.as_bytes(), "aaaa", &key, STRICT).is_err());
+    }
+
+    #[test]
+    fn payloads_are_compressed_when_it_helps() {
+        let mut key = CachedKey::generate("passphrase", None).expect("key");
+        let plaintext = format!(r#"{{"tree":[{}]}}"#, r#"{"content":"lorem ipsum "},"#.repeat(200));
+        let encrypted = encrypt_vault_fast(&plaintext, &key).expect("encrypt");
+        let envelope: serde_json::Value = serde_json::from_str(&encrypted).unwrap();
+        assert_eq!(envelope["version"], ENVELOPE_VERSION);
+        assert_eq!(envelope["compression"], "deflate");
+        assert!(encrypted.len() < plaintext.len());
+        assert_eq!(decrypt_vault_with_key(&encrypted, &key, STRICT).unwrap(), plaintext);
+
+        // Already-compressed images are stored as they are
+        let svg = plaintext.as_bytes();
+        let stored = encrypt_asset(svg, "svg1", "image/svg+xml", &key).expect("encrypt");
+        assert_eq!(stored[5], Compression::Deflate.to_byte());
+        assert_eq!(decrypt_asset(&stored, "svg1", &key, STRICT).unwrap(), svg);
+        let stored = encrypt_asset(svg, "png1", "image/png", &key).expect("encrypt");
+        assert_eq!(stored[5], Compression::None.to_byte());
+
+        let note = encrypt_note_file(svg, "f1", "n1", &key).expect("encrypt");
+        assert!(note.contains(r#""compression":"deflate""#));
+        assert_eq!(decrypt_note_file(note.as_bytes(), "f1", "n1", &key).unwrap().as_slice(), svg);
+
+        key.set_compression(false);
+        let encrypted = encrypt_vault_fast(&plaintext, &key).expect("encrypt");
+        assert!(!encrypted.contains("compression"));
+        assert_eq!(decrypt_vault_with_key(&encrypted, &key, STRICT).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn padding_hides_exact_lengths() {
+        assert_eq!(padded_len(0), MIN_PADDED_LEN);
+        assert_eq!(padded_len(1000), 1024);
+        // Overhead stays around 12% at most
+        for len in [600, 5_000, 70_000, 1_234_567] {
+            let padded = padded_len(len);
+            assert!(padded > len && padded as f64 <= len as f64 * 1.13, "{len} -> {padded}");
+        }
+
+        let mut key = CachedKey::generate("passphrase", None).expect("key");
+        key.set_compression(false);
+        key.set_padding(true);
+        let sizes: Vec<usize> = ["{\"a\":1}", "{\"a\":12345}"]
+            .iter()
+            .map(|plaintext| {
+                let encrypted = encrypt_vault_fast(plaintext, &key).expect("encrypt");
+                assert_eq!(decrypt_vault_with_key(&encrypted, &key, STRICT).unwrap(), *plaintext);
+                encrypted.len()
+            })
+            .collect();
+        assert_eq!(sizes[0], sizes[1]);
+
+        let image = vec![7u8; 3000];
+        let stored = encrypt_asset(&image, "img", "image/png", &key).expect("encrypt");
+        assert_eq!(stored[6], Padding::Padme.to_byte());
+        assert_eq!(decrypt_asset(&stored, "img", &key, STRICT).unwrap(), image);
+        let other = encrypt_asset(&image[..2990], "img", "image/png", &key).expect("encrypt");
+        assert_eq!(stored.len(), other.len());
+        // Dropping the padding flag breaks authentication
+        let mut stripped = stored.clone();
+        stripped[6] = Padding::None.to_byte();
+        assert!(decrypt_asset(&stripped, "img", &key, STRICT).is_err());
+
+        let note = encrypt_note_file(b"short", "f1", "n1", &key).expect("encrypt");
+        assert_eq!(decrypt_note_file(note.as_bytes(), "f1", "n1", &key).unwrap().as_slice(), b"short");
+        // Vault and note envelopes authenticate the flag as well
+        let stripped = note.replace(r#","padding":"padme""#, "");
+        assert_ne!(stripped, note);
+        assert!(decrypt_note_file(stripped.as_bytes(), "f1", "n1", &key).is_err());
+        let encrypted = encrypt_vault_fast("{}", &key).expect("encrypt");
+        let stripped = encrypted.replace(r#""padding":"padme","#, "");
+        assert_ne!(stripped, encrypted);
+        assert!(decrypt_vault_with_key(&stripped, &key, STRICT).is_err());
+    }
+
+    #[test]
+    fn decompression_output_is_bounded() {
+        let zeros = vec![0u8; 10_000];
+        let compressed = compress(&zeros, true).unwrap().expect("compressible");
+        assert_eq!(inflate(&compressed, zeros.len()).unwrap(), zeros);
+        assert!(matches!(inflate(&compressed, 1000), Err(CryptoError::InvalidData(_))));
+    }
+
+    #[test]
+    fn legacy_asset_still_decrypts() {
+        let key = CachedKey::generate("passphrase", None).expect("key");
+        let mut iv = [0u8; IV_LEN];
+        rand::thread_rng().fill_bytes(&mut iv);
+        let cipher = Aes256Gcm::new_from_slice(&key.key).unwrap();
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&iv), &b"old-image"[..]).unwrap();
+        let mut combined = key.salt.to_vec();
+        combined.extend_from_slice(&iv);
+        combined.extend_from_slice(&ciphertext);
+        let b64 = base64::engine::general_purpose::STANDARD.encode(&combined);
+        let legacy = format!(r#"{{"encrypted":true,"data":"{b64}"}}"#);
+
+        assert_eq!(asset_mime_type(legacy.as_bytes()), None);
+        assert_eq!(decrypt_asset(legacy.as_bytes(), "any-id", &key, STRICT).unwrap(), b"old-image");
+        // Raw, unencrypted bytes pass through unchanged unless refused
+        let raw = b"\x89PNG";
+        assert_eq!(decrypt_asset(raw, "any-id", &key, PlaintextPolicy::Allow).unwrap(), raw);
+        assert!(matches!(decrypt_asset(raw, "any-id", &key, STRICT), Err(CryptoError::Unencrypted)));
+    }
+
+    #[test]
+    fn decrypt_with_key_survives_passphrase_change() {
+        let key = CachedKey::generate("first", None).expect("key");
+        let backup = encrypt_vault_fast(r#"{"tree":[]}"#, &key).expect("encrypt");
+        let key = key.with_passphrase("second", None).expect("rewrap");
+        assert_eq!(decrypt_vault_with_key(&backup, &key, STRICT).unwrap(), r#"{"tree":[]}"#);
+
+        let other = CachedKey::generate("first", None).expect("key");
+        assert!(decrypt_vault_with_key(&backup, &other, STRICT).is_err());
+    }
+
+    #[test]
+    fn device_slot_unlocks_until_removed() {
+        let mut key = CachedKey::generate("device", None).expect("key");
+        let device_key = key.add_device_slot().expect("device slot");
+        let encrypted = encrypt_vault_fast(r#"{"tree":[]}"#, &key).expect("encrypt");
+        assert!(!encrypted.contains(&base64::engine::general_purpose::STANDARD.encode(key.key)));
+        let (json, mut unlocked) = decrypt_vault_with_device_key(&encrypted, &device_key).expect("decrypt");
+        assert_eq!(json, r#"{"tree":[]}"#);
+        // Saving with the unlocked key keeps the passphrase and the device slot
+        let saved = encrypt_vault_fast(&json, &unlocked).expect("encrypt");
+        assert!(decrypt_vault_returning_key(&saved, "device", None, STRICT).is_ok());
+        assert!(decrypt_vault_with_device_key(&saved, &device_key).is_ok());
+
+        let mut other = CachedKey::generate("device", None).expect("key");
+        let other_key = other.add_device_slot().expect("device slot");
+        assert!(matches!(decrypt_vault_with_device_key(&encrypted, &other_key), Err(CryptoError::DeviceKeyRevoked)));
+        assert!(matches!(decrypt_vault_with_device_key(&encrypted, b"short"), Err(CryptoError::DeviceKeyRevoked)));
+
+        // Forgetting the device drops its slot only
+        assert!(unlocked.remove_device_slot(&device_key));
+        assert!(!unlocked.remove_device_slot(&device_key));
+        let forgotten = encrypt_vault_fast(&json, &unlocked).expect("encrypt");
+        assert!(matches!(decrypt_vault_with_device_key(&forgotten, &device_key), Err(CryptoError::DeviceKeyRevoked)));
+        assert!(decrypt_vault_returning_key(&forgotten, "device", None, STRICT).is_ok());
+
+        // A damaged vault is not mistaken for a revoked key
+        let truncated = &encrypted[..encrypted.len() - 10];
+        assert!(matches!(decrypt_vault_with_device_key(truncated, &device_key), Err(CryptoError::InvalidData(_))));
+        let legacy = CachedKey::derive_legacy("device", Kdf::LEGACY).expect("derive");
+        assert!(legacy.clone().add_device_slot().is_err());
+    }
+
+    #[test]
+    fn passphrase_and_recovery_changes_revoke_device_slots() {
+        let mut key = CachedKey::generate("first", None).expect("key");
+        let device_key = key.add_device_slot().expect("device slot");
+        let changed = key.with_passphrase("second", None).expect("rewrap");
+        let encrypted = encrypt_vault_fast(r#"{"tree":[]}"#, &changed).expect("encrypt");
+        assert!(matches!(decrypt_vault_with_device_key(&encrypted, &device_key), Err(CryptoError::DeviceKeyRevoked)));
+        assert!(decrypt_vault_returning_key(&encrypted, "second", None, STRICT).is_ok());
+
+        let device_key = key.add_device_slot().expect("device slot");
+        key.add_recovery_code().expect("recovery code");
+        let encrypted = encrypt_vault_fast(r#"{"tree":[]}"#, &key).expect("encrypt");
+        assert!(matches!(decrypt_vault_with_device_key(&encrypted, &device_key), Err(CryptoError::DeviceKeyRevoked)));
+    }
+
     #[test]
     fn unencrypted_fallback() {
         let envelope = r#"{"encrypted":false,"data":"{\"tree\":[]}"}"#;
-        let (result, _key) = decrypt_vault_returning_key(envelope, "any").expect("decrypt");
+        let (result, _key) =
+            decrypt_vault_returning_key(envelope, "any", None, PlaintextPolicy::Allow).expect("decrypt");
         assert_eq!(result, r#"{"tree":[]}"#);
+        assert!(matches!(
+            decrypt_vault_returning_key(envelope, "any", None, STRICT),
+            Err(CryptoError::Unencrypted)
+        ));
+        assert!(!is_encrypted_envelope(envelope.as_bytes()));
     }
 
     #[test]
     fn cached_key_roundtrip() {
         let plaintext = r#"{"tree":[],"trash":[]}"#;
         let pass = "cached-key-test";
-        let cached = CachedKey::derive(pass);
+        let cached = CachedKey::generate(pass, None).expect("generate");
         let encrypted = encrypt_vault_fast(plaintext, &cached).expect("encrypt");
         assert!(encrypted.contains("\"encrypted\":true"));
-        let (decrypted, _key) = decrypt_vault_returning_key(&encrypted, pass).expect("decrypt");
+        let (decrypted, _key) = decrypt_vault_returning_key(&encrypted, pass, None, STRICT).expect("decrypt");
         assert_eq!(decrypted, plaintext);
     }
 
@@ -257,14 +1599,116 @@ mod tests {
     fn decrypt_returning_key_roundtrip() {
         let plaintext = r#"{"tree":[],"trash":[]}"#;
         let pass = "roundtrip-test-pass";
-        let cached = CachedKey::derive(pass);
+        let cached = CachedKey::generate(pass, None).expect("generate");
         let encrypted = encrypt_vault_fast(plaintext, &cached).expect("encrypt");
         let (decrypted, reused_key) =
-            decrypt_vault_returning_key(&encrypted, pass).expect("decrypt");
+            decrypt_vault_returning_key(&encrypted, pass, None, STRICT).expect("decrypt");
         assert_eq!(decrypted, plaintext);
         // Re-encrypt with reused key and verify roundtrip
         let re_encrypted = encrypt_vault_fast(plaintext, &reused_key).expect("re-encrypt");
-        let (re_decrypted, _) = decrypt_vault_returning_key(&re_encrypted, pass).expect("re-decrypt");
+        let (re_decrypted, _) = decrypt_vault_returning_key(&re_encrypted, pass, None, STRICT).expect("re-decrypt");
         assert_eq!(re_decrypted, plaintext);
     }
+
+    #[test]
+    fn legacy_envelope_reads_and_needs_upgrade() {
+        let plaintext = r#"{"tree":[]}"#;
+        let pass = "legacy-pass";
+        let legacy = CachedKey::derive_legacy(pass, Kdf::LEGACY).expect("derive");
+        let v2: serde_json::Value =
+            serde_json::from_str(&encrypt_vault_fast(plaintext, &legacy).expect("encrypt")).unwrap();
+        assert_eq!(v2["version"], 2);
+        // Version 1 files had no version or kdf fields
+        let v1 = format!(r#"{{"encrypted":true,"data":{}}}"#, v2["data"]);
+        let (decrypted, key) = decrypt_vault_returning_key(&v1, pass, None, STRICT).expect("decrypt");
+        assert_eq!(decrypted, plaintext);
+        assert!(!key.is_wrapped());
+        assert!(key.needs_upgrade());
+        assert!(!CachedKey::generate(pass, None).expect("generate").needs_upgrade());
+    }
+
+    #[test]
+    fn rejects_future_version_and_tampered_kdf() {
+        let cached = CachedKey::generate("pass", None).expect("generate");
+        let encrypted = encrypt_vault_fast("{}", &cached).expect("encrypt");
+        let mut envelope: serde_json::Value = serde_json::from_str(&encrypted).unwrap();
+        assert_eq!(envelope["keySlots"][0]["kdf"]["algorithm"], "argon2id");
+
+        envelope["version"] = 99.into();
+        assert!(decrypt_vault_returning_key(&envelope.to_string(), "pass", None, STRICT).is_err());
+
+        envelope["version"] = 3.into();
+        envelope["keySlots"][0]["kdf"]["memoryKib"] = u32::MAX.into();
+        assert!(matches!(
+            decrypt_vault_returning_key(&envelope.to_string(), "pass", None, STRICT),
+            Err(CryptoError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn recovery_code_and_passphrase_rewrap() {
+        let plaintext = r#"{"tree":[]}"#;
+        let mut cached = CachedKey::generate("old-pass", None).expect("generate");
+        let code = cached.add_recovery_code().expect("recovery");
+        assert_eq!(code.len(), 39); // 32 chars in groups of 4
+        let encrypted = encrypt_vault_fast(plaintext, &cached).expect("encrypt");
+
+        // Typed in lower case without dashes
+        let typed = code.replace('-', "").to_lowercase();
+        let (decrypted, recovered) =
+            decrypt_vault_with_recovery_code(&encrypted, &typed).expect("recover");
+        assert_eq!(decrypted, plaintext);
+
+        // Resetting the passphrase keeps the data key and the recovery slot
+        let rewrapped = recovered.with_passphrase("new-pass", None).expect("rewrap");
+        let encrypted = encrypt_vault_fast(plaintext, &rewrapped).expect("encrypt");
+        assert!(decrypt_vault_returning_key(&encrypted, "old-pass", None, STRICT).is_err());
+        let (_, key) = decrypt_vault_returning_key(&encrypted, "new-pass", None, STRICT).expect("new pass");
+        assert!(key.has_recovery_code());
+        assert_eq!(key.key, cached.key);
+        assert!(decrypt_vault_with_recovery_code(&encrypted, &code).is_ok());
+    }
+
+    #[test]
+    fn rewrap_replaces_slots_only() {
+        let plaintext = r#"{"tree":[]}"#;
+        let cached = CachedKey::generate("old-pass", None).expect("generate");
+        let encrypted = encrypt_vault_fast(plaintext, &cached).expect("encrypt");
+        let rewrapped_key = cached.with_passphrase("new-pass", None).expect("rewrap");
+
+        let rewrapped = rewrap_vault_envelope(&encrypted, &rewrapped_key).unwrap().expect("same data key");
+        let data = |json: &str| serde_json::from_str::<serde_json::Value>(json).unwrap()["data"].clone();
+        assert_eq!(data(&rewrapped), data(&encrypted));
+        assert!(decrypt_vault_returning_key(&rewrapped, "old-pass", None, STRICT).is_err());
+        let (decrypted, _) = decrypt_vault_returning_key(&rewrapped, "new-pass", None, STRICT).expect("new pass");
+        assert_eq!(decrypted, plaintext);
+
+        // Another data key leaves the envelope alone
+        let other = CachedKey::generate("new-pass", None).expect("generate");
+        assert!(rewrap_vault_envelope(&encrypted, &other).unwrap().is_none());
+    }
+
+    #[test]
+    fn keyfile_is_required_and_checked() {
+        let keyfile = Keyfile::from_reader(&b"keyfile contents"[..]).unwrap();
+        let other = Keyfile::from_reader(&b"some other file"[..]).unwrap();
+        let cached = CachedKey::generate("pass", Some(&keyfile)).expect("generate");
+        assert!(cached.requires_keyfile());
+        let encrypted = encrypt_vault_fast("{}", &cached).expect("encrypt");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&encrypted).unwrap()["keySlots"][0]["keyfile"],
+            true
+        );
+
+        assert!(matches!(
+            decrypt_vault_returning_key(&encrypted, "pass", None, STRICT),
+            Err(CryptoError::KeyfileRequired)
+        ));
+        assert!(matches!(
+            decrypt_vault_returning_key(&encrypted, "pass", Some(&other), STRICT),
+            Err(CryptoError::DecryptionFailed(_))
+        ));
+        let (_, key) = decrypt_vault_returning_key(&encrypted, "pass", Some(&keyfile), STRICT).expect("decrypt");
+        assert!(key.requires_keyfile());
+    }
 }
@@ -0,0 +1,712 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use crate::crypto::{self, CachedKey, PlaintextPolicy};
+use crate::note_files;
+use crate::vault::{self, AssetMeta, TreeItem, VaultData};
+use crate::writer_lock::{LockError, WriterLock};
+
+// ---------------------------------------------------------------------------
+// Asset references
+// ---------------------------------------------------------------------------
+
+/// Where a `vault://` reference was found. Holds the note's title.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefLocation {
+    Note(String),
+    Version(String),
+    Trash(String),
+}
+
+impl fmt::Display for RefLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Note(name) => write!(f, "note \u{201c}{name}\u{201d}"),
+            Self::Version(name) => write!(f, "version history of \u{201c}{name}\u{201d}"),
+            Self::Trash(name) => write!(f, "trashed note \u{201c}{name}\u{201d}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetReference {
+    pub asset_id: String,
+    pub location: RefLocation,
+}
+
+/// Asset IDs referenced as `vault://<id>` in markdown text.
+pub fn asset_ids_in(text: &str) -> impl Iterator<Item = &str> {
+    text.match_indices("vault://").filter_map(move |(start, prefix)| {
+        let rest = &text[start + prefix.len()..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(rest.len());
+        (len > 0).then(|| &rest[..len])
+    })
+}
+
+/// `text` with each `vault://<id>` reference for which `replace` returns a new
+/// ID pointing there instead.
+pub fn replace_asset_ids(text: &str, replace: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("vault://") {
+        let (before, after) = rest.split_at(start + "vault://".len());
+        out.push_str(before);
+        let len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(after.len());
+        let id = &after[..len];
+        match replace(id).filter(|_| len > 0) {
+            Some(new_id) => out.push_str(&new_id),
+            None => out.push_str(id),
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Every asset reference in notes, version history and trash.
+pub fn asset_references(data: &VaultData) -> Vec<AssetReference> {
+    fn walk(
+        items: &[TreeItem],
+        location: &dyn Fn(String) -> RefLocation,
+        names: &mut HashMap<String, String>,
+        out: &mut Vec<AssetReference>,
+    ) {
+        for item in items {
+            names.insert(item.id.clone(), item.name.clone());
+            if let Some(content) = &item.content {
+                out.extend(asset_ids_in(content).map(|id| AssetReference {
+                    asset_id: id.to_string(),
+                    location: location(item.name.clone()),
+                }));
+            }
+            if let Some(children) = &item.children {
+                walk(children, location, names, out);
+            }
+        }
+    }
+
+    let mut names = HashMap::new();
+    let mut out = Vec::new();
+    walk(&data.tree, &RefLocation::Note, &mut names, &mut out);
+    walk(&data.trash, &RefLocation::Trash, &mut names, &mut out);
+
+    let mut note_ids: Vec<&String> = data.note_versions.keys().collect();
+    note_ids.sort();
+    for note_id in note_ids {
+        let name = names.get(note_id).cloned().unwrap_or_else(|| note_id.clone());
+        let mut seen = HashSet::new();
+        for version in &data.note_versions[note_id] {
+            for id in asset_ids_in(&version.content) {
+                if seen.insert(id) {
+                    out.push(AssetReference {
+                        asset_id: id.to_string(),
+                        location: RefLocation::Version(name.clone()),
+                    });
+                }
+            }
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Integrity check
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub assets_checked: usize,
+    pub references_checked: usize,
+    /// Asset files that fail to decrypt or verify, with the error.
+    pub unreadable: Vec<(String, String)>,
+    /// Asset metadata with no file in `assets/`.
+    pub missing_files: Vec<String>,
+    /// Asset files with no metadata.
+    pub orphaned_files: Vec<String>,
+    /// `vault://` references to assets the vault has no metadata for.
+    pub broken_references: Vec<AssetReference>,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.unreadable.is_empty()
+            && self.missing_files.is_empty()
+            && self.orphaned_files.is_empty()
+            && self.broken_references.is_empty()
+    }
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Checked {} asset files and {} references.",
+            self.assets_checked, self.references_checked
+        )?;
+        if self.is_clean() {
+            return writeln!(f, "No problems found.");
+        }
+        for (id, error) in &self.unreadable {
+            writeln!(f, "unreadable: assets/{id}: {error}")?;
+        }
+        for id in &self.missing_files {
+            writeln!(f, "missing file: assets/{id} has metadata but no file")?;
+        }
+        for id in &self.orphaned_files {
+            writeln!(f, "orphaned file: assets/{id} has no metadata")?;
+        }
+        for reference in &self.broken_references {
+            writeln!(f, "broken reference: vault://{} in {}", reference.asset_id, reference.location)?;
+        }
+        Ok(())
+    }
+}
+
+/// Asset files in `assets/`, skipping temporary files from interrupted writes.
+fn asset_files(vault_folder: &str) -> io::Result<Vec<String>> {
+    let dir = vault::assets_dir(vault_folder);
+    let mut ids = Vec::new();
+    if dir.is_dir() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_tmp = Path::new(&name).extension().is_some_and(|ext| ext == "tmp");
+            if entry.file_type()?.is_file() && !is_tmp {
+                ids.push(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Decrypt every asset file and cross-check files, metadata and references.
+pub fn check_vault(
+    vault_folder: &str,
+    data: &VaultData,
+    key: &CachedKey,
+    policy: PlaintextPolicy,
+) -> io::Result<IntegrityReport> {
+    let mut report = IntegrityReport::default();
+    let files = asset_files(vault_folder)?;
+    let on_disk: HashSet<&str> = files.iter().map(String::as_str).collect();
+
+    for id in &files {
+        report.assets_checked += 1;
+        let raw = fs::read(vault::assets_dir(vault_folder).join(id))?;
+        if let Err(e) = crypto::decrypt_asset(&raw, id, key, policy) {
+            report.unreadable.push((id.clone(), e.to_string()));
+        }
+        if !data.assets.contains_key(id) {
+            report.orphaned_files.push(id.clone());
+        }
+    }
+
+    let mut missing: Vec<String> = data
+        .assets
+        .keys()
+        .filter(|id| !on_disk.contains(id.as_str()))
+        .cloned()
+        .collect();
+    missing.sort();
+    report.missing_files = missing;
+
+    let references = asset_references(data);
+    report.references_checked = references.len();
+    report.broken_references = references
+        .into_iter()
+        .filter(|r| !data.assets.contains_key(&r.asset_id))
+        .collect();
+    Ok(report)
+}
+
+// ---------------------------------------------------------------------------
+// Repairs
+// ---------------------------------------------------------------------------
+
+/// Remove metadata for assets whose file is missing. Returns the number removed.
+pub fn drop_dangling_metadata(assets: &mut HashMap<String, AssetMeta>, report: &IntegrityReport) -> usize {
+    report
+        .missing_files
+        .iter()
+        .filter(|id| assets.remove(id.as_str()).is_some())
+        .count()
+}
+
+pub fn quarantine_dir(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("quarantine")
+}
+
+/// Move unreadable asset files out of `assets/` into `quarantine/`, where they
+/// can be inspected or deleted by hand. Returns the number moved.
+pub fn quarantine_unreadable(vault_folder: &str, report: &IntegrityReport) -> io::Result<usize> {
+    let dir = quarantine_dir(vault_folder);
+    fs::create_dir_all(&dir)?;
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut moved = 0;
+    for (id, _) in &report.unreadable {
+        let source = vault::assets_dir(vault_folder).join(id);
+        if source.exists() {
+            fs::rename(&source, dir.join(format!("{id}.{stamp}")))?;
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
+
+/// Recreate metadata for orphaned asset files that decrypt cleanly. Returns the
+/// number restored.
+pub fn restore_orphan_metadata(
+    vault_folder: &str,
+    assets: &mut HashMap<String, AssetMeta>,
+    report: &IntegrityReport,
+    key: &CachedKey,
+    policy: PlaintextPolicy,
+) -> io::Result<usize> {
+    let mut restored = 0;
+    for id in &report.orphaned_files {
+        let path = vault::assets_dir(vault_folder).join(id);
+        let raw = fs::read(&path)?;
+        let Ok(plain) = crypto::decrypt_asset(&raw, id, key, policy) else {
+            continue;
+        };
+        let mime_type = crypto::asset_mime_type(&raw)
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let ext = match mime_type.as_str() {
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/svg+xml" => "svg",
+            _ => "bin",
+        };
+        let created_at = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        assets.insert(
+            id.clone(),
+            AssetMeta {
+                id: id.clone(),
+                original_name: format!("recovered-{id}.{ext}"),
+                mime_type,
+                size: plain.len() as u64,
+                created_at,
+                orphaned_at: None,
+            },
+        );
+        restored += 1;
+    }
+    Ok(restored)
+}
+
+// ---------------------------------------------------------------------------
+// Asset collection
+// ---------------------------------------------------------------------------
+//
+// An asset no note, version or trashed note refers to is first marked with the
+// time it was found unreferenced (`AssetMeta::orphaned_at`). Only once it has
+// stayed unreferenced for the grace period are its file and metadata removed,
+// so undoing an image deletion within that time still finds the image.
+
+/// How long an unreferenced asset is kept before it is collected.
+pub const ASSET_GC_GRACE_MS: i64 = 10 * 60 * 1000;
+/// How long an asset file without metadata is kept after it appeared on this
+/// device: it may belong to a manifest that is still syncing.
+pub const UNKNOWN_ASSET_GRACE_MS: i64 = note_files::SWEEP_GRACE_SECS * 1000;
+
+/// Asset IDs referenced anywhere in the vault. Templates count too, so an
+/// image in a template survives until the template is deleted.
+pub fn referenced_asset_ids(data: &VaultData) -> HashSet<String> {
+    let mut ids: HashSet<String> = asset_references(data).into_iter().map(|r| r.asset_id).collect();
+    for template in &data.custom_templates {
+        ids.extend(asset_ids_in(&template.content).map(str::to_string));
+    }
+    ids
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AssetGcPlan {
+    /// Newly unreferenced assets, to be marked with the current time.
+    pub mark: Vec<String>,
+    /// Marked assets that are referenced again.
+    pub unmark: Vec<String>,
+    /// Assets unreferenced for longer than the grace period.
+    pub collect: Vec<String>,
+    /// Unreferenced assets still inside the grace period (including new marks).
+    pub pending: usize,
+}
+
+/// Decide what a collection pass does. `files` lists the asset files on disk
+/// with the time they appeared (see `asset_files_changed`); files without
+/// metadata have no mark, so they are kept for `UNKNOWN_ASSET_GRACE_MS` from
+/// then instead.
+pub fn plan_asset_gc(data: &VaultData, files: &[(String, i64)], now: i64, grace_ms: i64) -> AssetGcPlan {
+    let referenced = referenced_asset_ids(data);
+    let mut plan = AssetGcPlan::default();
+
+    let mut ids: Vec<&String> = data.assets.keys().collect();
+    ids.sort();
+    for id in ids {
+        let orphaned_at = data.assets[id].orphaned_at;
+        match (referenced.contains(id), orphaned_at) {
+            (true, Some(_)) => plan.unmark.push(id.clone()),
+            (true, None) => {}
+            (false, None) => {
+                plan.mark.push(id.clone());
+                plan.pending += 1;
+            }
+            (false, Some(t)) if now - t >= grace_ms => plan.collect.push(id.clone()),
+            (false, Some(_)) => plan.pending += 1,
+        }
+    }
+    for (id, changed) in files {
+        if data.assets.contains_key(id) || referenced.contains(id) {
+            continue;
+        }
+        if now - changed >= UNKNOWN_ASSET_GRACE_MS {
+            plan.collect.push(id.clone());
+        } else {
+            plan.pending += 1;
+        }
+    }
+    plan
+}
+
+/// Asset files with their inode change time in milliseconds. Sync tools carry
+/// the modification time over, so a file that just arrived can look old by it.
+pub fn asset_files_changed(vault_folder: &str) -> io::Result<Vec<(String, i64)>> {
+    use std::os::unix::fs::MetadataExt;
+    let dir = vault::assets_dir(vault_folder);
+    asset_files(vault_folder)?
+        .into_iter()
+        .map(|id| {
+            let metadata = fs::metadata(dir.join(&id))?;
+            Ok((id, metadata.ctime() * 1000 + metadata.ctime_nsec() / 1_000_000))
+        })
+        .collect()
+}
+
+/// Apply a plan's metadata changes: set and clear marks, drop collected entries.
+pub fn apply_gc_plan(assets: &mut HashMap<String, AssetMeta>, plan: &AssetGcPlan, now: i64) {
+    for id in &plan.mark {
+        if let Some(meta) = assets.get_mut(id) {
+            meta.orphaned_at = Some(now);
+        }
+    }
+    for id in &plan.unmark {
+        if let Some(meta) = assets.get_mut(id) {
+            meta.orphaned_at = None;
+        }
+    }
+    for id in &plan.collect {
+        assets.remove(id);
+    }
+}
+
+/// Delete the files of collected assets. Returns the number deleted.
+pub fn delete_asset_files(vault_folder: &str, ids: &[String]) -> io::Result<usize> {
+    let mut deleted = 0;
+    for id in ids {
+        match fs::remove_file(vault::assets_dir(vault_folder).join(id)) {
+            Ok(()) => deleted += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(deleted)
+}
+
+// ---------------------------------------------------------------------------
+// Headless check:  mdnotebook --check-vault <folder> [--keyfile <path>] [--fix]
+// ---------------------------------------------------------------------------
+
+const CLI_USAGE: &str = "Usage: mdnotebook --check-vault <vault-folder> [--keyfile <path>] [--fix]\n\
+    The passphrase is read from MDNOTEBOOK_PASSPHRASE or standard input.\n\
+    --fix needs the vault to be closed everywhere else.";
+
+const CHANGED_DURING_CHECK: &str = "The vault file changed during the check, so nothing was fixed. Run it again.";
+
+/// Read a line from standard input, without echoing it if that is a terminal.
+fn read_secret_line(prompt: &str) -> Result<zeroize::Zeroizing<String>, String> {
+    use std::io::IsTerminal;
+    use std::process::{Command, Stdio};
+
+    eprint!("{prompt}");
+    let tty = io::stdin().is_terminal();
+    // stty works on its standard input, which is the terminal
+    let stty = |arg: &str| Command::new("stty").arg(arg).stdin(Stdio::inherit()).status();
+    if tty && !stty("-echo").is_ok_and(|status| status.success()) {
+        eprintln!();
+        return Err("Could not turn off echo for the passphrase; set MDNOTEBOOK_PASSPHRASE instead.".into());
+    }
+    let mut line = zeroize::Zeroizing::new(String::new());
+    let read = io::stdin().read_line(&mut line);
+    if tty {
+        let _ = stty("echo");
+        eprintln!();
+    }
+    read.map_err(|e| e.to_string())?;
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+    Ok(line)
+}
+
+/// Run the integrity check without the GUI. Returns the process exit code:
+/// 0 if the vault is clean (after fixes), 1 if problems remain, 2 on error.
+pub fn run_cli(args: &[String]) -> i32 {
+    match cli(args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("{e}");
+            2
+        }
+    }
+}
+
+fn cli(args: &[String]) -> Result<bool, String> {
+    let mut folder = None;
+    let mut keyfile_path = None;
+    let mut fix = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            "--keyfile" => keyfile_path = Some(args.next().ok_or(CLI_USAGE)?.clone()),
+            "-h" | "--help" => {
+                println!("{CLI_USAGE}");
+                return Ok(true);
+            }
+            _ if folder.is_none() && !arg.starts_with('-') => folder = Some(arg.clone()),
+            _ => return Err(CLI_USAGE.to_string()),
+        }
+    }
+    let folder = folder.ok_or(CLI_USAGE)?;
+
+    let keyfile = keyfile_path
+        .map(|path| {
+            fs::File::open(&path)
+                .and_then(crypto::Keyfile::from_reader)
+                .map_err(|e| format!("Could not read keyfile {path}: {e}"))
+        })
+        .transpose()?;
+    let passphrase = match std::env::var("MDNOTEBOOK_PASSPHRASE") {
+        Ok(p) => zeroize::Zeroizing::new(p),
+        Err(_) => read_secret_line("Passphrase: ")?,
+    };
+
+    // Fixes write to the vault folder, so no running instance may be using it
+    let _lock = fix
+        .then(|| WriterLock::acquire(&folder, false))
+        .transpose()
+        .map_err(|e| match e {
+            LockError::Held(_) => format!("{e}. Close it there before running --fix."),
+            LockError::Io(_) => format!("Could not lock the vault folder: {e}"),
+        })?;
+
+    let config = vault::load_config();
+    let policy = config.plaintext_policy();
+    // A plain check changes nothing on disk, so an interrupted re-key is only
+    // finished under the --fix lock and otherwise reported
+    let (raw, fingerprint) = if fix {
+        vault::recover_interrupted_rekey(&folder).map_err(|e| format!("Read error: {e}"))?;
+        vault::read_vault_fingerprinted(&folder).map(|read| read.map(|(raw, fingerprint)| (raw, Some(fingerprint))))
+    } else {
+        vault::read_vault_raw_untouched(&folder).map(|read| read.map(|raw| (raw, None)))
+    }
+    .map_err(|e| format!("Read error: {e}"))?
+    .ok_or_else(|| format!("No vault found in {folder}"))?;
+    let (json, mut key) = crypto::decrypt_vault_returning_key(&raw, &passphrase, keyfile.as_ref(), policy)
+        .map_err(|e| format!("Could not unlock the vault: {e}"))?;
+    key.set_compression(config.compress_payloads);
+    key.set_padding(config.pad_payloads);
+    let json = zeroize::Zeroizing::new(json);
+    let mut data: VaultData =
+        serde_json::from_str(&json).map_err(|e| format!("Corrupt vault: {e}"))?;
+    // Checks need the note contents; fixes are written back to the manifest
+    let mut full = data.clone();
+    note_files::load_note_files(&folder, &mut full, &key).map_err(|e| e.to_string())?;
+
+    let report = check_vault(&folder, &full, &key, policy).map_err(|e| e.to_string())?;
+    print!("{report}");
+    if !fix || report.is_clean() {
+        return Ok(report.is_clean());
+    }
+
+    // Another device may still have saved since it was read
+    if vault::vault_changed_since(&folder, fingerprint.as_ref()).map_err(|e| e.to_string())?.is_some() {
+        return Err(CHANGED_DURING_CHECK.into());
+    }
+    let quarantined = quarantine_unreadable(&folder, &report).map_err(|e| e.to_string())?;
+    let restored = restore_orphan_metadata(&folder, &mut data.assets, &report, &key, policy)
+        .map_err(|e| e.to_string())?;
+    let dropped = drop_dangling_metadata(&mut data.assets, &report);
+    if restored + dropped > 0 {
+        let json = zeroize::Zeroizing::new(serde_json::to_string(&data).map_err(|e| e.to_string())?);
+        let encrypted = crypto::encrypt_vault_fast(&json, &key).map_err(|e| e.to_string())?;
+        match vault::write_vault_checked(&folder, &encrypted, fingerprint.as_ref()).map_err(|e| e.to_string())? {
+            vault::VaultWrite::Written(_) => {}
+            vault::VaultWrite::Changed { .. } => return Err(CHANGED_DURING_CHECK.into()),
+        }
+    }
+    println!(
+        "Fixed: quarantined {quarantined} files, restored {restored} and dropped {dropped} metadata entries."
+    );
+
+    full.assets = data.assets;
+    let report = check_vault(&folder, &full, &key, policy).map_err(|e| e.to_string())?;
+    print!("{report}");
+    Ok(report.is_clean())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::test_fixtures::{asset_meta, note, temp_vault_dir};
+
+    #[test]
+    fn test_asset_ids_in() {
+        let text = "![a](vault://ab12) and ![b](vault://cd34 \"t\")\nvault:// alone";
+        assert_eq!(asset_ids_in(text).collect::<Vec<_>>(), vec!["ab12", "cd34"]);
+
+        // Whole IDs only: "ab12" must not match inside "ab123"
+        let text = "![a](vault://ab12) ![b](vault://ab123) vault://";
+        let replaced = replace_asset_ids(text, |id| (id == "ab12").then(|| "new".to_string()));
+        assert_eq!(replaced, "![a](vault://new) ![b](vault://ab123) vault://");
+    }
+
+    #[test]
+    fn test_plan_asset_gc() {
+        let mut data = VaultData {
+            tree: vec![note("n1", "Trip", "![x](vault://used)")],
+            trash: vec![note("n2", "Old", "![y](vault://trashed)")],
+            ..Default::default()
+        };
+        data.note_versions.insert(
+            "n1".into(),
+            vec![vault::VersionEntry { ts: 1, content: "![v](vault://versioned)".into() }],
+        );
+        data.custom_templates.push(vault::CustomTemplate {
+            name: "T".into(),
+            content: "![t](vault://templated)".into(),
+            tags: String::new(),
+        });
+        for id in ["used", "trashed", "versioned", "templated", "fresh", "waiting", "expired", "revived"] {
+            data.assets.insert(id.into(), asset_meta(id));
+        }
+        let now = 10 * UNKNOWN_ASSET_GRACE_MS;
+        data.assets.get_mut("waiting").unwrap().orphaned_at = Some(now - ASSET_GC_GRACE_MS / 2);
+        data.assets.get_mut("expired").unwrap().orphaned_at = Some(now - ASSET_GC_GRACE_MS);
+        data.assets.get_mut("revived").unwrap().orphaned_at = Some(0);
+        data.tree[0].content = Some("![x](vault://used) ![r](vault://revived)".into());
+        let files = vec![
+            ("used".to_string(), 0),
+            ("stray-old".to_string(), now - UNKNOWN_ASSET_GRACE_MS),
+            // Synced in ahead of its metadata
+            ("stray-new".to_string(), now - ASSET_GC_GRACE_MS),
+        ];
+
+        let plan = plan_asset_gc(&data, &files, now, ASSET_GC_GRACE_MS);
+        assert_eq!(plan.mark, vec!["fresh"]);
+        assert_eq!(plan.unmark, vec!["revived"]);
+        assert_eq!(plan.collect, vec!["expired", "stray-old"]);
+        assert_eq!(plan.pending, 3);
+        assert!(plan_asset_gc(&data, &files, now - 1, ASSET_GC_GRACE_MS).collect.iter().all(|id| id != "stray-old"));
+
+        apply_gc_plan(&mut data.assets, &plan, now);
+        assert_eq!(data.assets["fresh"].orphaned_at, Some(now));
+        assert_eq!(data.assets["revived"].orphaned_at, None);
+        assert!(!data.assets.contains_key("expired"));
+    }
+
+    #[test]
+    fn test_check_and_repair_vault() {
+        let dir = temp_vault_dir("integrity");
+        let folder = dir.to_str().unwrap();
+        let key = CachedKey::generate("passphrase", None).unwrap();
+
+        let good = crypto::encrypt_asset(b"png", "good", "image/png", &key).unwrap();
+        vault::write_asset(folder, "good", &good).unwrap();
+        // Encrypted for another ID, then swapped in
+        let swapped = crypto::encrypt_asset(b"png", "other", "image/png", &key).unwrap();
+        vault::write_asset(folder, "swapped", &swapped).unwrap();
+        let orphan = crypto::encrypt_asset(b"gif!", "orphan", "image/gif", &key).unwrap();
+        vault::write_asset(folder, "orphan", &orphan).unwrap();
+
+        let mut data = VaultData {
+            tree: vec![note("n1", "Trip", "![x](vault://good) ![y](vault://swapped)")],
+            trash: vec![note("n2", "Old", "![z](vault://gone)")],
+            ..Default::default()
+        };
+        data.note_versions.insert(
+            "n1".into(),
+            vec![vault::VersionEntry { ts: 1, content: "![w](vault://orphan)".into() }],
+        );
+        for id in ["good", "swapped", "missing"] {
+            data.assets.insert(id.into(), asset_meta(id));
+        }
+
+        let report = check_vault(folder, &data, &key, PlaintextPolicy::Refuse).unwrap();
+        assert_eq!(report.assets_checked, 3);
+        assert_eq!(report.references_checked, 4);
+        assert_eq!(report.unreadable.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["swapped"]);
+        assert_eq!(report.missing_files, vec!["missing"]);
+        assert_eq!(report.orphaned_files, vec!["orphan"]);
+        assert_eq!(
+            report.broken_references,
+            vec![
+                AssetReference { asset_id: "gone".into(), location: RefLocation::Trash("Old".into()) },
+                AssetReference { asset_id: "orphan".into(), location: RefLocation::Version("Trip".into()) },
+            ]
+        );
+
+        assert_eq!(quarantine_unreadable(folder, &report).unwrap(), 1);
+        assert!(!vault::assets_dir(folder).join("swapped").exists());
+        assert_eq!(fs::read_dir(quarantine_dir(folder)).unwrap().count(), 1);
+        assert_eq!(
+            restore_orphan_metadata(folder, &mut data.assets, &report, &key, PlaintextPolicy::Refuse).unwrap(),
+            1
+        );
+        assert_eq!(data.assets["orphan"].mime_type, "image/gif");
+        assert_eq!(data.assets["orphan"].size, 4);
+        assert_eq!(drop_dangling_metadata(&mut data.assets, &report), 1);
+
+        let report = check_vault(folder, &data, &key, PlaintextPolicy::Refuse).unwrap();
+        assert_eq!(report.missing_files, vec!["swapped"]);
+        assert_eq!(report.broken_references.len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_cli_fix_needs_the_writer_lock() {
+        let dir = temp_vault_dir("cli-lock");
+        let folder = dir.to_str().unwrap().to_string();
+        std::env::set_var("MDNOTEBOOK_PASSPHRASE", "passphrase");
+
+        let _held = WriterLock::acquire(&folder, false).unwrap();
+        let err = cli(&[folder.clone(), "--fix".into()]).unwrap_err();
+        assert!(err.contains("Close it there"), "{err}");
+        // A check alone doesn't write, so it goes ahead
+        let err = cli(std::slice::from_ref(&folder)).unwrap_err();
+        assert!(err.contains("No vault found"), "{err}");
+        // ...and leaves another instance's re-key alone
+        let staging = dir.join(".rekey");
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(staging.join("COMMITTED"), "").unwrap();
+        let err = cli(std::slice::from_ref(&folder)).unwrap_err();
+        assert!(err.contains("interrupted"), "{err}");
+        assert!(staging.join("COMMITTED").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
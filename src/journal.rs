@@ -0,0 +1,348 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, CachedKey};
+use crate::state::{unix_now, DocState, NoteItem};
+use crate::vault;
+
+// ---------------------------------------------------------------------------
+// Crash journal  (<vault>/journal.log)
+// ---------------------------------------------------------------------------
+//
+// Every debounced edit appends the active note, encrypted with the vault key,
+// as one line of the journal. A successful save drops the entries it covered;
+// when nothing is left the file is removed. Whatever survives a crash is read
+// back on the next unlock and offered for replay.
+//
+// Writes happen on a worker thread so the fsync after each append never blocks
+// the editor. Entries are numbered so a save only drops what it actually wrote,
+// not edits made while it was in flight.
+
+const JOURNAL_FILE: &str = "journal.log";
+
+pub fn journal_path(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join(JOURNAL_FILE)
+}
+
+/// One journaled edit: the full content of a note at that moment.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub note_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    pub content: String,
+    pub ts: i64,
+}
+
+impl JournalEntry {
+    pub fn from_note(note: &NoteItem) -> Self {
+        JournalEntry {
+            note_id: note.id.clone(),
+            name: note.name.clone(),
+            folder_id: note.parent_id.clone(),
+            content: note.content.clone(),
+            ts: unix_now(),
+        }
+    }
+}
+
+enum Op {
+    Append(u64, String),
+    /// Drop entries up to and including this number.
+    Flushed(u64),
+    /// Number the entries left over from a previous session, so the next save
+    /// drops them too.
+    Release(u64),
+}
+
+/// Handle to the journal of an unlocked vault. Dropping it waits for pending
+/// writes to finish.
+pub struct Journal {
+    tx: Option<mpsc::Sender<Op>>,
+    worker: Option<JoinHandle<()>>,
+    seq: Cell<u64>,
+}
+
+impl Journal {
+    /// Open the journal, returning the encrypted records left by a previous
+    /// session. They stay on disk until `release_recovered` and a later save.
+    pub fn open(vault_folder: &str) -> io::Result<(Journal, Vec<String>)> {
+        let path = journal_path(vault_folder);
+        let recovered: Vec<String> = match fs::read_to_string(&path) {
+            Ok(text) => text.lines().filter(|l| !l.trim().is_empty()).map(String::from).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let pending = recovered.iter().map(|line| (None, line.clone())).collect();
+        let (tx, rx) = mpsc::channel();
+        let worker = std::thread::spawn(move || run_worker(&path, rx, pending));
+        let journal = Journal { tx: Some(tx), worker: Some(worker), seq: Cell::new(0) };
+        Ok((journal, recovered))
+    }
+
+    /// Number of the last entry appended; pass it to `flushed` once a save
+    /// that started after it has reached disk.
+    pub fn last_seq(&self) -> u64 {
+        self.seq.get()
+    }
+
+    pub fn append(&self, entry: &JournalEntry, key: &CachedKey) -> Result<(), String> {
+        let json = Zeroizing::new(serde_json::to_vec(entry).map_err(|e| e.to_string())?);
+        let record = crypto::encrypt_journal_record(&json, key).map_err(|e| e.to_string())?;
+        let seq = self.seq.get() + 1;
+        self.seq.set(seq);
+        self.send(Op::Append(seq, record));
+        Ok(())
+    }
+
+    pub fn flushed(&self, seq: u64) {
+        self.send(Op::Flushed(seq));
+    }
+
+    /// The user has replayed or discarded the recovered entries.
+    pub fn release_recovered(&self) {
+        let seq = self.seq.get() + 1;
+        self.seq.set(seq);
+        self.send(Op::Release(seq));
+    }
+
+    fn send(&self, op: Op) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(op);
+        }
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        self.tx.take(); // Ends the worker once the queue is drained
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Entries not yet covered by a save; `None` marks ones from a previous session.
+type Pending = Vec<(Option<u64>, String)>;
+
+fn run_worker(path: &Path, rx: mpsc::Receiver<Op>, mut pending: Pending) {
+    for op in rx {
+        let result = match op {
+            Op::Append(seq, record) => {
+                pending.push((Some(seq), record.clone()));
+                append_record(path, &record)
+            }
+            Op::Flushed(seq) => {
+                let before = pending.len();
+                pending.retain(|(s, _)| s.is_none_or(|s| s > seq));
+                if pending.len() == before {
+                    Ok(())
+                } else {
+                    rewrite(path, &pending)
+                }
+            }
+            Op::Release(seq) => {
+                for (s, _) in pending.iter_mut().filter(|(s, _)| s.is_none()) {
+                    *s = Some(seq);
+                }
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Warning: crash journal write failed: {e}");
+        }
+    }
+}
+
+fn append_record(path: &Path, record: &str) -> io::Result<()> {
+    let created = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(record.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.sync_data()?;
+    if created {
+        if let Some(dir) = path.parent() {
+            vault::sync_dir(dir)?;
+        }
+    }
+    Ok(())
+}
+
+fn rewrite(path: &Path, pending: &Pending) -> io::Result<()> {
+    if pending.is_empty() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        return path.parent().map_or(Ok(()), vault::sync_dir);
+    }
+    let mut text = String::new();
+    for (_, record) in pending {
+        text.push_str(record);
+        text.push('\n');
+    }
+    vault::atomic_write(path, text.as_bytes())
+}
+
+/// Decrypt recovered records. Records that fail (another key, a torn last
+/// line) are skipped and counted.
+pub fn read_entries(records: &[String], key: &CachedKey) -> (Vec<JournalEntry>, usize) {
+    let mut entries = Vec::new();
+    let mut failed = 0;
+    for record in records {
+        match crypto::decrypt_journal_record(record, key)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()))
+        {
+            Ok(entry) => entries.push(entry),
+            Err(_) => failed += 1,
+        }
+    }
+    (entries, failed)
+}
+
+/// The newest entry per note, for notes whose saved content differs from it
+/// or that were never saved at all. In journal order.
+pub fn unflushed_entries(entries: Vec<JournalEntry>, state: &DocState) -> Vec<JournalEntry> {
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        latest.insert(entry.note_id.clone(), i);
+    }
+    entries
+        .into_iter()
+        .enumerate()
+        .filter(|(i, entry)| latest.get(&entry.note_id) == Some(i))
+        .map(|(_, entry)| entry)
+        .filter(|entry| {
+            state
+                .notes
+                .iter()
+                .find(|n| n.id == entry.note_id)
+                .is_none_or(|n| n.content != entry.content)
+        })
+        .collect()
+}
+
+/// Apply entries from `unflushed_entries` to the state. Notes that no longer
+/// exist are re-created. Returns the IDs of the notes changed.
+pub fn replay(state: &mut DocState, entries: Vec<JournalEntry>) -> Vec<String> {
+    let mut changed = Vec::new();
+    for entry in entries {
+        if let Some(note) = state.notes.iter_mut().find(|n| n.id == entry.note_id) {
+            note.content = entry.content;
+            note.updated_at = entry.ts;
+            changed.push(note.id.clone());
+            continue;
+        }
+        let id = format!("note-{}", state.next_note_seq);
+        state.next_note_seq += 1;
+        let mut note = NoteItem::new(id.clone(), entry.name, entry.content, Vec::new());
+        note.parent_id = entry.folder_id.filter(|f| state.folders.iter().any(|folder| &folder.id == f));
+        note.updated_at = entry.ts;
+        state.notes.push(note);
+        changed.push(id);
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::test_fixtures::temp_vault_dir;
+
+    fn entry(note_id: &str, content: &str) -> JournalEntry {
+        JournalEntry {
+            note_id: note_id.into(),
+            name: format!("Note {note_id}"),
+            folder_id: None,
+            content: content.into(),
+            ts: 1,
+        }
+    }
+
+    #[test]
+    fn test_unflushed_and_replay() {
+        let mut state = DocState {
+            notes: vec![
+                NoteItem::new("a".into(), "A".into(), "saved a".into(), Vec::new()),
+                NoteItem::new("b".into(), "B".into(), "saved b".into(), Vec::new()),
+            ],
+            ..DocState::default()
+        };
+        let entries = vec![
+            entry("a", "edit a 1"),
+            entry("b", "saved b"),
+            entry("c", "new note"),
+            entry("a", "edit a 2"),
+        ];
+        let unflushed = unflushed_entries(entries, &state);
+        // b already matches what was saved; a only keeps its newest entry
+        assert_eq!(unflushed, vec![entry("c", "new note"), entry("a", "edit a 2")]);
+
+        let before = state.next_note_seq;
+        let changed = replay(&mut state, unflushed);
+        assert_eq!(changed.len(), 2);
+        assert_eq!(state.notes[0].content, "edit a 2");
+        assert_eq!(state.notes.len(), 3);
+        assert_eq!(state.notes[2].content, "new note");
+        assert_eq!(state.notes[2].id, format!("note-{before}"));
+    }
+
+    #[test]
+    fn test_journal_survives_until_flushed() {
+        let dir = temp_vault_dir("journal");
+        let folder = dir.to_str().unwrap();
+        let key = CachedKey::generate("journal test", None).unwrap();
+
+        // A session that never saves leaves its entries behind
+        {
+            let (journal, recovered) = Journal::open(folder).unwrap();
+            assert!(recovered.is_empty());
+            journal.append(&entry("a", "one"), &key).unwrap();
+            journal.append(&entry("a", "two"), &key).unwrap();
+        }
+        let (journal, recovered) = Journal::open(folder).unwrap();
+        let (entries, failed) = read_entries(&recovered, &key);
+        assert_eq!(failed, 0);
+        assert_eq!(entries, vec![entry("a", "one"), entry("a", "two")]);
+        // Only ciphertext reaches the disk
+        assert!(!fs::read_to_string(journal_path(folder)).unwrap().contains("two"));
+
+        // A save doesn't drop recovered entries until they are released
+        journal.append(&entry("b", "three"), &key).unwrap();
+        journal.flushed(journal.last_seq());
+        drop(journal);
+        let (journal, recovered) = Journal::open(folder).unwrap();
+        assert_eq!(recovered.len(), 2);
+
+        journal.release_recovered();
+        let seq = journal.last_seq();
+        journal.append(&entry("b", "four"), &key).unwrap();
+        journal.flushed(seq);
+        drop(journal);
+        let (journal, recovered) = Journal::open(folder).unwrap();
+        assert_eq!(read_entries(&recovered, &key).0, vec![entry("b", "four")]);
+
+        // Flushing everything removes the file
+        journal.release_recovered();
+        journal.flushed(journal.last_seq());
+        drop(journal);
+        assert!(!journal_path(folder).exists());
+
+        // Records under another key are skipped, not fatal
+        let other = CachedKey::generate("other", None).unwrap();
+        let record = crypto::encrypt_journal_record(b"{}", &other).unwrap();
+        assert_eq!(read_entries(&[record, "garbage".into()], &key), (Vec::new(), 2));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,470 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+use gtk::gio;
+use gtk::glib;
+use gtk::glib::variant::ObjectPath;
+use gtk::glib::prelude::*;
+use gtk::glib::Variant;
+use zeroize::Zeroizing;
+
+// ---------------------------------------------------------------------------
+// Remembered vault keys  (Secret Service keyring)
+// ---------------------------------------------------------------------------
+//
+// "Remember on this device" keeps a device key in the desktop keyring
+// through the freedesktop Secret Service D-Bus API, so the vault unlocks on
+// the next launch without asking. Only the API every implementation has to
+// offer is used — the "plain" transfer algorithm on the local session bus and
+// the default collection — so GNOME Keyring, KWallet, KeePassXC and test
+// daemons all work. The item is found by its attributes; nothing about it is
+// written to the vault folder.
+//
+// Every call blocks, and an unlock prompt may wait for the user; call these
+// off the main thread.
+
+const SERVICE: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_IFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_IFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_IFACE: &str = "org.freedesktop.Secret.Item";
+const SESSION_IFACE: &str = "org.freedesktop.Secret.Session";
+const PROMPT_IFACE: &str = "org.freedesktop.Secret.Prompt";
+const APPLICATION: &str = "mdnotebook";
+
+fn failed(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("Keyring: {e}"))
+}
+
+/// Attributes identifying the item for the vault at `vault_folder`.
+fn attributes(vault_folder: &str) -> HashMap<String, String> {
+    HashMap::from([
+        ("application".to_string(), APPLICATION.to_string()),
+        ("vault".to_string(), vault_folder.to_string()),
+    ])
+}
+
+/// An open Secret Service session, closed on drop.
+struct Session {
+    conn: gio::DBusConnection,
+    path: ObjectPath,
+}
+
+impl Session {
+    fn open() -> io::Result<Session> {
+        let conn = gio::bus_get_sync(gio::BusType::Session, gio::Cancellable::NONE).map_err(failed)?;
+        let reply = Self::call_on(&conn, SERVICE_PATH, SERVICE_IFACE, "OpenSession", Some(&("plain", "".to_variant()).to_variant()))?;
+        let (_, path) = reply.get::<(Variant, ObjectPath)>().ok_or_else(|| failed("unexpected OpenSession reply"))?;
+        Ok(Session { conn, path })
+    }
+
+    fn call_on(
+        conn: &gio::DBusConnection,
+        path: &str,
+        interface: &str,
+        method: &str,
+        parameters: Option<&Variant>,
+    ) -> io::Result<Variant> {
+        conn.call_sync(
+            Some(SERVICE),
+            path,
+            interface,
+            method,
+            parameters,
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::Cancellable::NONE,
+        )
+        .map_err(failed)
+    }
+
+    fn call(&self, path: &str, interface: &str, method: &str, parameters: Option<&Variant>) -> io::Result<Variant> {
+        Self::call_on(&self.conn, path, interface, method, parameters)
+    }
+
+    /// Run a prompt the service asked for and wait for the user. Returns the
+    /// prompt's result, or `None` if it was dismissed. "/" means no prompt.
+    fn prompt(&self, prompt: &ObjectPath) -> io::Result<Option<Variant>> {
+        if prompt.as_str() == "/" {
+            return Ok(Some(().to_variant()));
+        }
+        // The Completed signal is delivered to this thread's own main context
+        let context = glib::MainContext::new();
+        context
+            .with_thread_default(|| {
+                let completed: Rc<RefCell<Option<(bool, Variant)>>> = Rc::new(RefCell::new(None));
+                let id = {
+                    let completed = completed.clone();
+                    self.conn.signal_subscribe(
+                        Some(SERVICE),
+                        Some(PROMPT_IFACE),
+                        Some("Completed"),
+                        Some(prompt.as_str()),
+                        None,
+                        gio::DBusSignalFlags::NONE,
+                        move |_, _, _, _, _, params| {
+                            *completed.borrow_mut() = params.get::<(bool, Variant)>();
+                        },
+                    )
+                };
+                let result = self.call(prompt, PROMPT_IFACE, "Prompt", Some(&("",).to_variant()));
+                if result.is_ok() {
+                    while completed.borrow().is_none() {
+                        context.iteration(true);
+                    }
+                }
+                self.conn.signal_unsubscribe(id);
+                result?;
+                let (dismissed, value) = completed.take().unwrap_or((true, ().to_variant()));
+                Ok((!dismissed).then_some(value))
+            })
+            .map_err(failed)?
+    }
+
+    /// Unlock `paths` if the service keeps them locked. False if the user
+    /// dismissed the prompt.
+    fn unlock(&self, paths: Vec<ObjectPath>) -> io::Result<bool> {
+        if paths.is_empty() {
+            return Ok(true);
+        }
+        let reply = self.call(SERVICE_PATH, SERVICE_IFACE, "Unlock", Some(&(paths,).to_variant()))?;
+        let (_, prompt) = reply.get::<(Vec<ObjectPath>, ObjectPath)>().ok_or_else(|| failed("unexpected Unlock reply"))?;
+        Ok(self.prompt(&prompt)?.is_some())
+    }
+
+    /// Items for the vault, unlocked.
+    fn find_items(&self, vault_folder: &str) -> io::Result<Vec<ObjectPath>> {
+        let reply = self.call(SERVICE_PATH, SERVICE_IFACE, "SearchItems", Some(&(attributes(vault_folder),).to_variant()))?;
+        let (mut unlocked, locked) = reply
+            .get::<(Vec<ObjectPath>, Vec<ObjectPath>)>()
+            .ok_or_else(|| failed("unexpected SearchItems reply"))?;
+        if !locked.is_empty() && self.unlock(locked.clone())? {
+            unlocked.extend(locked);
+        }
+        Ok(unlocked)
+    }
+
+    /// The default collection, created if the service has none yet.
+    fn default_collection(&self) -> io::Result<ObjectPath> {
+        let reply = self.call(SERVICE_PATH, SERVICE_IFACE, "ReadAlias", Some(&("default",).to_variant()))?;
+        let (collection,) = reply.get::<(ObjectPath,)>().ok_or_else(|| failed("unexpected ReadAlias reply"))?;
+        if collection.as_str() != "/" {
+            self.unlock(vec![collection.clone()])?;
+            return Ok(collection);
+        }
+        let properties = HashMap::from([(
+            "org.freedesktop.Secret.Collection.Label".to_string(),
+            "Default keyring".to_variant(),
+        )]);
+        let reply = self.call(SERVICE_PATH, SERVICE_IFACE, "CreateCollection", Some(&(properties, "default").to_variant()))?;
+        let (collection, prompt) = reply
+            .get::<(ObjectPath, ObjectPath)>()
+            .ok_or_else(|| failed("unexpected CreateCollection reply"))?;
+        if collection.as_str() != "/" {
+            return Ok(collection);
+        }
+        // Created through a prompt, which returns the new collection's path
+        self.prompt(&prompt)?
+            .and_then(|v| v.as_variant().unwrap_or(v).get::<ObjectPath>())
+            .ok_or_else(|| failed("no keyring collection was created"))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.call(&self.path, SESSION_IFACE, "Close", None);
+    }
+}
+
+/// Store `secret` for the vault at `vault_folder`, replacing an earlier one.
+/// `label` is what keyring managers show.
+pub fn store_secret(vault_folder: &str, label: &str, secret: &[u8]) -> io::Result<()> {
+    let session = Session::open()?;
+    let collection = session.default_collection()?;
+    let properties = HashMap::from([
+        ("org.freedesktop.Secret.Item.Label".to_string(), label.to_variant()),
+        ("org.freedesktop.Secret.Item.Attributes".to_string(), attributes(vault_folder).to_variant()),
+    ]);
+    let secret = Variant::tuple_from_iter([
+        session.path.to_variant(),
+        Variant::array_from_fixed_array::<u8>(&[]),
+        Variant::array_from_fixed_array(secret),
+        "application/octet-stream".to_variant(),
+    ]);
+    let params = Variant::tuple_from_iter([properties.to_variant(), secret, true.to_variant()]);
+    let reply = session.call(&collection, COLLECTION_IFACE, "CreateItem", Some(&params))?;
+    let (_, prompt) = reply.get::<(ObjectPath, ObjectPath)>().ok_or_else(|| failed("unexpected CreateItem reply"))?;
+    match session.prompt(&prompt)? {
+        Some(_) => Ok(()),
+        None => Err(failed("storing the key was cancelled")),
+    }
+}
+
+/// The secret stored for the vault at `vault_folder`, if any.
+pub fn lookup_secret(vault_folder: &str) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+    let session = Session::open()?;
+    let Some(item) = session.find_items(vault_folder)?.into_iter().next() else {
+        return Ok(None);
+    };
+    let reply = session.call(&item, ITEM_IFACE, "GetSecret", Some(&(session.path.clone(),).to_variant()))?;
+    // ((o session, ay parameters, ay value, s content_type),)
+    let secret = reply.child_value(0);
+    let value = secret.child_value(2);
+    let bytes = value.fixed_array::<u8>().map_err(failed)?;
+    Ok(Some(Zeroizing::new(bytes.to_vec())))
+}
+
+/// Delete what is stored for the vault at `vault_folder`. Returns whether
+/// there was anything.
+pub fn delete_secret(vault_folder: &str) -> io::Result<bool> {
+    let session = Session::open()?;
+    let items = session.find_items(vault_folder)?;
+    for item in &items {
+        let reply = session.call(item, ITEM_IFACE, "Delete", None)?;
+        let (prompt,) = reply.get::<(ObjectPath,)>().ok_or_else(|| failed("unexpected Delete reply"))?;
+        session.prompt(&prompt)?;
+    }
+    Ok(!items.is_empty())
+}
+
+/// A label for keyring managers.
+pub fn item_label(vault_name: &str) -> String {
+    format!("MDNotebook vault key ({vault_name})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    const INTROSPECTION: &str = r#"<node>
+      <interface name="org.freedesktop.Secret.Service">
+        <method name="OpenSession">
+          <arg type="s" direction="in"/><arg type="v" direction="in"/>
+          <arg type="v" direction="out"/><arg type="o" direction="out"/>
+        </method>
+        <method name="ReadAlias"><arg type="s" direction="in"/><arg type="o" direction="out"/></method>
+        <method name="SearchItems">
+          <arg type="a{ss}" direction="in"/><arg type="ao" direction="out"/><arg type="ao" direction="out"/>
+        </method>
+        <method name="Unlock">
+          <arg type="ao" direction="in"/><arg type="ao" direction="out"/><arg type="o" direction="out"/>
+        </method>
+      </interface>
+      <interface name="org.freedesktop.Secret.Collection">
+        <method name="CreateItem">
+          <arg type="a{sv}" direction="in"/><arg type="(oayays)" direction="in"/><arg type="b" direction="in"/>
+          <arg type="o" direction="out"/><arg type="o" direction="out"/>
+        </method>
+      </interface>
+      <interface name="org.freedesktop.Secret.Item">
+        <method name="GetSecret"><arg type="o" direction="in"/><arg type="(oayays)" direction="out"/></method>
+        <method name="Delete"><arg type="o" direction="out"/></method>
+      </interface>
+      <interface name="org.freedesktop.Secret.Session"><method name="Close"/></interface>
+      <interface name="org.freedesktop.Secret.Prompt">
+        <method name="Prompt"><arg type="s" direction="in"/></method>
+        <signal name="Completed"><arg type="b"/><arg type="v"/></signal>
+      </interface>
+    </node>"#;
+    const COLLECTION: &str = "/org/freedesktop/secrets/collection/login";
+
+    /// A Secret Service with one collection that starts out locked and is
+    /// unlocked through a prompt, like a keyring after login.
+    #[derive(Default)]
+    struct TestService {
+        locked: bool,
+        /// The next prompts are dismissed instead of unlocking.
+        dismiss: bool,
+        prompts: usize,
+        next_id: usize,
+        /// Path, attributes and secret of each item.
+        items: Vec<(String, HashMap<String, String>, Vec<u8>)>,
+    }
+
+    impl TestService {
+        fn path(&mut self, kind: &str) -> String {
+            self.next_id += 1;
+            format!("/org/freedesktop/secrets/{kind}/{}", self.next_id)
+        }
+    }
+
+    fn register(conn: &gio::DBusConnection, path: &str, interface: &str, service: &Arc<Mutex<TestService>>) {
+        let info = gio::DBusNodeInfo::for_xml(INTROSPECTION).unwrap().lookup_interface(interface).unwrap();
+        let service = service.clone();
+        conn.register_object(path, &info)
+            .method_call(move |conn, _, path, _, method, params, invocation| {
+                handle(&conn, path, method, &params, invocation, &service);
+            })
+            .build()
+            .unwrap();
+    }
+
+    fn handle(
+        conn: &gio::DBusConnection,
+        path: &str,
+        method: &str,
+        params: &Variant,
+        invocation: gio::DBusMethodInvocation,
+        service: &Arc<Mutex<TestService>>,
+    ) {
+        let mut s = service.lock().unwrap();
+        let reply = match method {
+            "OpenSession" => {
+                let session = s.path("session");
+                register(conn, &session, SESSION_IFACE, service);
+                Some(("".to_variant(), ObjectPath::try_from(session).unwrap()).to_variant())
+            }
+            "Close" => None,
+            "ReadAlias" => Some((ObjectPath::try_from(COLLECTION).unwrap(),).to_variant()),
+            "SearchItems" => {
+                let (wanted,) = params.get::<(HashMap<String, String>,)>().unwrap();
+                let found: Vec<ObjectPath> = s
+                    .items
+                    .iter()
+                    .filter(|(_, attributes, _)| wanted.iter().all(|(k, v)| attributes.get(k) == Some(v)))
+                    .map(|(path, _, _)| ObjectPath::try_from(path.as_str()).unwrap())
+                    .collect();
+                let (unlocked, locked) = if s.locked { (Vec::new(), found) } else { (found, Vec::new()) };
+                Some((unlocked, locked).to_variant())
+            }
+            "Unlock" => {
+                let (paths,) = params.get::<(Vec<ObjectPath>,)>().unwrap();
+                if s.locked {
+                    let prompt = s.path("prompt");
+                    register(conn, &prompt, PROMPT_IFACE, service);
+                    Some((Vec::<ObjectPath>::new(), ObjectPath::try_from(prompt).unwrap()).to_variant())
+                } else {
+                    Some((paths, ObjectPath::try_from("/").unwrap()).to_variant())
+                }
+            }
+            "Prompt" => {
+                s.prompts += 1;
+                let completed = if s.dismiss {
+                    (true, "".to_variant())
+                } else {
+                    s.locked = false;
+                    (false, vec![ObjectPath::try_from(COLLECTION).unwrap()].to_variant())
+                };
+                invocation.return_value(None);
+                conn.emit_signal(None, path, PROMPT_IFACE, "Completed", Some(&completed.to_variant()))
+                    .unwrap();
+                return;
+            }
+            "CreateItem" if s.locked => {
+                invocation.return_dbus_error("org.freedesktop.Secret.Error.IsLocked", "The collection is locked");
+                return;
+            }
+            "CreateItem" => {
+                let properties = params.child_value(0).get::<HashMap<String, Variant>>().unwrap();
+                let attributes = properties["org.freedesktop.Secret.Item.Attributes"]
+                    .get::<HashMap<String, String>>()
+                    .unwrap();
+                let secret = params.child_value(1).child_value(2).fixed_array::<u8>().unwrap().to_vec();
+                s.items.retain(|(_, existing, _)| *existing != attributes);
+                let item = s.path("item");
+                register(conn, &item, ITEM_IFACE, service);
+                s.items.push((item.clone(), attributes, secret));
+                Some((ObjectPath::try_from(item).unwrap(), ObjectPath::try_from("/").unwrap()).to_variant())
+            }
+            "GetSecret" => {
+                let (session,) = params.get::<(ObjectPath,)>().unwrap();
+                let secret = &s.items.iter().find(|(item, _, _)| item == path).unwrap().2;
+                let secret = Variant::tuple_from_iter([
+                    session.to_variant(),
+                    Variant::array_from_fixed_array::<u8>(&[]),
+                    Variant::array_from_fixed_array(secret),
+                    "application/octet-stream".to_variant(),
+                ]);
+                Some(Variant::tuple_from_iter([secret]))
+            }
+            "Delete" => {
+                s.items.retain(|(item, _, _)| item != path);
+                Some((ObjectPath::try_from("/").unwrap(),).to_variant())
+            }
+            _ => unreachable!("unexpected call {method}"),
+        };
+        invocation.return_value(reply.as_ref());
+    }
+
+    /// Serve `service` under the Secret Service name on the bus at `address`
+    /// from a thread of its own, as the client calls block.
+    fn start_service(address: String, service: Arc<Mutex<TestService>>) {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let context = glib::MainContext::new();
+            context
+                .with_thread_default(|| {
+                    let flags = gio::DBusConnectionFlags::AUTHENTICATION_CLIENT
+                        | gio::DBusConnectionFlags::MESSAGE_BUS_CONNECTION;
+                    let conn = gio::DBusConnection::for_address_sync(&address, flags, None, gio::Cancellable::NONE)
+                        .unwrap();
+                    register(&conn, SERVICE_PATH, SERVICE_IFACE, &service);
+                    register(&conn, COLLECTION, COLLECTION_IFACE, &service);
+                    conn.call_sync(
+                        Some("org.freedesktop.DBus"),
+                        "/org/freedesktop/DBus",
+                        "org.freedesktop.DBus",
+                        "RequestName",
+                        Some(&(SERVICE, 0u32).to_variant()),
+                        None,
+                        gio::DBusCallFlags::NONE,
+                        -1,
+                        gio::Cancellable::NONE,
+                    )
+                    .unwrap();
+                    ready_tx.send(()).unwrap();
+                    glib::MainLoop::new(Some(&context), false).run();
+                })
+                .unwrap();
+        });
+        ready_rx.recv().unwrap();
+    }
+
+    fn dbus_daemon_available() -> bool {
+        std::env::var_os("PATH")
+            .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join("dbus-daemon").is_file()))
+    }
+
+    #[test]
+    fn secrets_roundtrip_through_a_locked_keyring() {
+        if !dbus_daemon_available() {
+            eprintln!("Skipping the keyring test: dbus-daemon is not installed");
+            return;
+        }
+        let bus = gio::TestDBus::new(gio::TestDBusFlags::NONE);
+        bus.up();
+        let service = Arc::new(Mutex::new(TestService { locked: true, ..Default::default() }));
+        start_service(bus.bus_address().unwrap().to_string(), service.clone());
+
+        // Storing unlocks the default collection through a prompt first
+        store_secret("/vaults/work", &item_label("Work"), b"device key").unwrap();
+        assert_eq!(service.lock().unwrap().prompts, 1);
+        store_secret("/vaults/home", &item_label("Home"), b"other key").unwrap();
+        assert_eq!(service.lock().unwrap().prompts, 1);
+
+        // The keyring locked again: looking up prompts, then reads the item
+        service.lock().unwrap().locked = true;
+        assert_eq!(lookup_secret("/vaults/work").unwrap().as_deref().map(Vec::as_slice), Some(&b"device key"[..]));
+        assert_eq!(service.lock().unwrap().prompts, 2);
+
+        // A dismissed prompt finds nothing rather than failing
+        {
+            let mut s = service.lock().unwrap();
+            s.locked = true;
+            s.dismiss = true;
+        }
+        assert!(lookup_secret("/vaults/work").unwrap().is_none());
+        assert!(store_secret("/vaults/work", "Work", b"new key").is_err());
+
+        service.lock().unwrap().dismiss = false;
+        assert!(delete_secret("/vaults/work").unwrap());
+        assert!(!delete_secret("/vaults/work").unwrap());
+        assert!(lookup_secret("/vaults/work").unwrap().is_none());
+        assert!(lookup_secret("/vaults/home").unwrap().is_some());
+        bus.down();
+    }
+}
@@ -1,6 +1,17 @@
+mod backup;
+mod bundle;
 mod crypto;
+mod integrity;
+mod journal;
+mod keyring;
+mod merge;
+mod note_files;
 mod preview;
+mod share;
+mod strength;
+mod unlock_attempts;
 mod vault;
+mod writer_lock;
 mod wysiwyg;
 mod state;
 use state::*;
@@ -23,6 +34,12 @@ const AUTO_SAVE_INTERVAL_SECS: u32 = 30;
 // ---------------------------------------------------------------------------
 
 fn main() {
+    // Headless vault check for scripts: `mdnotebook --check-vault <folder> [--fix]`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--check-vault") {
+        std::process::exit(integrity::run_cli(&args[1..]));
+    }
+
     if let Err(err) = adw::init() {
         eprintln!("Failed to initialize Adwaita: {err}");
         std::process::exit(1);
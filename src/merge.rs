@@ -0,0 +1,417 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::state::{DocState, FolderItem, NoteItem};
+use crate::vault::{AssetMeta, TreeItem, VaultData};
+
+// ---------------------------------------------------------------------------
+// Merging a vault changed on another device
+// ---------------------------------------------------------------------------
+//
+// The merge base is each note's and folder's `updated_at` as of the last time
+// this device read or wrote vault.json. A note changed on only one side takes
+// that side; a note changed on both keeps this device's version and gets a
+// conflicted copy holding the other one. Folders merge the same way, except
+// that a folder renamed or moved on both sides simply keeps this device's
+// version. Deletions win only over unchanged notes and folders.
+
+/// `updated_at` per note and folder ID, as stored in the vault file.
+pub type MergeBase = HashMap<String, i64>;
+
+pub fn base_from_state(state: &DocState) -> MergeBase {
+    let notes = state.notes.iter().map(|n| (n.id.clone(), n.updated_at));
+    notes.chain(state.folders.iter().map(|f| (f.id.clone(), f.updated_at))).collect()
+}
+
+pub fn base_from_vault(data: &VaultData) -> MergeBase {
+    fn walk(items: &[TreeItem], base: &mut MergeBase) {
+        for item in items {
+            if item.item_type == "folder" {
+                walk(item.children.as_deref().unwrap_or_default(), base);
+            }
+            base.insert(item.id.clone(), item.updated_at);
+        }
+    }
+    let mut base = MergeBase::new();
+    walk(&data.tree, &mut base);
+    base
+}
+
+/// A note edited on both devices: ours is kept, theirs became `copy_id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub note_id: String,
+    pub copy_id: String,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// Notes taken or added from the other device.
+    pub pulled: usize,
+    /// Notes removed here because the other device deleted them.
+    pub removed: usize,
+    /// Folders added, renamed, moved or removed by the other device.
+    pub folders: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl MergeReport {
+    pub fn changed_anything(&self) -> bool {
+        self.pulled > 0 || self.removed > 0 || self.folders > 0 || !self.conflicts.is_empty()
+    }
+}
+
+/// Merge the other device's folders into ours, returning how many changed.
+fn merge_folders(ours: &mut Vec<FolderItem>, theirs: Vec<FolderItem>, base: &MergeBase) -> usize {
+    let mut changed = 0;
+    let their_ids: HashSet<String> = theirs.iter().map(|f| f.id.clone()).collect();
+    for folder in theirs {
+        let changed_there = base.get(&folder.id) != Some(&folder.updated_at);
+        match ours.iter().position(|f| f.id == folder.id) {
+            // Deleted here and untouched there stays deleted
+            None if base.contains_key(&folder.id) && !changed_there => {}
+            None => {
+                ours.push(folder);
+                changed += 1;
+            }
+            Some(index) => {
+                let changed_here = base.get(&folder.id) != Some(&ours[index].updated_at);
+                if changed_there && !changed_here {
+                    // Renamed or moved there
+                    ours[index] = folder;
+                    changed += 1;
+                }
+            }
+        }
+    }
+
+    // Folders the other device deleted, unless they were renamed or moved here since
+    let before = ours.len();
+    ours.retain(|f| their_ids.contains(&f.id) || base.get(&f.id).is_none_or(|&t| t != f.updated_at));
+    changed += before - ours.len();
+
+    // Parents that are gone, or a cycle from each side moving a folder into the other
+    let mut parents: HashMap<String, Option<String>> =
+        ours.iter().map(|f| (f.id.clone(), f.parent_id.clone())).collect();
+    for folder in ours.iter_mut() {
+        let mut seen = HashSet::from([folder.id.clone()]);
+        let mut parent = folder.parent_id.clone();
+        while let Some(id) = parent {
+            if !parents.contains_key(&id) || !seen.insert(id.clone()) {
+                folder.parent_id = None;
+                parents.insert(folder.id.clone(), None);
+                break;
+            }
+            parent = parents[&id].clone();
+        }
+    }
+    changed
+}
+
+/// Merge `theirs` (the vault file as another device left it) into `ours`.
+pub fn merge_foreign(ours: &mut DocState, theirs: DocState, base: &MergeBase) -> MergeReport {
+    let mut report = MergeReport::default();
+    ours.next_note_seq = ours.next_note_seq.max(theirs.next_note_seq);
+    let fresh_id = |state: &mut DocState| {
+        let id = format!("note-{}", state.next_note_seq);
+        state.next_note_seq += 1;
+        id
+    };
+
+    // Folders first, so pulled notes can keep their place
+    report.folders = merge_folders(&mut ours.folders, theirs.folders, base);
+
+    let their_ids: HashSet<String> = theirs.notes.iter().map(|n| n.id.clone()).collect();
+    let trashed_here: HashSet<String> = ours.trash.iter().map(|t| t.id.clone()).collect();
+
+    for theirs_note in theirs.notes {
+        let changed_there = base.get(&theirs_note.id) != Some(&theirs_note.updated_at);
+        let Some(index) = ours.notes.iter().position(|n| n.id == theirs_note.id) else {
+            // Deleted here and untouched there stays deleted
+            if base.contains_key(&theirs_note.id) && !changed_there {
+                continue;
+            }
+            if trashed_here.contains(&theirs_note.id) {
+                // Keep the trashed copy's ID to itself
+                let id = fresh_id(ours);
+                ours.notes.push(NoteItem { id, ..theirs_note });
+            } else {
+                ours.notes.push(theirs_note);
+            }
+            report.pulled += 1;
+            continue;
+        };
+        let our_note = &ours.notes[index];
+        if !base.contains_key(&theirs_note.id) {
+            // Both devices created a note with the same ID
+            if our_note.content != theirs_note.content {
+                let id = fresh_id(ours);
+                ours.notes.push(NoteItem { id, ..theirs_note });
+                report.pulled += 1;
+            }
+            continue;
+        }
+        let changed_here = base.get(&our_note.id) != Some(&our_note.updated_at);
+        if !changed_here {
+            if changed_there {
+                ours.notes[index] = theirs_note;
+                report.pulled += 1;
+            }
+        } else if changed_there
+            && (our_note.content != theirs_note.content || our_note.name != theirs_note.name)
+        {
+            let note_id = our_note.id.clone();
+            let copy_id = fresh_id(ours);
+            let name = format!("{} (conflicted copy)", theirs_note.name);
+            ours.notes.push(NoteItem { id: copy_id.clone(), name, ..theirs_note });
+            report.conflicts.push(Conflict { note_id, copy_id });
+        }
+    }
+
+    // Notes the other device deleted, unless they were edited here since
+    let before = ours.notes.len();
+    ours.notes.retain(|n| {
+        their_ids.contains(&n.id)
+            || !base.contains_key(&n.id)
+            || base.get(&n.id) != Some(&n.updated_at)
+    });
+    report.removed = before - ours.notes.len();
+
+    for item in theirs.trash {
+        let known = ours.trash.iter().any(|t| t.id == item.id) || ours.notes.iter().any(|n| n.id == item.id);
+        if !known {
+            ours.trash.push(item);
+        }
+    }
+    for (id, meta) in theirs.assets {
+        ours.assets.entry(id).or_insert(meta);
+    }
+
+    for note in &mut ours.notes {
+        if note.parent_id.as_ref().is_some_and(|p| !ours.folders.iter().any(|f| &f.id == p)) {
+            note.parent_id = None;
+        }
+    }
+    ours.open_tabs.retain(|id| ours.notes.iter().any(|n| &n.id == id));
+    if !ours.notes.iter().any(|n| n.id == ours.active_note_id) {
+        ours.active_note_id = ours.notes.first().map(|n| n.id.clone()).unwrap_or_default();
+    }
+    if ours.open_tabs.is_empty() && !ours.active_note_id.is_empty() {
+        ours.open_tabs.push(ours.active_note_id.clone());
+    }
+    report
+}
+
+// ---------------------------------------------------------------------------
+// Importing notes from another vault
+// ---------------------------------------------------------------------------
+//
+// IDs from another vault mean nothing here, so every imported note and folder
+// gets a fresh one. Folders go into the folder of the same name if there is
+// one; notes already present with the same name, content and folder are
+// skipped, so importing the same vault twice adds nothing. Trash stays behind.
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub added: usize,
+    pub skipped: usize,
+}
+
+/// Add the notes and folders of `theirs`, a different vault, to `ours`.
+pub fn merge_imported(ours: &mut DocState, theirs: DocState) -> ImportReport {
+    let mut report = ImportReport::default();
+    let fresh_id = |state: &mut DocState, prefix: &str| {
+        let id = format!("{prefix}-{}", state.next_note_seq);
+        state.next_note_seq += 1;
+        id
+    };
+
+    // Their folder ID -> ours; parents come before their children
+    let mut folder_ids: HashMap<String, String> = HashMap::new();
+    for folder in theirs.folders {
+        let parent_id = folder.parent_id.as_ref().and_then(|p| folder_ids.get(p)).cloned();
+        let existing = ours
+            .folders
+            .iter()
+            .find(|f| f.name == folder.name && f.parent_id == parent_id)
+            .map(|f| f.id.clone());
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let id = fresh_id(ours, "folder");
+                ours.folders.push(FolderItem { id: id.clone(), parent_id, ..folder.clone() });
+                id
+            }
+        };
+        folder_ids.insert(folder.id, id);
+    }
+
+    for note in theirs.notes {
+        let parent_id = note.parent_id.as_ref().and_then(|p| folder_ids.get(p)).cloned();
+        let present = ours
+            .notes
+            .iter()
+            .any(|n| n.name == note.name && n.content == note.content && n.parent_id == parent_id);
+        if present {
+            report.skipped += 1;
+            continue;
+        }
+        let id = fresh_id(ours, "note");
+        ours.notes.push(NoteItem { id, parent_id, file_path: None, ..note });
+        report.added += 1;
+    }
+
+    for (id, meta) in theirs.assets {
+        ours.assets.entry(id).or_insert(AssetMeta { orphaned_at: None, ..meta });
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, content: &str, updated_at: i64) -> NoteItem {
+        let mut note = NoteItem::new(id.into(), id.to_uppercase(), content.into(), Vec::new());
+        note.updated_at = updated_at;
+        note
+    }
+
+    fn state(notes: Vec<NoteItem>) -> DocState {
+        DocState { notes, next_note_seq: 10, ..DocState::default() }
+    }
+
+    fn content<'a>(state: &'a DocState, id: &str) -> Option<&'a str> {
+        state.notes.iter().find(|n| n.id == id).map(|n| n.content.as_str())
+    }
+
+    #[test]
+    fn test_merge_foreign() {
+        let base: MergeBase = [("a", 1), ("b", 1), ("c", 1), ("d", 1), ("e", 1)]
+            .into_iter()
+            .map(|(id, t)| (id.to_string(), t))
+            .collect();
+        let mut ours = state(vec![
+            note("a", "a here", 2),   // changed only here
+            note("b", "b", 1),        // changed only there
+            note("c", "c here", 2),   // changed on both
+            note("d", "d", 1),        // deleted there
+            note("e", "e here", 2),   // edited here, deleted there
+            note("n", "new here", 3), // new here
+        ]);
+        let theirs = state(vec![
+            note("a", "a", 1),
+            note("b", "b there", 2),
+            note("c", "c there", 3),
+            note("f", "new there", 2),
+            note("n", "other new", 2), // same ID, created independently
+        ]);
+
+        let report = merge_foreign(&mut ours, theirs, &base);
+        assert_eq!(content(&ours, "a"), Some("a here"));
+        assert_eq!(content(&ours, "b"), Some("b there"));
+        assert_eq!(content(&ours, "c"), Some("c here"));
+        assert_eq!(content(&ours, "d"), None);
+        assert_eq!(content(&ours, "e"), Some("e here"));
+        assert_eq!(content(&ours, "f"), Some("new there"));
+        assert_eq!(content(&ours, "n"), Some("new here"));
+        assert!(ours.notes.iter().any(|n| n.content == "other new" && n.id != "n"));
+
+        assert_eq!(report.conflicts.len(), 1);
+        let conflict = &report.conflicts[0];
+        assert_eq!(conflict.note_id, "c");
+        assert_eq!(content(&ours, &conflict.copy_id), Some("c there"));
+        assert_eq!(report.pulled, 3);
+        assert_eq!(report.removed, 1);
+    }
+
+    fn folder(id: &str, name: &str, parent: Option<&str>) -> FolderItem {
+        FolderItem {
+            id: id.into(),
+            name: name.into(),
+            expanded: true,
+            created_at: 0,
+            updated_at: 0,
+            parent_id: parent.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_merge_foreign_folders() {
+        let at = |mut f: FolderItem, updated_at: i64| {
+            f.updated_at = updated_at;
+            f
+        };
+        let base: MergeBase = [("f1", 1), ("f2", 1), ("f3", 1), ("f4", 1), ("f5", 1), ("f6", 1)]
+            .into_iter()
+            .map(|(id, t)| (id.to_string(), t))
+            .collect();
+        let mut ours = state(vec![note("a", "a", 1)]);
+        ours.folders = vec![
+            at(folder("f1", "One", None), 1),
+            at(folder("f2", "Two here", None), 2),   // renamed on both
+            at(folder("f3", "Three", None), 1),      // deleted there
+            at(folder("f4", "Four", Some("f5")), 2), // moved into f5 here...
+            at(folder("f5", "Five", None), 1),
+            at(folder("f6", "Six", None), 2), // renamed here, deleted there
+        ];
+        ours.notes[0].parent_id = Some("f3".into());
+        let mut theirs = state(vec![note("a", "a", 1)]);
+        theirs.folders = vec![
+            at(folder("f1", "One there", None), 2), // renamed there
+            at(folder("f2", "Two there", None), 2),
+            at(folder("f4", "Four", None), 1),
+            at(folder("f5", "Five", Some("f4")), 2), // ...and f5 into f4 there
+            at(folder("f7", "New", Some("f1")), 2),
+        ];
+
+        let report = merge_foreign(&mut ours, theirs, &base);
+        let find = |id: &str| ours.folders.iter().find(|f| f.id == id);
+        assert_eq!(find("f1").unwrap().name, "One there");
+        assert_eq!(find("f2").unwrap().name, "Two here");
+        assert!(find("f3").is_none());
+        assert_eq!(find("f6").unwrap().name, "Six");
+        assert_eq!(find("f7").unwrap().parent_id.as_deref(), Some("f1"));
+        assert!(find("f4").unwrap().parent_id.is_none());
+        assert_eq!(find("f5").unwrap().parent_id.as_deref(), Some("f4"));
+        assert!(ours.notes[0].parent_id.is_none());
+        assert_eq!(report.folders, 4);
+        assert!(report.changed_anything());
+    }
+
+    #[test]
+    fn test_merge_imported() {
+        let mut ours = state(vec![note("note-1", "ours", 1)]);
+        ours.folders.push(folder("folder-2", "Work", None));
+
+        let mut theirs = state(vec![note("note-1", "theirs", 1), note("note-3", "in work", 1)]);
+        theirs.folders.push(folder("folder-2", "Work", None));
+        theirs.folders.push(folder("folder-5", "Plans", Some("folder-2")));
+        theirs.notes[1].parent_id = Some("folder-5".into());
+        let again = DocState { notes: theirs.notes.clone(), folders: theirs.folders.clone(), ..state(Vec::new()) };
+
+        let report = merge_imported(&mut ours, theirs);
+        assert_eq!(report, ImportReport { added: 2, skipped: 0 });
+        assert_eq!(content(&ours, "note-1"), Some("ours"));
+        assert_eq!(ours.notes.iter().map(|n| n.id.as_str()).collect::<HashSet<_>>().len(), 3);
+        // "Work" is reused; "Plans" is new and sits inside it
+        assert_eq!(ours.folders.len(), 2);
+        let plans = ours.folders.iter().find(|f| f.name == "Plans").unwrap();
+        assert_eq!(plans.parent_id.as_deref(), Some("folder-2"));
+        let in_work = ours.notes.iter().find(|n| n.content == "in work").unwrap();
+        assert_eq!(in_work.parent_id.as_ref(), Some(&plans.id));
+
+        // A second import finds everything already there
+        assert_eq!(merge_imported(&mut ours, again), ImportReport { added: 0, skipped: 2 });
+        assert_eq!(ours.notes.len(), 3);
+    }
+
+    #[test]
+    fn test_merge_keeps_local_deletion() {
+        let base: MergeBase = [("a".to_string(), 1), ("b".to_string(), 1)].into();
+        let mut ours = state(vec![note("a", "a", 1)]);
+        let theirs = state(vec![note("a", "a", 1), note("b", "b", 1)]);
+        let report = merge_foreign(&mut ours, theirs, &base);
+        assert!(!report.changed_anything());
+        assert_eq!(ours.notes.len(), 1);
+    }
+}
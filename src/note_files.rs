@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, CachedKey};
+use crate::vault::{self, TreeItem, VaultData, VersionEntry};
+
+// ---------------------------------------------------------------------------
+// Split storage  (<vault>/notes/<file>)
+// ---------------------------------------------------------------------------
+//
+// vault.json holds a manifest: the tree, trash, settings and asset metadata.
+// Each note's content and version history live in their own encrypted file
+// under `notes/`, which the note's tree item names in `file`. A save encrypts
+// only the notes that changed since the last save, each into a new file with
+// a random name. The new files are on disk before the manifest that refers to
+// them, and the files they replaced are removed only after it, so a crash
+// leaves at worst an unreferenced file behind. Saves sweep such leftovers once
+// they have been here a day, long enough for the manifest of another device
+// to catch up with note files that synced ahead of it.
+//
+// Trashed notes stay inline in the manifest. Vaults with inline notes are
+// read as before and split by their first save.
+
+/// How long an unreferenced note file is kept after it appeared on this device.
+pub const SWEEP_GRACE_SECS: i64 = 24 * 60 * 60;
+
+pub fn notes_dir(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("notes")
+}
+
+/// Plaintext of one note file.
+#[derive(Serialize, Deserialize)]
+struct NoteFile {
+    content: String,
+    #[serde(default)]
+    versions: Vec<VersionEntry>,
+}
+
+/// The note files this device last read or wrote: for each note ID, the file
+/// name and the SHA-256 of the file's plaintext.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoteFiles(HashMap<String, (String, [u8; 32])>);
+
+impl NoteFiles {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+fn is_note_file_name(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn new_file_name() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Call `f` on every note in the tree, depth first.
+fn for_each_note(
+    items: &mut [TreeItem],
+    f: &mut dyn FnMut(&mut TreeItem) -> io::Result<()>,
+) -> io::Result<()> {
+    for item in items {
+        if item.item_type == "folder" {
+            for_each_note(item.children.as_deref_mut().unwrap_or_default(), f)?;
+        } else {
+            f(item)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the note files a manifest refers to back into it, so the result looks
+/// like a vault with inline notes. Notes that are already inline are kept.
+pub fn load_note_files(vault_folder: &str, data: &mut VaultData, key: &CachedKey) -> io::Result<NoteFiles> {
+    let dir = notes_dir(vault_folder);
+    let mut files = NoteFiles::default();
+    let mut versions = Vec::new();
+    for_each_note(&mut data.tree, &mut |item| {
+        let Some(file) = item.file.take() else {
+            return Ok(());
+        };
+        let failed = |e: &dyn std::fmt::Display| {
+            io::Error::other(format!("Note \u{201c}{}\u{201d} (notes/{file}): {e}", item.name))
+        };
+        let raw = fs::read(dir.join(&file)).map_err(|e| failed(&e))?;
+        let plain = crypto::decrypt_note_file(&raw, &file, &item.id, key).map_err(|e| failed(&e))?;
+        let note: NoteFile = serde_json::from_slice(&plain).map_err(|e| failed(&e))?;
+        files.0.insert(item.id.clone(), (file, Sha256::digest(&*plain).into()));
+        item.content = Some(note.content);
+        if !note.versions.is_empty() {
+            versions.push((item.id.clone(), note.versions));
+        }
+        Ok(())
+    })?;
+    data.note_versions.extend(versions);
+    Ok(files)
+}
+
+/// Move note contents and histories out of `data` into note files, turning it
+/// into a manifest. Notes unchanged since `previous` keep their file; the rest
+/// are encrypted into new files. Nothing is removed. Returns the note files
+/// the manifest now refers to.
+pub fn store_note_files(
+    vault_folder: &str,
+    data: &mut VaultData,
+    key: &CachedKey,
+    previous: &NoteFiles,
+) -> io::Result<NoteFiles> {
+    let dir = notes_dir(vault_folder);
+    fs::create_dir_all(&dir)?;
+    let mut files = NoteFiles::default();
+    let mut versions = std::mem::take(&mut data.note_versions);
+    let mut wrote = false;
+    for_each_note(&mut data.tree, &mut |item| {
+        let note = NoteFile {
+            content: item.content.take().unwrap_or_default(),
+            versions: versions.remove(&item.id).unwrap_or_default(),
+        };
+        let plain = Zeroizing::new(serde_json::to_vec(&note).map_err(io::Error::other)?);
+        let digest: [u8; 32] = Sha256::digest(&*plain).into();
+        let file = match previous.0.get(&item.id) {
+            Some((file, d)) if *d == digest && dir.join(file).exists() => file.clone(),
+            _ => {
+                let file = new_file_name();
+                let encrypted =
+                    crypto::encrypt_note_file(&plain, &file, &item.id, key).map_err(io::Error::other)?;
+                vault::write_synced(&dir.join(&file), encrypted.as_bytes())?;
+                wrote = true;
+                file
+            }
+        };
+        item.file = Some(file.clone());
+        files.0.insert(item.id.clone(), (file, digest));
+        Ok(())
+    })?;
+    // Histories of trashed notes stay in the manifest with them
+    data.note_versions = versions;
+    if wrote {
+        vault::sync_dir(&dir)?;
+    }
+    Ok(files)
+}
+
+/// Delete the files in `previous` that `current` no longer refers to. Files
+/// this device never saw, such as ones another device wrote for a manifest
+/// that hasn't synced yet, are left alone. Returns the number deleted.
+pub fn remove_replaced(vault_folder: &str, previous: &NoteFiles, current: &NoteFiles) -> io::Result<usize> {
+    let keep: std::collections::HashSet<&str> = current.0.values().map(|(file, _)| file.as_str()).collect();
+    let mut removed = 0;
+    for (file, _) in previous.0.values() {
+        if keep.contains(file.as_str()) {
+            continue;
+        }
+        match fs::remove_file(notes_dir(vault_folder).join(file)) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
+
+/// Delete the note files `current` doesn't refer to that appeared on this
+/// device at least `grace_secs` before `now` (Unix seconds). Returns the number
+/// deleted.
+pub fn sweep_unreferenced(vault_folder: &str, current: &NoteFiles, now: i64, grace_secs: i64) -> io::Result<usize> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = notes_dir(vault_folder);
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let keep: std::collections::HashSet<&str> = current.0.values().map(|(file, _)| file.as_str()).collect();
+    let mut removed = 0;
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str().filter(|n| is_note_file_name(n) && !keep.contains(n)) else {
+            continue;
+        };
+        // Sync tools carry the modification time over, but not the inode change time
+        if now - entry.metadata()?.ctime() < grace_secs {
+            continue;
+        }
+        match fs::remove_file(dir.join(name)) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::test_fixtures::{note, temp_vault_dir};
+
+    fn vault(a: &str, b: &str) -> VaultData {
+        let folder = TreeItem {
+            item_type: "folder".into(),
+            content: None,
+            children: Some(vec![note("b", "B", b)]),
+            ..note("f", "F", "")
+        };
+        let mut data = VaultData { tree: vec![note("a", "A", a), folder], ..VaultData::default() };
+        data.note_versions.insert("a".into(), vec![VersionEntry { ts: 1, content: "a0".into() }]);
+        data
+    }
+
+    fn files_on_disk(folder: &str) -> usize {
+        fs::read_dir(notes_dir(folder)).unwrap().count()
+    }
+
+    #[test]
+    fn test_store_and_load_note_files() {
+        let dir = temp_vault_dir("notes");
+        let folder = dir.to_str().unwrap();
+        let key = CachedKey::generate("notes test", None).unwrap();
+
+        let mut manifest = vault("alpha", "beta");
+        let first = store_note_files(folder, &mut manifest, &key, &NoteFiles::default()).unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(files_on_disk(folder), 2);
+        assert!(manifest.tree[0].content.is_none());
+        assert!(manifest.note_versions.is_empty());
+        // Only ciphertext reaches the disk
+        for entry in fs::read_dir(notes_dir(folder)).unwrap() {
+            assert!(!fs::read_to_string(entry.unwrap().path()).unwrap().contains("alpha"));
+        }
+
+        // Only the changed note gets a new file; the replaced one goes after
+        let mut manifest = vault("alpha", "beta 2");
+        let second = store_note_files(folder, &mut manifest, &key, &first).unwrap();
+        assert_eq!(second.0["a"], first.0["a"]);
+        assert_ne!(second.0["b"].0, first.0["b"].0);
+        assert_eq!(files_on_disk(folder), 3);
+        assert_eq!(remove_replaced(folder, &first, &second).unwrap(), 1);
+        assert_eq!(files_on_disk(folder), 2);
+
+        let loaded_files = load_note_files(folder, &mut manifest, &key).unwrap();
+        assert_eq!(loaded_files, second);
+        assert_eq!(manifest.tree[0].content.as_deref(), Some("alpha"));
+        assert_eq!(manifest.tree[1].children.as_ref().unwrap()[0].content.as_deref(), Some("beta 2"));
+        assert_eq!(manifest.note_versions["a"][0].content, "a0");
+        assert!(manifest.tree[0].file.is_none());
+
+        // A note file can't be passed off as another note's
+        let mut swapped = vault("", "");
+        store_note_files(folder, &mut swapped, &key, &second).unwrap();
+        let b_file = swapped.tree[1].children.as_ref().unwrap()[0].file.clone();
+        swapped.tree[0].file = b_file;
+        assert!(load_note_files(folder, &mut swapped, &key).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_sweep_unreferenced() {
+        let dir = temp_vault_dir("sweep");
+        let folder = dir.to_str().unwrap();
+        let key = CachedKey::generate("sweep test", None).unwrap();
+
+        let mut manifest = vault("alpha", "beta");
+        let files = store_note_files(folder, &mut manifest, &key, &NoteFiles::default()).unwrap();
+        // Left behind by a save that crashed before its manifest
+        fs::write(notes_dir(folder).join(new_file_name()), "orphan").unwrap();
+        fs::write(notes_dir(folder).join("README.txt"), "not a note file").unwrap();
+        assert_eq!(files_on_disk(folder), 4);
+
+        // Too recent: it may belong to a manifest still syncing
+        let now = crate::state::unix_now();
+        assert_eq!(sweep_unreferenced(folder, &files, now, SWEEP_GRACE_SECS).unwrap(), 0);
+        assert_eq!(sweep_unreferenced(folder, &files, now + SWEEP_GRACE_SECS, SWEEP_GRACE_SECS).unwrap(), 1);
+        assert_eq!(files_on_disk(folder), 3);
+        assert!(load_note_files(folder, &mut manifest, &key).is_ok());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::{fs, io};
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, CachedKey, PlaintextPolicy};
+use crate::integrity;
+use crate::state::file_name_stem;
+use crate::vault::{self, AssetMeta};
+
+// ---------------------------------------------------------------------------
+// Shared notes  (<note>.mdnshare)
+// ---------------------------------------------------------------------------
+//
+// A shared note is one JSON file holding a single note and the images it
+// shows, encrypted under a passphrase chosen just for it, so it can be handed
+// to someone who has no access to the vault. The note is encrypted like a
+// vault file with a new random key; each image is re-encrypted for that key.
+//
+// Importing stores the images in the receiving vault under new asset IDs and
+// its own key, and points the note's `vault://` references at them.
+
+pub const SHARE_EXTENSION: &str = "mdnshare";
+const SHARE_FORMAT: &str = "mdnotebook-shared-note";
+const SHARE_VERSION: u32 = 1;
+
+/// The plaintext of a shared note.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedNote {
+    pub name: String,
+    pub content: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The images in the file, by their ID in the sending vault.
+    #[serde(default)]
+    images: BTreeMap<String, SharedImage>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct SharedImage {
+    original_name: String,
+    mime_type: String,
+}
+
+/// A shared note as stored on disk.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareFile {
+    format: String,
+    version: u32,
+    /// The `SharedNote`, encrypted.
+    note: String,
+    /// Encrypted images by asset ID, base64.
+    images: BTreeMap<String, String>,
+}
+
+/// Encrypt a note and the images it refers to under `passphrase`. `key` is
+/// the vault's key; `policy` decides whether unencrypted images are taken in.
+/// Returns the file and the IDs of referenced images that have no file in the
+/// vault folder; they are left out.
+#[allow(clippy::too_many_arguments)]
+pub fn share_note(
+    vault_folder: &str,
+    name: &str,
+    content: &str,
+    tags: &[String],
+    assets: &HashMap<String, AssetMeta>,
+    key: &CachedKey,
+    passphrase: &str,
+    policy: PlaintextPolicy,
+) -> io::Result<(Vec<u8>, Vec<String>)> {
+    let share_key = CachedKey::generate(passphrase, None).map_err(io::Error::other)?;
+
+    let mut ids: Vec<&str> = integrity::asset_ids_in(content).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let mut images = BTreeMap::new();
+    let mut encrypted_images = BTreeMap::new();
+    let mut missing = Vec::new();
+    for id in ids {
+        let raw = match fs::read(vault::assets_dir(vault_folder).join(id)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                missing.push(id.to_string());
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let failed = |e: crypto::CryptoError| io::Error::other(format!("Image {id}: {e}"));
+        let plain = Zeroizing::new(crypto::decrypt_asset(&raw, id, key, policy).map_err(failed)?);
+        let mime_type = vault::asset_mime_type(&raw, id, assets);
+        let encrypted = crypto::encrypt_asset(&plain, id, &mime_type, &share_key).map_err(failed)?;
+        let original_name = assets.get(id).map_or_else(|| id.to_string(), |m| m.original_name.clone());
+        images.insert(id.to_string(), SharedImage { original_name, mime_type });
+        encrypted_images.insert(id.to_string(), base64::engine::general_purpose::STANDARD.encode(encrypted));
+    }
+
+    let note = SharedNote { name: name.to_string(), content: content.to_string(), tags: tags.to_vec(), images };
+    let json = Zeroizing::new(serde_json::to_string(&note).map_err(io::Error::other)?);
+    let file = ShareFile {
+        format: SHARE_FORMAT.to_string(),
+        version: SHARE_VERSION,
+        note: crypto::encrypt_vault_fast(&json, &share_key).map_err(io::Error::other)?,
+        images: encrypted_images,
+    };
+    Ok((serde_json::to_vec(&file).map_err(io::Error::other)?, missing))
+}
+
+pub fn parse_share_file(bytes: &[u8]) -> io::Result<ShareFile> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Not an MDNotebook shared note");
+    let file: ShareFile = serde_json::from_slice(bytes).map_err(|_| invalid())?;
+    if file.format != SHARE_FORMAT {
+        return Err(invalid());
+    }
+    if file.version > SHARE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "This note was shared by a newer version of MDNotebook",
+        ));
+    }
+    Ok(file)
+}
+
+/// A shared note decrypted with its passphrase.
+pub struct OpenedNote {
+    pub note: SharedNote,
+    /// Decrypted images by their ID in the sending vault.
+    images: BTreeMap<String, Zeroizing<Vec<u8>>>,
+}
+
+impl ShareFile {
+    /// Decrypt the note and its images (expensive — runs the KDF).
+    pub fn open(self, passphrase: &str) -> Result<OpenedNote, crypto::CryptoError> {
+        let (json, key) = crypto::decrypt_vault_returning_key(&self.note, passphrase, None, PlaintextPolicy::Refuse)?;
+        let json = Zeroizing::new(json);
+        let note: SharedNote = serde_json::from_str(&json)
+            .map_err(|e| crypto::CryptoError::InvalidData(format!("Corrupt shared note: {e}")))?;
+        let mut images = BTreeMap::new();
+        for (id, encoded) in self.images {
+            let raw = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| crypto::CryptoError::InvalidData(format!("Image {id}: {e}")))?;
+            images.insert(id.clone(), Zeroizing::new(crypto::decrypt_asset(&raw, &id, &key, PlaintextPolicy::Refuse)?));
+        }
+        Ok(OpenedNote { note, images })
+    }
+}
+
+impl OpenedNote {
+    /// Store the images in the vault at `vault_folder` under `key`, each with
+    /// an ID from `new_asset_id`, and rewrite the note to refer to them.
+    /// Returns the note and the metadata of the stored images.
+    pub fn store(
+        self,
+        vault_folder: &str,
+        key: &CachedKey,
+        mut new_asset_id: impl FnMut() -> String,
+        now: i64,
+    ) -> io::Result<(SharedNote, Vec<AssetMeta>)> {
+        let mut ids = HashMap::new();
+        let mut stored = Vec::new();
+        for (old_id, plain) in &self.images {
+            let Some(image) = self.note.images.get(old_id) else { continue };
+            let id = new_asset_id();
+            let encrypted = crypto::encrypt_asset(plain, &id, &image.mime_type, key).map_err(io::Error::other)?;
+            vault::write_asset(vault_folder, &id, &encrypted)?;
+            stored.push(AssetMeta {
+                id: id.clone(),
+                original_name: image.original_name.clone(),
+                mime_type: image.mime_type.clone(),
+                size: plain.len() as u64,
+                created_at: now,
+                orphaned_at: None,
+            });
+            ids.insert(old_id.clone(), id);
+        }
+        if !stored.is_empty() {
+            vault::sync_dir(&vault::assets_dir(vault_folder))?;
+        }
+        let mut note = self.note;
+        note.content = integrity::replace_asset_ids(&note.content, |id| ids.get(id).cloned());
+        note.images.clear();
+        Ok((note, stored))
+    }
+}
+
+/// Suggested file name for sharing the note called `name`.
+pub fn share_file_name(name: &str) -> String {
+    format!("{}.{SHARE_EXTENSION}", file_name_stem(name))
+}
+
+/// Whether `path` has the shared-note extension.
+pub fn is_share_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == SHARE_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::test_fixtures::{asset_meta, temp_vault_dir};
+
+    #[test]
+    fn test_share_and_import_note() {
+        let dir = temp_vault_dir("share");
+        let folder = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (source, target) = (folder("source"), folder("target"));
+        let key = CachedKey::generate("vault", None).unwrap();
+
+        let encrypted = crypto::encrypt_asset(b"png", "pic", "image/png", &key).unwrap();
+        vault::write_asset(&source, "pic", &encrypted).unwrap();
+        let mut assets = HashMap::new();
+        assets.insert("pic".to_string(), asset_meta("pic"));
+        let content = "Secret ![p](vault://pic) ![q](vault://pic) ![gone](vault://gone)";
+        let (bytes, missing) =
+            share_note(&source, "Trip", content, &["travel".into()], &assets, &key, "one-off pass", PlaintextPolicy::Refuse)
+                .unwrap();
+        assert_eq!(missing, vec!["gone".to_string()]);
+        assert!(!String::from_utf8_lossy(&bytes).contains("Secret"));
+
+        assert!(parse_share_file(&bytes).unwrap().open("wrong pass").is_err());
+        let opened = parse_share_file(&bytes).unwrap().open("one-off pass").unwrap();
+
+        // Images get IDs of the receiving vault and are encrypted under its key
+        let target_key = CachedKey::generate("target", None).unwrap();
+        let (note, stored) = opened.store(&target, &target_key, || "mine".to_string(), 5).unwrap();
+        assert_eq!(note.name, "Trip");
+        assert_eq!(note.tags, vec!["travel".to_string()]);
+        assert_eq!(note.content, "Secret ![p](vault://mine) ![q](vault://mine) ![gone](vault://gone)");
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].original_name, "pic.png");
+        let raw = fs::read(vault::assets_dir(&target).join("mine")).unwrap();
+        let plain = crypto::decrypt_asset(&raw, "mine", &target_key, PlaintextPolicy::Refuse).unwrap();
+        assert_eq!(plain, b"png");
+
+        assert!(parse_share_file(b"[]").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -2,15 +2,26 @@ use adw::prelude::*;
 use base64::Engine as _;
 use sourceview5::prelude::*;
 use sourceview5 as sourceview;
-use std::{cell::RefCell, collections::HashMap, rc::Rc, path::{Path, PathBuf}, fs};
+use std::{cell::Cell, cell::RefCell, collections::HashMap, rc::Rc, path::{Path, PathBuf}, fs};
 use crate::state::*;
+use crate::backup;
+use crate::bundle;
 use crate::crypto;
+use crate::integrity;
+use crate::journal;
+use crate::keyring;
+use crate::merge;
+use crate::share;
+use crate::strength;
+use crate::unlock_attempts;
 use crate::vault;
+use crate::note_files::NoteFiles;
+use crate::writer_lock;
 use crate::ui::types::*;
 use crate::*;
 
 #[allow(clippy::type_complexity)]
-pub fn build_content_menu() -> gtk::gio::Menu {
+pub fn build_content_menu(vault_folder: &str) -> gtk::gio::Menu {
     let menu = gtk::gio::Menu::new();
 
     let section1 = gtk::gio::Menu::new();
@@ -21,14 +32,33 @@ pub fn build_content_menu() -> gtk::gio::Menu {
     section1.append(Some("Move to Folder\u{2026}"), Some("win.move-to-folder"));
     section1.append(Some("Export as Markdown\u{2026}"), Some("win.export-markdown"));
     section1.append(Some("Export as HTML\u{2026}"), Some("win.export-html"));
+    section1.append(Some("Share Note Encrypted\u{2026}"), Some("win.share-note"));
+    section1.append(Some("Import Shared Note\u{2026}"), Some("win.import-shared-note"));
     menu.append_section(None, &section1);
 
     let section2 = gtk::gio::Menu::new();
     section2.append(Some("Zen Mode"), Some("win.zen-mode"));
     section2.append(Some("Fullscreen"), Some("win.fullscreen"));
+    section2.append(Some("New Window"), Some("win.new-window"));
     menu.append_section(None, &section2);
 
+    let section_vault = gtk::gio::Menu::new();
+    section_vault.append_submenu(Some("Switch Vault"), &build_vault_switcher_menu(vault_folder));
+    section_vault.append(Some("Change Passphrase\u{2026}"), Some("win.change-passphrase"));
+    section_vault.append(Some("New Recovery Code\u{2026}"), Some("win.new-recovery-code"));
+    section_vault.append(Some("Forget This Device"), Some("win.forget-device"));
+    section_vault.append(Some("Encrypt Unencrypted Files\u{2026}"), Some("win.encrypt-plaintext"));
+    section_vault.append(Some("Check Vault\u{2026}"), Some("win.check-vault"));
+    section_vault.append(Some("Remove Unused Images"), Some("win.collect-assets"));
+    section_vault.append(Some("Restore from Backup\u{2026}"), Some("win.restore-backup"));
+    section_vault.append(Some("Export Vault Bundle\u{2026}"), Some("win.export-bundle"));
+    section_vault.append(Some("Import Vault Bundle\u{2026}"), Some("win.import-bundle"));
+    section_vault.append(Some("Lock Vault"), Some("win.lock-vault"));
+    section_vault.append(Some("Close Vault"), Some("win.close-vault"));
+    menu.append_section(None, &section_vault);
+
     let section3 = gtk::gio::Menu::new();
+    section3.append(Some("Preferences"), Some("win.preferences"));
     section3.append(Some("Help"), Some("win.show-help"));
     section3.append(Some("Keyboard Shortcuts"), Some("win.show-shortcuts"));
     section3.append(Some("About MD Notebook"), Some("win.show-about"));
@@ -37,6 +67,23 @@ pub fn build_content_menu() -> gtk::gio::Menu {
     menu
 }
 
+/// The other known vaults, most recently opened first, and "Open Vault…".
+fn build_vault_switcher_menu(vault_folder: &str) -> gtk::gio::Menu {
+    let menu = gtk::gio::Menu::new();
+    let vaults = gtk::gio::Menu::new();
+    for known in vault::load_config().vaults.iter().filter(|v| v.path != vault_folder) {
+        // Menu labels treat "_" as a mnemonic marker
+        let item = gtk::gio::MenuItem::new(Some(&known.name.replace('_', "__")), None);
+        item.set_action_and_target_value(Some("win.switch-vault"), Some(&known.path.to_variant()));
+        vaults.append_item(&item);
+    }
+    menu.append_section(None, &vaults);
+    let open = gtk::gio::Menu::new();
+    open.append(Some("Open Vault\u{2026}"), Some("win.open-vault"));
+    menu.append_section(None, &open);
+    menu
+}
+
 fn sort_order_action_key(order: SortOrder) -> &'static str {
     match order {
         SortOrder::Manual => "manual",
@@ -154,6 +201,129 @@ pub fn wire_menu_actions(ctx: &EditorCtx) {
     { let ctx = ctx.clone(); action.connect_activate(move |_, _| export_as_html(&ctx)); }
     window.add_action(&action);
 
+    // Shared notes
+    let action = SimpleAction::new("share-note", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| show_share_note_dialog(&ctx)); }
+    window.add_action(&action);
+
+    let action = SimpleAction::new("import-shared-note", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| import_shared_note(&ctx)); }
+    window.add_action(&action);
+
+    // Copy the selection so clipboard managers skip it
+    let action = SimpleAction::new("copy-sensitive", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| copy_selection_as_sensitive(&ctx)); }
+    window.add_action(&action);
+
+    // Change passphrase
+    let action = SimpleAction::new("change-passphrase", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| show_change_passphrase_dialog(&ctx)); }
+    window.add_action(&action);
+
+    // New recovery code
+    let action = SimpleAction::new("new-recovery-code", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| confirm_new_recovery_code(&ctx)); }
+    window.add_action(&action);
+
+    // Forget the vault key remembered on this device
+    let action = SimpleAction::new("forget-device", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| forget_vault_key(&ctx)); }
+    window.add_action(&action);
+
+    // Encrypt plaintext vault files
+    let action = SimpleAction::new("encrypt-plaintext", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| encrypt_plaintext_files(&ctx)); }
+    window.add_action(&action);
+
+    // Check vault integrity
+    let action = SimpleAction::new("check-vault", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| show_check_vault_dialog(&ctx)); }
+    window.add_action(&action);
+
+    // Remove unused images
+    let action = SimpleAction::new("collect-assets", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| collect_unused_assets(&ctx, true)); }
+    window.add_action(&action);
+
+    // Restore from backup
+    let action = SimpleAction::new("restore-backup", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| show_restore_backup_dialog(&ctx)); }
+    window.add_action(&action);
+
+    // Vault bundles
+    let action = SimpleAction::new("export-bundle", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| show_export_bundle_dialog(&ctx)); }
+    window.add_action(&action);
+
+    let action = SimpleAction::new("import-bundle", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| import_vault_bundle(&ctx)); }
+    window.add_action(&action);
+
+    // Lock vault
+    let action = SimpleAction::new("lock-vault", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| lock_vault(&ctx)); }
+    window.add_action(&action);
+
+    // Close vault: save and lock, then show the vault picker
+    let action = SimpleAction::new("close-vault", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| {
+            *ctx.after_lock.borrow_mut() = AfterLock::Picker;
+            lock_vault(&ctx);
+        });
+    }
+    window.add_action(&action);
+
+    // Switch to another known vault in this window
+    let action = SimpleAction::new("switch-vault", Some(&String::static_variant_type()));
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, param| {
+            let Some(folder) = param.and_then(|p| p.get::<String>()) else { return };
+            if folder == *ctx.vault_folder.borrow() || !check_vault_available(&ctx.window, &folder) {
+                return;
+            }
+            *ctx.after_lock.borrow_mut() = AfterLock::Unlock(folder);
+            lock_vault(&ctx);
+        });
+    }
+    window.add_action(&action);
+
+    // Open a vault folder not in the list yet
+    let action = SimpleAction::new("open-vault", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| {
+            let ctx = ctx.clone();
+            choose_vault_folder(&ctx.window.clone(), move |folder| {
+                if folder == *ctx.vault_folder.borrow() {
+                    return;
+                }
+                *ctx.after_lock.borrow_mut() = AfterLock::Unlock(folder);
+                lock_vault(&ctx);
+            });
+        });
+    }
+    window.add_action(&action);
+
+    // New window, for keeping a second vault open
+    let action = SimpleAction::new("new-window", None);
+    {
+        let window = window.clone();
+        action.connect_activate(move |_, _| {
+            if let Some(app) = window.application().and_downcast::<adw::Application>() {
+                new_vault_window(&app, None);
+            }
+        });
+    }
+    window.add_action(&action);
+
+    // Preferences
+    let action = SimpleAction::new("preferences", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| show_preferences_dialog(&ctx)); }
+    window.add_action(&action);
+
     // Zen mode
     let action = SimpleAction::new("zen-mode", None);
     { let ctx = ctx.clone(); action.connect_activate(move |_, _| toggle_zen_mode(&ctx)); }
@@ -287,6 +457,13 @@ pub fn wire_menu_actions(ctx: &EditorCtx) {
     }); }
     window.add_action(&action);
 
+    let action = SimpleAction::new("fmt-secret", None);
+    { let ctx = ctx.clone(); action.connect_activate(move |_, _| {
+        wysiwyg::toggle_secret(&ctx.rich_buffer);
+        process_buffer_change(&ctx);
+    }); }
+    window.add_action(&action);
+
     let action = SimpleAction::new("fmt-link", None);
     { let ctx = ctx.clone(); action.connect_activate(move |_, _| {
         insert_link(&ctx);
@@ -897,6 +1074,9 @@ fn apply_sidebar_drop(
     dragged: SidebarRowKind,
     target: Option<SidebarRowKind>,
 ) -> bool {
+    if ctx.read_only.get() {
+        return false;
+    }
     let mut state = ctx.state.borrow_mut();
 
     if state.viewing_trash || !state.search_query.trim().is_empty() || !state.filter_tags.is_empty() {
@@ -951,6 +1131,9 @@ pub fn show_note_context_menu(
     y: f64,
     widget: &gtk::Widget,
 ) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let vbox = gtk::Box::new(gtk::Orientation::Vertical, 2);
     vbox.set_margin_start(6);
     vbox.set_margin_end(6);
@@ -1049,6 +1232,9 @@ pub fn show_folder_context_menu(
     y: f64,
     widget: &gtk::Widget,
 ) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let vbox = gtk::Box::new(gtk::Orientation::Vertical, 2);
     vbox.set_margin_start(6);
     vbox.set_margin_end(6);
@@ -1128,6 +1314,9 @@ pub fn show_folder_context_menu(
 }
 
 pub fn rename_folder_dialog(ctx: &EditorCtx, folder_id: &str) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let current_name = {
         let state = ctx.state.borrow();
         state
@@ -1193,6 +1382,9 @@ pub fn create_note_in_folder(
     tags: Vec<String>,
     folder_id: Option<String>,
 ) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let id = {
         let mut state = ctx.state.borrow_mut();
         let unique_name = deduplicate_note_name(&state.notes, &name, &folder_id);
@@ -1208,6 +1400,9 @@ pub fn create_note_in_folder(
 }
 
 pub fn delete_folder(ctx: &EditorCtx, folder_id: &str) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let dialog = adw::AlertDialog::new(
         Some("Delete Folder?"),
         Some("Notes inside this folder will be moved to the root level."),
@@ -1342,6 +1537,21 @@ pub fn wire_editor_signals(ctx: &EditorCtx, rich_view: &gtk::TextView, tag_entry
             process_buffer_change_debounced(&ctx);
         });
     }
+    // Copying out of either view: remember the clipboard content so it can be
+    // cleared again, and offer the sensitive copy in the context menu
+    let copy_menu = gtk::gio::Menu::new();
+    copy_menu.append(Some("Copy as Sensitive"), Some("win.copy-sensitive"));
+    for view in [rich_view.clone(), ctx.markdown_view.clone().upcast::<gtk::TextView>()] {
+        {
+            let ctx = ctx.clone();
+            view.connect_copy_clipboard(move |view| track_view_copy(&ctx, view));
+        }
+        {
+            let ctx = ctx.clone();
+            view.connect_cut_clipboard(move |view| track_view_copy(&ctx, view));
+        }
+        view.set_extra_menu(Some(&copy_menu));
+    }
     {
         let buffer = ctx.rich_buffer.clone();
         buffer.connect_insert_text(move |buf, location, text| {
@@ -1424,6 +1634,12 @@ pub fn wire_editor_signals(ctx: &EditorCtx, rich_view: &gtk::TextView, tag_entry
                 .unwrap_or(gdk::ModifierType::empty());
             let ctrl = mods.contains(gdk::ModifierType::CONTROL_MASK);
 
+            // Click on a secret's dots → show it for a while
+            if n_press == 1 && wysiwyg::reveal_secret_at(&ctx.rich_buffer, &iter) {
+                schedule_secret_remask(&ctx);
+                return;
+            }
+
             // Double-click on a link → edit dialog
             if n_press == 2 {
                 for tag in iter.tags() {
@@ -1633,6 +1849,7 @@ pub fn wire_keyboard_shortcuts(
         ("<Ctrl>d",          "win.fmt-strike"),
         ("<Ctrl>e",          "win.fmt-code"),
         ("<Ctrl>k",          "win.fmt-link"),
+        ("<Ctrl><Shift>h",   "win.fmt-secret"),
         ("<Ctrl>1",          "win.fmt-h1"),
         ("<Ctrl>2",          "win.fmt-h2"),
         ("<Ctrl>3",          "win.fmt-h3"),
@@ -1643,6 +1860,9 @@ pub fn wire_keyboard_shortcuts(
         ("<Ctrl><Shift>l",   "win.fmt-bullet-list"),
         ("<Ctrl>space",      "win.toggle-checkbox"),
         ("<Ctrl><Shift>p",   "win.command-palette"),
+        ("<Ctrl>l",          "win.lock-vault"),
+        ("<Ctrl><Shift>c",   "win.copy-sensitive"),
+        ("<Ctrl>comma",      "win.preferences"),
         ("F1",               "win.show-help"),
     ];
 
@@ -1655,6 +1875,7 @@ pub fn wire_keyboard_shortcuts(
             sc.add_shortcut(shortcut);
         }
     }
+    ctx.window_controllers.borrow_mut().push(sc.clone().upcast());
     window.add_controller(sc);
 
     // Rich-view-only: Enter (list continuation), Space (markdown shortcuts), Escape (zen exit)
@@ -1811,6 +2032,7 @@ pub fn make_search_snippet(content: &str, query: &str) -> Option<String> {
         return None;
     }
 
+    let content = &mask_secret_spans(content, SECRET_MASK);
     let lower = content.to_lowercase();
     let lower_query = query.to_lowercase();
     let match_pos = lower.find(&lower_query)?;
@@ -2223,9 +2445,10 @@ pub fn refresh_note_list(ctx: &EditorCtx) {
             .notes
             .iter()
             .filter(|note| {
+                // Secrets are left out, or a search could confirm a guess
                 let matches_search = query.trim().is_empty()
                     || note.name.to_lowercase().contains(&query)
-                    || note.content.to_lowercase().contains(&query);
+                    || mask_secret_spans(&note.content, " ").to_lowercase().contains(&query);
                 let matches_tags = if filter_tags.is_empty() {
                     true
                 } else if tag_filter_and {
@@ -2455,6 +2678,9 @@ pub fn refresh_tags(ctx: &EditorCtx) {
 }
 
 pub fn add_tag_to_active_note(ctx: &EditorCtx, tag: String) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     {
         let mut state = ctx.state.borrow_mut();
         if let Some(index) = find_note_index(&state.notes, &state.active_note_id) {
@@ -2474,6 +2700,9 @@ pub fn add_tag_to_active_note(ctx: &EditorCtx, tag: String) {
 }
 
 pub fn remove_tag_from_active_note(ctx: &EditorCtx, tag: &str) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     {
         let mut state = ctx.state.borrow_mut();
         if let Some(index) = find_note_index(&state.notes, &state.active_note_id) {
@@ -2491,6 +2720,9 @@ pub fn remove_tag_from_active_note(ctx: &EditorCtx, tag: &str) {
 // ---------------------------------------------------------------------------
 
 pub fn create_note(ctx: &EditorCtx, name: String, content: String, tags: Vec<String>) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let note_id = {
         let mut state = ctx.state.borrow_mut();
         let folder = state.active_folder_id.clone();
@@ -2611,6 +2843,9 @@ pub fn builtin_templates() -> Vec<(String, String, String)> {
 }
 
 pub fn show_template_picker(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let dialog = adw::Window::builder()
         .transient_for(&ctx.window)
         .modal(true)
@@ -2754,6 +2989,9 @@ pub fn show_template_picker(ctx: &EditorCtx) {
 }
 
 pub fn save_note_as_template(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let (name, content, tags) = {
         let state = ctx.state.borrow();
         find_note_index(&state.notes, &state.active_note_id)
@@ -2790,6 +3028,9 @@ pub fn save_note_as_template(ctx: &EditorCtx) {
 }
 
 pub fn save_manual_snapshot(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let markdown = current_markdown(ctx);
     {
         let mut state = ctx.state.borrow_mut();
@@ -2839,9 +3080,27 @@ pub fn build_command_entries() -> Vec<CommandEntry> {
         CommandEntry { label: "Move to Folder\u{2026}".into(), accel: "".into(), action_name: "win.move-to-folder".into() },
         CommandEntry { label: "Export as Markdown\u{2026}".into(), accel: "".into(), action_name: "win.export-markdown".into() },
         CommandEntry { label: "Export as HTML\u{2026}".into(), accel: "".into(), action_name: "win.export-html".into() },
+        CommandEntry { label: "Copy as Sensitive".into(), accel: "Ctrl+Shift+C".into(), action_name: "win.copy-sensitive".into() },
+        CommandEntry { label: "Share Note Encrypted\u{2026}".into(), accel: "".into(), action_name: "win.share-note".into() },
+        CommandEntry { label: "Import Shared Note\u{2026}".into(), accel: "".into(), action_name: "win.import-shared-note".into() },
+        CommandEntry { label: "Change Passphrase\u{2026}".into(), accel: "".into(), action_name: "win.change-passphrase".into() },
+        CommandEntry { label: "New Recovery Code\u{2026}".into(), accel: "".into(), action_name: "win.new-recovery-code".into() },
+        CommandEntry { label: "Forget This Device".into(), accel: "".into(), action_name: "win.forget-device".into() },
+        CommandEntry { label: "Encrypt Unencrypted Files\u{2026}".into(), accel: "".into(), action_name: "win.encrypt-plaintext".into() },
+        CommandEntry { label: "Check Vault\u{2026}".into(), accel: "".into(), action_name: "win.check-vault".into() },
+        CommandEntry { label: "Remove Unused Images".into(), accel: "".into(), action_name: "win.collect-assets".into() },
+        CommandEntry { label: "Restore from Backup\u{2026}".into(), accel: "".into(), action_name: "win.restore-backup".into() },
+        CommandEntry { label: "Export Vault Bundle\u{2026}".into(), accel: "".into(), action_name: "win.export-bundle".into() },
+        CommandEntry { label: "Import Vault Bundle\u{2026}".into(), accel: "".into(), action_name: "win.import-bundle".into() },
+        CommandEntry { label: "Lock Vault".into(), accel: "Ctrl+L".into(), action_name: "win.lock-vault".into() },
+        CommandEntry { label: "Close Vault".into(), accel: "".into(), action_name: "win.close-vault".into() },
+        CommandEntry { label: "Open Vault\u{2026}".into(), accel: "".into(), action_name: "win.open-vault".into() },
+        CommandEntry { label: "New Window".into(), accel: "".into(), action_name: "win.new-window".into() },
+        CommandEntry { label: "Preferences".into(), accel: "Ctrl+,".into(), action_name: "win.preferences".into() },
         CommandEntry { label: "Bold".into(), accel: "Ctrl+B".into(), action_name: "win.fmt-bold".into() },
         CommandEntry { label: "Italic".into(), accel: "Ctrl+I".into(), action_name: "win.fmt-italic".into() },
         CommandEntry { label: "Underline".into(), accel: "Ctrl+U".into(), action_name: "win.fmt-underline".into() },
+        CommandEntry { label: "Secret".into(), accel: "Ctrl+Shift+H".into(), action_name: "win.fmt-secret".into() },
         CommandEntry { label: "Strikethrough".into(), accel: "Ctrl+D".into(), action_name: "win.fmt-strike".into() },
         CommandEntry { label: "Inline Code".into(), accel: "Ctrl+E".into(), action_name: "win.fmt-code".into() },
         CommandEntry { label: "Insert Link".into(), accel: "Ctrl+K".into(), action_name: "win.fmt-link".into() },
@@ -3112,8 +3371,9 @@ pub fn show_history_dialog(ctx: &EditorCtx) {
             .take(5)
             .map(|(idx, version)| (idx, format_ts(version.ts)))
             .collect();
-        for (idx, (_, ts)) in history_rows.iter().enumerate() {
-            text.push_str(&format!("{}. {ts}\n", idx + 1));
+        for (idx, (version_idx, ts)) in history_rows.iter().enumerate() {
+            let preview = version_preview(&versions[*version_idx].content);
+            text.push_str(&format!("{}. {ts}  {preview}\n", idx + 1));
         }
         let dialog = adw::AlertDialog::new(
             Some(&format!("Version history: {note_name}")),
@@ -3147,7 +3407,22 @@ pub fn show_history_dialog(ctx: &EditorCtx) {
     }
 }
 
+/// The first line of a snapshot, shortened and with its secrets masked.
+fn version_preview(content: &str) -> String {
+    const MAX_CHARS: usize = 48;
+    let masked = mask_secret_spans(content, SECRET_MASK);
+    let line = masked.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("");
+    if line.chars().count() > MAX_CHARS {
+        format!("{}\u{2026}", line.chars().take(MAX_CHARS).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
 pub fn restore_snapshot(ctx: &EditorCtx, version_idx: usize) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let restored = {
         let mut state = ctx.state.borrow_mut();
         if let Some(note_idx) = find_note_index(&state.notes, &state.active_note_id) {
@@ -3172,6 +3447,9 @@ pub fn restore_snapshot(ctx: &EditorCtx, version_idx: usize) {
 }
 
 pub fn restore_latest_snapshot(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let latest = {
         let state = ctx.state.borrow();
         find_note_index(&state.notes, &state.active_note_id)
@@ -3201,6 +3479,9 @@ pub fn show_error(parent: &adw::ApplicationWindow, title: &str, message: &str) {
 // ---------------------------------------------------------------------------
 
 pub fn apply_block_type(ctx: &EditorCtx, block: wysiwyg::BlockType) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     wysiwyg::set_block_type(&ctx.rich_buffer, block);
     process_buffer_change(ctx);
 }
@@ -3228,6 +3509,11 @@ pub fn handle_wysiwyg_keys(
         return glib::Propagation::Stop;
     }
 
+    // Everything below edits the note
+    if ctx.read_only.get() {
+        return glib::Propagation::Proceed;
+    }
+
     // Enter continues lists in WYSIWYG mode
     if (key == gdk::Key::Return || key == gdk::Key::KP_Enter)
         && !mods.contains(gdk::ModifierType::CONTROL_MASK)
@@ -3418,6 +3704,7 @@ pub fn do_sync_and_undo_inner(ctx: &EditorCtx, force_checkpoint: bool) {
         refresh_note_list(ctx);
     }
     if content_changed {
+        journal_active_note(ctx);
         trigger_vault_save(ctx);
     }
 }
@@ -3464,6 +3751,7 @@ pub fn process_source_buffer_change(ctx: &EditorCtx) {
         refresh_note_list(ctx);
     }
     if content_changed {
+        journal_active_note(ctx);
         trigger_vault_save(ctx);
     }
 }
@@ -3476,14 +3764,19 @@ pub fn refresh_header(ctx: &EditorCtx) {
 
     ctx.doc_label.set_label(&file_name);
 
-    if state.dirty {
+    if ctx.read_only.get() {
+        ctx.dirty_label.set_visible(true);
+        ctx.dirty_label.set_label("Read-Only");
+    } else if state.dirty {
         ctx.dirty_label.set_visible(true);
         ctx.dirty_label.set_label("\u{25cf} Unsaved");
     } else {
         ctx.dirty_label.set_visible(false);
     }
 
-    let title = if state.dirty {
+    let title = if ctx.read_only.get() {
+        format!("{file_name} (Read-Only) \u{2014} MDNotebook")
+    } else if state.dirty {
         format!("*{file_name} \u{2014} MDNotebook")
     } else {
         format!("{file_name} \u{2014} MDNotebook")
@@ -3535,6 +3828,9 @@ pub fn new_document(ctx: &EditorCtx) {
 }
 
 pub fn undo(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let target = {
         let mut state = ctx.state.borrow_mut();
         let Some(previous) = state.undo_stack.pop() else {
@@ -3553,6 +3849,9 @@ pub fn undo(ctx: &EditorCtx) {
 }
 
 pub fn redo(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let target = {
         let mut state = ctx.state.borrow_mut();
         let Some(next) = state.redo_stack.pop() else {
@@ -3633,7 +3932,7 @@ pub fn toggle_source_mode(ctx: &EditorCtx, button: &gtk::Button) {
         sync_markdown_and_status(ctx);
         ctx.rich_panel.set_visible(false);
         ctx.markdown_panel.set_visible(true);
-        ctx.markdown_view.set_editable(true);
+        ctx.markdown_view.set_editable(!ctx.read_only.get());
         ctx.markdown_view.set_cursor_visible(true);
         ctx.split.set_position(0);
         button.set_label("RT");
@@ -3665,13 +3964,13 @@ pub fn update_formatting_actions_enabled(ctx: &EditorCtx, enabled: bool) {
     use gtk::gio::prelude::ActionMapExt;
     let fmt_actions = [
         "fmt-bold", "fmt-italic", "fmt-underline", "fmt-strike",
-        "fmt-code", "fmt-link", "fmt-h1", "fmt-h2", "fmt-h3", "fmt-h4", "fmt-h5", "fmt-h6",
+        "fmt-code", "fmt-secret", "fmt-link", "fmt-h1", "fmt-h2", "fmt-h3", "fmt-h4", "fmt-h5", "fmt-h6",
         "fmt-quote", "fmt-bullet-list", "fmt-ordered-list", "fmt-task-list",
     ];
     for name in fmt_actions {
         if let Some(action) = ctx.window.lookup_action(name) {
             if let Some(simple) = action.downcast_ref::<gtk::gio::SimpleAction>() {
-                simple.set_enabled(enabled);
+                simple.set_enabled(enabled && !ctx.read_only.get());
             }
         }
     }
@@ -3742,6 +4041,9 @@ pub fn set_save_status(ctx: &EditorCtx, status: &str) {
 // ---------------------------------------------------------------------------
 
 pub fn open_document(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let dialog = gtk::FileDialog::builder()
         .title("Open Markdown File")
         .accept_label("Open")
@@ -3840,6 +4142,34 @@ pub fn save_document_as(ctx: &EditorCtx) {
 
 pub fn export_as_html(ctx: &EditorCtx) {
     let markdown = current_markdown(ctx);
+    if !markdown.to_ascii_lowercase().contains(SECRET_OPEN) {
+        save_html_export(ctx, &markdown);
+        return;
+    }
+
+    let dialog = adw::AlertDialog::new(
+        Some("Include Secrets?"),
+        Some("This note has secret text. The exported file is not encrypted, so secrets are left out unless you include them."),
+    );
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("include", "Include Secrets");
+    dialog.add_response("blank", "Leave Out");
+    dialog.set_response_appearance("include", adw::ResponseAppearance::Destructive);
+    dialog.set_response_appearance("blank", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("blank"));
+    dialog.set_close_response("cancel");
+
+    let window = ctx.window.clone();
+    let ctx = ctx.clone();
+    dialog.connect_response(None, move |_, response| match response {
+        "include" => save_html_export(&ctx, &markdown),
+        "blank" => save_html_export(&ctx, &mask_secret_spans(&markdown, SECRET_MASK)),
+        _ => {}
+    });
+    dialog.present(Some(&window));
+}
+
+fn save_html_export(ctx: &EditorCtx, markdown: &str) {
     let note_name = {
         let state = ctx.state.borrow();
         find_note_index(&state.notes, &state.active_note_id)
@@ -3848,7 +4178,7 @@ pub fn export_as_html(ctx: &EditorCtx) {
     };
 
     // Convert markdown to HTML using pulldown-cmark
-    let parser = pulldown_cmark::Parser::new(&markdown);
+    let parser = pulldown_cmark::Parser::new(markdown);
     let mut html_body = String::new();
     pulldown_cmark::html::push_html(&mut html_body, parser);
 
@@ -4166,6 +4496,9 @@ pub fn insert_table_snippet(buffer: &gtk::TextBuffer) {
 }
 
 pub fn insert_image_snippet(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let dialog = gtk::FileDialog::builder()
         .title("Insert Image")
         .accept_label("Insert")
@@ -4258,11 +4591,19 @@ fn load_texture_from_markdown_url(ctx: &EditorCtx, url: &str) -> Option<gdk::Tex
         let asset_path = vault::assets_dir(&vault_folder).join(asset_id);
         let raw = fs::read(asset_path).ok()?;
         let decoded = if let Some(cached) = ctx.cached_key.borrow().clone() {
-            match crypto::decrypt_asset(&raw, &cached) {
+            let policy = vault::load_config().plaintext_policy();
+            match crypto::decrypt_asset(&raw, asset_id, &cached, policy) {
                 Ok(bytes) => bytes,
                 Err(e) => {
+                    // Never fall back to the raw bytes: a file that fails
+                    // verification may have been swapped in from elsewhere.
                     eprintln!("Warning: asset decryption failed for {asset_id}: {e}");
-                    raw
+                    send_toast(ctx, if matches!(e, crypto::CryptoError::Unencrypted) {
+                        "An unencrypted image was not shown \u{2014} use Encrypt Unencrypted Files"
+                    } else {
+                        "An image failed its integrity check and was not shown"
+                    });
+                    return None;
                 }
             }
         } else {
@@ -4316,6 +4657,24 @@ fn load_texture_from_markdown_url(ctx: &EditorCtx, url: &str) -> Option<gdk::Tex
     None
 }
 
+/// Seconds a revealed secret span stays shown.
+const SECRET_REVEAL_SECS: u32 = 20;
+
+/// Hide revealed secret spans again in `SECRET_REVEAL_SECS`; every reveal
+/// starts the wait over.
+fn schedule_secret_remask(ctx: &EditorCtx) {
+    if let Some(id) = ctx.secret_reveal_timeout_id.take() {
+        id.remove();
+    }
+    let timeout_cell = ctx.secret_reveal_timeout_id.clone();
+    let buffer = ctx.rich_buffer.clone();
+    let id = glib::timeout_add_seconds_local_once(SECRET_REVEAL_SECS, move || {
+        timeout_cell.set(None);
+        wysiwyg::mask_secrets(&buffer);
+    });
+    ctx.secret_reveal_timeout_id.set(Some(id));
+}
+
 pub fn render_wysiwyg_images(ctx: &EditorCtx) {
     #[derive(Clone)]
     struct ImageSpan {
@@ -4427,6 +4786,9 @@ pub fn mime_from_ext(ext: &str) -> String {
 }
 
 pub fn store_image_as_asset(ctx: &EditorCtx, data: &[u8], filename: &str, mime: &str) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let asset_id = generate_asset_id();
     let vault_folder = ctx.vault_folder.borrow().clone();
     let cached_key = ctx.cached_key.borrow().clone();
@@ -4439,11 +4801,12 @@ pub fn store_image_as_asset(ctx: &EditorCtx, data: &[u8], filename: &str, mime:
     let (tx, rx) = std::sync::mpsc::channel::<Result<(), String>>();
     let data_owned = data.to_vec();
     let asset_id_thread = asset_id.clone();
+    let mime_thread = mime_owned.clone();
 
     std::thread::spawn(move || {
         let write_data = if let Some(ref key) = cached_key {
-            match crypto::encrypt_asset(&data_owned, key) {
-                Ok(encrypted) => encrypted.into_bytes(),
+            match crypto::encrypt_asset(&data_owned, &asset_id_thread, &mime_thread, key) {
+                Ok(encrypted) => encrypted,
                 Err(e) => {
                     let _ = tx.send(Err(format!("Asset encryption failed: {e}")));
                     return;
@@ -4475,6 +4838,7 @@ pub fn store_image_as_asset(ctx: &EditorCtx, data: &[u8], filename: &str, mime:
                     mime_type: mime_owned.clone(),
                     size: data_size,
                     created_at: now,
+                    orphaned_at: None,
                 };
                 ctx.state.borrow_mut().assets.insert(asset_id.clone(), meta);
 
@@ -4621,6 +4985,9 @@ pub fn update_status_full(ctx: &EditorCtx, text: &str) {
 // ---------------------------------------------------------------------------
 
 pub fn delete_note(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let note_count = ctx.state.borrow().notes.len();
     if note_count <= 1 {
         show_info(
@@ -4663,6 +5030,9 @@ pub fn delete_note(ctx: &EditorCtx) {
 // ---------------------------------------------------------------------------
 
 pub fn rename_note_dialog(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let current_name = {
         let state = ctx.state.borrow();
         find_note_index(&state.notes, &state.active_note_id)
@@ -4733,9 +5103,11 @@ pub fn rename_note_dialog(ctx: &EditorCtx) {
 // ---------------------------------------------------------------------------
 
 pub fn wire_close_request(ctx: &EditorCtx) {
+    let writer_lock = ctx.writer_lock.clone();
     let ctx = ctx.clone();
     let win = ctx.window.clone();
-    win.connect_close_request(move |_window| {
+    let handlers = ctx.window_handlers.clone();
+    let handler = win.connect_close_request(move |_window| {
         let has_vault = ctx.cached_key.borrow().is_some() && !ctx.vault_folder.borrow().is_empty();
         if !has_vault {
             // No vault configured — just close
@@ -4750,6 +5122,12 @@ pub fn wire_close_request(ctx: &EditorCtx) {
         perform_vault_save_async(&ctx, false);
         glib::Propagation::Stop
     });
+    handlers.borrow_mut().push(handler);
+
+    let handler = win.connect_destroy(move |_| {
+        writer_lock.borrow_mut().take(); // Removes the lock file
+    });
+    handlers.borrow_mut().push(handler);
 }
 
 fn show_close_save_failed_dialog(window: &adw::ApplicationWindow, error: &str) {
@@ -4782,12 +5160,18 @@ fn show_close_save_failed_dialog(window: &adw::ApplicationWindow, error: &str) {
 pub fn setup_auto_save(ctx: &EditorCtx) {
     let ctx = ctx.clone();
     glib::timeout_add_seconds_local(AUTO_SAVE_INTERVAL_SECS, move || {
+        if ctx.locked.get() {
+            return glib::ControlFlow::Break;
+        }
         auto_save_tick(&ctx);
         glib::ControlFlow::Continue
     });
 }
 
 pub fn auto_save_tick(ctx: &EditorCtx) {
+    if ctx.read_only.get() {
+        return;
+    }
     // Push a snapshot to version history
     {
         let mut state = ctx.state.borrow_mut();
@@ -4800,198 +5184,4027 @@ pub fn auto_save_tick(ctx: &EditorCtx) {
 }
 
 // ---------------------------------------------------------------------------
-// Vault startup dialogs
+// Auto-lock
 // ---------------------------------------------------------------------------
 
-pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
-    let dialog = adw::Window::builder()
-        .transient_for(window)
-        .modal(true)
-        .title("Create Vault")
-        .default_width(460)
-        .default_height(340)
-        .build();
+const AUTO_LOCK_CHECK_SECS: u32 = 15;
 
-    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
-    vbox.set_margin_start(24);
-    vbox.set_margin_end(24);
-    vbox.set_margin_top(24);
-    vbox.set_margin_bottom(24);
+/// Track user activity, start the idle timer and listen for session lock/suspend.
+pub fn setup_auto_lock(ctx: &EditorCtx) {
+    // Any input event on the window counts as activity
+    {
+        let last_activity = ctx.last_activity.clone();
+        let legacy = gtk::EventControllerLegacy::new();
+        legacy.set_propagation_phase(gtk::PropagationPhase::Capture);
+        legacy.connect_event(move |_, _| {
+            last_activity.set(std::time::Instant::now());
+            glib::Propagation::Proceed
+        });
+        ctx.window_controllers.borrow_mut().push(legacy.clone().upcast());
+        ctx.window.add_controller(legacy);
+    }
 
-    let title = gtk::Label::new(Some("Create a New Vault"));
-    title.add_css_class("title-2");
-    vbox.append(&title);
+    {
+        let ctx = ctx.clone();
+        glib::timeout_add_seconds_local(AUTO_LOCK_CHECK_SECS, move || {
+            if ctx.locked.get() {
+                return glib::ControlFlow::Break;
+            }
+            let minutes = ctx.auto_lock_minutes.get();
+            let idle = ctx.last_activity.get().elapsed();
+            if minutes > 0 && idle >= std::time::Duration::from_secs(u64::from(minutes) * 60) {
+                lock_vault(&ctx);
+            }
+            glib::ControlFlow::Continue
+        });
+    }
 
-    let subtitle = gtk::Label::new(Some(
-        "Choose a folder and set a passphrase to encrypt your notes.",
-    ));
-    subtitle.add_css_class("dim-label");
-    subtitle.set_wrap(true);
-    vbox.append(&subtitle);
+    // Screen lock (GNOME, KDE and other freedesktop screensavers) on the session bus
+    {
+        let ctx = ctx.clone();
+        gtk::gio::bus_get(gtk::gio::BusType::Session, gtk::gio::Cancellable::NONE, move |conn| {
+            let Ok(conn) = conn else { return };
+            for interface in ["org.gnome.ScreenSaver", "org.freedesktop.ScreenSaver"] {
+                subscribe_lock_signal(&ctx, &conn, interface, "ActiveChanged");
+            }
+        });
+    }
+    // Suspend/hibernate via logind on the system bus. A delay inhibitor holds
+    // off the suspend until the lock has saved and wiped the editor.
+    {
+        let ctx = ctx.clone();
+        gtk::gio::bus_get(gtk::gio::BusType::System, gtk::gio::Cancellable::NONE, move |conn| {
+            let Ok(conn) = conn else { return };
+            if ctx.locked.get() {
+                return; // Locked before the bus connection was ready
+            }
+            take_sleep_inhibitor(&ctx, &conn);
+            let ctx_signal = ctx.clone();
+            let id = conn.signal_subscribe(
+                Some("org.freedesktop.login1"),
+                Some("org.freedesktop.login1.Manager"),
+                Some("PrepareForSleep"),
+                Some("/org/freedesktop/login1"),
+                None,
+                gtk::gio::DBusSignalFlags::NONE,
+                move |conn, _, _, _, _, params| {
+                    let suspending = params.get::<(bool,)>().is_some_and(|(start,)| start);
+                    if !suspending {
+                        // Resumed without locking; hold off the next suspend too
+                        take_sleep_inhibitor(&ctx_signal, conn);
+                    } else if ctx_signal.lock_on_session_lock.get() {
+                        lock_vault(&ctx_signal); // finish_lock releases the inhibitor
+                    } else {
+                        ctx_signal.sleep_inhibitor.borrow_mut().take();
+                    }
+                },
+            );
+            ctx.dbus_subscriptions.borrow_mut().push((conn, id));
+        });
+    }
+}
 
-    let folder_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let folder_label = gtk::Label::new(Some("No folder selected"));
-    folder_label.set_hexpand(true);
-    folder_label.set_xalign(0.0);
-    folder_label.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
-    let folder_btn = gtk::Button::with_label("Choose Folder...");
-    folder_btn.add_css_class("toolbar-pill");
-    folder_row.append(&folder_label);
-    folder_row.append(&folder_btn);
-    vbox.append(&folder_row);
+/// Ask logind to delay suspend until the inhibitor is released. Without one
+/// (no logind, or it refused) the lock still starts on suspend, but may not
+/// finish before the machine sleeps.
+fn take_sleep_inhibitor(ctx: &EditorCtx, conn: &gtk::gio::DBusConnection) {
+    if ctx.locked.get() || ctx.sleep_inhibitor.borrow().is_some() {
+        return;
+    }
+    let ctx = ctx.clone();
+    conn.call_with_unix_fd_list(
+        Some("org.freedesktop.login1"),
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+        "Inhibit",
+        Some(&("sleep", "MDNotebook", "Lock the vault before suspending", "delay").to_variant()),
+        Some(glib::VariantTy::new("(h)").expect("valid type string")),
+        gtk::gio::DBusCallFlags::NONE,
+        -1,
+        gtk::gio::UnixFDList::NONE,
+        gtk::gio::Cancellable::NONE,
+        move |result| match result {
+            // The reply's fd list owns the inhibitor fd; a lock in the
+            // meantime drops it straight away
+            Ok((_, Some(fds))) if !ctx.locked.get() => {
+                ctx.sleep_inhibitor.replace(Some(fds));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Could not delay suspend for locking: {e}"),
+        },
+    );
+}
 
-    let folder_path: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+/// Lock when `interface.member` fires with a `(true,)` argument.
+fn subscribe_lock_signal(
+    ctx: &EditorCtx,
+    conn: &gtk::gio::DBusConnection,
+    interface: &str,
+    member: &str,
+) {
+    if ctx.locked.get() {
+        return; // Locked before the bus connection was ready
+    }
+    let ctx_signal = ctx.clone();
+    let id = conn.signal_subscribe(
+        None,
+        Some(interface),
+        Some(member),
+        None,
+        None,
+        gtk::gio::DBusSignalFlags::NONE,
+        move |_, _, _, _, _, params| {
+            let active = params.get::<(bool,)>().is_some_and(|(active,)| active);
+            if active && ctx_signal.lock_on_session_lock.get() {
+                lock_vault(&ctx_signal);
+            }
+        },
+    );
+    ctx.dbus_subscriptions.borrow_mut().push((conn.clone(), id));
+}
 
-    let pass1 = gtk::PasswordEntry::builder()
-        .placeholder_text("Set passphrase")
-        .show_peek_icon(true)
-        .build();
-    vbox.append(&pass1);
+/// Save, then wipe the key and notes and return to the unlock dialog.
+/// Uses the same save queue as close so an in-flight save finishes first.
+pub fn lock_vault(ctx: &EditorCtx) {
+    if ctx.locked.get() || ctx.lock_requested.get() {
+        return;
+    }
+    ctx.lock_requested.set(true);
+    if let Some(source_id) = ctx.save_timeout_id.take() {
+        source_id.remove();
+    }
+    if ctx.saving.get() {
+        // The in-flight save (or passphrase change) re-saves and then locks
+        ctx.save_generation.set(ctx.save_generation.get() + 1);
+        return;
+    }
+    perform_vault_save_async(ctx, false);
+}
 
-    let pass2 = gtk::PasswordEntry::builder()
-        .placeholder_text("Confirm passphrase")
-        .show_peek_icon(true)
-        .build();
-    vbox.append(&pass2);
+/// Tear down the editor after the final save. Everything that could keep
+/// plaintext alive is cleared; the editor widgets are dropped with the content.
+/// The window then shows what `after_lock` asks for.
+pub fn finish_lock(ctx: &EditorCtx) {
+    close_editor(ctx);
+    ctx.writer_lock.borrow_mut().take();
+    ctx.sleep_inhibitor.borrow_mut().take(); // Let a pending suspend go ahead
+    match ctx.after_lock.take() {
+        AfterLock::Locked => {
+            let vault_folder = ctx.vault_folder.borrow().clone();
+            show_locked_page(&ctx.window, vault_folder);
+        }
+        AfterLock::Picker => show_vault_picker(&ctx.window),
+        AfterLock::Unlock(vault_folder) => {
+            show_vault_picker(&ctx.window);
+            unlock_vault(&ctx.window, vault_folder);
+        }
+    }
+}
 
-    let error_label = gtk::Label::new(None);
+/// Detach the editor from its window and wipe its contents. Returns the key so
+/// a caller that rebuilds the editor (restoring a backup) can keep using it.
+fn close_editor(ctx: &EditorCtx) -> Option<crypto::CachedKey> {
+    ctx.locked.set(true);
+    for cell in [&ctx.save_timeout_id, &ctx.sync_timeout_id, &ctx.search_timeout_id, &ctx.secret_reveal_timeout_id] {
+        if let Some(source_id) = cell.take() {
+            source_id.remove();
+        }
+    }
+
+    // Detach everything hooked onto the window itself
+    for (conn, id) in ctx.dbus_subscriptions.borrow_mut().drain(..) {
+        conn.signal_unsubscribe(id);
+    }
+    for handler in ctx.window_handlers.borrow_mut().drain(..) {
+        ctx.window.disconnect(handler);
+    }
+    for controller in ctx.window_controllers.borrow_mut().drain(..) {
+        ctx.window.remove_controller(&controller);
+    }
+    for name in ctx.window.list_actions() {
+        ctx.window.remove_action(&name);
+    }
+    clear_copied_content(ctx, true);
+
+    // Close dialogs that may be showing note content
+    while let Some(dialog) = ctx.window.visible_dialog() {
+        dialog.force_close();
+    }
+    let parent = ctx.window.clone().upcast::<gtk::Window>();
+    for toplevel in gtk::Window::list_toplevels() {
+        if let Ok(win) = toplevel.downcast::<gtk::Window>() {
+            if win.transient_for().as_ref() == Some(&parent) {
+                win.destroy();
+            }
+        }
+    }
+
+    let key = ctx.cached_key.borrow_mut().take(); // CachedKey zeroizes on drop
+    ctx.journal.borrow_mut().take(); // Waits for pending journal writes
+    ctx.state.borrow_mut().wipe_contents();
+
+    // Widgets still hold copies of the text; clear them before dropping
+    ctx.rich_buffer.set_text("");
+    ctx.markdown_buffer.set_text("");
+    while let Some(child) = ctx.notes_list.first_child() {
+        ctx.notes_list.remove(&child);
+    }
+    while let Some(child) = ctx.tags_box.first_child() {
+        ctx.tags_box.remove(&child);
+    }
+    while ctx.tab_view.n_pages() > 0 {
+        ctx.tab_view.close_page(&ctx.tab_view.nth_page(0));
+    }
+    ctx.search_entry.set_text("");
+    key
+}
+
+fn show_locked_page(window: &adw::ApplicationWindow, vault_folder: String) {
+    let page = adw::StatusPage::builder()
+        .icon_name("system-lock-screen-symbolic")
+        .title("Vault Locked")
+        .description("Your notes have been saved and removed from memory.")
+        .build();
+    let unlock_btn = gtk::Button::with_label("Unlock\u{2026}");
+    unlock_btn.add_css_class("suggested-action");
+    unlock_btn.add_css_class("pill");
+    unlock_btn.set_halign(gtk::Align::Center);
+    page.set_child(Some(&unlock_btn));
+
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+    toolbar_view.set_content(Some(&page));
+    window.set_content(Some(&toolbar_view));
+
+    {
+        let window = window.clone();
+        let vault_folder = vault_folder.clone();
+        unlock_btn.connect_clicked(move |_| show_unlock_vault_dialog(&window, vault_folder.clone()));
+    }
+    show_unlock_vault_dialog(window, vault_folder);
+}
+
+fn show_lock_save_failed_dialog(ctx: &EditorCtx, error: &str) {
+    let dialog = adw::AlertDialog::new(
+        Some("Save Failed"),
+        Some(&format!(
+            "Your changes could not be saved before locking:\n\n{error}\n\nLock anyway and lose unsaved changes?"
+        )),
+    );
+    dialog.add_response("cancel", "Keep Unlocked");
+    dialog.add_response("lock", "Lock Without Saving");
+    dialog.set_response_appearance("lock", adw::ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+
+    let ctx_for_response = ctx.clone();
+    dialog.connect_response(None, move |_, response| {
+        if response == "lock" {
+            finish_lock(&ctx_for_response);
+        } else {
+            ctx_for_response.after_lock.take(); // Stay here; don't close or switch either
+            ctx_for_response.last_activity.set(std::time::Instant::now());
+        }
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+// ---------------------------------------------------------------------------
+// Clipboard
+// ---------------------------------------------------------------------------
+
+/// MIME type clipboard managers (Klipper, GPaste, KeePassXC's convention)
+/// check before keeping an entry; "secret" asks them not to.
+const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+/// How long sensitive copies stay when no timeout is configured.
+const SENSITIVE_CLEAR_SECS: u32 = 30;
+
+/// Clipboard content holding `text`, marked so clipboard managers skip it.
+fn sensitive_content(text: &str) -> gdk::ContentProvider {
+    gdk::ContentProvider::new_union(&[
+        gdk::ContentProvider::for_value(&text.to_value()),
+        gdk::ContentProvider::for_bytes(PASSWORD_MANAGER_HINT, &glib::Bytes::from_static(b"secret")),
+    ])
+}
+
+/// Empty `clipboard` after `secs` seconds if it still holds `provider`.
+fn clear_clipboard_after(clipboard: &gdk::Clipboard, provider: gdk::ContentProvider, secs: u32) -> glib::SourceId {
+    let clipboard = clipboard.clone();
+    glib::timeout_add_seconds_local_once(secs, move || {
+        if clipboard.content().as_ref() == Some(&provider) {
+            let _ = clipboard.set_content(None::<&gdk::ContentProvider>);
+        }
+    })
+}
+
+/// After `view` copied or cut its selection, keep track of what is now on
+/// the clipboard.
+fn track_view_copy(ctx: &EditorCtx, view: &gtk::TextView) {
+    if !view.buffer().has_selection() {
+        return;
+    }
+    // The view's own handler sets the clipboard after this one
+    let ctx = ctx.clone();
+    let clipboard = view.clipboard();
+    glib::idle_add_local_once(move || track_copied_content(&ctx, &clipboard, false));
+}
+
+/// Remember the content this editor just put on `clipboard` and arm its
+/// clearing: after the configured timeout and on lock, as set in
+/// Preferences. Sensitive copies are always cleared.
+fn track_copied_content(ctx: &EditorCtx, clipboard: &gdk::Clipboard, sensitive: bool) {
+    // Content only exists here if this process owns the clipboard
+    let Some(provider) = clipboard.content() else { return };
+    if let Some(previous) = ctx.copied_content.borrow_mut().take() {
+        if let Some(source_id) = previous.clear_timeout {
+            source_id.remove();
+        }
+    }
+    let secs = match ctx.clear_clipboard_secs.get() {
+        0 if sensitive => SENSITIVE_CLEAR_SECS,
+        secs => secs,
+    };
+    if secs == 0 && !ctx.clear_clipboard_on_lock.get() {
+        return;
+    }
+    let clear_timeout = (secs > 0).then(|| {
+        let ctx = ctx.clone();
+        glib::timeout_add_seconds_local_once(secs, move || {
+            // The source is done; don't remove it again below
+            if let Some(copied) = ctx.copied_content.borrow_mut().as_mut() {
+                copied.clear_timeout.take();
+            }
+            clear_copied_content(&ctx, false);
+        })
+    });
+    *ctx.copied_content.borrow_mut() = Some(CopiedContent { provider, sensitive, clear_timeout });
+}
+
+/// Empty the clipboard if it still holds what this editor copied. On lock
+/// that depends on the setting, and the text selection (the middle-click
+/// clipboard) is cleared as well if it came from here.
+fn clear_copied_content(ctx: &EditorCtx, locking: bool) {
+    let clear_on_lock = locking && ctx.clear_clipboard_on_lock.get();
+    let Some(copied) = ctx.copied_content.borrow_mut().take() else {
+        if clear_on_lock {
+            clear_local_primary(ctx);
+        }
+        return;
+    };
+    if let Some(source_id) = copied.clear_timeout {
+        source_id.remove();
+    }
+    if locking && !clear_on_lock && !copied.sensitive {
+        return;
+    }
+    let clipboard = ctx.window.clipboard();
+    if clipboard.content().as_ref() == Some(&copied.provider) {
+        let _ = clipboard.set_content(None::<&gdk::ContentProvider>);
+    }
+    if clear_on_lock {
+        clear_local_primary(ctx);
+    }
+}
+
+fn clear_local_primary(ctx: &EditorCtx) {
+    let primary = ctx.window.primary_clipboard();
+    if primary.is_local() {
+        let _ = primary.set_content(None::<&gdk::ContentProvider>);
+    }
+}
+
+/// Copy the selection of the note view in use, marked so clipboard managers
+/// don't keep it, and clear it again soon.
+fn copy_selection_as_sensitive(ctx: &EditorCtx) {
+    let source = ctx.markdown_view.has_focus() || ctx.state.borrow().source_mode;
+    let buffer: gtk::TextBuffer = if source {
+        ctx.markdown_buffer.clone().upcast()
+    } else {
+        ctx.rich_buffer.clone()
+    };
+    let Some((start, end)) = buffer.selection_bounds() else {
+        send_toast(ctx, "Select the text to copy first");
+        return;
+    };
+    let text = buffer.text(&start, &end, false);
+    let clipboard = ctx.window.clipboard();
+    if clipboard.set_content(Some(&sensitive_content(&text))).is_err() {
+        send_toast(ctx, "Could not copy");
+        return;
+    }
+    track_copied_content(ctx, &clipboard, true);
+    let secs = match ctx.clear_clipboard_secs.get() {
+        0 => SENSITIVE_CLEAR_SECS,
+        secs => secs,
+    };
+    send_toast(ctx, &format!("Copied as sensitive \u{2014} cleared in {secs} seconds"));
+}
+
+// ---------------------------------------------------------------------------
+// Preferences
+// ---------------------------------------------------------------------------
+
+pub fn show_preferences_dialog(ctx: &EditorCtx) {
+    let config = vault::load_config();
+
+    let dialog = adw::PreferencesDialog::new();
+    dialog.set_title("Preferences");
+
+    let page = adw::PreferencesPage::new();
+    page.set_title("Security");
+    page.set_icon_name(Some("security-high-symbolic"));
+
+    let lock_group = adw::PreferencesGroup::new();
+    lock_group.set_title("Auto-Lock");
+    lock_group.set_description(Some(
+        "Locking saves your notes, removes them and the key from memory, and asks for the passphrase again.",
+    ));
+
+    let idle_row = adw::SpinRow::with_range(0.0, 240.0, 1.0);
+    idle_row.set_title("Lock after inactivity");
+    idle_row.set_subtitle("Minutes without input (0 = never)");
+    idle_row.set_value(f64::from(config.auto_lock_minutes));
+    {
+        let ctx = ctx.clone();
+        idle_row.connect_value_notify(move |row| {
+            let minutes = row.value().round() as u32;
+            vault::update_config(|config| config.auto_lock_minutes = minutes);
+            ctx.auto_lock_minutes.set(minutes);
+        });
+    }
+    lock_group.add(&idle_row);
+
+    let session_row = adw::SwitchRow::builder()
+        .title("Lock with the session")
+        .subtitle("Lock when the screen locks or the computer suspends")
+        .active(config.lock_on_session_lock)
+        .build();
+    {
+        let ctx = ctx.clone();
+        session_row.connect_active_notify(move |row| {
+            vault::update_config(|config| config.lock_on_session_lock = row.is_active());
+            ctx.lock_on_session_lock.set(row.is_active());
+        });
+    }
+    lock_group.add(&session_row);
+
+    let files_group = adw::PreferencesGroup::new();
+    files_group.set_title("Vault Files");
+
+    let strict_row = adw::SwitchRow::builder()
+        .title("Refuse unencrypted files")
+        .subtitle("Never open a vault or image that is stored without encryption")
+        .active(config.strict_encryption)
+        .build();
+    strict_row.connect_active_notify(|row| {
+        vault::update_config(|config| config.strict_encryption = row.is_active());
+    });
+    files_group.add(&strict_row);
+
+    let collect_row = adw::SwitchRow::builder()
+        .title("Remove unused images after emptying trash")
+        .subtitle(format!(
+            "Images nothing refers to any more are deleted after {} minutes",
+            integrity::ASSET_GC_GRACE_MS / 60_000
+        ))
+        .active(config.collect_assets_after_empty_trash)
+        .build();
+    collect_row.connect_active_notify(|row| {
+        vault::update_config(|config| config.collect_assets_after_empty_trash = row.is_active());
+    });
+    files_group.add(&collect_row);
+
+    let compress_row = adw::SwitchRow::builder()
+        .title("Compress notes and images")
+        .subtitle("Smaller vault files and faster saves; applies to files written from now on")
+        .active(config.compress_payloads)
+        .build();
+    {
+        let ctx = ctx.clone();
+        compress_row.connect_active_notify(move |row| {
+            vault::update_config(|config| config.compress_payloads = row.is_active());
+            if let Some(key) = ctx.cached_key.borrow_mut().as_mut() {
+                key.set_compression(row.is_active());
+            }
+        });
+    }
+    files_group.add(&compress_row);
+
+    let pad_row = adw::SwitchRow::builder()
+        .title("Hide exact file sizes")
+        .subtitle("Pad notes and images to size steps, adding up to about 12%; applies to files written from now on")
+        .active(config.pad_payloads)
+        .build();
+    {
+        let ctx = ctx.clone();
+        pad_row.connect_active_notify(move |row| {
+            vault::update_config(|config| config.pad_payloads = row.is_active());
+            if let Some(key) = ctx.cached_key.borrow_mut().as_mut() {
+                key.set_padding(row.is_active());
+            }
+        });
+    }
+    files_group.add(&pad_row);
+
+    let backups_group = adw::PreferencesGroup::new();
+    backups_group.set_title("Backups");
+    backups_group.set_description(Some(
+        "The vault is backed up before the first save of every hour. Older backups are thinned to the newest one per hour, day and week.",
+    ));
+    let retention = config.backup_retention;
+    for (title, subtitle, value, max, set) in [
+        ("Hourly backups", "Hours to keep one backup for", retention.hourly, 168.0, (|r, v| r.hourly = v) as fn(&mut backup::Retention, usize)),
+        ("Daily backups", "Days to keep one backup for", retention.daily, 90.0, |r, v| r.daily = v),
+        ("Weekly backups", "Weeks to keep one backup for", retention.weekly, 104.0, |r, v| r.weekly = v),
+    ] {
+        let row = adw::SpinRow::with_range(0.0, max, 1.0);
+        row.set_title(title);
+        row.set_subtitle(subtitle);
+        row.set_value(value as f64);
+        row.connect_value_notify(move |row| {
+            vault::update_config(|config| set(&mut config.backup_retention, row.value().round() as usize));
+        });
+        backups_group.add(&row);
+    }
+
+    let clipboard_group = adw::PreferencesGroup::new();
+    clipboard_group.set_title("Clipboard");
+    clipboard_group.set_description(Some(
+        "Only text copied from your notes is cleared, and only if nothing else was copied since.",
+    ));
+
+    let clear_row = adw::SpinRow::with_range(0.0, 600.0, 5.0);
+    clear_row.set_title("Clear copied text");
+    clear_row.set_subtitle("Seconds after copying (0 = never)");
+    clear_row.set_value(f64::from(config.clear_clipboard_secs));
+    {
+        let ctx = ctx.clone();
+        clear_row.connect_value_notify(move |row| {
+            let secs = row.value().round() as u32;
+            vault::update_config(|config| config.clear_clipboard_secs = secs);
+            ctx.clear_clipboard_secs.set(secs);
+        });
+    }
+    clipboard_group.add(&clear_row);
+
+    let clear_on_lock_row = adw::SwitchRow::builder()
+        .title("Clear when locking")
+        .subtitle("Remove copied text from the clipboard when the vault locks or closes")
+        .active(config.clear_clipboard_on_lock)
+        .build();
+    {
+        let ctx = ctx.clone();
+        clear_on_lock_row.connect_active_notify(move |row| {
+            vault::update_config(|config| config.clear_clipboard_on_lock = row.is_active());
+            ctx.clear_clipboard_on_lock.set(row.is_active());
+        });
+    }
+    clipboard_group.add(&clear_on_lock_row);
+
+    page.add(&lock_group);
+    page.add(&clipboard_group);
+    page.add(&files_group);
+    page.add(&backups_group);
+    dialog.add(&page);
+    dialog.present(Some(&ctx.window));
+}
+
+// ---------------------------------------------------------------------------
+// Single-writer lock
+// ---------------------------------------------------------------------------
+
+/// Take the vault's writer lock (see `writer_lock.rs`) and open the editor.
+/// If another instance holds it, show who and offer read-only mode, or a
+/// takeover when the lock looks stale. `on_open` runs once the editor exists.
+fn open_vault_editor(
+    window: &adw::ApplicationWindow,
+    state: DocState,
+    vault_folder: String,
+    key: crypto::CachedKey,
+    note_files: NoteFiles,
+    read_only: bool,
+    on_open: impl FnOnce(&EditorCtx) + 'static,
+) {
+    vault::update_config(|config| config.remember_vault(&vault_folder, unix_now()));
+
+    if read_only {
+        // Asked for: no lock file, nothing written to the vault folder
+        let ctx = build_editor(window, state, vault_folder, key, None, note_files);
+        on_open(&ctx);
+        return;
+    }
+
+    let holder = match writer_lock::WriterLock::acquire(&vault_folder, false) {
+        Ok(lock) => {
+            let ctx = build_locked_editor(window, state, vault_folder, key, lock, note_files);
+            on_open(&ctx);
+            return;
+        }
+        Err(writer_lock::LockError::Held(holder)) => holder,
+        Err(e) => {
+            eprintln!("Could not lock the vault folder: {e}");
+            let ctx = build_editor(window, state, vault_folder, key, None, note_files);
+            show_error(
+                &ctx.window,
+                "Opened Read-Only",
+                &format!("The vault folder could not be claimed for writing, so nothing will be saved in this session.\n\n{e}"),
+            );
+            on_open(&ctx);
+            return;
+        }
+    };
+
+    let place = if holder.is_this_host() {
+        format!("on this computer (process {})", holder.pid)
+    } else {
+        format!("on \u{201c}{}\u{201d}", holder.host)
+    };
+    let stale = holder.is_stale();
+    let mut body = format!(
+        "This vault was opened {place} at {}, last active at {}.",
+        format_ts(holder.since),
+        format_ts(holder.heartbeat)
+    );
+    body.push_str(if stale {
+        "\n\nThat instance seems to have stopped without closing the vault. Take over only if it is no longer running."
+    } else {
+        "\n\nSaving from two places would overwrite each other\u{2019}s changes. Close it there, or open the vault read-only here."
+    });
+
+    let dialog = adw::AlertDialog::new(Some("Vault Already Open"), Some(&body));
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("read-only", "Open Read-Only");
+    if stale {
+        dialog.add_response("take-over", "Take Over");
+        dialog.set_response_appearance("take-over", adw::ResponseAppearance::Destructive);
+    }
+    dialog.set_default_response(Some("read-only"));
+    dialog.set_close_response("cancel");
+
+    let pending = RefCell::new(Some((state, key, note_files, on_open)));
+    let window_for_present = window.clone();
+    let window = window.clone();
+    dialog.connect_response(None, move |_, response| {
+        let Some((mut state, key, note_files, on_open)) = pending.borrow_mut().take() else {
+            return;
+        };
+        let lock = match response {
+            "read-only" => None,
+            "take-over" => match writer_lock::WriterLock::acquire(&vault_folder, true) {
+                Ok(lock) => {
+                    let ctx = build_locked_editor(&window, state, vault_folder.clone(), key, lock, note_files);
+                    on_open(&ctx);
+                    return;
+                }
+                Err(e) => {
+                    state.wipe_contents();
+                    show_locked_page(&window, vault_folder.clone());
+                    show_error(&window, "Take Over Failed", &e.to_string());
+                    return;
+                }
+            },
+            _ => {
+                state.wipe_contents();
+                show_locked_page(&window, vault_folder.clone());
+                return;
+            }
+        };
+        let ctx = build_editor(&window, state, vault_folder.clone(), key, lock, note_files);
+        on_open(&ctx);
+    });
+    dialog.present(Some(&window_for_present));
+}
+
+/// Build the editor with the writer lock held, first finishing or discarding
+/// a re-key that was interrupted; the unlock read the vault as that leaves
+/// it. If that fails the lock is let go and nothing is saved this session.
+fn build_locked_editor(
+    window: &adw::ApplicationWindow,
+    state: DocState,
+    vault_folder: String,
+    key: crypto::CachedKey,
+    lock: writer_lock::WriterLock,
+    note_files: NoteFiles,
+) -> EditorCtx {
+    let Err(e) = vault::recover_interrupted_rekey(&vault_folder) else {
+        return build_editor(window, state, vault_folder, key, Some(lock), note_files);
+    };
+    drop(lock);
+    let ctx = build_editor(window, state, vault_folder, key, None, note_files);
+    show_error(
+        &ctx.window,
+        "Opened Read-Only",
+        &format!("An interrupted passphrase change could not be finished, so nothing will be saved in this session.\n\n{e}"),
+    );
+    ctx
+}
+
+/// Bring the key of a vault unlocked with an old format or KDF up to date,
+/// once the editor holds the writer lock. On failure the old key stays in
+/// use. `then` runs afterwards either way.
+fn upgrade_vault_key(
+    ctx: &EditorCtx,
+    passphrase: zeroize::Zeroizing<String>,
+    keyfile: Option<crypto::Keyfile>,
+    then: impl FnOnce(&EditorCtx) + 'static,
+) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return then(ctx);
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let expected = ctx.vault_file.borrow().clone();
+    ctx.saving.set(true);
+    let gen = ctx.save_generation.get();
+
+    let policy = vault::load_config().plaintext_policy();
+
+    let (tx, rx) = std::sync::mpsc::channel::<Result<(crypto::CachedKey, vault::VaultWrite), String>>();
+    std::thread::spawn(move || {
+        let result =
+            vault::set_vault_passphrase(&vault_folder, &key, &passphrase, keyfile.as_ref(), expected.as_ref(), policy)
+                .map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+
+    let ctx = ctx.clone();
+    let mut then = Some(then);
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => Err("Upgrade thread disconnected".to_string()),
+        };
+        ctx.saving.set(false);
+        match result {
+            Ok((mut key, vault::VaultWrite::Written(fingerprint))) => {
+                let config = vault::load_config();
+                key.set_compression(config.compress_payloads);
+                key.set_padding(config.pad_payloads);
+                *ctx.cached_key.borrow_mut() = Some(key);
+                *ctx.vault_file.borrow_mut() = Some(fingerprint);
+            }
+            // Another device saved meanwhile; the next unlock tries again
+            Ok((_, vault::VaultWrite::Changed { .. })) => {}
+            Err(e) => eprintln!("Warning: could not upgrade the vault key: {e}"),
+        }
+        if ctx.save_generation.get() > gen {
+            perform_vault_save_async(&ctx, false);
+        }
+        if let Some(then) = then.take() {
+            then(&ctx);
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// Whether this instance may still write the vault. A takeover by another
+/// instance switches the editor to read-only.
+fn check_writer_lock(ctx: &EditorCtx) -> bool {
+    if ctx.read_only.get() {
+        return false;
+    }
+    let held = ctx.writer_lock.borrow().as_ref().is_some_and(|lock| lock.still_held());
+    if !held {
+        set_read_only(ctx);
+    }
+    held
+}
+
+/// Stop writing to the vault folder: no saves, no journal, no editing.
+fn set_read_only(ctx: &EditorCtx) {
+    ctx.read_only.set(true);
+    ctx.writer_lock.borrow_mut().take();
+    ctx.journal.borrow_mut().take(); // Entries already written stay for the next writer to recover
+    if let Some(source_id) = ctx.save_timeout_id.take() {
+        source_id.remove();
+    }
+    disable_editing(ctx);
+}
+
+/// Actions that change the vault, disabled while it is read-only. Search,
+/// navigation, copying and export stay available.
+const EDIT_ACTIONS: &[&str] = &[
+    "new-note", "new-folder", "new-from-template", "import-file", "rename-note",
+    "delete-note", "move-to-folder", "save-snapshot", "empty-trash", "save-vault", "undo", "redo",
+    "fmt-bold", "fmt-italic", "fmt-underline", "fmt-strike", "fmt-code", "fmt-secret", "fmt-link",
+    "fmt-h1", "fmt-h2", "fmt-h3", "fmt-h4", "fmt-h5", "fmt-h6", "fmt-quote",
+    "fmt-bullet-list", "fmt-ordered-list", "fmt-task-list", "toggle-checkbox",
+    "table-add-row", "table-add-col", "change-passphrase", "new-recovery-code",
+    "encrypt-plaintext", "collect-assets", "restore-backup", "import-shared-note",
+];
+
+/// Make the editor view-only for a read-only session and say so.
+pub fn disable_editing(ctx: &EditorCtx) {
+    use gtk::gio::prelude::ActionMapExt;
+    if let Some(view) = ctx.rich_panel.child().and_downcast::<gtk::TextView>() {
+        view.set_editable(false);
+    }
+    ctx.markdown_view.set_editable(false);
+    for widget in &ctx.edit_controls {
+        widget.set_sensitive(false);
+    }
+    for name in EDIT_ACTIONS {
+        if let Some(action) = ctx.window.lookup_action(name).and_downcast::<gtk::gio::SimpleAction>() {
+            action.set_enabled(false);
+        }
+    }
+    ctx.read_only_banner.set_revealed(true);
+    refresh_header(ctx);
+}
+
+/// In a read-only session, tell the user the change can't be made and return
+/// true. For actions reachable other than through `EDIT_ACTIONS`.
+fn refuse_in_read_only(ctx: &EditorCtx) -> bool {
+    if ctx.read_only.get() {
+        send_toast(ctx, "Read-only \u{2014} this vault can\u{2019}t be changed");
+    }
+    ctx.read_only.get()
+}
+
+// ---------------------------------------------------------------------------
+// Vault startup dialogs
+// ---------------------------------------------------------------------------
+
+/// A "keyfile" picker row for the vault dialogs. Only the keyfile's digest is
+/// kept; with `allow_generate` the user can also create a new random keyfile.
+fn build_keyfile_row(
+    parent: &adw::Window,
+    allow_generate: bool,
+) -> (gtk::Box, Rc<RefCell<Option<crypto::Keyfile>>>) {
+    let keyfile: Rc<RefCell<Option<crypto::Keyfile>>> = Rc::new(RefCell::new(None));
+
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let label = gtk::Label::new(Some("No keyfile"));
+    label.add_css_class("dim-label");
+    label.set_hexpand(true);
+    label.set_xalign(0.0);
+    label.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+    row.append(&label);
+
+    let clear_btn = gtk::Button::from_icon_name("edit-clear-symbolic");
+    clear_btn.add_css_class("flat");
+    clear_btn.set_tooltip_text(Some("Don't use a keyfile"));
+    clear_btn.set_visible(false);
+    row.append(&clear_btn);
+
+    let choose_btn = gtk::Button::with_label("Keyfile\u{2026}");
+    choose_btn.add_css_class("toolbar-pill");
+    choose_btn.set_tooltip_text(Some("Use any file as a second unlock factor"));
+    row.append(&choose_btn);
+
+    let set_keyfile = {
+        let keyfile = keyfile.clone();
+        let label = label.clone();
+        let clear_btn = clear_btn.clone();
+        Rc::new(move |result: Result<(String, crypto::Keyfile), String>| match result {
+            Ok((name, digest)) => {
+                *keyfile.borrow_mut() = Some(digest);
+                label.set_label(&name);
+                label.remove_css_class("dim-label");
+                clear_btn.set_visible(true);
+            }
+            Err(e) => {
+                *keyfile.borrow_mut() = None;
+                label.set_label(&e);
+                label.add_css_class("dim-label");
+                clear_btn.set_visible(false);
+            }
+        })
+    };
+
+    {
+        let set_keyfile = set_keyfile.clone();
+        clear_btn.connect_clicked(move |_| set_keyfile(Err("No keyfile".to_string())));
+    }
+    {
+        let parent = parent.clone();
+        let set_keyfile = set_keyfile.clone();
+        choose_btn.connect_clicked(move |_| {
+            let chooser = gtk::FileDialog::builder().title("Select Keyfile").build();
+            let set_keyfile = set_keyfile.clone();
+            chooser.open(Some(&parent), gtk::gio::Cancellable::NONE, move |result| {
+                let Some(path) = result.ok().and_then(|f| f.path()) else { return };
+                let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                set_keyfile(
+                    fs::File::open(&path)
+                        .and_then(crypto::Keyfile::from_reader)
+                        .map(|digest| (name, digest))
+                        .map_err(|e| format!("Could not read keyfile: {e}")),
+                );
+            });
+        });
+    }
+
+    if allow_generate {
+        let generate_btn = gtk::Button::with_label("Generate\u{2026}");
+        generate_btn.add_css_class("toolbar-pill");
+        generate_btn.set_tooltip_text(Some("Create a new random keyfile"));
+        row.append(&generate_btn);
+
+        let parent = parent.clone();
+        generate_btn.connect_clicked(move |_| {
+            let chooser = gtk::FileDialog::builder()
+                .title("Save New Keyfile")
+                .initial_name("mdnotebook.key")
+                .build();
+            let set_keyfile = set_keyfile.clone();
+            chooser.save(Some(&parent), gtk::gio::Cancellable::NONE, move |result| {
+                let Some(path) = result.ok().and_then(|f| f.path()) else { return };
+                let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                let contents = crypto::Keyfile::generate_contents();
+                let written = {
+                    use std::io::Write;
+                    use std::os::unix::fs::OpenOptionsExt;
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .mode(0o600)
+                        .open(&path)
+                        .and_then(|mut f| f.write_all(&contents).and_then(|_| f.sync_all()))
+                };
+                set_keyfile(
+                    written
+                        .and_then(|_| crypto::Keyfile::from_reader(&contents[..]))
+                        .map(|digest| (name, digest))
+                        .map_err(|e| format!("Could not write keyfile: {e}")),
+                );
+            });
+        });
+    }
+
+    (row, keyfile)
+}
+
+/// A live strength meter for `entry`: a level bar, the rating with crack times
+/// at the KDF new vaults use, and what makes the passphrase guessable.
+/// `user_inputs` gives names an attacker would try, such as the vault's.
+fn build_strength_meter(entry: &gtk::PasswordEntry, user_inputs: impl Fn() -> Vec<String> + 'static) -> gtk::Box {
+    let meter = gtk::Box::new(gtk::Orientation::Vertical, 4);
+
+    let bar = gtk::LevelBar::for_interval(0.0, 5.0);
+    bar.set_mode(gtk::LevelBarMode::Discrete);
+    bar.add_offset_value(gtk::LEVEL_BAR_OFFSET_LOW, 2.0);
+    bar.add_offset_value(gtk::LEVEL_BAR_OFFSET_HIGH, 4.0);
+    bar.add_offset_value(gtk::LEVEL_BAR_OFFSET_FULL, 5.0);
+    meter.append(&bar);
+
+    let summary = gtk::Label::new(None);
+    summary.add_css_class("caption");
+    summary.add_css_class("dim-label");
+    summary.set_wrap(true);
+    summary.set_xalign(0.0);
+    meter.append(&summary);
+
+    let warning = gtk::Label::new(None);
+    warning.add_css_class("caption");
+    warning.add_css_class("warning");
+    warning.set_wrap(true);
+    warning.set_xalign(0.0);
+    warning.set_visible(false);
+    meter.append(&warning);
+
+    entry.connect_changed(move |entry| {
+        let text = entry.text();
+        if text.is_empty() {
+            bar.set_value(0.0);
+            summary.set_label("");
+            warning.set_visible(false);
+            return;
+        }
+        let inputs = user_inputs();
+        let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+        let estimate = strength::estimate(&text, &inputs);
+        let kdf = crypto::Kdf::RECOMMENDED;
+        let rating = estimate.strength(kdf);
+        bar.set_value(f64::from(rating.score()) + 1.0);
+        let times: Vec<String> = strength::ATTACKERS
+            .iter()
+            .map(|(name, devices)| {
+                format!("{} with {name}", strength::format_duration(estimate.crack_seconds(kdf, *devices)))
+            })
+            .collect();
+        summary.set_label(&format!("{} \u{2014} cracked in about {}", rating.label(), times.join(", ")));
+        let hint = estimate.warning().filter(|_| rating <= strength::Strength::Fair);
+        warning.set_label(hint.unwrap_or_default());
+        warning.set_visible(hint.is_some());
+    });
+    meter
+}
+
+/// Ask before creating a vault with a passphrase rated weak; `proceed` runs
+/// if the user keeps it.
+fn confirm_weak_passphrase(parent: &adw::Window, estimate: &strength::Estimate, proceed: impl Fn() + 'static) {
+    let (attacker, devices) = strength::ATTACKERS[strength::ATTACKERS.len() - 1];
+    let time = strength::format_duration(estimate.crack_seconds(crypto::Kdf::RECOMMENDED, devices));
+    let mut body = String::new();
+    if let Some(warning) = estimate.warning() {
+        body.push_str(warning);
+        body.push(' ');
+    }
+    body.push_str(&format!(
+        "Someone who gets hold of the vault files could find this passphrase in about {time} with {attacker}.\n\n\
+         \u{201c}Generate\u{201d} picks a passphrase of random words that is both strong and easy to type."
+    ));
+    let dialog = adw::AlertDialog::new(Some("Weak Passphrase"), Some(&body));
+    dialog.add_response("cancel", "Choose Another");
+    dialog.add_response("use", "Use Anyway");
+    dialog.set_response_appearance("use", adw::ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+    dialog.connect_response(None, move |_, response| {
+        if response == "use" {
+            proceed();
+        }
+    });
+    dialog.present(Some(parent));
+}
+
+// ---------------------------------------------------------------------------
+// Vault picker
+// ---------------------------------------------------------------------------
+
+/// The page shown while no vault is open in the window: the vaults this device
+/// knows, most recently opened first, and buttons to open or create another.
+pub fn show_vault_picker(window: &adw::ApplicationWindow) {
+    window.set_title(Some("MDNotebook"));
+    let config = vault::load_config();
+
+    let page = adw::StatusPage::builder()
+        .icon_name("com.mdnotebook.editor")
+        .title("MDNotebook")
+        .description(if config.vaults.is_empty() {
+            "Create a vault to get started, or open one you already have."
+        } else {
+            "Choose a vault to unlock."
+        })
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 18);
+    let list = gtk::ListBox::new();
+    list.add_css_class("boxed-list");
+    list.set_selection_mode(gtk::SelectionMode::None);
+    list.set_visible(!config.vaults.is_empty());
+    for known in &config.vaults {
+        list.append(&build_vault_row(window, known));
+    }
+    vbox.append(&list);
+
+    let btn_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    btn_row.set_halign(gtk::Align::Center);
+    let open_btn = gtk::Button::with_label("Open Vault\u{2026}");
+    open_btn.add_css_class("pill");
+    let create_btn = gtk::Button::with_label("Create Vault\u{2026}");
+    create_btn.add_css_class("pill");
+    if config.vaults.is_empty() {
+        create_btn.add_css_class("suggested-action");
+    }
+    btn_row.append(&open_btn);
+    btn_row.append(&create_btn);
+    vbox.append(&btn_row);
+
+    let clamp = adw::Clamp::builder().maximum_size(520).child(&vbox).build();
+    page.set_child(Some(&clamp));
+
+    let toolbar_view = adw::ToolbarView::new();
+    toolbar_view.add_top_bar(&adw::HeaderBar::new());
+    toolbar_view.set_content(Some(&page));
+    window.set_content(Some(&toolbar_view));
+
+    {
+        let window = window.clone();
+        open_btn.connect_clicked(move |_| {
+            let window_for_unlock = window.clone();
+            choose_vault_folder(&window, move |folder| unlock_vault(&window_for_unlock, folder));
+        });
+    }
+    {
+        let window = window.clone();
+        create_btn.connect_clicked(move |_| show_create_vault_dialog(&window));
+    }
+}
+
+fn build_vault_row(window: &adw::ApplicationWindow, known: &vault::KnownVault) -> adw::ActionRow {
+    let found = vault::vault_file_path(&known.path).exists();
+    let subtitle = if !found {
+        format!("Not found \u{2014} {}", known.path)
+    } else if known.last_opened > 0 {
+        format!("{} \u{2014} opened {}", known.path, format_ts(known.last_opened))
+    } else {
+        known.path.clone()
+    };
+    let row = adw::ActionRow::builder()
+        .title(glib::markup_escape_text(&known.name).as_str())
+        .subtitle(glib::markup_escape_text(&subtitle).as_str())
+        .activatable(found)
+        .build();
+    if !found {
+        row.add_css_class("dim-label");
+    }
+
+    let suffix_button = |icon_name: &str, tooltip: &str| {
+        let button = gtk::Button::from_icon_name(icon_name);
+        button.set_tooltip_text(Some(tooltip));
+        set_accessible_label(&button, tooltip);
+        button.add_css_class("flat");
+        button.set_valign(gtk::Align::Center);
+        button
+    };
+    let rename_btn = suffix_button("document-edit-symbolic", "Rename");
+    let window_btn = suffix_button("window-new-symbolic", "Open in New Window");
+    let forget_btn = suffix_button("list-remove-symbolic", "Remove from List");
+    window_btn.set_sensitive(found);
+    row.add_suffix(&rename_btn);
+    row.add_suffix(&window_btn);
+    row.add_suffix(&forget_btn);
+
+    {
+        let window = window.clone();
+        let path = known.path.clone();
+        row.connect_activated(move |_| {
+            if check_vault_available(&window, &path) {
+                unlock_vault(&window, path.clone());
+            }
+        });
+    }
+    {
+        let window = window.clone();
+        let path = known.path.clone();
+        window_btn.connect_clicked(move |_| {
+            if !check_vault_available(&window, &path) {
+                return;
+            }
+            if let Some(app) = window.application().and_downcast::<adw::Application>() {
+                new_vault_window(&app, Some(path.clone()));
+            }
+        });
+    }
+    {
+        let window = window.clone();
+        let known = known.clone();
+        rename_btn.connect_clicked(move |_| {
+            let dialog = adw::AlertDialog::new(Some("Rename Vault"), None);
+            let entry = gtk::Entry::builder().text(known.name.as_str()).activates_default(true).build();
+            dialog.set_extra_child(Some(&entry));
+            dialog.add_response("cancel", "Cancel");
+            dialog.add_response("rename", "Rename");
+            dialog.set_response_appearance("rename", adw::ResponseAppearance::Suggested);
+            dialog.set_default_response(Some("rename"));
+            dialog.set_close_response("cancel");
+            let window_for_response = window.clone();
+            let path = known.path.clone();
+            dialog.connect_response(None, move |_, response| {
+                if response != "rename" {
+                    return;
+                }
+                vault::update_config(|config| config.rename_vault(&path, &entry.text()));
+                show_vault_picker(&window_for_response);
+            });
+            dialog.present(Some(&window));
+        });
+    }
+    {
+        let window = window.clone();
+        let known = known.clone();
+        forget_btn.connect_clicked(move |_| {
+            let dialog = adw::AlertDialog::new(
+                Some(&format!("Remove \u{201c}{}\u{201d} from the List?", known.name)),
+                Some("The vault folder and its notes are left as they are. Use \u{201c}Open Vault\u{201d} to add it back."),
+            );
+            dialog.add_response("cancel", "Cancel");
+            dialog.add_response("forget", "Remove");
+            dialog.set_response_appearance("forget", adw::ResponseAppearance::Destructive);
+            dialog.set_close_response("cancel");
+            let window_for_response = window.clone();
+            let path = known.path.clone();
+            dialog.connect_response(None, move |_, response| {
+                if response != "forget" {
+                    return;
+                }
+                let mut config = vault::load_config();
+                if config.is_key_remembered(&path) {
+                    // An unlisted vault must not keep unlocking by itself
+                    let path = path.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = keyring::delete_secret(&path) {
+                            eprintln!("Failed to forget vault key: {e}");
+                        }
+                    });
+                }
+                config.forget_vault(&path);
+                if let Err(e) = vault::save_config(&config) {
+                    eprintln!("Failed to save config: {e}");
+                }
+                show_vault_picker(&window_for_response);
+            });
+            dialog.present(Some(&window));
+        });
+    }
+    row
+}
+
+/// Whether the vault in `vault_folder` can be unlocked in `window`: it exists
+/// and no other window of this app has it open. Tells the user if not.
+pub fn check_vault_available(window: &adw::ApplicationWindow, vault_folder: &str) -> bool {
+    let name = vault::load_config().vault_name(vault_folder);
+    if !vault::vault_file_path(vault_folder).exists() {
+        show_error(
+            window,
+            "Vault Not Found",
+            &format!("There is no vault in {vault_folder} any more. It may have been moved, or its drive is not mounted."),
+        );
+        return false;
+    }
+    if writer_lock::held_by_this_process(vault_folder) {
+        show_info(window, "Already Open", &format!("\u{201c}{name}\u{201d} is already open in another window."));
+        return false;
+    }
+    true
+}
+
+/// Let the user pick an existing vault folder, then call `on_chosen` with it.
+fn choose_vault_folder(window: &adw::ApplicationWindow, on_chosen: impl Fn(String) + 'static) {
+    let chooser = gtk::FileDialog::builder()
+        .title("Open Vault")
+        .accept_label("Open")
+        .build();
+    let window_for_result = window.clone();
+    chooser.select_folder(Some(window), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
+        let Some(path) = result.ok().and_then(|file| file.path()) else { return };
+        let folder = path.to_string_lossy().to_string();
+        if !vault::vault_file_path(&folder).exists() {
+            show_error(
+                &window_for_result,
+                "Not a Vault",
+                &format!("{folder} does not contain a vault. Use \u{201c}Create Vault\u{201d} to start a new one there."),
+            );
+            return;
+        }
+        if check_vault_available(&window_for_result, &folder) {
+            on_chosen(folder);
+        }
+    });
+}
+
+pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
+    let dialog = adw::Window::builder()
+        .transient_for(window)
+        .modal(true)
+        .title("Create Vault")
+        .default_width(460)
+        .default_height(420)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let title = gtk::Label::new(Some("Create a New Vault"));
+    title.add_css_class("title-2");
+    vbox.append(&title);
+
+    let subtitle = gtk::Label::new(Some(
+        "Choose a folder and set a passphrase to encrypt your notes.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let folder_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let folder_label = gtk::Label::new(Some("No folder selected"));
+    folder_label.set_hexpand(true);
+    folder_label.set_xalign(0.0);
+    folder_label.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+    let folder_btn = gtk::Button::with_label("Choose Folder...");
+    folder_btn.add_css_class("toolbar-pill");
+    folder_row.append(&folder_label);
+    folder_row.append(&folder_btn);
+    vbox.append(&folder_row);
+
+    let folder_path: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+
+    let pass_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let pass1 = gtk::PasswordEntry::builder()
+        .placeholder_text("Set passphrase")
+        .show_peek_icon(true)
+        .hexpand(true)
+        .build();
+    let generate_btn = gtk::Button::with_label("Generate");
+    generate_btn.add_css_class("toolbar-pill");
+    generate_btn.set_tooltip_text(Some("Pick random words from the built-in list"));
+    pass_row.append(&pass1);
+    pass_row.append(&generate_btn);
+    vbox.append(&pass_row);
+
+    let meter = {
+        let folder_path = folder_path.clone();
+        build_strength_meter(&pass1, move || {
+            folder_path.borrow().as_deref().map(vault::default_vault_name).into_iter().collect()
+        })
+    };
+    vbox.append(&meter);
+
+    // Shows a generated passphrase until it is edited
+    let generated_label = gtk::Label::new(None);
+    generated_label.set_selectable(true);
+    generated_label.set_wrap(true);
+    generated_label.set_xalign(0.0);
+    generated_label.set_visible(false);
+    vbox.append(&generated_label);
+
+    let pass2 = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass2);
+
+    // Set once the user chose to keep a weak passphrase; editing it resets
+    let weak_confirmed = Rc::new(Cell::new(false));
+    let generated: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+    {
+        let weak_confirmed = weak_confirmed.clone();
+        let generated = generated.clone();
+        let generated_label = generated_label.clone();
+        pass1.connect_changed(move |entry| {
+            weak_confirmed.set(false);
+            if generated.borrow().as_deref() != Some(entry.text().as_str()) {
+                generated.borrow_mut().take();
+                generated_label.set_visible(false);
+            }
+        });
+    }
+    {
+        let pass1 = pass1.clone();
+        let pass2 = pass2.clone();
+        let generated = generated.clone();
+        let generated_label = generated_label.clone();
+        generate_btn.connect_clicked(move |_| {
+            let passphrase = strength::generate_passphrase(strength::GENERATED_WORDS);
+            *generated.borrow_mut() = Some(passphrase.clone());
+            pass1.set_text(&passphrase);
+            pass2.set_text(&passphrase);
+            generated_label.set_markup(&format!(
+                "Write this down or learn it by heart:\n<b>{}</b>",
+                glib::markup_escape_text(&passphrase)
+            ));
+            generated_label.set_visible(true);
+        });
+    }
+
+    // Optional second factor
+    let keyfile_label = gtk::Label::new(Some("Keyfile (optional)"));
+    keyfile_label.add_css_class("heading");
+    keyfile_label.set_xalign(0.0);
+    vbox.append(&keyfile_label);
+    let (keyfile_row, keyfile) = build_keyfile_row(&dialog, true);
+    vbox.append(&keyfile_row);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let create_btn = gtk::Button::with_label("Create Vault");
+    create_btn.add_css_class("suggested-action");
+    create_btn.add_css_class("pill");
+    vbox.append(&create_btn);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    // Folder chooser
+    {
+        let dialog_ref = dialog.clone();
+        let folder_label = folder_label.clone();
+        let folder_path = folder_path.clone();
+        folder_btn.connect_clicked(move |_| {
+            let chooser = gtk::FileDialog::builder()
+                .title("Select Vault Folder")
+                .accept_label("Select")
+                .build();
+            let fl = folder_label.clone();
+            let fp = folder_path.clone();
+            let dlg = dialog_ref.clone();
+            chooser.select_folder(Some(&dlg), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
+                if let Ok(file) = result {
+                    if let Some(path) = file.path() {
+                        let p = path.to_string_lossy().to_string();
+                        fl.set_label(&p);
+                        *fp.borrow_mut() = Some(p);
+                    }
+                }
+            });
+        });
+    }
+
+    // Create action
+    {
+        let window = window.clone();
+        let dialog = dialog.clone();
+        let folder_path = folder_path.clone();
+        let pass1 = pass1.clone();
+        let pass2 = pass2.clone();
+        let keyfile = keyfile.clone();
+        let error_label = error_label.clone();
+        let weak_confirmed = weak_confirmed.clone();
+        let create_btn_inner = create_btn.clone();
+        create_btn.connect_clicked(move |_| {
+            let create_btn = create_btn_inner.clone();
+            let keyfile = keyfile.borrow().clone();
+            let p1 = pass1.text().to_string();
+            let p2 = pass2.text().to_string();
+            let fp = folder_path.borrow().clone();
+
+            if fp.is_none() {
+                error_label.set_label("Please select a folder.");
+                error_label.set_visible(true);
+                return;
+            }
+            if p1.len() < 8 {
+                error_label.set_label("Passphrase must be at least 8 characters.");
+                error_label.set_visible(true);
+                return;
+            }
+            if p1 != p2 {
+                error_label.set_label("Passphrases do not match.");
+                error_label.set_visible(true);
+                return;
+            }
+
+            let Some(vault_folder) = fp else {
+                error_label.set_label("Please select a folder.");
+                error_label.set_visible(true);
+                return;
+            };
+            if vault::vault_file_path(&vault_folder).exists() {
+                error_label.set_label("This folder already holds a vault. Open it from the vault list instead.");
+                error_label.set_visible(true);
+                return;
+            }
+            let estimate = strength::estimate(&p1, &[&vault::default_vault_name(&vault_folder)]);
+            if estimate.strength(crypto::Kdf::RECOMMENDED) < strength::Strength::Fair && !weak_confirmed.get() {
+                error_label.set_visible(false);
+                let weak_confirmed = weak_confirmed.clone();
+                let create_btn = create_btn.clone();
+                confirm_weak_passphrase(&dialog, &estimate, move || {
+                    weak_confirmed.set(true);
+                    create_btn.emit_clicked();
+                });
+                return;
+            }
+            let default_state = DocState::default();
+            let vault_data = vault::doc_state_to_vault(&default_state);
+            let json = match serde_json::to_string(&vault_data) {
+                Ok(j) => j,
+                Err(e) => {
+                    error_label.set_label(&format!("Serialization failed: {e}"));
+                    error_label.add_css_class("error");
+                    error_label.set_visible(true);
+                    return;
+                }
+            };
+
+            // Show spinner while deriving key (the KDF is expensive)
+            create_btn.set_sensitive(false);
+            error_label.set_label("Creating vault\u{2026}");
+            error_label.remove_css_class("error");
+            error_label.set_visible(true);
+
+            let (tx, rx) = std::sync::mpsc::channel::<Result<(crypto::CachedKey, String), String>>();
+            let vault_folder_for_thread = vault_folder.clone();
+            std::thread::spawn(move || {
+                let result = (|| {
+                    let mut cached_key = crypto::CachedKey::generate(&p1, keyfile.as_ref())
+                        .map_err(|e| format!("Key generation failed: {e}"))?;
+                    let recovery_code = cached_key
+                        .add_recovery_code()
+                        .map_err(|e| format!("Key generation failed: {e}"))?;
+                    let encrypted = crypto::encrypt_vault_fast(&json, &cached_key)
+                        .map_err(|e| format!("Encryption failed: {e}"))?;
+                    vault::write_vault_raw(&vault_folder_for_thread, &encrypted)
+                        .map_err(|e| format!("Write failed: {e}"))?;
+                    Ok((cached_key, recovery_code))
+                })();
+                let _ = tx.send(result);
+            });
+
+            let window = window.clone();
+            let dialog = dialog.clone();
+            let vault_folder_c = vault_folder.clone();
+            let error_label = error_label.clone();
+            let create_btn = create_btn.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(_) => return glib::ControlFlow::Break,
+                };
+                match result {
+                    Ok((cached_key, recovery_code)) => {
+                        gtk::prelude::GtkWindowExt::set_focus(&window, gtk::Widget::NONE);
+                        dialog.close();
+                        open_vault_editor(
+                            &window,
+                            DocState::default(),
+                            vault_folder_c.clone(),
+                            cached_key,
+                            NoteFiles::default(),
+                            false,
+                            |_| {},
+                        );
+                        show_recovery_code_dialog(&window, &recovery_code);
+                    }
+                    Err(e) => {
+                        error_label.add_css_class("error");
+                        error_label.set_label(&e);
+                        create_btn.set_sensitive(true);
+                    }
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+
+    dialog.present();
+}
+
+pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: String) {
+    let dialog = adw::Window::builder()
+        .transient_for(window)
+        .modal(true)
+        .title("Unlock Vault")
+        .default_width(400)
+        .default_height(260)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let title = gtk::Label::new(Some("Unlock Your Vault"));
+    title.add_css_class("title-2");
+    vbox.append(&title);
+
+    let subtitle = gtk::Label::new(Some("Enter your passphrase to decrypt your notes."));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let pass_entry = gtk::PasswordEntry::builder()
+        .placeholder_text("Passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass_entry);
+
+    // Shown once the vault reports that it needs a keyfile
+    let (keyfile_row, keyfile) = build_keyfile_row(&dialog, false);
+    keyfile_row.set_visible(false);
+    vbox.append(&keyfile_row);
+
+    // Toggles between passphrase and recovery-code unlock
+    let use_recovery = Rc::new(Cell::new(false));
+    let recovery_btn = gtk::Button::with_label("Unlock with recovery code");
+    recovery_btn.add_css_class("flat");
+    recovery_btn.add_css_class("caption");
+    recovery_btn.set_halign(gtk::Align::Start);
+    vbox.append(&recovery_btn);
+    {
+        let use_recovery = use_recovery.clone();
+        let subtitle = subtitle.clone();
+        let pass_entry = pass_entry.clone();
+        let keyfile_row = keyfile_row.clone();
+        let keyfile = keyfile.clone();
+        recovery_btn.connect_clicked(move |btn| {
+            let recovery = !use_recovery.get();
+            use_recovery.set(recovery);
+            // The recovery code is a factor on its own — no keyfile involved
+            keyfile_row.set_visible(!recovery && keyfile.borrow().is_some());
+            if recovery {
+                subtitle.set_label("Enter the recovery code you saved when the vault was created.");
+                pass_entry.set_placeholder_text(Some("XXXX-XXXX-XXXX-XXXX-XXXX-XXXX-XXXX-XXXX"));
+                btn.set_label("Unlock with passphrase");
+            } else {
+                subtitle.set_label("Enter your passphrase to decrypt your notes.");
+                pass_entry.set_placeholder_text(Some("Passphrase"));
+                btn.set_label("Unlock with recovery code");
+            }
+            pass_entry.set_text("");
+            pass_entry.grab_focus();
+        });
+    }
+
+    let read_only_check = gtk::CheckButton::with_label("Open read-only");
+    read_only_check.set_tooltip_text(Some("Browse and export without changing anything in the vault folder"));
+    vbox.append(&read_only_check);
+
+    // Hidden once the key is remembered; "Forget This Device" undoes it
+    let remember_check = gtk::CheckButton::with_label("Remember on this device");
+    remember_check.set_tooltip_text(Some(
+        "Keep a key for this device in the system keyring and unlock without the passphrase next time",
+    ));
+    remember_check.set_visible(!vault::load_config().is_key_remembered(&vault_folder));
+    vbox.append(&remember_check);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_wrap(true);
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let btn_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let unlock_btn = gtk::Button::with_label("Unlock");
+    unlock_btn.add_css_class("suggested-action");
+    unlock_btn.add_css_class("pill");
+    unlock_btn.set_hexpand(true);
+    let change_btn = gtk::Button::with_label("Change Vault");
+    change_btn.add_css_class("flat");
+    btn_row.append(&unlock_btn);
+    btn_row.append(&change_btn);
+    vbox.append(&btn_row);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    // Still waiting after failed attempts, possibly from before a restart
+    if unlock_attempts::UnlockAttempts::load().wait_secs(&vault_folder, unix_now()) > 0 {
+        wait_before_retry(&vault_folder, "Too many failed attempts.", &error_label, &unlock_btn, &pass_entry);
+    }
+    // Failures in this dialog; the summary after unlocking is about others
+    let own_failures = Rc::new(Cell::new(0u32));
+
+    // Unlock action
+    {
+        let window = window.clone();
+        let dialog = dialog.clone();
+        let vault_folder = vault_folder.clone();
+        let pass_entry_for_activate = pass_entry.clone();
+        let pass_entry = pass_entry.clone();
+        let error_label = error_label.clone();
+        let unlock_btn = unlock_btn.clone();
+        let unlock_btn_for_connect = unlock_btn.clone();
+        let use_recovery = use_recovery.clone();
+        let keyfile_row = keyfile_row.clone();
+        let keyfile = keyfile.clone();
+        let read_only_check = read_only_check.clone();
+        let remember_check = remember_check.clone();
+        let own_failures = own_failures.clone();
+        let do_unlock = move || {
+            if unlock_attempts::UnlockAttempts::load().wait_secs(&vault_folder, unix_now()) > 0 {
+                wait_before_retry(&vault_folder, "Too many failed attempts.", &error_label, &unlock_btn, &pass_entry);
+                return;
+            }
+            let passphrase = pass_entry.text().to_string();
+            let recovery = use_recovery.get();
+            let read_only = read_only_check.is_active();
+            let remember = remember_check.is_active();
+            let keyfile_cell = keyfile.clone();
+            let keyfile = keyfile.borrow().clone();
+            let has_keyfile = keyfile.is_some();
+            if passphrase.is_empty() {
+                error_label.set_label(if recovery {
+                    "Please enter your recovery code."
+                } else {
+                    "Please enter your passphrase."
+                });
+                error_label.set_visible(true);
+                return;
+            }
+
+            let vault_folder_thread = vault_folder.clone();
+
+            // Show spinner while decrypting (the KDF is expensive)
+            unlock_btn.set_sensitive(false);
+            pass_entry.set_sensitive(false);
+            error_label.set_label("Unlocking\u{2026}");
+            error_label.remove_css_class("error");
+            error_label.set_visible(true);
+
+            type Unlocked = (vault::VaultData, NoteFiles, crypto::CachedKey);
+            let (tx, rx) = std::sync::mpsc::channel::<Result<Unlocked, String>>();
+            std::thread::spawn(move || {
+                // Reading the note files is part of the unlock, so it stays off the UI thread
+                let send = |result: Result<(String, crypto::CachedKey), String>| {
+                    let unlocked = result.and_then(|(json, key)| {
+                        let json = zeroize::Zeroizing::new(json);
+                        let (data, files) =
+                            vault::load_vault_json(&vault_folder_thread, &json, &key).map_err(|e| e.to_string())?;
+                        Ok((data, files, key))
+                    });
+                    let _ = tx.send(unlocked);
+                };
+                // Nothing on disk changes before the writer lock is held
+                let raw = if read_only {
+                    vault::read_vault_raw_untouched(&vault_folder_thread)
+                } else {
+                    vault::read_vault_raw_pending(&vault_folder_thread)
+                };
+                let raw = match raw {
+                    Ok(Some(data)) => data,
+                    Ok(None) => {
+                        send(Err("Vault file not found.".to_string()));
+                        return;
+                    }
+                    Err(e) => {
+                        send(Err(format!("Read error: {e}")));
+                        return;
+                    }
+                };
+
+                if recovery {
+                    let result = match crypto::decrypt_vault_with_recovery_code(&raw, &passphrase) {
+                        Ok(r) => Ok(r),
+                        Err(crypto::CryptoError::InvalidData(msg)) => Err(msg),
+                        Err(_) => Err("wrong_recovery_code".to_string()),
+                    };
+                    send(result);
+                    return;
+                }
+
+                let policy = vault::load_config().plaintext_policy();
+                match crypto::decrypt_vault_returning_key(&raw, &passphrase, keyfile.as_ref(), policy) {
+                    Ok((json, cached_key)) => {
+                        send(Ok((json, cached_key)));
+                    }
+                    Err(crypto::CryptoError::KeyfileRequired) => {
+                        send(Err("keyfile_required".to_string()));
+                    }
+                    Err(crypto::CryptoError::Unencrypted) => {
+                        send(Err("unencrypted".to_string()));
+                    }
+                    Err(_) => {
+                        send(Err("wrong_passphrase".to_string()));
+                    }
+                }
+            });
+
+            let window = window.clone();
+            let dialog = dialog.clone();
+            let vault_folder = vault_folder.clone();
+            let error_label = error_label.clone();
+            let pass_entry = pass_entry.clone();
+            let unlock_btn = unlock_btn.clone();
+            let keyfile_row = keyfile_row.clone();
+            let own_failures = own_failures.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(_) => return glib::ControlFlow::Break,
+                };
+                match result {
+                    Ok((vault_data, note_files, cached_key)) => {
+                        let state = vault::vault_to_doc_state(vault_data);
+                        gtk::prelude::GtkWindowExt::set_focus(&window, gtk::Widget::NONE);
+                        dialog.close();
+                        let has_recovery_code = cached_key.has_recovery_code();
+                        let failed = unlock_attempts::note_success(&vault_folder, unix_now());
+                        let own_failures = own_failures.get();
+                        // Old vault format or KDF — upgraded once the vault is ours to write.
+                        // A keyfile is only carried over if the vault already used one.
+                        let upgrade = (!recovery && cached_key.needs_upgrade()).then(|| {
+                            let keyfile = keyfile_cell.borrow().clone().filter(|_| cached_key.requires_keyfile());
+                            (zeroize::Zeroizing::new(pass_entry.text().to_string()), keyfile)
+                        });
+                        open_vault_editor(&window, state, vault_folder.clone(), cached_key, note_files, read_only, move |ctx| {
+                            report_failed_unlocks(ctx, failed, own_failures);
+                            let remember_key = move |ctx: &EditorCtx| {
+                                if remember {
+                                    remember_vault_key(ctx);
+                                }
+                            };
+                            match upgrade {
+                                Some((passphrase, keyfile)) if !ctx.read_only.get() => {
+                                    upgrade_vault_key(ctx, passphrase, keyfile, remember_key);
+                                }
+                                _ => remember_key(ctx),
+                            }
+                            if recovery && ctx.read_only.get() {
+                                send_toast(ctx, "Read-only \u{2014} unlock again once the vault is free to set a new passphrase");
+                            } else if recovery {
+                                show_reset_passphrase_dialog(ctx);
+                            } else if !has_recovery_code && !ctx.read_only.get() {
+                                send_toast(ctx, "No recovery code \u{2014} create one from the menu");
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        error_label.add_css_class("error");
+                        let mut wait = 0;
+                        if e == "keyfile_required" {
+                            error_label.set_label(
+                                "This vault also requires its keyfile. Choose it below.",
+                            );
+                            keyfile_row.set_visible(true);
+                        } else if e == "unencrypted" && read_only {
+                            error_label.set_label(
+                                "This vault is stored without encryption, and encrypting it needs write access. Unlock it without \u{201c}Open read-only\u{201d} first.",
+                            );
+                        } else if e == "unencrypted" {
+                            // Strict mode: encrypt only after the user has seen the file list
+                            error_label.set_label("This vault is stored without encryption.");
+                            let folder = vault_folder.clone();
+                            let passphrase = pass_entry.text().to_string();
+                            let keyfile = keyfile_cell.borrow().clone();
+                            let prepare = move || {
+                                let raw = vault::read_vault_raw_pending(&folder)
+                                    .map_err(|e| format!("Read error: {e}"))?
+                                    .ok_or_else(|| "Vault file not found.".to_string())?;
+                                crypto::decrypt_vault_returning_key(
+                                    &raw,
+                                    &passphrase,
+                                    keyfile.as_ref(),
+                                    crypto::PlaintextPolicy::Allow,
+                                )
+                                .map_err(|e| e.to_string())
+                            };
+                            let error_label = error_label.clone();
+                            let pass_entry = pass_entry.clone();
+                            let unlock_btn = unlock_btn.clone();
+                            confirm_plaintext_migration(
+                                dialog.upcast_ref(),
+                                vault_folder.clone(),
+                                prepare,
+                                move |result| match result {
+                                    Some(Ok(_)) => {
+                                        // Unlock again, now through the encrypted path
+                                        unlock_btn.set_sensitive(true);
+                                        pass_entry.set_sensitive(true);
+                                        unlock_btn.emit_clicked();
+                                    }
+                                    Some(Err(e)) => {
+                                        error_label.set_label(&e);
+                                        unlock_btn.set_sensitive(true);
+                                        pass_entry.set_sensitive(true);
+                                    }
+                                    None => {
+                                        error_label.set_label(
+                                            "Unencrypted vaults are refused. Encrypt it, or turn off \u{201c}Refuse unencrypted files\u{201d} in Preferences.",
+                                        );
+                                        unlock_btn.set_sensitive(true);
+                                        pass_entry.set_sensitive(true);
+                                    }
+                                },
+                            );
+                            return glib::ControlFlow::Break;
+                        } else if e == "wrong_passphrase" || e == "wrong_recovery_code" {
+                            own_failures.set(own_failures.get() + 1);
+                            wait = unlock_attempts::note_failure(&vault_folder, unix_now());
+                            error_label.set_label(if e == "wrong_recovery_code" {
+                                "Wrong recovery code. Try again."
+                            } else if has_keyfile {
+                                "Wrong passphrase or keyfile. Try again."
+                            } else {
+                                "Wrong passphrase. Try again."
+                            });
+                            pass_entry.set_text("");
+                            pass_entry.grab_focus();
+                        } else {
+                            error_label.set_label(&e);
+                        }
+                        unlock_btn.set_sensitive(true);
+                        pass_entry.set_sensitive(true);
+                        if wait > 0 {
+                            let reason = if e == "wrong_recovery_code" { "Wrong recovery code." } else { "Wrong passphrase." };
+                            wait_before_retry(&vault_folder, reason, &error_label, &unlock_btn, &pass_entry);
+                        }
+                    }
+                }
+                glib::ControlFlow::Break
+            });
+        };
+
+        let do_unlock_click = do_unlock.clone();
+        unlock_btn_for_connect.connect_clicked(move |_| do_unlock_click());
+
+        let do_unlock_enter = do_unlock;
+        pass_entry_for_activate.connect_activate(move |_| do_unlock_enter());
+    }
+
+    // Change vault: back to the list of vaults
+    {
+        let window = window.clone();
+        let dialog = dialog.clone();
+        change_btn.connect_clicked(move |_| {
+            dialog.close();
+            show_vault_picker(&window);
+        });
+    }
+
+    dialog.present();
+    pass_entry.grab_focus();
+}
+
+/// Keep the unlock dialog's controls disabled until the vault may be tried
+/// again (see `unlock_attempts.rs`), counting down after `reason`.
+fn wait_before_retry(
+    vault_folder: &str,
+    reason: &str,
+    error_label: &gtk::Label,
+    unlock_btn: &gtk::Button,
+    pass_entry: &gtk::PasswordEntry,
+) {
+    let vault_folder = vault_folder.to_string();
+    let reason = reason.to_string();
+    let error_label = error_label.clone();
+    let unlock_btn = unlock_btn.clone();
+    let pass_entry = pass_entry.clone();
+    let update = move || {
+        let wait = unlock_attempts::UnlockAttempts::load().wait_secs(&vault_folder, unix_now());
+        let waiting = wait > 0;
+        unlock_btn.set_sensitive(!waiting);
+        pass_entry.set_sensitive(!waiting);
+        error_label.add_css_class("error");
+        error_label.set_visible(true);
+        if waiting {
+            let time = if wait < 60 {
+                format!("{wait} second{}", if wait == 1 { "" } else { "s" })
+            } else {
+                let minutes = (wait + 59) / 60;
+                format!("{minutes} minute{}", if minutes == 1 { "" } else { "s" })
+            };
+            error_label.set_label(&format!("{reason} Try again in {time}."));
+        } else {
+            error_label.set_label(&format!("{reason} You can try again now."));
+            pass_entry.grab_focus();
+        }
+        waiting
+    };
+    if !update() {
+        return;
+    }
+    glib::timeout_add_seconds_local(1, move || {
+        if update() {
+            glib::ControlFlow::Continue
+        } else {
+            glib::ControlFlow::Break
+        }
+    });
+}
+
+/// Tell the user about failed unlocks since the vault was last opened, unless
+/// they were all `own` mistakes made just now.
+fn report_failed_unlocks(ctx: &EditorCtx, failed: Option<unlock_attempts::FailedAttempts>, own: u32) {
+    let Some(failed) = failed.filter(|f| f.count > own) else { return };
+    let attempts = if failed.count == 1 {
+        "1 failed unlock attempt".to_string()
+    } else {
+        format!("{} failed unlock attempts", failed.count)
+    };
+    let since = match failed.since.map(relative_day) {
+        Some(day) if day == "today" => "since earlier today".to_string(),
+        Some(day) => format!("since {day}"),
+        None => format!("starting {}", relative_day(failed.first)),
+    };
+    let last_day = match relative_day(failed.last) {
+        day if day == "today" || day == "yesterday" => day,
+        day => format!("on {day}"),
+    };
+    let last_time = glib::DateTime::from_unix_local(failed.last)
+        .and_then(|dt| dt.format("%H:%M"))
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+    show_info(
+        &ctx.window,
+        "Failed Unlock Attempts",
+        &format!(
+            "{attempts} {since}, the last at {last_time} {last_day}.\n\n\
+             If that wasn\u{2019}t you, someone may have tried to guess the passphrase on this computer."
+        ),
+    );
+}
+
+/// "today", "yesterday", a weekday within the past week, or a date.
+fn relative_day(ts: i64) -> String {
+    let (Ok(then), Ok(now)) = (glib::DateTime::from_unix_local(ts), glib::DateTime::now_local()) else {
+        return format_ts(ts);
+    };
+    let midnight = |dt: &glib::DateTime| glib::DateTime::from_local(dt.year(), dt.month(), dt.day_of_month(), 0, 0, 0.0);
+    let days = match (midnight(&now), midnight(&then)) {
+        (Ok(a), Ok(b)) => (a.difference(&b).as_seconds() as f64 / 86_400.0).round() as i64,
+        _ => i64::MAX,
+    };
+    let format = match days {
+        0 => return "today".to_string(),
+        1 => return "yesterday".to_string(),
+        2..=6 => "%A",
+        _ if then.year() == now.year() => "%B %-d",
+        _ => "%B %-d, %Y",
+    };
+    then.format(format).map(|s| s.to_string()).unwrap_or_else(|_| format_ts(ts))
+}
+
+pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Change Passphrase")
+        .default_width(420)
+        .default_height(320)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let subtitle = gtk::Label::new(Some(
+        "Your recovery code keeps working after the passphrase is changed.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let current_pass = gtk::PasswordEntry::builder()
+        .placeholder_text("Current passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&current_pass);
+
+    let pass1 = gtk::PasswordEntry::builder()
+        .placeholder_text("New passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass1);
+
+    let pass2 = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm new passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass2);
+
+    // Keyfiles: the current one (if the vault needs it) and an optional new one
+    let requires_keyfile = ctx
+        .cached_key
+        .borrow()
+        .as_ref()
+        .is_some_and(|k| k.requires_keyfile());
+    let (current_keyfile_row, current_keyfile) = build_keyfile_row(&dialog, false);
+    let current_keyfile_label = gtk::Label::new(Some("Current keyfile"));
+    current_keyfile_label.add_css_class("heading");
+    current_keyfile_label.set_xalign(0.0);
+    current_keyfile_label.set_visible(requires_keyfile);
+    current_keyfile_row.set_visible(requires_keyfile);
+    vbox.append(&current_keyfile_label);
+    vbox.append(&current_keyfile_row);
+
+    let (new_keyfile_row, new_keyfile) = build_keyfile_row(&dialog, true);
+    let new_keyfile_label = gtk::Label::new(Some(if requires_keyfile {
+        "New keyfile (optional \u{2014} keeps the current one if empty)"
+    } else {
+        "Keyfile (optional)"
+    }));
+    new_keyfile_label.add_css_class("heading");
+    new_keyfile_label.set_xalign(0.0);
+    new_keyfile_label.set_wrap(true);
+    vbox.append(&new_keyfile_label);
+    vbox.append(&new_keyfile_row);
+
+    let remove_keyfile = gtk::CheckButton::with_label("Stop requiring a keyfile");
+    remove_keyfile.set_visible(requires_keyfile);
+    vbox.append(&remove_keyfile);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_wrap(true);
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let change_btn = gtk::Button::with_label("Change Passphrase");
+    change_btn.add_css_class("suggested-action");
+    change_btn.add_css_class("pill");
+    vbox.append(&change_btn);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let error_label = error_label.clone();
+        let change_btn_inner = change_btn.clone();
+        change_btn.connect_clicked(move |_| {
+            let change_btn = change_btn_inner.clone();
+            let old = current_pass.text().to_string();
+            let p1 = pass1.text().to_string();
+            let p2 = pass2.text().to_string();
+            let old_keyfile = current_keyfile.borrow().clone();
+            let keyfile = if remove_keyfile.is_active() {
+                None
+            } else {
+                new_keyfile.borrow().clone().or_else(|| old_keyfile.clone())
+            };
+
+            if old.is_empty() {
+                error_label.set_label("Please enter your current passphrase.");
+                error_label.set_visible(true);
+                return;
+            }
+            if requires_keyfile && old_keyfile.is_none() {
+                error_label.set_label("Please choose the vault's current keyfile.");
+                error_label.set_visible(true);
+                return;
+            }
+            if p1.len() < 8 {
+                error_label.set_label("Passphrase must be at least 8 characters.");
+                error_label.set_visible(true);
+                return;
+            }
+            if p1 != p2 {
+                error_label.set_label("Passphrases do not match.");
+                error_label.set_visible(true);
+                return;
+            }
+            if ctx.saving.get() {
+                error_label.set_label("A save is in progress. Please try again in a moment.");
+                error_label.set_visible(true);
+                return;
+            }
+
+            // Cancel any pending debounced save; saves requested while re-keying
+            // are queued through the generation counter and replayed afterwards.
+            if let Some(source_id) = ctx.save_timeout_id.take() {
+                source_id.remove();
+            }
+            let Some(key) = ctx.cached_key.borrow().clone() else {
+                error_label.set_label("Vault is not unlocked.");
+                error_label.set_visible(true);
+                return;
+            };
+            let vault_folder = ctx.vault_folder.borrow().clone();
+
+            change_btn.set_sensitive(false);
+            error_label.set_label("Changing passphrase\u{2026}");
+            error_label.remove_css_class("error");
+            error_label.set_visible(true);
+            ctx.saving.set(true);
+            let gen = ctx.save_generation.get();
+            let expected = ctx.vault_file.borrow().clone();
+            let policy = vault::load_config().plaintext_policy();
+
+            let (tx, rx) = std::sync::mpsc::channel::<Result<(crypto::CachedKey, vault::VaultFingerprint), String>>();
+            std::thread::spawn(move || {
+                use zeroize::Zeroize;
+                let result = (|| {
+                    let raw = vault::read_vault_raw(&vault_folder)
+                        .map_err(|e| format!("Read error: {e}"))?
+                        .ok_or_else(|| "Vault file not found.".to_string())?;
+                    let (mut on_disk, _) =
+                        crypto::decrypt_vault_returning_key(
+                            &raw,
+                            &old,
+                            old_keyfile.as_ref(),
+                            crypto::PlaintextPolicy::Refuse,
+                        )
+                        .map_err(|_| "wrong_passphrase".to_string())?;
+                    on_disk.zeroize();
+                    // Only the key slots change; unsaved edits go in the save that follows
+                    match vault::set_vault_passphrase(&vault_folder, &key, &p1, keyfile.as_ref(), expected.as_ref(), policy)
+                        .map_err(|e| format!("Re-encryption failed: {e}"))?
+                    {
+                        (key, vault::VaultWrite::Written(fingerprint)) => Ok((key, fingerprint)),
+                        (_, vault::VaultWrite::Changed { .. }) => Err(CHANGED_ELSEWHERE.to_string()),
+                    }
+                })();
+                let _ = tx.send(result);
+            });
+
+            let ctx = ctx.clone();
+            let dialog = dialog.clone();
+            let error_label = error_label.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(_) => {
+                        ctx.saving.set(false);
+                        return glib::ControlFlow::Break;
+                    }
+                };
+                ctx.saving.set(false);
+                let save = ctx.save_generation.get() > gen || ctx.state.borrow().dirty;
+                match result {
+                    Ok((new_key, fingerprint)) => {
+                        // Only the key slots changed, so the merge base and note files still hold
+                        *ctx.vault_file.borrow_mut() = Some(fingerprint);
+                        *ctx.cached_key.borrow_mut() = Some(new_key);
+                        forget_revoked_device_key(&ctx.vault_folder.borrow());
+                        dialog.close();
+                        send_toast(&ctx, "Passphrase changed");
+                    }
+                    Err(e) => {
+                        error_label.add_css_class("error");
+                        if e == "wrong_passphrase" {
+                            error_label.set_label(if requires_keyfile {
+                                "Current passphrase or keyfile is incorrect."
+                            } else {
+                                "Current passphrase is incorrect."
+                            });
+                        } else if e == CHANGED_ELSEWHERE {
+                            error_label.set_label(&e);
+                            trigger_vault_save(&ctx);
+                        } else {
+                            error_label.set_label(&e);
+                        }
+                        change_btn.set_sensitive(true);
+                    }
+                }
+                // Replay saves that were requested or cancelled while the vault was being re-keyed
+                if save {
+                    perform_vault_save_async(&ctx, false);
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+
+    dialog.present();
+}
+
+/// Show a newly generated recovery code. The dialog can only be dismissed
+/// after the user confirms the code has been stored.
+pub fn show_recovery_code_dialog(window: &adw::ApplicationWindow, code: &str) {
+    let dialog = adw::Window::builder()
+        .transient_for(window)
+        .modal(true)
+        .deletable(false)
+        .title("Recovery Code")
+        .default_width(460)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let title = gtk::Label::new(Some("Save Your Recovery Code"));
+    title.add_css_class("title-2");
+    vbox.append(&title);
+
+    let subtitle = gtk::Label::new(Some(
+        "If you forget your passphrase, this code is the only way to unlock the vault. \
+         Write it down or print it and keep it somewhere safe. It will not be shown again.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    subtitle.set_justify(gtk::Justification::Center);
+    vbox.append(&subtitle);
+
+    let code_label = gtk::Label::new(Some(code));
+    code_label.add_css_class("title-3");
+    code_label.add_css_class("monospace");
+    code_label.set_selectable(true);
+    code_label.set_wrap(true);
+    code_label.set_margin_top(6);
+    code_label.set_margin_bottom(6);
+    vbox.append(&code_label);
+
+    let copy_btn = gtk::Button::with_label("Copy to Clipboard");
+    copy_btn.add_css_class("flat");
+    copy_btn.set_halign(gtk::Align::Center);
+    {
+        let code = code.to_string();
+        copy_btn.connect_clicked(move |btn| {
+            let clipboard = btn.clipboard();
+            let provider = sensitive_content(&code);
+            if clipboard.set_content(Some(&provider)).is_ok() {
+                clear_clipboard_after(&clipboard, provider, SENSITIVE_CLEAR_SECS);
+            }
+        });
+    }
+    vbox.append(&copy_btn);
+
+    let confirm_check = gtk::CheckButton::with_label("I have stored my recovery code safely");
+    vbox.append(&confirm_check);
+
+    let done_btn = gtk::Button::with_label("Done");
+    done_btn.add_css_class("suggested-action");
+    done_btn.add_css_class("pill");
+    done_btn.set_sensitive(false);
+    vbox.append(&done_btn);
+
+    {
+        let done_btn = done_btn.clone();
+        confirm_check.connect_toggled(move |check| done_btn.set_sensitive(check.is_active()));
+    }
+    {
+        let dialog = dialog.clone();
+        done_btn.connect_clicked(move |_| dialog.close());
+    }
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+    dialog.present();
+}
+
+/// Scan `vault_folder` for files stored without encryption and, once the user
+/// has confirmed the list, encrypt them. `prepare` runs on a worker thread and
+/// returns the vault document and key to encrypt with. `on_done` receives the
+/// encrypted files, or `None` if the user cancelled.
+fn confirm_plaintext_migration<P>(
+    parent: &gtk::Widget,
+    vault_folder: String,
+    prepare: P,
+    on_done: impl Fn(Option<Result<vault::PlaintextFiles, String>>) + 'static,
+) where
+    P: FnOnce() -> Result<(String, crypto::CachedKey), String> + Send + 'static,
+{
+    let (tx, rx) = std::sync::mpsc::channel::<Result<vault::PlaintextFiles, String>>();
+    {
+        let vault_folder = vault_folder.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(vault::find_plaintext_files(&vault_folder).map_err(|e| e.to_string()));
+        });
+    }
+
+    let parent = parent.clone();
+    let on_done = Rc::new(on_done);
+    let mut prepare = Some(prepare);
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let files = match rx.try_recv() {
+            Ok(Ok(files)) => files,
+            Ok(Err(e)) => {
+                on_done(Some(Err(format!("Could not scan the vault folder: {e}"))));
+                return glib::ControlFlow::Break;
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        if files.is_empty() {
+            on_done(Some(Ok(files)));
+            return glib::ControlFlow::Break;
+        }
+
+        let paths = files.paths();
+        let dialog = adw::AlertDialog::new(
+            Some("Unencrypted Files"),
+            Some(&format!(
+                "{} {} in this vault {} stored without encryption. Encrypt {} now?",
+                paths.len(),
+                if paths.len() == 1 { "file" } else { "files" },
+                if paths.len() == 1 { "is" } else { "are" },
+                if paths.len() == 1 { "it" } else { "them" },
+            )),
+        );
+        let list = gtk::Label::new(Some(&paths.join("\n")));
+        list.add_css_class("monospace");
+        list.set_selectable(true);
+        list.set_xalign(0.0);
+        let scroller = gtk::ScrolledWindow::builder()
+            .child(&list)
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .propagate_natural_height(true)
+            .max_content_height(200)
+            .build();
+        dialog.set_extra_child(Some(&scroller));
+        dialog.add_response("cancel", "Cancel");
+        dialog.add_response("encrypt", "Encrypt Files");
+        dialog.set_response_appearance("encrypt", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("encrypt"));
+        dialog.set_close_response("cancel");
+
+        let vault_folder = vault_folder.clone();
+        let on_done = on_done.clone();
+        let prepare = RefCell::new(prepare.take());
+        dialog.connect_response(None, move |_, response| {
+            let Some(prepare) = prepare.borrow_mut().take() else { return };
+            if response != "encrypt" {
+                on_done(None);
+                return;
+            }
+            let (tx, rx) = std::sync::mpsc::channel::<Result<(), String>>();
+            let vault_folder = vault_folder.clone();
+            let files_thread = files.clone();
+            std::thread::spawn(move || {
+                let result = prepare().and_then(|(json, key)| {
+                    vault::encrypt_plaintext_files(&vault_folder, &json, &key, &files_thread)
+                        .map_err(|e| format!("Encryption failed: {e}"))
+                });
+                let _ = tx.send(result);
+            });
+            let on_done = on_done.clone();
+            let files = files.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                match rx.try_recv() {
+                    Ok(result) => on_done(Some(result.map(|_| files.clone()))),
+                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(_) => {}
+                }
+                glib::ControlFlow::Break
+            });
+        });
+        dialog.present(Some(&parent));
+        glib::ControlFlow::Break
+    });
+}
+
+/// Menu entry point for `confirm_plaintext_migration` on an unlocked vault.
+fn encrypt_plaintext_files(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
+    let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
+        return;
+    };
+    let json = match serde_json::to_string(&vault_data) {
+        Ok(j) => j,
+        Err(e) => {
+            show_error(&ctx.window, "Encrypt Files", &format!("Serialization failed: {e}"));
+            return;
+        }
+    };
+    let ctx_done = ctx.clone();
+    confirm_plaintext_migration(
+        ctx.window.upcast_ref(),
+        vault_folder,
+        move || Ok((json, key)),
+        move |result| match result {
+            Some(Ok(files)) if files.is_empty() => {
+                send_toast(&ctx_done, "All vault files are encrypted");
+            }
+            Some(Ok(files)) => {
+                // Catch up on edits made since the vault document was captured
+                if files.vault {
+                    perform_vault_save_async(&ctx_done, false);
+                }
+                render_wysiwyg_images(&ctx_done);
+                send_toast(&ctx_done, &format!("Encrypted {} files", files.paths().len()));
+            }
+            Some(Err(e)) => show_error(&ctx_done.window, "Encrypt Files", &e),
+            None => {}
+        },
+    );
+}
+
+/// Integrity report for the vault folder, with one-click repairs.
+pub fn show_check_vault_dialog(ctx: &EditorCtx) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Check Vault")
+        .default_width(520)
+        .default_height(560)
+        .build();
+
+    let page = gtk::Box::new(gtk::Orientation::Vertical, 18);
+    page.set_margin_start(24);
+    page.set_margin_end(24);
+    page.set_margin_top(24);
+    page.set_margin_bottom(24);
+
+    let recheck_btn = gtk::Button::from_icon_name("view-refresh-symbolic");
+    recheck_btn.set_tooltip_text(Some("Check Again"));
+    let toolbar = adw::HeaderBar::new();
+    toolbar.pack_start(&recheck_btn);
+    let scroller = gtk::ScrolledWindow::builder()
+        .child(&page)
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .build();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&scroller);
+    dialog.set_content(Some(&content));
+
+    {
+        let ctx = ctx.clone();
+        let page = page.clone();
+        recheck_btn.connect_clicked(move |_| run_vault_check(&ctx, &page));
+    }
+    run_vault_check(ctx, &page);
+    dialog.present();
+}
+
+fn run_vault_check(ctx: &EditorCtx, page: &gtk::Box) {
+    while let Some(child) = page.first_child() {
+        page.remove(&child);
+    }
+    let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
+        return;
+    };
+
+    let spinner = gtk::Spinner::new();
+    spinner.start();
+    let status = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    status.set_halign(gtk::Align::Center);
+    status.append(&spinner);
+    status.append(&gtk::Label::new(Some("Checking vault\u{2026}")));
+    page.append(&status);
+
+    let policy = vault::load_config().plaintext_policy();
+    let (tx, rx) = std::sync::mpsc::channel::<Result<integrity::IntegrityReport, String>>();
+    {
+        let vault_folder = vault_folder.clone();
+        std::thread::spawn(move || {
+            let result = integrity::check_vault(&vault_folder, &vault_data, &key, policy)
+                .map_err(|e| e.to_string());
+            let _ = tx.send(result);
+        });
+    }
+
+    let ctx = ctx.clone();
+    let page = page.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        page.remove(&status);
+        match result {
+            Ok(report) => show_vault_check_report(&ctx, &page, Rc::new(report), &vault_folder),
+            Err(e) => {
+                let label = gtk::Label::new(Some(&format!("The check failed: {e}")));
+                label.add_css_class("error");
+                label.set_wrap(true);
+                page.append(&label);
+            }
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// Run an asset collection pass (see `integrity::plan_asset_gc`). Images that
+/// just became unreferenced are only marked; a follow-up pass removes them once
+/// the grace period has passed.
+pub fn collect_unused_assets(ctx: &EditorCtx, announce: bool) {
+    if ctx.locked.get() || (ctx.read_only.get() && !announce) || refuse_in_read_only(ctx) {
+        return;
+    }
+    let Some((vault_data, _key, vault_folder)) = prepare_vault_save(ctx) else {
+        return;
+    };
+    let files = match integrity::asset_files_changed(&vault_folder) {
+        Ok(files) => files,
+        Err(e) => {
+            if announce {
+                show_error(&ctx.window, "Remove Unused Images", &format!("Could not read assets: {e}"));
+            }
+            return;
+        }
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let plan = integrity::plan_asset_gc(&vault_data, &files, now, integrity::ASSET_GC_GRACE_MS);
+    integrity::apply_gc_plan(&mut ctx.state.borrow_mut().assets, &plan, now);
+    let deleted = integrity::delete_asset_files(&vault_folder, &plan.collect);
+    if !(plan.mark.is_empty() && plan.unmark.is_empty() && plan.collect.is_empty()) {
+        trigger_vault_save(ctx);
+    }
+
+    if !plan.mark.is_empty() {
+        let ctx = ctx.clone();
+        let grace = std::time::Duration::from_millis(integrity::ASSET_GC_GRACE_MS as u64);
+        glib::timeout_add_local_once(grace, move || collect_unused_assets(&ctx, false));
+    }
+
+    if announce {
+        match deleted {
+            Ok(0) if plan.pending == 0 => send_toast(ctx, "No unused images"),
+            Ok(n) => {
+                let mut message = format!("Removed {n} unused {}", if n == 1 { "image" } else { "images" });
+                if plan.pending > 0 {
+                    message.push_str(&format!(
+                        "; {} recently unused kept for {} minutes",
+                        plan.pending,
+                        integrity::ASSET_GC_GRACE_MS / 60_000
+                    ));
+                }
+                send_toast(ctx, &message);
+            }
+            Err(e) => show_error(&ctx.window, "Remove Unused Images", &format!("Could not delete assets: {e}")),
+        }
+    } else if let Err(e) = deleted {
+        eprintln!("Warning: asset collection failed: {e}");
+    }
+}
+
+/// Repairs every item in one group of the vault check; returns how many were fixed.
+type RepairFn = Box<dyn Fn(&EditorCtx) -> Result<usize, String>>;
+
+fn show_vault_check_report(
+    ctx: &EditorCtx,
+    page: &gtk::Box,
+    report: Rc<integrity::IntegrityReport>,
+    vault_folder: &str,
+) {
+    let summary = adw::StatusPage::builder()
+        .icon_name(if report.is_clean() {
+            "emblem-ok-symbolic"
+        } else {
+            "dialog-warning-symbolic"
+        })
+        .title(if report.is_clean() { "No Problems Found" } else { "Problems Found" })
+        .description(format!(
+            "Checked {} asset files and {} image references.",
+            report.assets_checked, report.references_checked
+        ))
+        .build();
+    summary.add_css_class("compact");
+    page.append(&summary);
+
+    // One group per kind of problem; `fix` adds a header button that repairs
+    // the whole group and then re-runs the check.
+    let add_group = |title: &str,
+                     description: &str,
+                     rows: Vec<(String, String)>,
+                     fix: Option<(&str, RepairFn)>| {
+        if rows.is_empty() {
+            return;
+        }
+        let group = adw::PreferencesGroup::new();
+        group.set_title(title);
+        group.set_description(Some(description));
+        for (row_title, subtitle) in rows {
+            let row = adw::ActionRow::builder().title(row_title).subtitle(subtitle).build();
+            row.set_title_selectable(true);
+            group.add(&row);
+        }
+        // Repairs write to the vault folder
+        if let Some((label, fix)) = fix.filter(|_| !ctx.read_only.get()) {
+            let button = gtk::Button::with_label(label);
+            button.add_css_class("flat");
+            button.set_valign(gtk::Align::Center);
+            let ctx = ctx.clone();
+            let page = page.clone();
+            button.connect_clicked(move |_| {
+                match fix(&ctx) {
+                    Ok(n) => send_toast(&ctx, &format!("Fixed {n} {}", if n == 1 { "item" } else { "items" })),
+                    Err(e) => show_error(&ctx.window, "Repair failed", &e),
+                }
+                run_vault_check(&ctx, &page);
+            });
+            group.set_header_suffix(Some(&button));
+        }
+        page.append(&group);
+    };
+
+    let policy = vault::load_config().plaintext_policy();
+    let folder = vault_folder.to_string();
+
+    let fix_report = report.clone();
+    let fix_folder = folder.clone();
+    add_group(
+        "Unreadable Files",
+        "These files fail to decrypt or verify. They may be corrupted or swapped in from elsewhere.",
+        report.unreadable.iter().map(|(id, e)| (id.clone(), e.clone())).collect(),
+        Some((
+            "Quarantine",
+            Box::new(move |_| {
+                integrity::quarantine_unreadable(&fix_folder, &fix_report).map_err(|e| e.to_string())
+            }),
+        )),
+    );
+
+    let fix_report = report.clone();
+    add_group(
+        "Missing Files",
+        "The vault has metadata for these images, but their files are gone.",
+        report
+            .missing_files
+            .iter()
+            .map(|id| {
+                let name = ctx.state.borrow().assets.get(id).map(|m| m.original_name.clone());
+                (id.clone(), name.unwrap_or_default())
+            })
+            .collect(),
+        Some((
+            "Drop Metadata",
+            Box::new(move |ctx| {
+                let dropped =
+                    integrity::drop_dangling_metadata(&mut ctx.state.borrow_mut().assets, &fix_report);
+                trigger_vault_save(ctx);
+                Ok(dropped)
+            }),
+        )),
+    );
+
+    let fix_report = report.clone();
+    let fix_folder = folder.clone();
+    add_group(
+        "Orphaned Files",
+        "These files have no metadata. Restoring it makes images that still reference them load again.",
+        report.orphaned_files.iter().map(|id| (id.clone(), String::new())).collect(),
+        Some((
+            "Restore Metadata",
+            Box::new(move |ctx| {
+                let Some(key) = ctx.cached_key.borrow().clone() else {
+                    return Err("Vault is not unlocked.".to_string());
+                };
+                let restored = integrity::restore_orphan_metadata(
+                    &fix_folder,
+                    &mut ctx.state.borrow_mut().assets,
+                    &fix_report,
+                    &key,
+                    policy,
+                )
+                .map_err(|e| e.to_string())?;
+                trigger_vault_save(ctx);
+                Ok(restored)
+            }),
+        )),
+    );
+
+    add_group(
+        "Broken References",
+        "These images point to assets the vault knows nothing about. Remove or replace them in the notes.",
+        report
+            .broken_references
+            .iter()
+            .map(|r| (format!("vault://{}", r.asset_id), r.location.to_string()))
+            .collect(),
+        None,
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Restore from backup
+// ---------------------------------------------------------------------------
+
+/// Passphrase and keyfile for a backup made before the vault key changed.
+type BackupCredentials = (String, Option<crypto::Keyfile>);
+
+/// Browse the rotating backups (see `backup.rs`), preview one and restore
+/// either the whole vault or individual notes from it.
+pub fn show_restore_backup_dialog(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let backups = match backup::list_backups(&vault_folder) {
+        Ok(backups) => backups,
+        Err(e) => {
+            show_error(&ctx.window, "Restore from Backup", &format!("Could not read backups: {e}"));
+            return;
+        }
+    };
+    if backups.is_empty() {
+        show_info(
+            &ctx.window,
+            "Restore from Backup",
+            "There are no backups yet. The vault file is backed up before the first save of every hour.",
+        );
+        return;
+    }
+
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Restore from Backup")
+        .default_width(760)
+        .default_height(560)
+        .build();
+
+    let backup_list = gtk::ListBox::new();
+    backup_list.add_css_class("navigation-sidebar");
+    for b in &backups {
+        let row = adw::ActionRow::builder()
+            .title(format_ts(b.created as i64))
+            .subtitle(format!("{} KB", b.size.div_ceil(1024)))
+            .build();
+        backup_list.append(&row);
+    }
+    let list_scroller = gtk::ScrolledWindow::builder()
+        .child(&backup_list)
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .build();
+
+    let preview = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    preview.set_margin_start(18);
+    preview.set_margin_end(18);
+    preview.set_margin_top(18);
+    preview.set_margin_bottom(18);
+
+    let status = gtk::Label::new(Some("Select a backup to preview its notes."));
+    status.set_wrap(true);
+    status.set_xalign(0.0);
+    preview.append(&status);
+
+    // Shown when a backup was written under a key that has since been replaced
+    let fallback = gtk::Box::new(gtk::Orientation::Vertical, 8);
+    fallback.set_visible(false);
+    let pass_entry = gtk::PasswordEntry::builder()
+        .show_peek_icon(true)
+        .placeholder_text("Passphrase at the time of the backup")
+        .build();
+    fallback.append(&pass_entry);
+    let (keyfile_row, keyfile) = build_keyfile_row(&dialog, false);
+    fallback.append(&keyfile_row);
+    let decrypt_btn = gtk::Button::with_label("Decrypt");
+    decrypt_btn.set_halign(gtk::Align::End);
+    fallback.append(&decrypt_btn);
+    preview.append(&fallback);
+
+    let tree_box = gtk::Box::new(gtk::Orientation::Vertical, 4);
+    let tree_scroller = gtk::ScrolledWindow::builder()
+        .child(&tree_box)
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .build();
+    preview.append(&tree_scroller);
+
+    let restore_notes_btn = gtk::Button::with_label("Restore Selected Notes");
+    restore_notes_btn.add_css_class("suggested-action");
+    restore_notes_btn.add_css_class("pill");
+    restore_notes_btn.set_sensitive(false);
+    let restore_all_btn = gtk::Button::with_label("Restore Entire Vault\u{2026}");
+    restore_all_btn.add_css_class("destructive-action");
+    restore_all_btn.add_css_class("pill");
+    restore_all_btn.set_sensitive(false);
+    let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    buttons.set_halign(gtk::Align::End);
+    buttons.append(&restore_all_btn);
+    buttons.append(&restore_notes_btn);
+    preview.append(&buttons);
+
+    let paned = gtk::Paned::new(gtk::Orientation::Horizontal);
+    paned.set_start_child(Some(&list_scroller));
+    paned.set_end_child(Some(&preview));
+    paned.set_shrink_start_child(false);
+    paned.set_position(240);
+    paned.set_vexpand(true);
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&adw::HeaderBar::new());
+    content.append(&paned);
+    dialog.set_content(Some(&content));
+
+    let backups = Rc::new(backups);
+    // The decrypted backup on display and one checkbox per note in it
+    let loaded: Rc<RefCell<Option<(i64, vault::VaultData)>>> = Rc::new(RefCell::new(None));
+    let checks: Rc<RefCell<Vec<(gtk::CheckButton, vault::TreeItem)>>> = Rc::new(RefCell::new(Vec::new()));
+    // Results of a decryption started before another row was picked are dropped
+    let request = Rc::new(Cell::new(0u64));
+
+    // Decrypt the selected backup with the open vault's key, or with an older
+    // passphrase (and keyfile) when one is given.
+    let decrypt: Rc<dyn Fn(Option<BackupCredentials>)> = {
+        let ctx = ctx.clone();
+        let backups = backups.clone();
+        let backup_list = backup_list.clone();
+        let status = status.clone();
+        let fallback = fallback.clone();
+        let pass_entry = pass_entry.clone();
+        let tree_box = tree_box.clone();
+        let restore_notes_btn = restore_notes_btn.clone();
+        let restore_all_btn = restore_all_btn.clone();
+        let loaded = loaded.clone();
+        let checks = checks.clone();
+        let request = request.clone();
+        Rc::new(move |credentials| {
+            let Some(row) = backup_list.selected_row() else { return };
+            let Some(backup) = backups.get(row.index() as usize).cloned() else { return };
+            let Some(key) = ctx.cached_key.borrow().clone() else { return };
+            let id = request.get() + 1;
+            request.set(id);
+
+            loaded.borrow_mut().take();
+            checks.borrow_mut().clear();
+            while let Some(child) = tree_box.first_child() {
+                tree_box.remove(&child);
+            }
+            restore_notes_btn.set_sensitive(false);
+            restore_all_btn.set_sensitive(false);
+            status.set_label("Decrypting backup\u{2026}");
+            if credentials.is_none() {
+                fallback.set_visible(false);
+            }
+
+            let policy = vault::load_config().plaintext_policy();
+            let (tx, rx) = std::sync::mpsc::channel::<Result<vault::VaultData, String>>();
+            std::thread::spawn(move || {
+                let result = (|| {
+                    let raw = fs::read_to_string(&backup.path).map_err(|e| format!("Read error: {e}"))?;
+                    let json = match credentials {
+                        None => crypto::decrypt_vault_with_key(&raw, &key, policy)
+                            .map_err(|_| "different_key".to_string())?,
+                        Some((passphrase, keyfile)) => {
+                            crypto::decrypt_vault_returning_key(&raw, &passphrase, keyfile.as_ref(), policy)
+                                .map(|(json, _)| json)
+                                .map_err(|e| match e {
+                                    crypto::CryptoError::KeyfileRequired => {
+                                        "This backup also requires its keyfile.".to_string()
+                                    }
+                                    _ => "Wrong passphrase or keyfile.".to_string(),
+                                })?
+                        }
+                    };
+                    let json = zeroize::Zeroizing::new(json);
+                    serde_json::from_str(&json).map_err(|e| format!("Corrupt backup: {e}"))
+                })();
+                let _ = tx.send(result);
+            });
+
+            let status = status.clone();
+            let fallback = fallback.clone();
+            let pass_entry = pass_entry.clone();
+            let tree_box = tree_box.clone();
+            let restore_notes_btn = restore_notes_btn.clone();
+            let restore_all_btn = restore_all_btn.clone();
+            let loaded = loaded.clone();
+            let checks = checks.clone();
+            let request = request.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(_) => return glib::ControlFlow::Break,
+                };
+                if request.get() != id {
+                    return glib::ControlFlow::Break;
+                }
+                match result {
+                    Ok(data) => {
+                        fallback.set_visible(false);
+                        pass_entry.set_text("");
+                        let mut items = Vec::new();
+                        append_backup_tree(&tree_box, &data.tree, 0, &mut items);
+                        status.set_label(&format!(
+                            "Backup from {}: {} notes. Check the notes to restore, or restore the entire vault.",
+                            format_ts(backup.created as i64),
+                            items.len()
+                        ));
+                        *checks.borrow_mut() = items;
+                        *loaded.borrow_mut() = Some((backup.created as i64, data));
+                        restore_notes_btn.set_sensitive(true);
+                        restore_all_btn.set_sensitive(true);
+                    }
+                    Err(e) if e == "different_key" => {
+                        status.set_label(
+                            "This backup was made before the vault key changed. Enter the passphrase it was saved with.",
+                        );
+                        fallback.set_visible(true);
+                        pass_entry.grab_focus();
+                    }
+                    Err(e) => status.set_label(&e),
+                }
+                glib::ControlFlow::Break
+            });
+        })
+    };
+
+    {
+        let decrypt = decrypt.clone();
+        backup_list.connect_row_selected(move |_, row| {
+            if row.is_some() {
+                decrypt(None);
+            }
+        });
+    }
+    {
+        let decrypt = decrypt.clone();
+        let pass_entry = pass_entry.clone();
+        decrypt_btn.connect_clicked(move |_| {
+            decrypt(Some((pass_entry.text().to_string(), keyfile.borrow().clone())));
+        });
+    }
+    {
+        let decrypt_btn = decrypt_btn.clone();
+        pass_entry.connect_activate(move |_| decrypt_btn.emit_clicked());
+    }
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let loaded = loaded.clone();
+        restore_notes_btn.connect_clicked(move |_| {
+            let notes: Vec<vault::TreeItem> = checks
+                .borrow()
+                .iter()
+                .filter(|(check, _)| check.is_active())
+                .map(|(_, item)| item.clone())
+                .collect();
+            if notes.is_empty() {
+                send_toast(&ctx, "Check the notes to restore first");
+                return;
+            }
+            let Some((_, data)) = loaded.borrow_mut().take() else { return };
+            dialog.close();
+            restore_backup_notes(&ctx, notes, &data.assets);
+        });
+    }
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        restore_all_btn.connect_clicked(move |_| {
+            let Some(created) = loaded.borrow().as_ref().map(|(created, _)| *created) else { return };
+            let confirm = adw::AlertDialog::new(
+                Some("Restore Entire Vault?"),
+                Some(&format!(
+                    "All notes will be replaced with the backup from {}. The current vault is backed up first, so this can be undone from the same list.",
+                    format_ts(created)
+                )),
+            );
+            confirm.add_response("cancel", "Cancel");
+            confirm.add_response("restore", "Restore Vault");
+            confirm.set_response_appearance("restore", adw::ResponseAppearance::Destructive);
+            confirm.set_default_response(Some("cancel"));
+            confirm.set_close_response("cancel");
+
+            let ctx = ctx.clone();
+            let dialog_c = dialog.clone();
+            let loaded = loaded.clone();
+            confirm.connect_response(None, move |_, response| {
+                if response != "restore" {
+                    return;
+                }
+                let Some((created, data)) = loaded.borrow_mut().take() else { return };
+                dialog_c.close();
+                restore_entire_vault(&ctx, data, created);
+            });
+            confirm.present(Some(&dialog));
+        });
+    }
+
+    dialog.present();
+    if let Some(first) = backup_list.row_at_index(0) {
+        backup_list.select_row(Some(&first));
+    }
+}
+
+/// Add one row per folder and one checkbox per note, indented by depth.
+fn append_backup_tree(
+    tree_box: &gtk::Box,
+    items: &[vault::TreeItem],
+    depth: i32,
+    checks: &mut Vec<(gtk::CheckButton, vault::TreeItem)>,
+) {
+    for item in items {
+        if item.item_type == "folder" {
+            let label = gtk::Label::new(Some(&item.name));
+            label.add_css_class("heading");
+            label.set_xalign(0.0);
+            label.set_margin_start(depth * 18);
+            tree_box.append(&label);
+            append_backup_tree(tree_box, item.children.as_deref().unwrap_or_default(), depth + 1, checks);
+        } else {
+            let check = gtk::CheckButton::with_label(&item.name);
+            check.set_margin_start(depth * 18);
+            tree_box.append(&check);
+            checks.push((check, item.clone()));
+        }
+    }
+}
+
+/// Re-create notes from a backup as new notes at the top level. Metadata for
+/// images they reference is brought back if the vault no longer has it.
+fn restore_backup_notes(
+    ctx: &EditorCtx,
+    notes: Vec<vault::TreeItem>,
+    assets: &HashMap<String, vault::AssetMeta>,
+) {
+    {
+        let mut state = ctx.state.borrow_mut();
+        for note in &notes {
+            for id in integrity::asset_ids_in(note.content.as_deref().unwrap_or_default()) {
+                if let Some(meta) = assets.get(id) {
+                    state.assets.entry(id.to_string()).or_insert_with(|| vault::AssetMeta {
+                        orphaned_at: None,
+                        ..meta.clone()
+                    });
+                }
+            }
+        }
+    }
+    let count = notes.len();
+    for note in notes {
+        create_note_in_folder(
+            ctx,
+            note.name,
+            note.content.unwrap_or_default(),
+            note.tags.unwrap_or_default(),
+            None,
+        );
+    }
+    send_toast(ctx, &format!("Restored {count} {}", if count == 1 { "note" } else { "notes" }));
+}
+
+/// Replace the whole vault with a backup. The current state is saved and
+/// backed up first; the editor is then rebuilt around the restored data.
+fn restore_entire_vault(ctx: &EditorCtx, restored: vault::VaultData, created: i64) {
+    if ctx.saving.get() {
+        send_toast(ctx, "A save is in progress \u{2014} try again in a moment");
+        return;
+    }
+    let journal_seq = journal_seq(ctx);
+    let Some((current, key, vault_folder)) = prepare_vault_save(ctx) else {
+        return;
+    };
+    if let Some(source_id) = ctx.save_timeout_id.take() {
+        source_id.remove();
+    }
+    // Holds off other saves until the editor is rebuilt
+    ctx.saving.set(true);
+
+    let expected = ctx.vault_file.borrow().clone();
+    let previous = ctx.note_files.borrow().clone();
+    let (tx, rx) = std::sync::mpsc::channel::<Result<NoteFiles, String>>();
+    {
+        let restored = restored.clone();
+        let vault_folder = vault_folder.clone();
+        std::thread::spawn(move || {
+            let result = (|| {
+                let current_backup = vault::encrypt_vault_data(&current, &key).map_err(|e| format!("Backup: {e}"))?;
+                let SaveOutcome::Saved(saved, _, files) =
+                    vault_save_blocking(current, &key, &vault_folder, expected.as_ref(), &previous)?
+                else {
+                    return Err(CHANGED_ELSEWHERE.to_string());
+                };
+                backup::create_backup(&vault_folder, &current_backup).map_err(|e| format!("Backup: {e}"))?;
+                match vault_save_blocking(restored, &key, &vault_folder, Some(&saved), &files)? {
+                    SaveOutcome::Saved(_, _, files) => Ok(files),
+                    SaveOutcome::Changed(..) => Err(CHANGED_ELSEWHERE.to_string()),
+                }
+            })();
+            let _ = tx.send(result);
+        });
+    }
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => Err("Restore thread disconnected".to_string()),
+        };
+        ctx.saving.set(false);
+        match result {
+            Ok(files) => {
+                journal_flushed(&ctx, journal_seq);
+                let writer_lock = ctx.writer_lock.borrow_mut().take();
+                let Some(key) = close_editor(&ctx) else {
+                    return glib::ControlFlow::Break;
+                };
+                let state = vault::vault_to_doc_state(restored.clone());
+                let new_ctx = build_editor(&ctx.window, state, vault_folder.clone(), key, writer_lock, files);
+                send_toast(&new_ctx, &format!("Restored the backup from {}", format_ts(created)));
+            }
+            Err(e) => {
+                show_error(&ctx.window, "Restore Failed", &e);
+                trigger_vault_save(&ctx);
+            }
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// Replace the recovery code, after confirming the old one may be discarded.
+fn confirm_new_recovery_code(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
+    let is_wrapped = ctx.cached_key.borrow().as_ref().is_some_and(|k| k.is_wrapped());
+    if !is_wrapped {
+        show_error(&ctx.window, "Recovery Code", "Unlock the vault again to upgrade it before creating a recovery code.");
+        return;
+    }
+    let has_code = ctx.cached_key.borrow().as_ref().is_some_and(|k| k.has_recovery_code());
+    let dialog = adw::AlertDialog::new(
+        Some("Create New Recovery Code?"),
+        Some(if has_code {
+            "A new recovery code will be generated. Your current recovery code will stop working."
+        } else {
+            "A recovery code lets you unlock the vault if you forget your passphrase."
+        }),
+    );
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("create", "Create Code");
+    dialog.set_response_appearance("create", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("create"));
+    dialog.set_close_response("cancel");
+
+    let ctx_for_response = ctx.clone();
+    dialog.connect_response(None, move |_, response| {
+        if response == "create" {
+            create_new_recovery_code(&ctx_for_response);
+        }
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+fn create_new_recovery_code(ctx: &EditorCtx) {
+    if ctx.saving.get() {
+        send_toast(ctx, "A save is in progress. Please try again in a moment.");
+        return;
+    }
+    if let Some(source_id) = ctx.save_timeout_id.take() {
+        source_id.remove();
+    }
+    let snapshot = current_markdown(ctx);
+    let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
+        return;
+    };
+    ctx.saving.set(true);
+    let gen = ctx.save_generation.get();
+
+    let expected = ctx.vault_file.borrow().clone();
+    let previous = ctx.note_files.borrow().clone();
+    let (tx, rx) = std::sync::mpsc::channel::<Result<(crypto::CachedKey, String, SaveOutcome), String>>();
+    std::thread::spawn(move || {
+        let result = (|| {
+            let mut new_key = key.clone();
+            let code = new_key
+                .add_recovery_code()
+                .map_err(|e| format!("Key generation failed: {e}"))?;
+            match vault_save_blocking(vault_data, &new_key, &vault_folder, expected.as_ref(), &previous)? {
+                saved @ SaveOutcome::Saved(..) => {
+                    // The replaced code must not open older backups either
+                    if let Err(e) = backup::rewrap_backups(&vault_folder, &new_key) {
+                        eprintln!("Warning: could not re-wrap backups: {e}");
+                    }
+                    Ok((new_key, code, saved))
+                }
+                SaveOutcome::Changed(..) => Err(CHANGED_ELSEWHERE.to_string()),
+            }
+        })();
+        let _ = tx.send(result);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => {
+                ctx.saving.set(false);
+                return glib::ControlFlow::Break;
+            }
+        };
+        ctx.saving.set(false);
+        match result {
+            Ok((new_key, code, saved)) => {
+                if let SaveOutcome::Saved(fingerprint, base, files) = saved {
+                    remember_vault_file(&ctx, fingerprint, base, files);
+                }
+                *ctx.cached_key.borrow_mut() = Some(new_key);
+                forget_revoked_device_key(&ctx.vault_folder.borrow());
+                {
+                    // Reads the state itself, so before it is borrowed here
+                    let current = current_markdown(&ctx);
+                    let mut state = ctx.state.borrow_mut();
+                    state.saved_snapshot = snapshot.clone();
+                    if current == snapshot {
+                        state.dirty = false;
+                    }
+                }
+                refresh_header(&ctx);
+                show_recovery_code_dialog(&ctx.window, &code);
+            }
+            Err(e) => {
+                if e == CHANGED_ELSEWHERE {
+                    trigger_vault_save(&ctx);
+                }
+                show_error(&ctx.window, "Recovery Code Failed", &e);
+            }
+        }
+        if ctx.save_generation.get() > gen {
+            perform_vault_save_async(&ctx, false);
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// After unlocking with the recovery code, ask for a new passphrase. The data
+/// key is already in memory, so only the passphrase slot is re-wrapped.
+pub fn show_reset_passphrase_dialog(ctx: &EditorCtx) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Set New Passphrase")
+        .default_width(420)
+        .default_height(280)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let subtitle = gtk::Label::new(Some(
+        "You unlocked the vault with its recovery code. Choose a new passphrase for future unlocks.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let pass1 = gtk::PasswordEntry::builder()
+        .placeholder_text("New passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass1);
+
+    let pass2 = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm new passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass2);
+
+    // A lost keyfile is a common reason to use the recovery code, so the
+    // new passphrase slot only requires one if the user picks it again.
+    let keyfile_label = gtk::Label::new(Some("Keyfile (optional)"));
+    keyfile_label.add_css_class("heading");
+    keyfile_label.set_xalign(0.0);
+    vbox.append(&keyfile_label);
+    let (keyfile_row, keyfile) = build_keyfile_row(&dialog, true);
+    vbox.append(&keyfile_row);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_wrap(true);
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let set_btn = gtk::Button::with_label("Set Passphrase");
+    set_btn.add_css_class("suggested-action");
+    set_btn.add_css_class("pill");
+    vbox.append(&set_btn);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let set_btn_inner = set_btn.clone();
+        set_btn.connect_clicked(move |_| {
+            let set_btn = set_btn_inner.clone();
+            let p1 = pass1.text().to_string();
+            let p2 = pass2.text().to_string();
+            if p1.len() < 8 {
+                error_label.set_label("Passphrase must be at least 8 characters.");
+                error_label.set_visible(true);
+                return;
+            }
+            if p1 != p2 {
+                error_label.set_label("Passphrases do not match.");
+                error_label.set_visible(true);
+                return;
+            }
+            let Some(key) = ctx.cached_key.borrow().clone() else { return };
+            let keyfile = keyfile.borrow().clone();
+            let vault_folder = ctx.vault_folder.borrow().clone();
+
+            set_btn.set_sensitive(false);
+            error_label.set_label("Setting passphrase\u{2026}");
+            error_label.remove_css_class("error");
+            error_label.set_visible(true);
+
+            let (tx, rx) = std::sync::mpsc::channel::<Result<crypto::CachedKey, String>>();
+            std::thread::spawn(move || {
+                let result = key.with_passphrase(&p1, keyfile.as_ref()).map_err(|e| e.to_string());
+                if let Ok(new_key) = &result {
+                    if let Err(e) = backup::rewrap_backups(&vault_folder, new_key) {
+                        eprintln!("Warning: could not re-wrap backups: {e}");
+                    }
+                }
+                let _ = tx.send(result);
+            });
+
+            let ctx = ctx.clone();
+            let dialog = dialog.clone();
+            let error_label = error_label.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(_) => return glib::ControlFlow::Break,
+                };
+                match result {
+                    Ok(new_key) => {
+                        // The next save writes the new passphrase slot
+                        *ctx.cached_key.borrow_mut() = Some(new_key);
+                        forget_revoked_device_key(&ctx.vault_folder.borrow());
+                        dialog.close();
+                        perform_vault_save_async(&ctx, false);
+                        send_toast(&ctx, "Passphrase set");
+                    }
+                    Err(e) => {
+                        error_label.add_css_class("error");
+                        error_label.set_label(&e);
+                        set_btn.set_sensitive(true);
+                    }
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+
+    dialog.present();
+}
+
+// ---------------------------------------------------------------------------
+// Vault bundles
+// ---------------------------------------------------------------------------
+
+fn bundle_file_filters() -> gtk::gio::ListStore {
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("Vault bundles"));
+    filter.add_pattern(&format!("*.{}", bundle::BUNDLE_EXTENSION));
+    let filters = gtk::gio::ListStore::new::<gtk::FileFilter>();
+    filters.append(&filter);
+    filters
+}
+
+/// Export the vault and the images its notes use as one file (see
+/// `bundle.rs`), under the vault's own passphrase or a new one.
+pub fn show_export_bundle_dialog(ctx: &EditorCtx) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Export Vault Bundle")
+        .default_width(420)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let subtitle = gtk::Label::new(Some(
+        "The bundle holds every note and the images they use, still encrypted. It opens with this vault\u{2019}s passphrase and keyfile, or with a new passphrase chosen here.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let use_new = gtk::CheckButton::with_label("Use a different passphrase");
+    vbox.append(&use_new);
+
+    let pass1 = gtk::PasswordEntry::builder()
+        .placeholder_text("Bundle passphrase")
+        .show_peek_icon(true)
+        .sensitive(false)
+        .build();
+    vbox.append(&pass1);
+
+    let pass2 = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm bundle passphrase")
+        .show_peek_icon(true)
+        .sensitive(false)
+        .build();
+    vbox.append(&pass2);
+
+    use_new.bind_property("active", &pass1, "sensitive").build();
+    use_new.bind_property("active", &pass2, "sensitive").build();
+
+    let error_label = gtk::Label::new(None);
     error_label.add_css_class("error");
+    error_label.set_wrap(true);
     error_label.set_visible(false);
     vbox.append(&error_label);
 
-    let create_btn = gtk::Button::with_label("Create Vault");
-    create_btn.add_css_class("suggested-action");
-    create_btn.add_css_class("pill");
-    vbox.append(&create_btn);
+    let export_btn = gtk::Button::with_label("Export\u{2026}");
+    export_btn.add_css_class("suggested-action");
+    export_btn.add_css_class("pill");
+    vbox.append(&export_btn);
 
-    let toolbar = adw::HeaderBar::new();
     let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
-    content.append(&toolbar);
+    content.append(&adw::HeaderBar::new());
     content.append(&vbox);
     dialog.set_content(Some(&content));
 
-    // Folder chooser
     {
-        let dialog_ref = dialog.clone();
-        let folder_label = folder_label.clone();
-        let folder_path = folder_path.clone();
-        folder_btn.connect_clicked(move |_| {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        export_btn.connect_clicked(move |_| {
+            let passphrase = if use_new.is_active() {
+                let p1 = zeroize::Zeroizing::new(pass1.text().to_string());
+                if p1.len() < 8 {
+                    error_label.set_label("Passphrase must be at least 8 characters.");
+                    error_label.set_visible(true);
+                    return;
+                }
+                if *p1 != pass2.text().as_str() {
+                    error_label.set_label("Passphrases do not match.");
+                    error_label.set_visible(true);
+                    return;
+                }
+                Some(p1)
+            } else {
+                None
+            };
+            let Some((data, key, vault_folder)) = prepare_vault_save(&ctx) else {
+                error_label.set_label("Vault is not unlocked.");
+                error_label.set_visible(true);
+                return;
+            };
+            dialog.close();
+
             let chooser = gtk::FileDialog::builder()
-                .title("Select Vault Folder")
-                .accept_label("Select")
+                .title("Export Vault Bundle")
+                .accept_label("Export")
+                .initial_name(bundle::bundle_file_name(&vault::load_config().vault_name(&vault_folder)))
+                .filters(&bundle_file_filters())
                 .build();
-            let fl = folder_label.clone();
-            let fp = folder_path.clone();
-            let dlg = dialog_ref.clone();
-            chooser.select_folder(Some(&dlg), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
-                if let Ok(file) = result {
-                    if let Some(path) = file.path() {
-                        let p = path.to_string_lossy().to_string();
-                        fl.set_label(&p);
-                        *fp.borrow_mut() = Some(p);
-                    }
+            let ctx = ctx.clone();
+            chooser.save(Some(&ctx.window.clone()), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
+                let Some(mut path) = result.ok().and_then(|file| file.path()) else { return };
+                if !bundle::is_bundle_path(&path) {
+                    path.set_extension(bundle::BUNDLE_EXTENSION);
                 }
+                send_toast(&ctx, "Exporting vault bundle\u{2026}");
+                let policy = vault::load_config().plaintext_policy();
+                let (tx, rx) = std::sync::mpsc::channel::<Result<bundle::ExportSummary, String>>();
+                std::thread::spawn(move || {
+                    let result = (|| {
+                        let new_key = match &passphrase {
+                            Some(p) => Some(crypto::CachedKey::generate(p, None).map_err(|e| e.to_string())?),
+                            None => None,
+                        };
+                        let (bytes, summary) = bundle::export_bundle(&vault_folder, &data, &key, new_key.as_ref(), policy)
+                            .map_err(|e| e.to_string())?;
+                        vault::atomic_write(&path, &bytes).map_err(|e| format!("Could not write the bundle: {e}"))?;
+                        Ok(summary)
+                    })();
+                    let _ = tx.send(result);
+                });
+                let ctx = ctx.clone();
+                glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                    let result = match rx.try_recv() {
+                        Ok(r) => r,
+                        Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                        Err(_) => return glib::ControlFlow::Break,
+                    };
+                    match result {
+                        Ok(summary) if summary.missing.is_empty() => send_toast(
+                            &ctx,
+                            &format!(
+                                "Exported {} {} and {} {}",
+                                summary.notes,
+                                if summary.notes == 1 { "note" } else { "notes" },
+                                summary.assets,
+                                if summary.assets == 1 { "image" } else { "images" },
+                            ),
+                        ),
+                        Ok(summary) => show_info(
+                            &ctx.window,
+                            "Vault Bundle Exported",
+                            &format!(
+                                "{} referenced {} could not be found in the vault and {} left out. Check Vault lists the notes that use them.",
+                                summary.missing.len(),
+                                if summary.missing.len() == 1 { "image" } else { "images" },
+                                if summary.missing.len() == 1 { "was" } else { "were" },
+                            ),
+                        ),
+                        Err(e) => show_error(&ctx.window, "Export Failed", &e),
+                    }
+                    glib::ControlFlow::Break
+                });
             });
         });
     }
 
-    // Create action
+    dialog.present();
+}
+
+/// Pick a vault bundle, unlock it, and restore it as a new vault or merge its
+/// notes into this one.
+pub fn import_vault_bundle(ctx: &EditorCtx) {
+    let chooser = gtk::FileDialog::builder()
+        .title("Import Vault Bundle")
+        .accept_label("Open")
+        .filters(&bundle_file_filters())
+        .build();
+    let ctx = ctx.clone();
+    chooser.open(Some(&ctx.window.clone()), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
+        if let Some(path) = result.ok().and_then(|file| file.path()) {
+            show_import_bundle_dialog(&ctx, path);
+        }
+    });
+}
+
+fn show_import_bundle_dialog(ctx: &EditorCtx, path: PathBuf) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Import Vault Bundle")
+        .default_width(420)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let subtitle = gtk::Label::new(Some(&format!(
+        "Enter the passphrase \u{201c}{file_name}\u{201d} was exported with."
+    )));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let pass_entry = gtk::PasswordEntry::builder()
+        .placeholder_text("Bundle passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass_entry);
+
+    // Only asked for once the bundle turns out to need one
+    let (keyfile_row, keyfile) = build_keyfile_row(&dialog, false);
+    keyfile_row.set_visible(false);
+    vbox.append(&keyfile_row);
+
+    let as_new = gtk::CheckButton::with_label("Restore as a new vault");
+    let merge_into = gtk::CheckButton::with_label("Merge its notes into this vault");
+    merge_into.set_group(Some(&as_new));
+    if ctx.read_only.get() {
+        as_new.set_active(true);
+        merge_into.set_sensitive(false);
+    } else {
+        merge_into.set_active(true);
+    }
+    vbox.append(&as_new);
+    vbox.append(&merge_into);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_wrap(true);
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let import_btn = gtk::Button::with_label("Import");
+    import_btn.add_css_class("suggested-action");
+    import_btn.add_css_class("pill");
+    vbox.append(&import_btn);
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&adw::HeaderBar::new());
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
     {
-        let window = window.clone();
+        let ctx = ctx.clone();
         let dialog = dialog.clone();
-        let folder_path = folder_path.clone();
-        let pass1 = pass1.clone();
-        let pass2 = pass2.clone();
-        let error_label = error_label.clone();
-        let create_btn_inner = create_btn.clone();
-        create_btn.connect_clicked(move |_| {
-            let create_btn = create_btn_inner.clone();
-            let p1 = pass1.text().to_string();
-            let p2 = pass2.text().to_string();
-            let fp = folder_path.borrow().clone();
-
-            if fp.is_none() {
-                error_label.set_label("Please select a folder.");
+        let import_btn_inner = import_btn.clone();
+        let pass_entry = pass_entry.clone();
+        import_btn.connect_clicked(move |_| {
+            let import_btn = import_btn_inner.clone();
+            let passphrase = zeroize::Zeroizing::new(pass_entry.text().to_string());
+            if passphrase.is_empty() {
+                error_label.set_label("Please enter the bundle\u{2019}s passphrase.");
                 error_label.set_visible(true);
                 return;
             }
-            if p1.len() < 8 {
+            let keyfile = keyfile.borrow().clone();
+            import_btn.set_sensitive(false);
+            error_label.set_label("Decrypting bundle\u{2026}");
+            error_label.remove_css_class("error");
+            error_label.set_visible(true);
+
+            let path = path.clone();
+            let (tx, rx) = std::sync::mpsc::channel::<Result<bundle::Bundle, Option<String>>>();
+            std::thread::spawn(move || {
+                let result = fs::read(&path)
+                    .and_then(|bytes| bundle::parse_bundle(&bytes))
+                    .map_err(|e| Some(e.to_string()))
+                    .and_then(|file| {
+                        file.open(&passphrase, keyfile.as_ref()).map_err(|e| match e {
+                            crypto::CryptoError::KeyfileRequired => None,
+                            crypto::CryptoError::InvalidData(msg) => Some(msg),
+                            _ => Some("Wrong passphrase or keyfile.".to_string()),
+                        })
+                    });
+                let _ = tx.send(result);
+            });
+
+            let ctx = ctx.clone();
+            let dialog = dialog.clone();
+            let error_label = error_label.clone();
+            let keyfile_row = keyfile_row.clone();
+            let merge = merge_into.is_active();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                    Err(_) => return glib::ControlFlow::Break,
+                };
+                match result {
+                    Ok(bundle) => {
+                        dialog.close();
+                        if merge {
+                            merge_bundle_into_vault(&ctx, bundle);
+                        } else {
+                            restore_bundle_as_vault(&ctx.window, bundle);
+                        }
+                    }
+                    Err(message) => {
+                        import_btn.set_sensitive(true);
+                        error_label.add_css_class("error");
+                        match message {
+                            Some(message) => error_label.set_label(&message),
+                            None => {
+                                error_label.set_label("This bundle also requires its keyfile.");
+                                keyfile_row.set_visible(true);
+                            }
+                        }
+                    }
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+    {
+        let import_btn = import_btn.clone();
+        pass_entry.connect_activate(move |_| import_btn.emit_clicked());
+    }
+
+    dialog.present();
+}
+
+/// Write an unlocked bundle out as a vault in a folder the user picks, list
+/// it among the known vaults, and offer to open it.
+fn restore_bundle_as_vault(window: &adw::ApplicationWindow, bundle: bundle::Bundle) {
+    let chooser = gtk::FileDialog::builder()
+        .title("Choose a Folder for the Restored Vault")
+        .accept_label("Restore Here")
+        .build();
+    let window_for_result = window.clone();
+    chooser.select_folder(Some(window), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
+        let Some(path) = result.ok().and_then(|file| file.path()) else { return };
+        let window = window_for_result.clone();
+        let folder = path.to_string_lossy().to_string();
+        if vault::vault_file_path(&folder).exists() {
+            show_error(
+                &window,
+                "Folder Holds a Vault",
+                &format!("{folder} already contains a vault. Choose an empty folder to restore the bundle into."),
+            );
+            return;
+        }
+
+        let (tx, rx) = std::sync::mpsc::channel::<Result<(), String>>();
+        {
+            let folder = folder.clone();
+            std::thread::spawn(move || {
+                let _ = tx.send(bundle.write_as_vault(&folder).map_err(|e| e.to_string()));
+            });
+        }
+        glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+            let result = match rx.try_recv() {
+                Ok(r) => r,
+                Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                Err(_) => return glib::ControlFlow::Break,
+            };
+            if let Err(e) = result {
+                show_error(&window, "Import Failed", &format!("Could not restore the vault: {e}"));
+                return glib::ControlFlow::Break;
+            }
+            let config = vault::update_config(|config| config.add_vault(&folder));
+
+            let done = adw::AlertDialog::new(
+                Some("Vault Restored"),
+                Some(&format!(
+                    "\u{201c}{}\u{201d} is now listed among your vaults. It opens with the bundle\u{2019}s passphrase.",
+                    config.vault_name(&folder)
+                )),
+            );
+            done.add_response("close", "Close");
+            done.add_response("open", "Open in New Window");
+            done.set_response_appearance("open", adw::ResponseAppearance::Suggested);
+            done.set_default_response(Some("open"));
+            done.set_close_response("close");
+            let app_window = window.clone();
+            let folder = folder.clone();
+            done.connect_response(None, move |_, response| {
+                if response != "open" {
+                    return;
+                }
+                if let Some(app) = app_window.application().and_downcast::<adw::Application>() {
+                    new_vault_window(&app, Some(folder.clone()));
+                }
+            });
+            done.present(Some(&window));
+            glib::ControlFlow::Break
+        });
+    });
+}
+
+/// Store an unlocked bundle's images under this vault's key, then add its
+/// notes with fresh IDs (see `merge::merge_imported`) and save.
+fn merge_bundle_into_vault(ctx: &EditorCtx, bundle: bundle::Bundle) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
+    let Some(key) = ctx.cached_key.borrow().clone() else { return };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    send_toast(ctx, "Importing vault bundle\u{2026}");
+
+    let (tx, rx) = std::sync::mpsc::channel::<(bundle::Bundle, Result<usize, String>)>();
+    std::thread::spawn(move || {
+        let result = bundle.import_assets(&vault_folder, &key).map_err(|e| e.to_string());
+        let _ = tx.send((bundle, result));
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let (bundle, result) = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        if ctx.locked.get() || refuse_in_read_only(&ctx) {
+            return glib::ControlFlow::Break;
+        }
+        if let Err(e) = result {
+            show_error(&ctx.window, "Import Failed", &format!("Could not store the bundle\u{2019}s images: {e}"));
+            return glib::ControlFlow::Break;
+        }
+
+        let editor_text = current_markdown(&ctx);
+        update_active_note_content(&ctx, &editor_text);
+        let report = merge::merge_imported(&mut ctx.state.borrow_mut(), vault::vault_to_doc_state(bundle.data));
+        refresh_note_list(&ctx);
+        refresh_tabs(&ctx);
+        refresh_tags(&ctx);
+        trigger_vault_save(&ctx);
+
+        let mut message = format!(
+            "Imported {} {}",
+            report.added,
+            if report.added == 1 { "note" } else { "notes" }
+        );
+        if report.skipped > 0 {
+            message.push_str(&format!(" ({} already here)", report.skipped));
+        }
+        send_toast(&ctx, &message);
+        glib::ControlFlow::Break
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Shared notes
+// ---------------------------------------------------------------------------
+
+fn share_file_filters() -> gtk::gio::ListStore {
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("Shared notes"));
+    filter.add_pattern(&format!("*.{}", share::SHARE_EXTENSION));
+    let filters = gtk::gio::ListStore::new::<gtk::FileFilter>();
+    filters.append(&filter);
+    filters
+}
+
+/// Write the active note and its images to a file encrypted under a
+/// passphrase of its own (see `share.rs`).
+pub fn show_share_note_dialog(ctx: &EditorCtx) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Share Note Encrypted")
+        .default_width(420)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let subtitle = gtk::Label::new(Some(
+        "Choose a passphrase just for this note and tell it to the recipient some other way than the file. They open it with \u{201c}Import Shared Note\u{201d}.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let pass1 = gtk::PasswordEntry::builder()
+        .placeholder_text("Passphrase for this note")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass1);
+
+    let pass2 = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass2);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_wrap(true);
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let share_btn = gtk::Button::with_label("Share\u{2026}");
+    share_btn.add_css_class("suggested-action");
+    share_btn.add_css_class("pill");
+    vbox.append(&share_btn);
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&adw::HeaderBar::new());
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        share_btn.connect_clicked(move |_| {
+            let passphrase = zeroize::Zeroizing::new(pass1.text().to_string());
+            if passphrase.len() < 8 {
                 error_label.set_label("Passphrase must be at least 8 characters.");
                 error_label.set_visible(true);
                 return;
             }
-            if p1 != p2 {
+            if *passphrase != pass2.text().as_str() {
                 error_label.set_label("Passphrases do not match.");
                 error_label.set_visible(true);
                 return;
             }
-
-            let Some(vault_folder) = fp else {
-                error_label.set_label("Please select a folder.");
-                error_label.set_visible(true);
-                return;
-            };
-            let default_state = DocState::default();
-            let vault_data = vault::doc_state_to_vault(&default_state);
-            let json = match serde_json::to_string_pretty(&vault_data) {
-                Ok(j) => j,
-                Err(e) => {
-                    error_label.set_label(&format!("Serialization failed: {e}"));
-                    error_label.add_css_class("error");
-                    error_label.set_visible(true);
-                    return;
-                }
+            let Some(key) = ctx.cached_key.borrow().clone() else { return };
+            let markdown = current_markdown(&ctx);
+            let (name, tags, assets) = {
+                let state = ctx.state.borrow();
+                let Some(index) = find_note_index(&state.notes, &state.active_note_id) else { return };
+                (state.notes[index].name.clone(), state.notes[index].tags.clone(), state.assets.clone())
             };
+            let vault_folder = ctx.vault_folder.borrow().clone();
+            dialog.close();
 
-            // Show spinner while deriving key (PBKDF2 is expensive)
-            create_btn.set_sensitive(false);
-            error_label.set_label("Creating vault\u{2026}");
-            error_label.remove_css_class("error");
-            error_label.set_visible(true);
-
-            let (tx, rx) = std::sync::mpsc::channel::<Result<crypto::CachedKey, String>>();
-            let vault_folder_for_thread = vault_folder.clone();
-            std::thread::spawn(move || {
-                let cached_key = crypto::CachedKey::derive(&p1);
-                match crypto::encrypt_vault_fast(&json, &cached_key) {
-                    Ok(encrypted) => {
-                        if let Err(e) = vault::write_vault_raw(&vault_folder_for_thread, &encrypted) {
-                            let _ = tx.send(Err(format!("Write failed: {e}")));
-                        } else {
-                            let _ = tx.send(Ok(cached_key));
-                        }
-                    }
-                    Err(e) => { let _ = tx.send(Err(format!("Encryption failed: {e}"))); }
+            let chooser = gtk::FileDialog::builder()
+                .title("Share Note Encrypted")
+                .accept_label("Save")
+                .initial_name(share::share_file_name(&name))
+                .filters(&share_file_filters())
+                .build();
+            let ctx = ctx.clone();
+            chooser.save(Some(&ctx.window.clone()), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
+                let Some(mut path) = result.ok().and_then(|file| file.path()) else { return };
+                if !share::is_share_path(&path) {
+                    path.set_extension(share::SHARE_EXTENSION);
                 }
-            });
-
-            let window = window.clone();
-            let dialog = dialog.clone();
-            let vault_folder_c = vault_folder.clone();
-            let error_label = error_label.clone();
-            let create_btn = create_btn.clone();
-            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
-                let result = match rx.try_recv() {
-                    Ok(r) => r,
-                    Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
-                    Err(_) => return glib::ControlFlow::Break,
-                };
-                match result {
-                    Ok(cached_key) => {
-                        let _ = vault::save_config(&vault::AppConfig {
-                            vault_path: Some(vault_folder_c.clone()),
-                        });
-                        gtk::prelude::GtkWindowExt::set_focus(&window, gtk::Widget::NONE);
-                        dialog.close();
-                        build_editor(&window, DocState::default(), vault_folder_c.clone(), cached_key);
-                    }
-                    Err(e) => {
-                        error_label.add_css_class("error");
-                        error_label.set_label(&e);
-                        create_btn.set_sensitive(true);
-                    }
+                let (tx, rx) = std::sync::mpsc::channel::<Result<Vec<String>, String>>();
+                {
+                    let markdown = zeroize::Zeroizing::new(markdown.clone());
+                    let name = name.clone();
+                    let tags = tags.clone();
+                    let assets = assets.clone();
+                    let key = key.clone();
+                    let vault_folder = vault_folder.clone();
+                    let passphrase = passphrase.clone();
+                    let policy = vault::load_config().plaintext_policy();
+                    std::thread::spawn(move || {
+                        let result = share::share_note(&vault_folder, &name, &markdown, &tags, &assets, &key, &passphrase, policy)
+                            .map_err(|e| e.to_string())
+                            .and_then(|(bytes, missing)| {
+                                vault::atomic_write(&path, &bytes)
+                                    .map_err(|e| format!("Could not write the file: {e}"))?;
+                                Ok(missing)
+                            });
+                        let _ = tx.send(result);
+                    });
                 }
-                glib::ControlFlow::Break
+                let ctx = ctx.clone();
+                let name = name.clone();
+                glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                    let result = match rx.try_recv() {
+                        Ok(r) => r,
+                        Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                        Err(_) => return glib::ControlFlow::Break,
+                    };
+                    match result {
+                        Ok(missing) if missing.is_empty() => send_toast(&ctx, &format!("Shared \u{201c}{name}\u{201d}")),
+                        Ok(missing) => show_info(
+                            &ctx.window,
+                            "Note Shared",
+                            &format!(
+                                "{} {} in \u{201c}{name}\u{201d} could not be found in the vault and {} left out.",
+                                missing.len(),
+                                if missing.len() == 1 { "image" } else { "images" },
+                                if missing.len() == 1 { "was" } else { "were" },
+                            ),
+                        ),
+                        Err(e) => show_error(&ctx.window, "Sharing Failed", &e),
+                    }
+                    glib::ControlFlow::Break
+                });
             });
         });
     }
@@ -4999,13 +9212,31 @@ pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
     dialog.present();
 }
 
-pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: String) {
+/// Pick a shared note, decrypt it with its passphrase, and add it to this
+/// vault as a new note with its images stored as this vault's assets.
+pub fn import_shared_note(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
+    let chooser = gtk::FileDialog::builder()
+        .title("Import Shared Note")
+        .accept_label("Open")
+        .filters(&share_file_filters())
+        .build();
+    let ctx = ctx.clone();
+    chooser.open(Some(&ctx.window.clone()), gtk::gio::Cancellable::NONE, move |result: Result<gtk::gio::File, gtk::glib::Error>| {
+        if let Some(path) = result.ok().and_then(|file| file.path()) {
+            show_import_shared_note_dialog(&ctx, path);
+        }
+    });
+}
+
+fn show_import_shared_note_dialog(ctx: &EditorCtx, path: PathBuf) {
     let dialog = adw::Window::builder()
-        .transient_for(window)
+        .transient_for(&ctx.window)
         .modal(true)
-        .title("Unlock Vault")
-        .default_width(400)
-        .default_height(260)
+        .title("Import Shared Note")
+        .default_width(420)
         .build();
 
     let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
@@ -5014,11 +9245,10 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
     vbox.set_margin_top(24);
     vbox.set_margin_bottom(24);
 
-    let title = gtk::Label::new(Some("Unlock Your Vault"));
-    title.add_css_class("title-2");
-    vbox.append(&title);
-
-    let subtitle = gtk::Label::new(Some("Enter your passphrase to decrypt your notes."));
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let subtitle = gtk::Label::new(Some(&format!(
+        "Enter the passphrase \u{201c}{file_name}\u{201d} was shared with."
+    )));
     subtitle.add_css_class("dim-label");
     subtitle.set_wrap(true);
     vbox.append(&subtitle);
@@ -5031,83 +9261,68 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
 
     let error_label = gtk::Label::new(None);
     error_label.add_css_class("error");
+    error_label.set_wrap(true);
     error_label.set_visible(false);
     vbox.append(&error_label);
 
-    let btn_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-    let unlock_btn = gtk::Button::with_label("Unlock");
-    unlock_btn.add_css_class("suggested-action");
-    unlock_btn.add_css_class("pill");
-    unlock_btn.set_hexpand(true);
-    let change_btn = gtk::Button::with_label("Change Vault");
-    change_btn.add_css_class("flat");
-    btn_row.append(&unlock_btn);
-    btn_row.append(&change_btn);
-    vbox.append(&btn_row);
+    let import_btn = gtk::Button::with_label("Import");
+    import_btn.add_css_class("suggested-action");
+    import_btn.add_css_class("pill");
+    vbox.append(&import_btn);
 
-    let toolbar = adw::HeaderBar::new();
     let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
-    content.append(&toolbar);
+    content.append(&adw::HeaderBar::new());
     content.append(&vbox);
     dialog.set_content(Some(&content));
 
-    // Unlock action
     {
-        let window = window.clone();
+        let ctx = ctx.clone();
         let dialog = dialog.clone();
-        let vault_folder = vault_folder.clone();
-        let pass_entry_for_activate = pass_entry.clone();
+        let import_btn_inner = import_btn.clone();
         let pass_entry = pass_entry.clone();
-        let error_label = error_label.clone();
-        let unlock_btn = unlock_btn.clone();
-        let unlock_btn_for_connect = unlock_btn.clone();
-        let do_unlock = move || {
-            let passphrase = pass_entry.text().to_string();
+        import_btn.connect_clicked(move |_| {
+            let import_btn = import_btn_inner.clone();
+            let passphrase = zeroize::Zeroizing::new(pass_entry.text().to_string());
             if passphrase.is_empty() {
-                error_label.set_label("Please enter your passphrase.");
+                error_label.set_label("Please enter the passphrase.");
                 error_label.set_visible(true);
                 return;
             }
-
-            let vault_folder_thread = vault_folder.clone();
-
-            // Show spinner while decrypting (PBKDF2 is expensive)
-            unlock_btn.set_sensitive(false);
-            pass_entry.set_sensitive(false);
-            error_label.set_label("Unlocking\u{2026}");
+            let Some(key) = ctx.cached_key.borrow().clone() else { return };
+            let vault_folder = ctx.vault_folder.borrow().clone();
+            import_btn.set_sensitive(false);
+            error_label.set_label("Decrypting note\u{2026}");
             error_label.remove_css_class("error");
             error_label.set_visible(true);
 
-            let (tx, rx) = std::sync::mpsc::channel::<Result<(String, crypto::CachedKey), String>>();
+            let path = path.clone();
+            type Imported = (share::SharedNote, Vec<vault::AssetMeta>);
+            let (tx, rx) = std::sync::mpsc::channel::<Result<Imported, String>>();
             std::thread::spawn(move || {
-                let raw = match vault::read_vault_raw(&vault_folder_thread) {
-                    Ok(Some(data)) => data,
-                    Ok(None) => {
-                        let _ = tx.send(Err("Vault file not found.".to_string()));
-                        return;
-                    }
-                    Err(e) => {
-                        let _ = tx.send(Err(format!("Read error: {e}")));
-                        return;
-                    }
-                };
-
-                match crypto::decrypt_vault_returning_key(&raw, &passphrase) {
-                    Ok((json, cached_key)) => {
-                        let _ = tx.send(Ok((json, cached_key)));
-                    }
-                    Err(_) => {
-                        let _ = tx.send(Err("wrong_passphrase".to_string()));
-                    }
-                }
+                let result = fs::read(&path)
+                    .and_then(|bytes| share::parse_share_file(&bytes))
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        file.open(&passphrase).map_err(|e| match e {
+                            crypto::CryptoError::InvalidData(msg) => msg,
+                            _ => "Wrong passphrase.".to_string(),
+                        })
+                    })
+                    .and_then(|opened| {
+                        let now = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as i64;
+                        opened
+                            .store(&vault_folder, &key, generate_asset_id, now)
+                            .map_err(|e| format!("Could not store the note\u{2019}s images: {e}"))
+                    });
+                let _ = tx.send(result);
             });
 
-            let window = window.clone();
+            let ctx = ctx.clone();
             let dialog = dialog.clone();
-            let vault_folder = vault_folder.clone();
             let error_label = error_label.clone();
-            let pass_entry = pass_entry.clone();
-            let unlock_btn = unlock_btn.clone();
             glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
                 let result = match rx.try_recv() {
                     Ok(r) => r,
@@ -5115,59 +9330,311 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
                     Err(_) => return glib::ControlFlow::Break,
                 };
                 match result {
-                    Ok((json, cached_key)) => {
-                        let vault_data: vault::VaultData = match serde_json::from_str(&json) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error_label.add_css_class("error");
-                                error_label.set_label(&format!("Corrupt vault: {e}"));
-                                unlock_btn.set_sensitive(true);
-                                pass_entry.set_sensitive(true);
-                                return glib::ControlFlow::Break;
-                            }
-                        };
-                        let state = vault::vault_to_doc_state(vault_data);
-                        gtk::prelude::GtkWindowExt::set_focus(&window, gtk::Widget::NONE);
+                    Ok((note, assets)) => {
                         dialog.close();
-                        build_editor(&window, state, vault_folder.clone(), cached_key);
+                        if ctx.locked.get() {
+                            return glib::ControlFlow::Break;
+                        }
+                        {
+                            let mut state = ctx.state.borrow_mut();
+                            for meta in assets {
+                                state.assets.insert(meta.id.clone(), meta);
+                            }
+                        }
+                        let name = note.name.clone();
+                        create_note_in_folder(&ctx, note.name, note.content, note.tags, None);
+                        send_toast(&ctx, &format!("Imported \u{201c}{name}\u{201d}"));
                     }
                     Err(e) => {
+                        import_btn.set_sensitive(true);
                         error_label.add_css_class("error");
-                        if e == "wrong_passphrase" {
-                            error_label.set_label("Wrong passphrase. Try again.");
-                            pass_entry.set_text("");
-                            pass_entry.grab_focus();
-                        } else {
-                            error_label.set_label(&e);
-                        }
-                        unlock_btn.set_sensitive(true);
-                        pass_entry.set_sensitive(true);
+                        error_label.set_label(&e);
+                    }
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+    {
+        let import_btn = import_btn.clone();
+        pass_entry.connect_activate(move |_| import_btn.emit_clicked());
+    }
+
+    dialog.present();
+}
+
+// ---------------------------------------------------------------------------
+// Remembered vault keys
+// ---------------------------------------------------------------------------
+
+/// Unlock the vault in `vault_folder` with the device key remembered on this
+/// device (see `keyring.rs`), or ask for the passphrase if there is none. A
+/// key the vault no longer has a slot for is forgotten; a vault that can't be
+/// read is reported and the key kept.
+pub fn unlock_vault(window: &adw::ApplicationWindow, vault_folder: String) {
+    if !vault::load_config().is_key_remembered(&vault_folder) {
+        show_unlock_vault_dialog(window, vault_folder);
+        return;
+    }
+
+    enum Outcome {
+        Unlocked(Box<(vault::VaultData, NoteFiles, crypto::CachedKey)>),
+        /// The keyring has no key for the vault, or one that doesn't open it
+        Stale,
+        /// The keyring couldn't be reached
+        Failed(String),
+        /// The vault file couldn't be read or decrypted, e.g. while a sync is
+        /// half done or after a newer version wrote it
+        Unreadable(String),
+    }
+    let (tx, rx) = std::sync::mpsc::channel::<Outcome>();
+    let folder = vault_folder.clone();
+    std::thread::spawn(move || {
+        let outcome = (|| {
+            let Some(secret) = keyring::lookup_secret(&folder).map_err(|e| Outcome::Failed(e.to_string()))? else {
+                return Ok(Outcome::Stale);
+            };
+            let raw = vault::read_vault_raw_pending(&folder)
+                .map_err(|e| Outcome::Unreadable(format!("Read error: {e}")))?
+                .ok_or_else(|| Outcome::Unreadable("Vault file not found.".to_string()))?;
+            let (json, key) = match crypto::decrypt_vault_with_device_key(&raw, &secret) {
+                Ok(unlocked) => unlocked,
+                Err(crypto::CryptoError::DeviceKeyRevoked) => {
+                    if let Err(e) = keyring::delete_secret(&folder) {
+                        eprintln!("Failed to forget vault key: {e}");
+                    }
+                    return Ok(Outcome::Stale);
+                }
+                Err(e) => return Err(Outcome::Unreadable(e.to_string())),
+            };
+            let json = zeroize::Zeroizing::new(json);
+            let (data, files) =
+                vault::load_vault_json(&folder, &json, &key).map_err(|e| Outcome::Unreadable(e.to_string()))?;
+            Ok(Outcome::Unlocked(Box::new((data, files, key))))
+        })();
+        let _ = tx.send(outcome.unwrap_or_else(|failed| failed));
+    });
+
+    let window = window.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let outcome = match rx.try_recv() {
+            Ok(outcome) => outcome,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        match outcome {
+            Outcome::Unlocked(unlocked) => {
+                let (data, note_files, key) = *unlocked;
+                let state = vault::vault_to_doc_state(data);
+                let failed = unlock_attempts::note_success(&vault_folder, unix_now());
+                open_vault_editor(&window, state, vault_folder.clone(), key, note_files, false, move |ctx| {
+                    send_toast(ctx, "Unlocked with the key remembered on this device");
+                    report_failed_unlocks(ctx, failed, 0);
+                });
+            }
+            Outcome::Stale => {
+                vault::update_config(|config| config.set_key_remembered(&vault_folder, false));
+                show_unlock_vault_dialog(&window, vault_folder.clone());
+            }
+            Outcome::Failed(e) => {
+                // Keep remembering: the keyring may just not be running yet
+                eprintln!("Could not unlock with the remembered key: {e}");
+                show_unlock_vault_dialog(&window, vault_folder.clone());
+            }
+            Outcome::Unreadable(e) => {
+                // Keep remembering: the key may open the vault once it is whole again
+                show_unlock_vault_dialog(&window, vault_folder.clone());
+                show_error(
+                    &window,
+                    "Could Not Open Vault",
+                    &format!("The vault file could not be read, so it was not unlocked with the key remembered on this device. If it is still syncing, try again once it has finished.\n\n{e}"),
+                );
+            }
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// Give this device its own key slot in the vault and keep the key that opens
+/// it in the keyring, so the next launch unlocks without asking. The slot is
+/// written by the save that follows.
+fn remember_vault_key(ctx: &EditorCtx) {
+    let key = ctx.cached_key.borrow().clone().filter(|key| key.is_wrapped());
+    let Some(mut key) = key.filter(|_| !ctx.read_only.get()) else {
+        send_toast(ctx, "This vault can be remembered once it has been unlocked for writing");
+        return;
+    };
+    if ctx.saving.get() {
+        send_toast(ctx, "A save is in progress. Please try again in a moment.");
+        return;
+    }
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let label = keyring::item_label(&vault::load_config().vault_name(&vault_folder));
+    ctx.saving.set(true);
+    let gen = ctx.save_generation.get();
+
+    let (tx, rx) = std::sync::mpsc::channel::<Result<crypto::CachedKey, String>>();
+    {
+        let vault_folder = vault_folder.clone();
+        std::thread::spawn(move || {
+            let result = (|| {
+                // Remembering again replaces the slot of the earlier key
+                if let Ok(Some(earlier)) = keyring::lookup_secret(&vault_folder) {
+                    key.remove_device_slot(&earlier);
+                }
+                let device_key = key.add_device_slot().map_err(|e| e.to_string())?;
+                keyring::store_secret(&vault_folder, &label, &device_key).map_err(|e| e.to_string())?;
+                Ok(key)
+            })();
+            let _ = tx.send(result);
+        });
+    }
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => Err("Keyring thread disconnected".to_string()),
+        };
+        ctx.saving.set(false);
+        match result {
+            Ok(key) => {
+                *ctx.cached_key.borrow_mut() = Some(key);
+                vault::update_config(|config| config.set_key_remembered(&vault_folder, true));
+                perform_vault_save_async(&ctx, false);
+                send_toast(&ctx, "This device will unlock the vault without asking");
+            }
+            Err(e) => {
+                show_error(
+                    &ctx.window,
+                    "Could Not Remember Vault",
+                    &format!("The device key could not be stored in the system keyring.\n\n{e}"),
+                );
+                if ctx.save_generation.get() > gen {
+                    perform_vault_save_async(&ctx, false);
+                }
+            }
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// Drop the slot the remembered device key opens, save, and only then delete
+/// the key from the keyring; the passphrase is asked for again from the next
+/// unlock on. If the save fails the key stays, so it still matches the vault.
+fn forget_vault_key(ctx: &EditorCtx) {
+    if ctx.read_only.get() {
+        send_toast(ctx, "This vault can be forgotten once it has been unlocked for writing");
+        return;
+    }
+    if ctx.saving.get() {
+        send_toast(ctx, "A save is in progress. Please try again in a moment.");
+        return;
+    }
+    if let Some(source_id) = ctx.save_timeout_id.take() {
+        source_id.remove();
+    }
+    let snapshot = current_markdown(ctx);
+    let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
+        return;
+    };
+    ctx.saving.set(true);
+    let gen = ctx.save_generation.get();
+
+    let expected = ctx.vault_file.borrow().clone();
+    let previous = ctx.note_files.borrow().clone();
+    type Forgotten = (bool, Option<(crypto::CachedKey, SaveOutcome)>);
+    let (tx, rx) = std::sync::mpsc::channel::<Result<Forgotten, String>>();
+    {
+        let vault_folder = vault_folder.clone();
+        std::thread::spawn(move || {
+            let result = (|| {
+                let keyring_error = |e: std::io::Error| {
+                    format!("The vault key could not be removed from the system keyring.\n\n{e}")
+                };
+                let Some(device_key) = keyring::lookup_secret(&vault_folder).map_err(keyring_error)? else {
+                    return Ok((false, None));
+                };
+                let mut new_key = key;
+                let saved = if new_key.remove_device_slot(&device_key) {
+                    match vault_save_blocking(vault_data, &new_key, &vault_folder, expected.as_ref(), &previous)? {
+                        saved @ SaveOutcome::Saved(..) => Some((new_key, saved)),
+                        SaveOutcome::Changed(..) => return Err(CHANGED_ELSEWHERE.to_string()),
+                    }
+                } else {
+                    None
+                };
+                let found = keyring::delete_secret(&vault_folder).map_err(keyring_error)?;
+                Ok((found, saved))
+            })();
+            let _ = tx.send(result);
+        });
+    }
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => Err("Keyring thread disconnected".to_string()),
+        };
+        ctx.saving.set(false);
+        match result {
+            Ok((found, saved)) => {
+                if let Some((new_key, SaveOutcome::Saved(fingerprint, base, files))) = saved {
+                    remember_vault_file(&ctx, fingerprint, base, files);
+                    *ctx.cached_key.borrow_mut() = Some(new_key);
+                    let current = current_markdown(&ctx);
+                    let mut state = ctx.state.borrow_mut();
+                    state.saved_snapshot = snapshot.clone();
+                    if current == snapshot {
+                        state.dirty = false;
                     }
                 }
-                glib::ControlFlow::Break
-            });
-        };
-
-        let do_unlock_click = do_unlock.clone();
-        unlock_btn_for_connect.connect_clicked(move |_| do_unlock_click());
+                refresh_header(&ctx);
+                let mut remembered = false;
+                vault::update_config(|config| {
+                    remembered = config.is_key_remembered(&vault_folder);
+                    config.set_key_remembered(&vault_folder, false);
+                });
+                send_toast(&ctx, if found || remembered {
+                    "This device no longer remembers the vault key"
+                } else {
+                    "This device had not remembered the vault key"
+                });
+            }
+            Err(e) => {
+                if e == CHANGED_ELSEWHERE {
+                    trigger_vault_save(&ctx);
+                }
+                show_error(&ctx.window, "Could Not Forget Vault", &e);
+            }
+        }
+        if ctx.save_generation.get() > gen {
+            perform_vault_save_async(&ctx, false);
+        }
+        glib::ControlFlow::Break
+    });
+}
 
-        let do_unlock_enter = do_unlock;
-        pass_entry_for_activate.connect_activate(move |_| do_unlock_enter());
+/// A passphrase or recovery code change drops every device slot, so a key
+/// this device remembered no longer opens the vault; forget it here too.
+fn forget_revoked_device_key(vault_folder: &str) {
+    let mut config = vault::load_config();
+    if !config.is_key_remembered(vault_folder) {
+        return;
     }
-
-    // Change vault
-    {
-        let window = window.clone();
-        let dialog = dialog.clone();
-        change_btn.connect_clicked(move |_| {
-            let _ = vault::save_config(&vault::AppConfig { vault_path: None });
-            dialog.close();
-            show_create_vault_dialog(&window);
-        });
+    config.set_key_remembered(vault_folder, false);
+    if let Err(e) = vault::save_config(&config) {
+        eprintln!("Failed to save config: {e}");
     }
-
-    dialog.present();
-    pass_entry.grab_focus();
+    let vault_folder = vault_folder.to_string();
+    std::thread::spawn(move || {
+        if let Err(e) = keyring::delete_secret(&vault_folder) {
+            eprintln!("Failed to forget vault key: {e}");
+        }
+    });
 }
 
 // ---------------------------------------------------------------------------
@@ -5218,6 +9685,9 @@ pub fn toggle_theme(ctx: &EditorCtx) {
 // ---------------------------------------------------------------------------
 
 pub fn trigger_vault_save(ctx: &EditorCtx) {
+    if ctx.read_only.get() {
+        return;
+    }
     if let Some(source_id) = ctx.save_timeout_id.take() {
         source_id.remove();
     }
@@ -5253,52 +9723,134 @@ pub fn prepare_vault_save(ctx: &EditorCtx) -> Option<(vault::VaultData, crypto::
     Some((vault_data, key, vault_folder))
 }
 
-/// Perform serialization + encryption + write (expensive, blocking).
+pub enum SaveOutcome {
+    /// Written; the new file fingerprint, merge base and note files.
+    Saved(vault::VaultFingerprint, merge::MergeBase, NoteFiles),
+    /// vault.json was changed elsewhere since `expected`; nothing was written.
+    /// Holds the decrypted foreign version to merge and its note files.
+    Changed(Box<vault::VaultData>, vault::VaultFingerprint, NoteFiles),
+}
+
+const CHANGED_ELSEWHERE: &str =
+    "The vault file was changed on another device. Your notes will be merged with it; try again afterwards.";
+
+/// Merges a save attempts before giving up on a vault file that changes
+/// again each time.
+const MAX_SAVE_MERGES: u32 = 3;
+
+const KEEPS_CHANGING: &str = "The vault file keeps changing on another device. \
+     Your notes were merged with its latest version but not saved yet; try again once the other device is idle.";
+
+/// Perform serialization + encryption + write (expensive, blocking). Only
+/// notes changed since `previous` are re-encrypted. Refuses to overwrite a
+/// vault file that no longer matches `expected`.
 pub fn vault_save_blocking(
     vault_data: vault::VaultData,
     key: &crypto::CachedKey,
     vault_folder: &str,
-) -> Result<(), String> {
-    use zeroize::Zeroize;
-    let mut json = serde_json::to_string_pretty(&vault_data)
-        .map_err(|e| format!("Serialization: {e}"))?;
-    let result = crypto::encrypt_vault_fast(&json, key)
-        .map_err(|e| format!("Encryption: {e}"));
-    json.zeroize(); // Wipe plaintext vault contents from memory
-    let encrypted = result?;
-    vault::write_vault_raw(vault_folder, &encrypted)
-        .map_err(|e| format!("Write: {e}"))?;
-    Ok(())
+    expected: Option<&vault::VaultFingerprint>,
+    previous: &NoteFiles,
+) -> Result<SaveOutcome, String> {
+    let base = merge::base_from_vault(&vault_data);
+    let config = vault::load_config();
+    let policy = config.plaintext_policy();
+    match vault::write_vault_split(vault_folder, vault_data, key, expected, previous, config.backup_retention, policy)
+        .map_err(|e| format!("Write: {e}"))?
+    {
+        (vault::VaultWrite::Written(fingerprint), files) => Ok(SaveOutcome::Saved(fingerprint, base, files)),
+        (vault::VaultWrite::Changed { raw, fingerprint }, _) => {
+            let json = zeroize::Zeroizing::new(crypto::decrypt_vault_with_key(&raw, key, policy).map_err(|e| {
+                format!("The vault file was replaced by a version this session can't read ({e}). Lock and unlock to load it.")
+            })?);
+            let (theirs, files) = vault::load_vault_json(vault_folder, &json, key)
+                .map_err(|e| format!("The vault file was replaced by a version that can't be loaded: {e}"))?;
+            Ok(SaveOutcome::Changed(Box::new(theirs), fingerprint, files))
+        }
+    }
+}
+
+/// Record what a successful save wrote.
+fn remember_vault_file(
+    ctx: &EditorCtx,
+    fingerprint: vault::VaultFingerprint,
+    base: merge::MergeBase,
+    files: NoteFiles,
+) {
+    *ctx.vault_file.borrow_mut() = Some(fingerprint);
+    *ctx.merge_base.borrow_mut() = base;
+    *ctx.note_files.borrow_mut() = files;
 }
 
 /// Synchronous vault save — used only for close-request where we must block.
 pub fn perform_vault_save_sync(ctx: &EditorCtx) -> bool {
-    let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
+    if !check_writer_lock(ctx) {
         return false;
-    };
-    match vault_save_blocking(vault_data, &key, &vault_folder) {
-        Ok(()) => {
-            send_toast(ctx, "Saved");
-            true
-        }
-        Err(e) => {
-            show_error(&ctx.window, "Save Failed", &format!("Vault save failed: {e}"));
-            false
+    }
+    for _ in 0..=MAX_SAVE_MERGES {
+        let journal_seq = journal_seq(ctx);
+        let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
+            return false;
+        };
+        let expected = ctx.vault_file.borrow().clone();
+        let previous = ctx.note_files.borrow().clone();
+        match vault_save_blocking(vault_data, &key, &vault_folder, expected.as_ref(), &previous) {
+            Ok(SaveOutcome::Saved(fingerprint, base, files)) => {
+                remember_vault_file(ctx, fingerprint, base, files);
+                journal_flushed(ctx, journal_seq);
+                send_toast(ctx, "Saved");
+                return true;
+            }
+            Ok(SaveOutcome::Changed(theirs, fingerprint, files)) => {
+                merge_external_changes(ctx, *theirs, fingerprint, files);
+            }
+            Err(e) => {
+                show_error(&ctx.window, "Save Failed", &format!("Vault save failed: {e}"));
+                return false;
+            }
         }
     }
+    show_error(&ctx.window, "Save Failed", KEEPS_CHANGING);
+    false
 }
 
 /// Async vault save — runs serialization + encryption + write on a background thread.
 /// Uses generation IDs to prevent stale saves from overwriting newer data.
 /// Only marks document clean after the write succeeds.
 pub fn perform_vault_save_async(ctx: &EditorCtx, toast: bool) {
+    save_vault_async(ctx, toast, 0);
+}
+
+/// `perform_vault_save_async`, after `merges` merges with a vault file that
+/// changed under this save.
+fn save_vault_async(ctx: &EditorCtx, toast: bool, merges: u32) {
+    if ctx.locked.get() {
+        return;
+    }
     // If a save is already in flight, bump generation so it re-saves when done
     if ctx.saving.get() {
         ctx.save_generation.set(ctx.save_generation.get() + 1);
         return;
     }
+    if ctx.read_only.get() {
+        // Nothing to write; a pending close or lock goes ahead at once
+        if ctx.close_requested.replace(false) {
+            ctx.window.destroy();
+        } else if ctx.lock_requested.replace(false) {
+            finish_lock(ctx);
+        }
+        return;
+    }
+    if !check_writer_lock(ctx) {
+        report_save_failure(
+            ctx,
+            "Another instance took over this vault, so this window is now read-only. \
+             Copy any changes made here into that instance.",
+        );
+        return;
+    }
 
     let snapshot = current_markdown(ctx);
+    let journal_seq = journal_seq(ctx);
     let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
         if toast {
             show_error(
@@ -5320,18 +9872,33 @@ pub fn perform_vault_save_async(ctx: &EditorCtx, toast: bool) {
     ctx.save_generation.set(gen);
     ctx.saving.set(true);
 
-    let (tx, rx) = std::sync::mpsc::channel::<Result<(), String>>();
+    let expected = ctx.vault_file.borrow().clone();
+    let previous = ctx.note_files.borrow().clone();
+    let (tx, rx) = std::sync::mpsc::channel::<Result<SaveOutcome, String>>();
 
     std::thread::spawn(move || {
-        let result = vault_save_blocking(vault_data, &key, &vault_folder);
+        let result = vault_save_blocking(vault_data, &key, &vault_folder, expected.as_ref(), &previous);
         let _ = tx.send(result);
     });
 
     let ctx = ctx.clone();
     glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
         match rx.try_recv() {
-            Ok(Ok(())) => {
+            Ok(Ok(SaveOutcome::Changed(theirs, fingerprint, files))) => {
+                // Merge, then save the result; a pending close or lock proceeds after that
+                ctx.saving.set(false);
+                merge_external_changes(&ctx, *theirs, fingerprint, files);
+                if merges < MAX_SAVE_MERGES {
+                    save_vault_async(&ctx, toast, merges + 1);
+                } else {
+                    report_save_failure(&ctx, KEEPS_CHANGING);
+                }
+                glib::ControlFlow::Break
+            }
+            Ok(Ok(SaveOutcome::Saved(fingerprint, base, files))) => {
                 ctx.saving.set(false);
+                remember_vault_file(&ctx, fingerprint, base, files);
+                journal_flushed(&ctx, journal_seq);
                 // Only mark clean if no new edits happened during save
                 let current = current_markdown(&ctx);
                 {
@@ -5352,38 +9919,420 @@ pub fn perform_vault_save_async(ctx: &EditorCtx, toast: bool) {
                 }
                 if ctx.close_requested.replace(false) {
                     ctx.window.destroy();
+                } else if ctx.lock_requested.replace(false) {
+                    finish_lock(&ctx);
                 }
                 glib::ControlFlow::Break
             }
             Ok(Err(e)) => {
                 ctx.saving.set(false);
                 eprintln!("Vault save failed: {e}");
-                if ctx.close_requested.replace(false) {
-                    show_close_save_failed_dialog(&ctx.window, &e);
-                } else {
-                    show_error(&ctx.window, "Save Failed", &e);
-                }
+                report_save_failure(&ctx, &e);
                 glib::ControlFlow::Break
             }
             Err(std::sync::mpsc::TryRecvError::Empty) => glib::ControlFlow::Continue,
             Err(std::sync::mpsc::TryRecvError::Disconnected) => {
                 ctx.saving.set(false);
-                if ctx.close_requested.replace(false) {
-                    show_close_save_failed_dialog(&ctx.window, "Save thread disconnected");
-                } else {
-                    show_error(&ctx.window, "Save Failed", "Save thread disconnected");
-                }
+                report_save_failure(&ctx, "Save thread disconnected");
                 glib::ControlFlow::Break
             }
         }
     });
 }
 
+/// Tell the user a save failed, asking about a pending close or lock.
+fn report_save_failure(ctx: &EditorCtx, error: &str) {
+    if ctx.close_requested.replace(false) {
+        show_close_save_failed_dialog(&ctx.window, error);
+    } else if ctx.lock_requested.replace(false) {
+        show_lock_save_failed_dialog(ctx, error);
+    } else {
+        show_error(&ctx.window, "Save Failed", error);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Crash journal
+// ---------------------------------------------------------------------------
+
+/// Open the crash journal (see `journal.rs`) and offer to replay edits from a
+/// previous session that never reached a save.
+pub fn open_crash_journal(ctx: &EditorCtx) {
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let (journal, records) = match journal::Journal::open(&vault_folder) {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Warning: could not open crash journal: {e}");
+            return;
+        }
+    };
+    let entries = match ctx.cached_key.borrow().as_ref() {
+        Some(key) if !records.is_empty() => {
+            let (entries, failed) = journal::read_entries(&records, key);
+            if failed > 0 {
+                eprintln!("Warning: skipped {failed} unreadable crash journal records");
+            }
+            journal::unflushed_entries(entries, &ctx.state.borrow())
+        }
+        _ => Vec::new(),
+    };
+    if entries.is_empty() {
+        journal.release_recovered();
+    }
+    *ctx.journal.borrow_mut() = Some(journal);
+    if !entries.is_empty() {
+        offer_journal_replay(ctx, entries);
+    }
+}
+
+fn offer_journal_replay(ctx: &EditorCtx, entries: Vec<journal::JournalEntry>) {
+    const SHOWN: usize = 8;
+    let mut list = entries
+        .iter()
+        .take(SHOWN)
+        .map(|e| format!("\u{2022} {} ({})", e.name, format_ts(e.ts)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if entries.len() > SHOWN {
+        list.push_str(&format!("\n\u{2026} and {} more", entries.len() - SHOWN));
+    }
+    let dialog = adw::AlertDialog::new(
+        Some("Recover Unsaved Edits?"),
+        Some(&format!(
+            "MD Notebook closed before these edits were saved:\n\n{list}\n\nRecovering replaces the saved version of each note."
+        )),
+    );
+    dialog.add_response("discard", "Discard");
+    dialog.add_response("recover", "Recover");
+    dialog.set_response_appearance("discard", adw::ResponseAppearance::Destructive);
+    dialog.set_response_appearance("recover", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("recover"));
+    // Dismissing decides nothing; the edits are offered again next time
+    dialog.set_close_response("later");
+
+    let ctx_for_response = ctx.clone();
+    let entries = RefCell::new(Some(entries));
+    dialog.connect_response(None, move |_, response| {
+        let ctx = &ctx_for_response;
+        let Some(entries) = entries.borrow_mut().take() else { return };
+        match response {
+            "recover" => {
+                let changed = journal::replay(&mut ctx.state.borrow_mut(), entries);
+                let active = {
+                    let state = ctx.state.borrow();
+                    changed
+                        .contains(&state.active_note_id)
+                        .then(|| find_note_index(&state.notes, &state.active_note_id))
+                        .flatten()
+                        .map(|i| state.notes[i].content.clone())
+                };
+                if let Some(markdown) = active {
+                    load_document(ctx, &markdown, None);
+                }
+                refresh_note_list(ctx);
+                refresh_tabs(ctx);
+                refresh_tags(ctx);
+                send_toast(
+                    ctx,
+                    &format!("Recovered {} {}", changed.len(), if changed.len() == 1 { "note" } else { "notes" }),
+                );
+            }
+            "discard" => {}
+            _ => return,
+        }
+        if let Some(journal) = ctx.journal.borrow().as_ref() {
+            journal.release_recovered();
+        }
+        trigger_vault_save(ctx);
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+/// Append the active note to the crash journal after a debounced edit.
+fn journal_active_note(ctx: &EditorCtx) {
+    let journal = ctx.journal.borrow();
+    let key = ctx.cached_key.borrow();
+    let (Some(journal), Some(key)) = (journal.as_ref(), key.as_ref()) else {
+        return;
+    };
+    let entry = {
+        let state = ctx.state.borrow();
+        let Some(index) = find_note_index(&state.notes, &state.active_note_id) else {
+            return;
+        };
+        journal::JournalEntry::from_note(&state.notes[index])
+    };
+    if let Err(e) = journal.append(&entry, key) {
+        eprintln!("Warning: crash journal append failed: {e}");
+    }
+}
+
+/// Journal position to pass to `journal_flushed` once a save started now succeeds.
+fn journal_seq(ctx: &EditorCtx) -> u64 {
+    ctx.journal.borrow().as_ref().map_or(0, |j| j.last_seq())
+}
+
+fn journal_flushed(ctx: &EditorCtx, seq: u64) {
+    if let Some(journal) = ctx.journal.borrow().as_ref() {
+        journal.flushed(seq);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// External changes
+// ---------------------------------------------------------------------------
+
+/// Merge a vault file written by another device (see `merge.rs`) into the
+/// open vault and refresh the editor. The caller saves the result.
+fn merge_external_changes(
+    ctx: &EditorCtx,
+    theirs: vault::VaultData,
+    fingerprint: vault::VaultFingerprint,
+    their_files: NoteFiles,
+) {
+    let editor_text = current_markdown(ctx);
+    update_active_note_content(ctx, &editor_text);
+    let their_base = merge::base_from_vault(&theirs);
+    let report = {
+        let base = ctx.merge_base.borrow();
+        merge::merge_foreign(&mut ctx.state.borrow_mut(), vault::vault_to_doc_state(theirs), &base)
+    };
+    remember_vault_file(ctx, fingerprint, their_base, their_files);
+    if !report.changed_anything() {
+        return;
+    }
+
+    let active = {
+        let state = ctx.state.borrow();
+        find_note_index(&state.notes, &state.active_note_id).map(|i| state.notes[i].content.clone())
+    };
+    if let Some(markdown) = active.filter(|m| *m != editor_text) {
+        load_document(ctx, &markdown, None);
+    }
+    refresh_note_list(ctx);
+    refresh_tabs(ctx);
+    refresh_tags(ctx);
+    if report.conflicts.is_empty() {
+        send_toast(ctx, "Merged changes from another device");
+    } else {
+        show_merge_conflicts(ctx, report.conflicts);
+    }
+}
+
+/// List notes edited on both devices; each can be compared side by side.
+fn show_merge_conflicts(ctx: &EditorCtx, conflicts: Vec<merge::Conflict>) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Conflicting Edits")
+        .default_width(480)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 18);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let intro = gtk::Label::new(Some(
+        "These notes were edited here and on another device. Your version is kept and the other one was added as a conflicted copy.",
+    ));
+    intro.set_wrap(true);
+    intro.set_xalign(0.0);
+    vbox.append(&intro);
+
+    let list = gtk::ListBox::new();
+    list.add_css_class("boxed-list");
+    list.set_selection_mode(gtk::SelectionMode::None);
+    vbox.append(&list);
+
+    let close_btn = gtk::Button::with_label("Done");
+    close_btn.add_css_class("pill");
+    close_btn.set_halign(gtk::Align::Center);
+    vbox.append(&close_btn);
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&adw::HeaderBar::new());
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    for conflict in conflicts {
+        let name = {
+            let state = ctx.state.borrow();
+            find_note_index(&state.notes, &conflict.note_id).map(|i| state.notes[i].name.clone())
+        };
+        let Some(name) = name else { continue };
+        let row = adw::ActionRow::builder().title(&name).build();
+        let compare_btn = gtk::Button::with_label("Compare\u{2026}");
+        compare_btn.set_valign(gtk::Align::Center);
+        row.add_suffix(&compare_btn);
+        list.append(&row);
+
+        let ctx = ctx.clone();
+        let list = list.clone();
+        let dialog = dialog.clone();
+        compare_btn.connect_clicked(move |_| {
+            let list = list.clone();
+            let row = row.clone();
+            let dialog = dialog.clone();
+            show_conflict_compare(&ctx, &conflict, move || {
+                list.remove(&row);
+                if list.first_child().is_none() {
+                    dialog.close();
+                }
+            });
+        });
+    }
+
+    {
+        let dialog = dialog.clone();
+        close_btn.connect_clicked(move |_| dialog.close());
+    }
+    dialog.present();
+}
+
+/// Both versions of a conflicted note side by side. `on_resolved` runs once
+/// one side was kept.
+fn show_conflict_compare(ctx: &EditorCtx, conflict: &merge::Conflict, on_resolved: impl Fn() + 'static) {
+    let (ours, theirs) = {
+        let state = ctx.state.borrow();
+        let find = |id: &str| find_note_index(&state.notes, id).map(|i| state.notes[i].clone());
+        (find(&conflict.note_id), find(&conflict.copy_id))
+    };
+    let (Some(ours), Some(theirs)) = (ours, theirs) else {
+        on_resolved();
+        return;
+    };
+
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title(&ours.name)
+        .default_width(960)
+        .default_height(600)
+        .build();
+
+    // Secrets stay masked unless asked for, as in the editor
+    let column = |title: &str, note: &NoteItem| {
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 8);
+        let label = gtk::Label::new(Some(title));
+        label.add_css_class("heading");
+        label.set_xalign(0.0);
+        vbox.append(&label);
+        let meta = gtk::Label::new(Some(&format!("Changed {}", format_ts(note.updated_at))));
+        meta.add_css_class("dim-label");
+        meta.set_xalign(0.0);
+        vbox.append(&meta);
+        let view = gtk::TextView::new();
+        view.set_editable(false);
+        view.set_monospace(true);
+        view.set_wrap_mode(gtk::WrapMode::WordChar);
+        view.buffer().set_text(&mask_secret_spans(&note.content, SECRET_MASK));
+        let scroller = gtk::ScrolledWindow::builder().child(&view).vexpand(true).hexpand(true).build();
+        scroller.add_css_class("card");
+        vbox.append(&scroller);
+        (vbox, view.buffer(), note.content.clone())
+    };
+
+    let columns = gtk::Box::new(gtk::Orientation::Horizontal, 18);
+    columns.set_homogeneous(true);
+    let shown = [column("This Device", &ours), column("Other Device", &theirs)];
+    for (vbox, _, _) in &shown {
+        columns.append(vbox);
+    }
+    let show_secrets_btn = gtk::ToggleButton::with_label("Show Secrets");
+    show_secrets_btn.set_visible(
+        shown
+            .iter()
+            .any(|(_, _, content)| mask_secret_spans(content, SECRET_MASK) != *content),
+    );
+    show_secrets_btn.connect_toggled(move |btn| {
+        for (_, buffer, content) in &shown {
+            if btn.is_active() {
+                buffer.set_text(content);
+            } else {
+                buffer.set_text(&mask_secret_spans(content, SECRET_MASK));
+            }
+        }
+    });
+
+    let keep_ours_btn = gtk::Button::with_label("Keep This Device\u{2019}s");
+    let keep_theirs_btn = gtk::Button::with_label("Keep Other Device\u{2019}s");
+    let keep_both_btn = gtk::Button::with_label("Keep Both");
+    keep_both_btn.add_css_class("suggested-action");
+    let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    buttons.set_halign(gtk::Align::End);
+    show_secrets_btn.add_css_class("pill");
+    buttons.append(&show_secrets_btn);
+    for btn in [&keep_ours_btn, &keep_theirs_btn, &keep_both_btn] {
+        btn.add_css_class("pill");
+        buttons.append(btn);
+    }
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 18);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(12);
+    vbox.set_margin_bottom(24);
+    vbox.append(&columns);
+    vbox.append(&buttons);
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&adw::HeaderBar::new());
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    let on_resolved = Rc::new(on_resolved);
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let on_resolved = on_resolved.clone();
+        let copy_id = conflict.copy_id.clone();
+        keep_ours_btn.connect_clicked(move |_| {
+            dialog.close();
+            do_move_to_trash(&ctx, &copy_id);
+            on_resolved();
+        });
+    }
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let on_resolved = on_resolved.clone();
+        let conflict = conflict.clone();
+        keep_theirs_btn.connect_clicked(move |_| {
+            dialog.close();
+            let reload = {
+                let mut state = ctx.state.borrow_mut();
+                if let Some(i) = find_note_index(&state.notes, &conflict.note_id) {
+                    state.notes[i].content = theirs.content.clone();
+                    state.notes[i].updated_at = unix_now();
+                }
+                state.active_note_id == conflict.note_id
+            };
+            if reload {
+                load_document(&ctx, &theirs.content, None);
+            }
+            do_move_to_trash(&ctx, &conflict.copy_id);
+            on_resolved();
+        });
+    }
+    {
+        let dialog = dialog.clone();
+        keep_both_btn.connect_clicked(move |_| {
+            dialog.close();
+            on_resolved();
+        });
+    }
+    dialog.present();
+}
+
 // ---------------------------------------------------------------------------
 // Trash operations
 // ---------------------------------------------------------------------------
 
 pub fn move_to_trash(ctx: &EditorCtx, note_id: &str) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     {
         let state = ctx.state.borrow();
         if state.notes.len() <= 1 {
@@ -5445,6 +10394,9 @@ fn do_move_to_trash(ctx: &EditorCtx, note_id: &str) {
 }
 
 pub fn restore_from_trash(ctx: &EditorCtx, trash_id: &str) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let note_id = {
         let mut state = ctx.state.borrow_mut();
         let Some(idx) = state.trash.iter().position(|t| t.id == trash_id) else {
@@ -5478,6 +10430,9 @@ pub fn restore_from_trash(ctx: &EditorCtx, trash_id: &str) {
 }
 
 pub fn delete_permanently(ctx: &EditorCtx, trash_id: &str) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     ctx.state
         .borrow_mut()
         .trash
@@ -5487,6 +10442,9 @@ pub fn delete_permanently(ctx: &EditorCtx, trash_id: &str) {
 }
 
 pub fn empty_trash_action(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let count = ctx.state.borrow().trash.len();
     if count == 0 {
         return;
@@ -5509,6 +10467,9 @@ pub fn empty_trash_action(ctx: &EditorCtx) {
         if response == "empty" {
             ctx.state.borrow_mut().trash.clear();
             refresh_trash_view(&ctx);
+            if vault::load_config().collect_assets_after_empty_trash {
+                collect_unused_assets(&ctx, false);
+            }
             trigger_vault_save(&ctx);
         }
     });
@@ -5595,6 +10556,9 @@ pub fn refresh_trash_view(ctx: &EditorCtx) {
 // ---------------------------------------------------------------------------
 
 pub fn create_folder(ctx: &EditorCtx, parent_id: Option<String>) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let dialog = adw::AlertDialog::new(
         Some("New Folder"),
         Some("Enter a name for the folder."),
@@ -5643,6 +10607,9 @@ pub fn create_folder(ctx: &EditorCtx, parent_id: Option<String>) {
 }
 
 pub fn move_note_to_folder(ctx: &EditorCtx) {
+    if refuse_in_read_only(ctx) {
+        return;
+    }
     let (folders, active_id, current_parent) = {
         let state = ctx.state.borrow();
         let folders: Vec<(String, String)> = state
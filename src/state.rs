@@ -89,7 +89,7 @@ pub const PAGE_FEATURES: &str = r#"# Keyboard shortcuts
 - **Backlinks** — see which notes link to the current one using `[[wiki-link]]` syntax
 - **Tabs** — open multiple notes in tabs, drag to reorder, close with Ctrl+W
 - **Export** — save any note as Markdown (.md) or HTML (.html) from the menu
-- **Encryption** — AES-256-GCM with PBKDF2 key derivation (600k iterations)
+- **Encryption** — AES-256-GCM with Argon2id key derivation
 "#;
 
 pub const PAGE_FORMATTING: &str = r#"# Formatting examples
@@ -98,6 +98,8 @@ pub const PAGE_FORMATTING: &str = r#"# Formatting examples
 
 **Bold**, *italic*, <u>underline</u>, ~~strikethrough~~, and `inline code`.
 
+Secrets stay masked until clicked: <secret>hunter2</secret>.
+
 Combine styles: ***bold italic***, **~~bold strikethrough~~**.
 
 ## Lists
@@ -335,6 +337,51 @@ pub fn unix_now() -> i64 {
         .as_secs() as i64
 }
 
+/// `name` as a file name to suggest: path separators become dashes, and a
+/// name that would be hidden, empty or a directory reference loses its
+/// leading dots or becomes "Untitled".
+pub fn file_name_stem(name: &str) -> String {
+    let stem: String = name.chars().map(|c| if c == '/' || c == '\\' { '-' } else { c }).collect();
+    match stem.trim().trim_start_matches('.').trim() {
+        "" => "Untitled".to_string(),
+        stem => stem.to_string(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Secret spans
+// ---------------------------------------------------------------------------
+//
+// `<secret>API token</secret>` marks text to keep out of sight: the editor
+// shows it as dots until clicked, and search snippets and HTML export leave
+// it out. It is inline HTML, so other Markdown tools keep the text as is.
+
+pub const SECRET_OPEN: &str = "<secret>";
+pub const SECRET_CLOSE: &str = "</secret>";
+/// Shown in place of a secret; the same length whatever the secret.
+pub const SECRET_MASK: &str = "\u{25cf}\u{25cf}\u{25cf}\u{25cf}\u{25cf}\u{25cf}\u{25cf}\u{25cf}";
+
+/// `text` with every secret span, markers included, replaced by `mask`. An
+/// unclosed span runs to the end of the text.
+pub fn mask_secret_spans(text: &str, mask: &str) -> String {
+    // ASCII lowercasing keeps byte offsets, so they apply to `text` too
+    let lower = text.to_ascii_lowercase();
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    while let Some(found) = lower[pos..].find(SECRET_OPEN) {
+        let start = pos + found;
+        out.push_str(&text[pos..start]);
+        out.push_str(mask);
+        let content = start + SECRET_OPEN.len();
+        pos = match lower[content..].find(SECRET_CLOSE) {
+            Some(end) => content + end + SECRET_CLOSE.len(),
+            None => text.len(),
+        };
+    }
+    out.push_str(&text[pos..]);
+    out
+}
+
 impl DocState {
     /// Moves a note to the trash and updates open tabs.
     /// Returns `Some(new_active_note_id)` if the active note was trashed and a switch is needed.
@@ -379,6 +426,53 @@ impl DocState {
             None
         }
     }
+
+    /// Overwrite and drop all note text held in memory (used when the vault locks).
+    /// The state is left empty with `suppress_sync` set so no stale save can run.
+    pub fn wipe_contents(&mut self) {
+        use zeroize::Zeroize;
+        for note in &mut self.notes {
+            note.name.zeroize();
+            note.content.zeroize();
+            note.tags.zeroize();
+            for version in &mut note.versions {
+                version.content.zeroize();
+            }
+        }
+        for item in &mut self.trash {
+            item.name.zeroize();
+            item.content.zeroize();
+            item.tags.zeroize();
+            for version in &mut item.versions {
+                version.content.zeroize();
+            }
+        }
+        for folder in &mut self.folders {
+            folder.name.zeroize();
+        }
+        for (name, content, tags) in &mut self.custom_templates {
+            name.zeroize();
+            content.zeroize();
+            tags.zeroize();
+        }
+        self.notes.clear();
+        self.trash.clear();
+        self.folders.clear();
+        self.custom_templates.clear();
+        self.undo_stack.zeroize();
+        self.redo_stack.zeroize();
+        self.saved_snapshot.zeroize();
+        self.last_snapshot.zeroize();
+        self.search_query.zeroize();
+        self.filter_tags.zeroize();
+        self.open_tabs.clear();
+        self.visible_row_items.clear();
+        self.assets.clear();
+        self.active_note_id.clear();
+        self.cached_key = None;
+        self.dirty = false;
+        self.suppress_sync = true;
+    }
 }
 
 #[cfg(test)]
@@ -440,4 +534,40 @@ mod tests {
         assert_eq!(state.notes.len(), 1); // Should deny deleting the last note
         assert_eq!(state.trash.len(), 0);
     }
+
+    #[test]
+    fn test_mask_secret_spans() {
+        assert_eq!(mask_secret_spans("no secrets", "*"), "no secrets");
+        assert_eq!(
+            mask_secret_spans("key <secret>abc</secret>, pin <SECRET>1234</Secret>.", "*"),
+            "key *, pin *."
+        );
+        assert_eq!(mask_secret_spans("token: <secret>unclosed\nmore", "*"), "token: *");
+        assert_eq!(mask_secret_spans("\u{e9}<secret>\u{fc}</secret>\u{e9}", ""), "\u{e9}\u{e9}");
+    }
+
+    #[test]
+    fn test_file_name_stem() {
+        assert_eq!(file_name_stem("Trip notes"), "Trip notes");
+        assert_eq!(file_name_stem("a/b\\c"), "a-b-c");
+        assert_eq!(file_name_stem(".."), "Untitled");
+        assert_eq!(file_name_stem(" . "), "Untitled");
+        assert_eq!(file_name_stem(""), "Untitled");
+        assert_eq!(file_name_stem(".hidden"), "hidden");
+    }
+
+    #[test]
+    fn test_wipe_contents() {
+        let mut state = DocState::default();
+        state.move_note_to_trash("note-2");
+        state.undo_stack.push("secret".to_string());
+
+        state.wipe_contents();
+
+        assert!(state.notes.is_empty());
+        assert!(state.trash.is_empty());
+        assert!(state.undo_stack.is_empty());
+        assert!(state.saved_snapshot.is_empty());
+        assert!(state.suppress_sync);
+    }
 }
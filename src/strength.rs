@@ -0,0 +1,678 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+use rand::seq::SliceRandom;
+
+use crate::crypto::Kdf;
+
+// ---------------------------------------------------------------------------
+// Passphrase strength
+// ---------------------------------------------------------------------------
+//
+// Estimates how many guesses it takes to find a passphrase, the way an
+// attacker who knows how people choose them would go about it: common
+// passwords and words first, then keyboard walks, runs like "abc" or "1234",
+// repeats and dates, and brute force for whatever is left. Like zxcvbn, the
+// passphrase is split into the cheapest sequence of such pieces and their
+// costs are added up. Guesses are turned into crack times for the KDF new
+// vaults are created with.
+//
+// The generator draws words from the bundled list (6^4 words, so it also works
+// with four dice); every word adds log2(1296) ≈ 10.3 bits.
+
+const WORDLIST: &str = include_str!("wordlist.txt");
+
+/// Words in a generated passphrase.
+pub const GENERATED_WORDS: usize = 6;
+const GENERATED_SEPARATOR: &str = "-";
+
+/// Most common passwords, most common first. Finding one of these costs an
+/// attacker about as many guesses as its rank.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "password", "123456789", "12345678", "12345", "qwerty", "1234567", "111111", "123123", "abc123",
+    "1234567890", "password1", "000000", "iloveyou", "1234", "1q2w3e4r", "qwertyuiop", "123321", "654321", "dragon",
+    "monkey", "letmein", "football", "baseball", "sunshine", "princess", "welcome", "shadow", "superman", "michael",
+    "master", "696969", "123qwe", "mustang", "access", "trustno1", "batman", "passw0rd", "zaq1zaq1", "qazwsx",
+    "starwars", "whatever", "freedom", "hello", "charlie", "donald", "login", "admin", "secret", "flower",
+    "hottie", "loveme", "jennifer", "jordan", "hunter", "ranger", "buster", "soccer", "harley", "thomas",
+    "tigger", "robert", "daniel", "computer", "michelle", "jessica", "pepper", "ginger", "summer", "cheese",
+    "killer", "andrew", "joshua", "maggie", "matrix", "nicole", "asshole", "hannah", "amanda", "orange",
+    "silver", "golfer", "cookie", "chelsea", "diamond", "yankees", "thunder", "taylor", "matthew", "banana",
+    "lovely", "zxcvbnm", "asdfgh", "asdfghjkl", "qwerty123", "1qaz2wsx", "aa123456", "password123", "admin123", "root",
+    "test", "guest", "changeme", "default", "pass", "passpass", "abcdef", "abcd1234", "iloveu", "love",
+    "god", "angel", "pokemon", "superstar", "blink182", "samsung", "apple", "google", "windows", "linux",
+];
+
+/// Characters that commonly stand in for letters.
+const LEET: &[(char, char)] = &[
+    ('0', 'o'), ('1', 'i'), ('!', 'i'), ('3', 'e'), ('4', 'a'), ('@', 'a'), ('5', 's'), ('$', 's'), ('7', 't'), ('+', 't'),
+    ('8', 'b'), ('9', 'g'),
+];
+
+/// Keyboard rows (US layout) with their shifted counterparts. Each row is
+/// shifted by one column against the one above, as on the keyboard.
+const KEYBOARD_ROWS: &[(&str, &str)] = &[
+    ("`1234567890-=", "~!@#$%^&*()_+"),
+    ("\tqwertyuiop[]\\", "\tQWERTYUIOP{}|"),
+    ("\tasdfghjkl;'", "\tASDFGHJKL:\""),
+    ("\tzxcvbnm,./", "\tZXCVBNM<>?"),
+];
+
+/// Characters accepted between the words of a passphrase.
+const SEPARATORS: &[char] = &[' ', '-', '_', '.', ','];
+
+/// Passphrases longer than this are only looked at up to here; the rest is
+/// counted as brute force.
+const MAX_ANALYSED_CHARS: usize = 100;
+
+/// Guesses per second one high-end graphics card manages against the
+/// recommended Argon2id cost (64 MiB, 3 passes), and against one round of
+/// PBKDF2-SHA256. Argon2id throughput scales with memory × passes.
+const ARGON2_REFERENCE_RATE: f64 = 1_000.0;
+const ARGON2_REFERENCE_COST: f64 = 64.0 * 1024.0 * 3.0;
+const PBKDF2_SINGLE_ROUND_RATE: f64 = 1e10;
+
+/// The attackers crack times are given for: their name and graphics cards.
+pub const ATTACKERS: &[(&str, f64)] = &[("one graphics card", 1.0), ("a thousand graphics cards", 1_000.0)];
+
+fn wordlist() -> &'static [&'static str] {
+    static WORDS: OnceLock<Vec<&'static str>> = OnceLock::new();
+    WORDS.get_or_init(|| WORDLIST.lines().map(str::trim).filter(|w| !w.is_empty()).collect())
+}
+
+fn dictionary() -> &'static HashSet<&'static str> {
+    static SET: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    SET.get_or_init(|| wordlist().iter().copied().collect())
+}
+
+fn common_ranks() -> &'static HashMap<&'static str, usize> {
+    static RANKS: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+    RANKS.get_or_init(|| {
+        let mut ranks = HashMap::new();
+        for (i, p) in COMMON_PASSWORDS.iter().enumerate() {
+            ranks.entry(*p).or_insert(i + 1);
+        }
+        ranks
+    })
+}
+
+/// Row, column and whether shift is held, for each character on the keyboard.
+fn keyboard() -> &'static HashMap<char, (i32, i32, bool)> {
+    static KEYS: OnceLock<HashMap<char, (i32, i32, bool)>> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        let mut keys = HashMap::new();
+        for (row, (plain, shifted)) in KEYBOARD_ROWS.iter().enumerate() {
+            for (col, (p, s)) in plain.chars().zip(shifted.chars()).enumerate() {
+                if p != '\t' {
+                    keys.insert(p, (row as i32, col as i32, false));
+                    keys.insert(s, (row as i32, col as i32, true));
+                }
+            }
+        }
+        keys
+    })
+}
+
+/// The kinds of guessable pieces a passphrase can contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    CommonPassword,
+    /// Part of the name the user gave the vault or its folder.
+    UserInput,
+    Word,
+    Keyboard,
+    Sequence,
+    Repeat,
+    Date,
+}
+
+impl Pattern {
+    fn warning(self) -> &'static str {
+        match self {
+            Pattern::CommonPassword => "This is one of the most common passwords.",
+            Pattern::UserInput => "Avoid the vault\u{2019}s name in its passphrase.",
+            Pattern::Word => "A few words on their own are easy to guess. Add more unrelated words.",
+            Pattern::Keyboard => "Keyboard patterns like \u{201c}qwerty\u{201d} are easy to guess.",
+            Pattern::Sequence => "Runs like \u{201c}abc\u{201d} or \u{201c}6789\u{201d} are easy to guess.",
+            Pattern::Repeat => "Repeated characters or words add little.",
+            Pattern::Date => "Dates and years are easy to guess.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Strength {
+    VeryWeak,
+    Weak,
+    Fair,
+    Strong,
+    VeryStrong,
+}
+
+impl Strength {
+    pub fn label(self) -> &'static str {
+        match self {
+            Strength::VeryWeak => "Very weak",
+            Strength::Weak => "Weak",
+            Strength::Fair => "Fair",
+            Strength::Strong => "Strong",
+            Strength::VeryStrong => "Very strong",
+        }
+    }
+
+    /// 0 (very weak) to 4 (very strong), for meters.
+    pub fn score(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    /// log2 of the guesses needed to be sure of finding the passphrase.
+    pub bits: f64,
+    /// The patterns the cheapest guess is made of, in passphrase order.
+    pub patterns: Vec<Pattern>,
+}
+
+/// A guessable piece of the passphrase: chars `start..end`, costing `bits`.
+struct Match {
+    start: usize,
+    end: usize,
+    bits: f64,
+    pattern: Pattern,
+}
+
+/// Estimate `passphrase`. `user_inputs` are strings an attacker would try
+/// first, such as the vault's name.
+pub fn estimate(passphrase: &str, user_inputs: &[&str]) -> Estimate {
+    let chars: Vec<char> = passphrase.chars().collect();
+    if chars.is_empty() {
+        return Estimate { bits: 0.0, patterns: Vec::new() };
+    }
+    let analysed = &chars[..chars.len().min(MAX_ANALYSED_CHARS)];
+    let brute_force = brute_force_costs(&chars);
+
+    let mut matches = Vec::new();
+    dictionary_matches(analysed, user_inputs, &mut matches);
+    keyboard_matches(analysed, &mut matches);
+    sequence_matches(analysed, &mut matches);
+    repeat_matches(analysed, user_inputs, &mut matches);
+    date_matches(analysed, &mut matches);
+
+    // best[j]: cheapest cost of chars[..j], with how it ends
+    let n = chars.len();
+    let mut best = vec![(0.0f64, None::<usize>); n + 1];
+    for j in 1..=n {
+        best[j] = (best[j - 1].0 + brute_force[j - 1], None);
+        for (k, m) in matches.iter().enumerate().filter(|(_, m)| m.end == j) {
+            // Each pattern also costs a guess of which pattern comes next
+            let cost = best[m.start].0 + m.bits + 1.0;
+            if cost < best[j].0 {
+                best[j] = (cost, Some(k));
+            }
+        }
+    }
+
+    let mut patterns = Vec::new();
+    let mut j = n;
+    while j > 0 {
+        match best[j].1 {
+            Some(k) => {
+                patterns.push(matches[k].pattern);
+                j = matches[k].start;
+            }
+            None => j -= 1,
+        }
+    }
+    patterns.reverse();
+    Estimate { bits: best[n].0, patterns }
+}
+
+impl Estimate {
+    /// Average seconds to find the passphrase with `devices` graphics cards
+    /// against `kdf`.
+    pub fn crack_seconds(&self, kdf: Kdf, devices: f64) -> f64 {
+        // On average the passphrase turns up halfway through
+        2f64.powf(self.bits - 1.0) / (guesses_per_second(kdf) * devices)
+    }
+
+    /// Rated by how long the larger attacker in `ATTACKERS` needs.
+    pub fn strength(&self, kdf: Kdf) -> Strength {
+        const DAY: f64 = 86_400.0;
+        const YEAR: f64 = 365.25 * DAY;
+        let (_, devices) = ATTACKERS[ATTACKERS.len() - 1];
+        let seconds = self.crack_seconds(kdf, devices);
+        if seconds < DAY {
+            Strength::VeryWeak
+        } else if seconds < YEAR {
+            Strength::Weak
+        } else if seconds < 100.0 * YEAR {
+            Strength::Fair
+        } else if seconds < 100_000.0 * YEAR {
+            Strength::Strong
+        } else {
+            Strength::VeryStrong
+        }
+    }
+
+    /// What to change, if anything stands out.
+    pub fn warning(&self) -> Option<&'static str> {
+        const BY_PRIORITY: [Pattern; 7] = [
+            Pattern::CommonPassword,
+            Pattern::UserInput,
+            Pattern::Keyboard,
+            Pattern::Repeat,
+            Pattern::Sequence,
+            Pattern::Date,
+            Pattern::Word,
+        ];
+        BY_PRIORITY.iter().find(|p| self.patterns.contains(p)).map(|p| p.warning())
+    }
+}
+
+/// Guesses per second for one graphics card against `kdf`.
+pub fn guesses_per_second(kdf: Kdf) -> f64 {
+    match kdf {
+        Kdf::Pbkdf2Sha256 { iterations } => PBKDF2_SINGLE_ROUND_RATE / f64::from(iterations.max(1)),
+        Kdf::Argon2id { memory_kib, iterations, .. } => {
+            let cost = f64::from(memory_kib.max(1)) * f64::from(iterations.max(1));
+            ARGON2_REFERENCE_RATE * ARGON2_REFERENCE_COST / cost
+        }
+    }
+}
+
+/// A rough human reading of `seconds`, such as "3 hours" or "centuries".
+pub fn format_duration(seconds: f64) -> String {
+    const MINUTE: f64 = 60.0;
+    const HOUR: f64 = 60.0 * MINUTE;
+    const DAY: f64 = 24.0 * HOUR;
+    const MONTH: f64 = 30.44 * DAY;
+    const YEAR: f64 = 365.25 * DAY;
+    let plural = |n: f64, unit: &str| {
+        let n = n.round().max(1.0) as u64;
+        format!("{n} {unit}{}", if n == 1 { "" } else { "s" })
+    };
+    if seconds < 1.0 {
+        return "less than a second".to_string();
+    }
+    let units = [
+        (1.0, "second", MINUTE),
+        (MINUTE, "minute", HOUR),
+        (HOUR, "hour", DAY),
+        (DAY, "day", MONTH),
+        (MONTH, "month", YEAR),
+        (YEAR, "year", 1_000.0 * YEAR),
+    ];
+    // Compare after rounding, so 59.7 minutes reads "1 hour", not "60 minutes"
+    for (size, unit, next) in units {
+        if (seconds / size).round() * size < next {
+            return plural(seconds / size, unit);
+        }
+    }
+    let thousands = (seconds / YEAR / 1_000.0).round();
+    if thousands < 1_000.0 {
+        format!("{thousands} thousand years")
+    } else {
+        "millions of years".to_string()
+    }
+}
+
+/// A random passphrase of `words` words from the bundled list.
+pub fn generate_passphrase(words: usize) -> String {
+    let mut rng = rand::thread_rng();
+    let list = wordlist();
+    (0..words)
+        .filter_map(|_| list.choose(&mut rng).copied())
+        .collect::<Vec<_>>()
+        .join(GENERATED_SEPARATOR)
+}
+
+/// Bits a generated passphrase of `words` words is worth.
+#[cfg(test)]
+fn generated_bits(words: usize) -> f64 {
+    words as f64 * (wordlist().len() as f64).log2()
+}
+
+// ---------------------------------------------------------------------------
+// Matchers
+// ---------------------------------------------------------------------------
+
+/// Cost of guessing each character by brute force, from the character
+/// classes used anywhere in the passphrase. A separator that repeats the one
+/// used before is free: "word-word-word" picks one separator, not three.
+fn brute_force_costs(chars: &[char]) -> Vec<f64> {
+    let has = |f: fn(&char) -> bool| chars.iter().any(f);
+    let mut cardinality = 0.0;
+    if has(char::is_ascii_lowercase) {
+        cardinality += 26.0;
+    }
+    if has(char::is_ascii_uppercase) {
+        cardinality += 26.0;
+    }
+    if has(char::is_ascii_digit) {
+        cardinality += 10.0;
+    }
+    if has(|c| c.is_ascii_punctuation() && !SEPARATORS.contains(c)) {
+        cardinality += 33.0;
+    }
+    if has(|c| !c.is_ascii()) {
+        cardinality += 100.0;
+    }
+    let per_char = f64::max(cardinality, 10.0).log2();
+    let separator = (SEPARATORS.len() as f64).log2();
+
+    let mut last_separator = None;
+    chars
+        .iter()
+        .map(|c| {
+            if !SEPARATORS.contains(c) {
+                per_char
+            } else if last_separator.replace(*c) == Some(*c) {
+                0.0
+            } else {
+                separator
+            }
+        })
+        .collect()
+}
+
+/// Extra bits for the capitalisation of a word: none for lower case, one for
+/// a capital first letter or all capitals, one per capital otherwise.
+fn case_bits(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let first_only = upper == 1 && word[0].is_uppercase();
+    if upper == 0 {
+        0.0
+    } else if first_only || upper == word.len() {
+        1.0
+    } else {
+        upper as f64
+    }
+}
+
+fn dictionary_matches(chars: &[char], user_inputs: &[&str], matches: &mut Vec<Match>) {
+    let dictionary = dictionary();
+    let common = common_ranks();
+    let user_inputs: Vec<String> = user_inputs.iter().map(|s| s.trim().to_lowercase()).filter(|s| s.chars().count() >= 3).collect();
+    for start in 0..chars.len() {
+        for end in start + 3..=chars.len().min(start + 20) {
+            let piece = &chars[start..end];
+            // Undo look-alike substitutions; each one is a bit of extra work
+            let mut substituted = 0;
+            let plain: String = piece
+                .iter()
+                .map(|c| match LEET.iter().find(|(from, _)| from == c) {
+                    Some((_, to)) => {
+                        substituted += 1;
+                        *to
+                    }
+                    None => c.to_ascii_lowercase(),
+                })
+                .collect();
+            let lower: String = piece.iter().map(|c| c.to_ascii_lowercase()).collect();
+            let extra = case_bits(piece);
+            let reversed: String = plain.chars().rev().collect();
+
+            for (candidate, extra) in [
+                (&lower, extra),
+                (&plain, extra + substituted as f64),
+                (&reversed, extra + substituted as f64 + 1.0),
+            ] {
+                let found = if let Some(rank) = common.get(candidate.as_str()) {
+                    Some(((*rank as f64).log2(), Pattern::CommonPassword))
+                } else if user_inputs.contains(candidate) {
+                    Some((0.0, Pattern::UserInput))
+                } else if dictionary.contains(candidate.as_str()) {
+                    Some(((wordlist().len() as f64).log2(), Pattern::Word))
+                } else {
+                    None
+                };
+                if let Some((bits, pattern)) = found {
+                    matches.push(Match { start, end, bits: bits + extra, pattern });
+                }
+            }
+        }
+    }
+}
+
+fn keyboard_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let keys = keyboard();
+    let adjacent = |a: char, b: char| {
+        let (Some(&(ra, ca, _)), Some(&(rb, cb, _))) = (keys.get(&a), keys.get(&b)) else {
+            return None;
+        };
+        let step = (rb - ra, cb - ca);
+        let neighbour = matches!(step, (0, -1) | (0, 1) | (-1, 0) | (-1, 1) | (1, -1) | (1, 0));
+        neighbour.then_some(step)
+    };
+    let starting_keys = (keys.len() / 2) as f64;
+
+    for start in 0..chars.len() {
+        let mut turns = 0;
+        let mut direction = None;
+        for end in start + 1..chars.len() {
+            let Some(step) = adjacent(chars[end - 1], chars[end]) else { break };
+            if direction.is_some_and(|d| d != step) {
+                turns += 1;
+            }
+            direction = Some(step);
+            let len = end + 1 - start;
+            if len >= 4 {
+                let piece = &chars[start..=end];
+                let shifted = piece.iter().filter(|c| keys.get(c).is_some_and(|k| k.2)).count();
+                let shift_bits = if shifted == 0 || shifted == len { shifted.min(1) as f64 } else { shifted as f64 };
+                let bits = (starting_keys * len as f64).log2() + turns as f64 * 6f64.log2() + shift_bits;
+                matches.push(Match { start, end: end + 1, bits, pattern: Pattern::Keyboard });
+            }
+        }
+    }
+}
+
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let class = |c: char| {
+        if c.is_ascii_alphabetic() {
+            Some(26.0)
+        } else if c.is_ascii_digit() {
+            Some(10.0)
+        } else {
+            None
+        }
+    };
+    for start in 0..chars.len() {
+        let Some(base) = class(chars[start]) else { continue };
+        let mut delta = None;
+        for end in start + 1..chars.len() {
+            let step = chars[end] as i32 - chars[end - 1] as i32;
+            let same_class = chars[end].is_ascii_digit() == chars[start].is_ascii_digit()
+                && chars[end].is_ascii_uppercase() == chars[start].is_ascii_uppercase();
+            if !same_class || class(chars[end]).is_none() || !(step == 1 || step == -1) || delta.is_some_and(|d| d != step) {
+                break;
+            }
+            delta = Some(step);
+            let len = end + 1 - start;
+            if len >= 3 {
+                // Runs from the obvious starting points are tried first
+                let obvious = matches!(chars[start], 'a' | 'A' | 'z' | 'Z' | '0' | '1' | '9');
+                let start_bits = if obvious { 1.0 } else { f64::log2(base) };
+                let bits = start_bits + (len as f64).log2() + if step < 0 { 1.0 } else { 0.0 };
+                matches.push(Match { start, end: end + 1, bits, pattern: Pattern::Sequence });
+            }
+        }
+    }
+}
+
+fn repeat_matches(chars: &[char], user_inputs: &[&str], matches: &mut Vec<Match>) {
+    for start in 0..chars.len() {
+        for block in 1..=(chars.len() - start) / 2 {
+            let unit = &chars[start..start + block];
+            let mut count = 1;
+            while start + (count + 1) * block <= chars.len()
+                && chars[start + count * block..start + (count + 1) * block] == *unit
+            {
+                count += 1;
+            }
+            if count < 2 || (block == 1 && count < 3) {
+                continue;
+            }
+            let unit_text: String = unit.iter().collect();
+            let unit_bits = estimate(&unit_text, user_inputs).bits;
+            for repeats in 2..=count {
+                let bits = unit_bits + (repeats as f64).log2();
+                matches.push(Match { start, end: start + repeats * block, bits, pattern: Pattern::Repeat });
+            }
+            // Longer blocks here are repeats of this one
+            break;
+        }
+    }
+}
+
+fn date_matches(chars: &[char], matches: &mut Vec<Match>) {
+    for start in 0..chars.len() {
+        if !chars[start].is_ascii_digit() {
+            continue;
+        }
+        for end in start + 4..=chars.len().min(start + 10) {
+            if !chars[end - 1].is_ascii_digit() {
+                continue;
+            }
+            let piece: String = chars[start..end].iter().collect();
+            if let Some(bits) = date_bits(&piece) {
+                matches.push(Match { start, end, bits, pattern: Pattern::Date });
+            }
+        }
+    }
+}
+
+fn plausible_year(year: u32) -> bool {
+    (1900..=2099).contains(&year)
+}
+
+fn plausible_day_month(day: u32, month: u32) -> bool {
+    (1..=31).contains(&day) && (1..=12).contains(&month)
+}
+
+/// Bits for `text` if it reads as a date or a year: with or without
+/// separators, day/month/year in any of the usual orders.
+fn date_bits(text: &str) -> Option<f64> {
+    const YEARS: f64 = 200.0;
+    const DAYS: f64 = 366.0;
+    let separators: Vec<char> = text.chars().filter(|c| !c.is_ascii_digit()).collect();
+    let parts: Vec<&str> = if separators.is_empty() {
+        Vec::new()
+    } else {
+        if separators.len() != 2 || separators[0] != separators[1] || !"/-. ".contains(separators[0]) {
+            return None;
+        }
+        text.split(separators[0]).collect()
+    };
+    let num = |s: &str| s.parse::<u32>().ok();
+
+    if separators.is_empty() {
+        let (a, b, c, d) = match text.len() {
+            4 => {
+                let year = num(text)?;
+                let md = (num(&text[..2])?, num(&text[2..])?);
+                return if plausible_year(year) {
+                    Some(YEARS.log2())
+                } else if plausible_day_month(md.0, md.1) || plausible_day_month(md.1, md.0) {
+                    Some(DAYS.log2())
+                } else {
+                    None
+                };
+            }
+            6 => (num(&text[..2])?, num(&text[2..4])?, num(&text[4..])?, None),
+            8 => (num(&text[..2])?, num(&text[2..4])?, num(&text[4..])?, Some((num(&text[..4])?, num(&text[4..6])?, num(&text[6..])?))),
+            _ => return None,
+        };
+        // 6 digits: DDMMYY, MMDDYY or YYMMDD; 8 digits: DDMMYYYY, MMDDYYYY or YYYYMMDD
+        let six = text.len() == 6 && (plausible_day_month(a, b) || plausible_day_month(b, a) || plausible_day_month(c, b));
+        let eight = text.len() == 8
+            && ((plausible_year(c) && (plausible_day_month(a, b) || plausible_day_month(b, a)))
+                || d.is_some_and(|(y, m, dd)| plausible_year(y) && plausible_day_month(dd, m)));
+        return if six {
+            Some((DAYS * 100.0).log2())
+        } else if eight {
+            Some((DAYS * YEARS).log2())
+        } else {
+            None
+        };
+    }
+
+    let [a, b, c] = parts[..] else { return None };
+    let (x, y, z) = (num(a)?, num(b)?, num(c)?);
+    let year_first = a.len() == 4 && plausible_year(x) && plausible_day_month(z, y);
+    let year_last = (c.len() == 2 || (c.len() == 4 && plausible_year(z)))
+        && a.len() <= 2
+        && b.len() <= 2
+        && (plausible_day_month(x, y) || plausible_day_month(y, x));
+    let years = if c.len() == 2 { 100.0 } else { YEARS };
+    // The separator is one more choice to guess
+    (year_first || year_last).then(|| (DAYS * years).log2() + 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_patterns() {
+        let kdf = Kdf::RECOMMENDED;
+        let rate = |p: &str| estimate(p, &["Work Notes"]).strength(kdf);
+
+        assert_eq!(wordlist().len(), 1296);
+        assert_eq!(rate("password"), Strength::VeryWeak);
+        assert_eq!(rate("P@ssw0rd"), Strength::VeryWeak);
+        assert_eq!(rate("qwertyuiop"), Strength::VeryWeak);
+        assert_eq!(rate("abcdefgh"), Strength::VeryWeak);
+        assert_eq!(rate("aaaaaaaaaaaa"), Strength::VeryWeak);
+        assert_eq!(rate("19871224"), Strength::VeryWeak);
+        assert_eq!(rate("work notes"), Strength::VeryWeak);
+        assert_eq!(estimate("zxcvbnm", &[]).warning(), Some(Pattern::CommonPassword.warning()));
+        assert_eq!(estimate("poiuytr", &[]).patterns, vec![Pattern::Keyboard]);
+        assert_eq!(estimate("24/12/1987", &[]).patterns, vec![Pattern::Date]);
+        assert_eq!(estimate("tigerlamp", &[]).patterns, vec![Pattern::Word, Pattern::Word]);
+        assert_eq!(estimate("tigertigertiger", &[]).patterns, vec![Pattern::Repeat]);
+
+        // Patterns make a passphrase weaker than its length suggests
+        assert!(estimate("12345678", &[]).bits < estimate("81726354", &[]).bits);
+        assert!(estimate("Summer2024!", &[]).bits < estimate("Xq7#kP2m!vR4", &[]).bits);
+
+        // A generated passphrase is rated at what it is worth, give or take
+        // the separator
+        let generated = generate_passphrase(GENERATED_WORDS);
+        assert_eq!(generated.split(GENERATED_SEPARATOR).count(), GENERATED_WORDS);
+        assert!(generated.split(GENERATED_SEPARATOR).all(|w| dictionary().contains(w)));
+        let bits = estimate("ocean-robot-lamp-tulip-cabin-frost", &[]).bits;
+        assert!((bits - generated_bits(GENERATED_WORDS)).abs() < 10.0, "{bits}");
+        assert!(rate("ocean-robot-lamp-tulip-cabin-frost") >= Strength::Strong);
+        assert_eq!(rate("j#8Vq!2m@Lw9&xZ4pR"), Strength::VeryStrong);
+    }
+
+    #[test]
+    fn test_crack_times() {
+        let estimate = Estimate { bits: 40.0, patterns: Vec::new() };
+        // A slower KDF takes proportionally longer
+        let fast = estimate.crack_seconds(Kdf::Argon2id { memory_kib: 64 * 1024, iterations: 1, parallelism: 1 }, 1.0);
+        let slow = estimate.crack_seconds(Kdf::RECOMMENDED, 1.0);
+        assert!((slow / fast - 3.0).abs() < 1e-9);
+        assert!(estimate.crack_seconds(Kdf::LEGACY, 1.0) < slow);
+
+        assert_eq!(format_duration(0.2), "less than a second");
+        assert_eq!(format_duration(90.0), "2 minutes");
+        assert_eq!(format_duration(3.0 * 86_400.0), "3 days");
+        assert_eq!(format_duration(5.0 * 365.25 * 86_400.0), "5 years");
+        assert_eq!(format_duration(1e20), "millions of years");
+        // Values that round up to the next unit move on to it
+        assert_eq!(format_duration(59.7), "1 minute");
+        assert_eq!(format_duration(999.7 * 365.25 * 86_400.0), "1 thousand years");
+        assert_eq!(format_duration(999_700.0 * 365.25 * 86_400.0), "millions of years");
+    }
+
+    #[test]
+    fn test_common_passwords_are_unique() {
+        let mut seen = HashSet::new();
+        for password in COMMON_PASSWORDS {
+            assert!(seen.insert(password), "{password} listed twice");
+        }
+    }
+}
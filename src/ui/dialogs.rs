@@ -15,6 +15,9 @@ pub fn show_shortcuts_window(parent: &adw::ApplicationWindow) {
             ("Ctrl+Shift+P", "Command palette"),
             ("Ctrl+Shift+J", "Zen mode"),
             ("Ctrl+Shift+T", "Daily note"),
+            ("Ctrl+L", "Lock vault"),
+            ("Ctrl+Shift+C", "Copy as sensitive"),
+            ("Ctrl+,", "Preferences"),
             ("F1", "Help"),
             ("F2", "Rename note"),
             ("F11", "Fullscreen"),
@@ -25,6 +28,7 @@ pub fn show_shortcuts_window(parent: &adw::ApplicationWindow) {
             ("Ctrl+U", "Underline"),
             ("Ctrl+D", "Strikethrough"),
             ("Ctrl+E", "Inline code"),
+            ("Ctrl+Shift+H", "Secret"),
             ("Ctrl+K", "Insert link"),
             ("Ctrl+1/2/3", "Heading 1/2/3"),
             ("Ctrl+Shift+Q", "Block quote"),
@@ -312,7 +316,7 @@ fn build_vault_page() -> adw::NavigationPage {
     list.add_css_class("boxed-list");
     list.set_selection_mode(gtk::SelectionMode::None);
     for desc in [
-        "Your password is used for AES-256 encryption via PBKDF2 key derivation",
+        "Your password is used for AES-256 encryption via Argon2id key derivation",
         "The vault file is saved to your chosen folder as an encrypted .mdvault file",
         "Nobody can read your notes without the password \u{2014} not even with direct file access",
     ] {
@@ -320,9 +324,187 @@ fn build_vault_page() -> adw::NavigationPage {
     }
     c.append(&list);
 
+    c.append(&help_section("Choosing a Passphrase"));
+    c.append(&help_paragraph(
+        "While you type a new vault\u{2019}s passphrase, a meter rates it and estimates \
+         how long guessing it would take with one graphics card and with a thousand, \
+         given how slow the vault\u{2019}s key derivation makes every guess. Common \
+         passwords, words, keyboard patterns like \u{201c}qwerty\u{201d}, runs, repeats \
+         and dates count for little, and weak choices ask for confirmation. \
+         \u{201c}Generate\u{201d} picks six random words from a built-in list of 1296."
+    ));
+
+    c.append(&help_section("Changing Your Passphrase"));
+    c.append(&help_paragraph(
+        "Use the primary menu \u{2192} \u{201c}Change Passphrase\u{201d}. Your notes \
+         are encrypted with a random vault key; the passphrase only protects that key, \
+         so changing it is quick and your recovery code keeps working."
+    ));
+
+    c.append(&help_section("Recovery Code"));
+    c.append(&help_paragraph(
+        "A recovery code is shown once when you create a vault. Store it somewhere \
+         safe: if you forget your passphrase, choose \u{201c}Unlock with recovery \
+         code\u{201d} in the unlock dialog and then set a new passphrase. Use \
+         \u{201c}New Recovery Code\u{201d} in the primary menu to replace a lost code."
+    ));
+
+    c.append(&help_section("Keyfile"));
+    c.append(&help_paragraph(
+        "A keyfile is an optional second factor: any file you choose (or a random one \
+         generated for you) must be selected together with the passphrase to unlock \
+         the vault. Keep it on separate storage such as a USB stick, and keep a copy \u{2014} \
+         the file must stay byte-for-byte unchanged. The recovery code does not need the \
+         keyfile. Add, replace or remove the keyfile from Change Passphrase."
+    ));
+
+    c.append(&help_section("Remember on This Device"));
+    c.append(&help_paragraph(
+        "Tick \u{201c}Remember on this device\u{201d} when unlocking to give this computer \
+         its own key to the vault, kept in your desktop keyring (GNOME Keyring, KWallet \
+         or another Secret Service). Later launches then unlock without the passphrase or \
+         keyfile for as long as the keyring itself is unlocked; locking the vault still asks \
+         for the passphrase. Only do this on a computer you trust. \u{201c}Forget This \
+         Device\u{201d} in the primary menu removes the key again, and changing the \
+         passphrase or recovery code removes it from every device."
+    ));
+
+    c.append(&help_section("Failed Unlock Attempts"));
+    c.append(&help_paragraph(
+        "After three wrong passphrases or recovery codes in a row the unlock dialog \
+         makes you wait, starting at 5 seconds and doubling with every further miss \
+         up to 15 minutes. Restarting the app does not reset the wait. The next \
+         successful unlock tells you how many attempts failed since the vault was \
+         last opened on this computer, in case someone else has been trying."
+    ));
+
+    c.append(&help_section("Copied Text"));
+    c.append(&help_paragraph(
+        "Text you copy from a note stays on the clipboard, outside the vault. By default \
+         it is cleared when the vault locks or closes; Preferences can also clear it a \
+         set number of seconds after copying. Only text copied from MDNotebook is \
+         cleared, and only if nothing else was copied since. \u{201c}Copy as Sensitive\u{201d} \
+         in the editor\u{2019}s context menu marks the copy so clipboard managers don\u{2019}t \
+         keep it in their history, and clears it after 30 seconds unless a timeout is set."
+    ));
+    c.append(&shortcut_list(&[
+        ("Ctrl+Shift+C", "Copy the selection as sensitive"),
+    ]));
+
+    c.append(&help_section("Unencrypted Files"));
+    c.append(&help_paragraph(
+        "By default a vault or image stored without encryption is refused rather than \
+         opened, so a tampered vault folder cannot slip plaintext content in. When such \
+         files are found you are shown exactly which ones and asked before they are \
+         encrypted; \u{201c}Encrypt Unencrypted Files\u{201d} in the primary menu runs \
+         the same check. Turn off \u{201c}Refuse unencrypted files\u{201d} in Preferences \
+         to read them as before."
+    ));
+
+    c.append(&help_section("Checking Your Vault"));
+    c.append(&help_paragraph(
+        "\u{201c}Check Vault\u{201d} in the primary menu decrypts every image and \
+         compares image files, their metadata and the images your notes, version \
+         history and trash refer to. Problems are listed with one-click fixes: \
+         unreadable files are moved to a quarantine folder, missing files have their \
+         metadata dropped, and orphaned files get their metadata back. Run \
+         mdnotebook --check-vault from a terminal to do the same without the app."
+    ));
+
+    c.append(&help_section("Unused Images"));
+    c.append(&help_paragraph(
+        "Images stay in the vault while any note, version or trashed note refers to \
+         them. \u{201c}Remove Unused Images\u{201d} in the primary menu deletes the rest. \
+         An image that has only just become unused is kept for ten more minutes so \
+         undo can still bring it back, then removed automatically. Image files the \
+         vault has no record of, such as ones synced in ahead of the notes that use \
+         them, are kept for a day after they arrive. Preferences can run this every \
+         time you empty the trash."
+    ));
+
+    c.append(&help_section("Backups"));
+    c.append(&help_paragraph(
+        "While saving, a complete copy of the vault is written to the backups folder \
+         inside the vault at most once an hour. The newest backup of each of the last 24 hours, \
+         7 days and 8 weeks is kept. \u{201c}Restore from Backup\u{201d} in the primary \
+         menu previews a backup\u{2019}s notes; restore single notes as new notes, or \
+         replace the entire vault. The current vault is backed up first, so a restore \
+         can be undone the same way."
+    ));
+
+    c.append(&help_section("Moving a Vault"));
+    c.append(&help_paragraph(
+        "\u{201c}Export Vault Bundle\u{201d} in the primary menu writes the whole \
+         vault and every image its notes use into a single encrypted file. It opens \
+         with the vault\u{2019}s passphrase, or with a different one you choose when \
+         exporting. \u{201c}Import Vault Bundle\u{201d} restores a bundle as a new \
+         vault in an empty folder, or merges its notes and images into the open \
+         vault; notes that are already there are skipped."
+    ));
+
+    c.append(&help_section("Sharing a Note"));
+    c.append(&help_paragraph(
+        "\u{201c}Share Note Encrypted\u{201d} saves the open note and its images as \
+         one file, encrypted under a passphrase you choose just for it. Send the file \
+         and tell the passphrase separately. \u{201c}Import Shared Note\u{201d} \
+         decrypts such a file into a new note; its images become images of your \
+         vault."
+    ));
+
+    c.append(&help_section("Synced Folders"));
+    c.append(&help_paragraph(
+        "If the vault folder is synced between devices (Syncthing, Nextcloud and the \
+         like), each save first checks whether the vault file was changed elsewhere. \
+         If so, the other device\u{2019}s changes are merged in note by note before \
+         saving. A note edited on both devices keeps your version and gains a \
+         \u{201c}conflicted copy\u{201d} with the other one; compare the two side by \
+         side and keep either or both."
+    ));
+
+    c.append(&help_section("Several Vaults"));
+    c.append(&help_paragraph(
+        "Every vault you create or open is remembered, and the one opened last is \
+         offered at startup. \u{201c}Switch Vault\u{201d} in the primary menu saves \
+         and locks the current vault and unlocks another in the same window; \
+         \u{201c}Close Vault\u{201d} goes back to the list of vaults, where they can \
+         be renamed or removed from the list. To keep two vaults open side by side, \
+         choose \u{201c}New Window\u{201d} and open the second vault there."
+    ));
+
+    c.append(&help_section("Vault Already Open"));
+    c.append(&help_paragraph(
+        "While a vault is unlocked, a lock file in its folder names the computer \
+         that has it open. Opening the vault elsewhere at the same time shows who \
+         holds it and offers a read-only view, where nothing is saved. If that \
+         instance stopped without closing the vault, you can take the lock over; \
+         the old instance then becomes read-only as well."
+    ));
+
+    c.append(&help_section("Read-Only"));
+    c.append(&help_paragraph(
+        "Tick \u{201c}Open read-only\u{201d} when unlocking to look through a vault \
+         without any chance of changing it, for example a copy on a backup disk. \
+         Nothing is saved, no lock file or journal is written and editing is off; \
+         searching, opening notes, copying and export still work. A banner shows \
+         while a window is read-only."
+    ));
+
+    c.append(&help_section("Auto-Lock"));
+    c.append(&help_paragraph(
+        "The vault locks itself after a period without input, and when your screen \
+         locks or the computer suspends. Locking saves your notes, removes them and \
+         the key from memory, and shows the unlock dialog again. Change the timeout \
+         in Preferences."
+    ));
+    c.append(&shortcut_list(&[
+        ("Ctrl+L", "Lock the vault now"),
+    ]));
+
     c.append(&help_section("Saving"));
     c.append(&help_paragraph(
-        "Changes are auto-saved periodically. You can also save manually."
+        "Changes are auto-saved periodically. You can also save manually. Between \
+         saves, each edit is also written to an encrypted crash journal; if the app \
+         closes before saving, the next unlock offers to recover those edits."
     ));
     c.append(&shortcut_list(&[
         ("Ctrl+S", "Save vault"),
@@ -385,9 +567,21 @@ fn build_formatting_page() -> adw::NavigationPage {
         ("Ctrl+U", "Underline"),
         ("Ctrl+D", "Strikethrough"),
         ("Ctrl+E", "Inline code"),
+        ("Ctrl+Shift+H", "Secret"),
         ("Ctrl+K", "Insert link"),
     ]));
 
+    c.append(&help_section("Secret Text"));
+    c.append(&help_paragraph(
+        "Mark passwords, API tokens and the like as secret with Ctrl+Shift+H. In \
+         the editor, version history and conflict compare they show as dots; click \
+         the dots, or Show Secrets in conflict compare, to show the text. Shown \
+         secrets in the editor are hidden again after 20 seconds. \
+         Search skips secret text, and Export as HTML leaves it out unless you choose \
+         to include it. In Markdown a secret is written <secret>\u{2026}</secret> \
+         and is not masked in the source view or inside tables."
+    ));
+
     c.append(&help_section("Block Types"));
     c.append(&shortcut_list(&[
         ("Ctrl+1\u{2026}6", "Heading 1 through 6"),
@@ -645,6 +839,7 @@ fn build_shortcuts_page() -> adw::NavigationPage {
             ("Ctrl+U", "Underline"),
             ("Ctrl+D", "Strikethrough"),
             ("Ctrl+E", "Inline code"),
+            ("Ctrl+Shift+H", "Secret"),
             ("Ctrl+K", "Insert link"),
             ("Ctrl+1\u{2026}6", "Heading 1\u{2026}6"),
             ("Ctrl+Shift+Q", "Block quote"),
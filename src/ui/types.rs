@@ -4,6 +4,11 @@ use std::{cell::Cell, cell::RefCell, rc::Rc};
 
 use crate::state::DocState;
 use crate::crypto;
+use crate::journal;
+use crate::merge;
+use crate::note_files;
+use crate::vault;
+use crate::writer_lock;
 
 #[derive(Clone)]
 pub struct EditorCtx {
@@ -24,10 +29,52 @@ pub struct EditorCtx {
     pub state: Rc<RefCell<DocState>>,
     pub vault_folder: Rc<RefCell<String>>,
     pub cached_key: Rc<RefCell<Option<crypto::CachedKey>>>,
+    /// Crash journal of edits not yet saved; dropped when the editor closes.
+    pub journal: Rc<RefCell<Option<journal::Journal>>>,
+    /// vault.json as last read or written here, to detect changes made by
+    /// another device, and the note timestamps it held (the merge base).
+    pub vault_file: Rc<RefCell<Option<vault::VaultFingerprint>>>,
+    pub merge_base: Rc<RefCell<merge::MergeBase>>,
+    /// Note files the manifest on disk refers to, so a save only re-encrypts
+    /// the notes that changed.
+    pub note_files: Rc<RefCell<note_files::NoteFiles>>,
+    /// Lock file claiming the vault folder for this instance; `None` when the
+    /// vault was opened read-only.
+    pub writer_lock: Rc<RefCell<Option<writer_lock::WriterLock>>>,
+    /// Nothing is written to the vault folder and the editor is not editable.
+    pub read_only: Rc<Cell<bool>>,
+    pub read_only_banner: adw::Banner,
+    /// Toolbar buttons and entries that change the note, made insensitive
+    /// while read-only.
+    pub edit_controls: Vec<gtk::Widget>,
     pub save_timeout_id: Rc<Cell<Option<glib::SourceId>>>,
     pub save_generation: Rc<Cell<u64>>,
     pub saving: Rc<Cell<bool>>,
     pub close_requested: Rc<Cell<bool>>,
+    // Auto-lock
+    pub lock_requested: Rc<Cell<bool>>,
+    /// Where the window goes once a requested lock has saved.
+    pub after_lock: Rc<RefCell<AfterLock>>,
+    pub locked: Rc<Cell<bool>>,
+    pub last_activity: Rc<Cell<std::time::Instant>>,
+    /// Auto-lock settings, read from the config when the editor opens and
+    /// updated by Preferences.
+    pub auto_lock_minutes: Rc<Cell<u32>>,
+    pub lock_on_session_lock: Rc<Cell<bool>>,
+    /// logind delay inhibitor holding off suspend until the vault has locked;
+    /// dropping the fd list closes the fd and releases it.
+    pub sleep_inhibitor: Rc<RefCell<Option<gtk::gio::UnixFDList>>>,
+    /// Handlers and controllers attached to the window itself, removed on lock
+    /// so a re-unlocked editor doesn't stack duplicates.
+    pub window_handlers: Rc<RefCell<Vec<glib::SignalHandlerId>>>,
+    pub window_controllers: Rc<RefCell<Vec<gtk::EventController>>>,
+    pub dbus_subscriptions: Rc<RefCell<Vec<(gtk::gio::DBusConnection, gtk::gio::SignalSubscriptionId)>>>,
+    /// Note text this editor last put on the clipboard, to be cleared.
+    pub copied_content: Rc<RefCell<Option<CopiedContent>>>,
+    /// Clipboard settings, read from the config when the editor opens and
+    /// updated by Preferences.
+    pub clear_clipboard_secs: Rc<Cell<u32>>,
+    pub clear_clipboard_on_lock: Rc<Cell<bool>>,
     // HIG layout widgets
     pub split_view: adw::OverlaySplitView,
     pub toast_overlay: adw::ToastOverlay,
@@ -43,12 +90,35 @@ pub struct EditorCtx {
     pub source_toggle: gtk::Button,
     pub sync_timeout_id: Rc<Cell<Option<glib::SourceId>>>,
     pub search_timeout_id: Rc<Cell<Option<glib::SourceId>>>,
+    pub secret_reveal_timeout_id: Rc<Cell<Option<glib::SourceId>>>,
+}
+
+/// Clipboard content copied out of the vault. It is cleared after the
+/// configured timeout or on lock, unless something else was copied since.
+pub struct CopiedContent {
+    pub provider: gtk::gdk::ContentProvider,
+    /// Copied with "Copy as Sensitive": always cleared, whatever the settings.
+    pub sensitive: bool,
+    pub clear_timeout: Option<glib::SourceId>,
+}
+
+/// What replaces the editor when the vault locks.
+#[derive(Debug, Clone, Default)]
+pub enum AfterLock {
+    /// The locked page, ready to unlock the same vault again.
+    #[default]
+    Locked,
+    /// The vault picker ("Close Vault").
+    Picker,
+    /// The unlock dialog of another vault ("Switch Vault").
+    Unlock(String),
 }
 
 pub struct ContentPaneWidgets {
     pub toast_overlay: adw::ToastOverlay,
     pub content_toolbar_view: adw::ToolbarView,
     pub content_header: adw::HeaderBar,
+    pub read_only_banner: adw::Banner,
     pub doc_label: gtk::Label,
     pub dirty_label: gtk::Label,
     pub toolbar_scroll: gtk::ScrolledWindow,
@@ -5,7 +5,10 @@ use std::{cell::Cell, cell::RefCell, rc::Rc};
 
 use crate::state::*;
 use crate::crypto;
+use crate::merge;
+use crate::note_files;
 use crate::vault;
+use crate::writer_lock;
 use crate::ui::types::*;
 use crate::*; // For everything left in main.rs temporarily
 
@@ -33,6 +36,23 @@ pub fn build_ui(app: &adw::Application) {
         eprintln!("No GTK display available while registering icon search paths");
     }
 
+    // The vault opened last, if it is still there and not open already (the
+    // app was launched again while running)
+    let config = vault::load_config();
+    let last_vault = config
+        .vault_path
+        .filter(|path| vault::vault_file_path(path).exists() && !writer_lock::held_by_this_process(path));
+    if last_vault.is_none() && config.vaults.is_empty() {
+        let window = new_vault_window(app, None);
+        show_create_vault_dialog(&window);
+    } else {
+        new_vault_window(app, last_vault);
+    }
+}
+
+/// A new window showing the vault picker, with the unlock dialog of
+/// `vault_folder` on top if given. Each window holds at most one open vault.
+pub fn new_vault_window(app: &adw::Application, vault_folder: Option<String>) -> adw::ApplicationWindow {
     let window = adw::ApplicationWindow::builder()
         .application(app)
         .title("MDNotebook")
@@ -45,17 +65,11 @@ pub fn build_ui(app: &adw::Application) {
 
     window.present();
 
-    let config = vault::load_config();
-
-    match config.vault_path {
-        Some(ref path) if vault::vault_file_path(path).exists() => {
-            let folder = path.clone();
-            show_unlock_vault_dialog(&window, folder);
-        }
-        _ => {
-            show_create_vault_dialog(&window);
-        }
+    show_vault_picker(&window);
+    if let Some(folder) = vault_folder {
+        unlock_vault(&window, folder);
     }
+    window
 }
 
 pub fn build_editor(
@@ -63,7 +77,9 @@ pub fn build_editor(
     initial_state: DocState,
     vault_folder: String,
     cached_key: crypto::CachedKey,
-) {
+    writer_lock: Option<writer_lock::WriterLock>,
+    note_files: note_files::NoteFiles,
+) -> EditorCtx {
     // --- Build sidebar pane ---
     let (sidebar_toolbar_view, _sidebar_header, search_bar, search_entry, notes_list, tag_filter_box) =
         build_sidebar();
@@ -73,6 +89,7 @@ pub fn build_editor(
         toast_overlay,
         content_toolbar_view: _content_toolbar_view,
         content_header: _content_header,
+        read_only_banner,
         doc_label,
         dirty_label,
         toolbar_scroll,
@@ -95,7 +112,7 @@ pub fn build_editor(
         toolbar_widgets: tb,
         source_toggle,
         content_stack,
-    } = build_content_pane();
+    } = build_content_pane(&vault_folder);
 
     // --- Assemble OverlaySplitView ---
     let split_view = adw::OverlaySplitView::new();
@@ -105,13 +122,20 @@ pub fn build_editor(
     split_view.set_min_sidebar_width(180.0);
     split_view.set_max_sidebar_width(400.0);
 
+    // The breakpoint lives on a bin rather than the window so that it is dropped
+    // together with the editor when the vault locks.
+    let breakpoint_bin = adw::BreakpointBin::new();
+    breakpoint_bin.set_width_request(window.width_request());
+    breakpoint_bin.set_height_request(window.height_request());
+    breakpoint_bin.set_child(Some(&split_view));
+
     // Adaptive: collapse sidebar into overlay before toolbar icons get clipped.
     // Toolbar needs ~960px, sidebar ~300px → collapse below ~1260px.
     match adw::BreakpointCondition::parse("max-width: 1260sp") {
         Ok(cond) => {
             let bp = adw::Breakpoint::new(cond);
             bp.add_setter(&split_view, "collapsed", Some(&true.to_value()));
-            window.add_breakpoint(bp);
+            breakpoint_bin.add_breakpoint(bp);
         }
         Err(err) => eprintln!("Failed to parse breakpoint condition: {err}"),
     }
@@ -127,7 +151,29 @@ pub fn build_editor(
     apply_theme(&initial_state.theme);
     apply_sourceview_theme(&md_view, is_dark_active());
 
+    let merge_base = merge::base_from_state(&initial_state);
+    let vault_file = vault::vault_fingerprint(&vault_folder).unwrap_or_else(|e| {
+        eprintln!("Warning: could not read vault file: {e}");
+        None
+    });
     let state = Rc::new(RefCell::new(initial_state));
+    let mut cached_key = cached_key;
+    let config = vault::load_config();
+    cached_key.set_compression(config.compress_payloads);
+    cached_key.set_padding(config.pad_payloads);
+    let read_only = writer_lock.is_none();
+    rich_view.set_editable(!read_only);
+    let mut edit_controls: Vec<gtk::Widget> = [
+        &tb.undo, &tb.redo, &tb.bold, &tb.italic, &tb.underline, &tb.strike, &tb.code,
+        &tb.block_paragraph, &tb.block_h1, &tb.block_h2, &tb.block_h3, &tb.block_h4,
+        &tb.block_h5, &tb.block_h6, &tb.block_quote, &tb.bullet, &tb.ordered, &tb.task,
+        &tb.link, &tb.table, &tb.rule, &tb.image,
+    ]
+    .into_iter()
+    .map(|button| button.clone().upcast())
+    .collect();
+    edit_controls.extend(tb.code_languages.iter().map(|(_, button)| button.clone().upcast()));
+    edit_controls.push(tag_entry.clone().upcast());
 
     let ctx = EditorCtx {
         window: window.clone(),
@@ -147,10 +193,31 @@ pub fn build_editor(
         state,
         vault_folder: Rc::new(RefCell::new(vault_folder)),
         cached_key: Rc::new(RefCell::new(Some(cached_key))),
+        journal: Rc::new(RefCell::new(None)),
+        vault_file: Rc::new(RefCell::new(vault_file)),
+        merge_base: Rc::new(RefCell::new(merge_base)),
+        note_files: Rc::new(RefCell::new(note_files)),
+        writer_lock: Rc::new(RefCell::new(writer_lock)),
+        read_only: Rc::new(Cell::new(read_only)),
+        read_only_banner,
+        edit_controls,
         save_timeout_id: Rc::new(Cell::new(None)),
         save_generation: Rc::new(Cell::new(0)),
         saving: Rc::new(Cell::new(false)),
         close_requested: Rc::new(Cell::new(false)),
+        lock_requested: Rc::new(Cell::new(false)),
+        after_lock: Rc::new(RefCell::new(AfterLock::default())),
+        locked: Rc::new(Cell::new(false)),
+        last_activity: Rc::new(Cell::new(std::time::Instant::now())),
+        auto_lock_minutes: Rc::new(Cell::new(config.auto_lock_minutes)),
+        lock_on_session_lock: Rc::new(Cell::new(config.lock_on_session_lock)),
+        sleep_inhibitor: Rc::new(RefCell::new(None)),
+        window_handlers: Rc::new(RefCell::new(Vec::new())),
+        window_controllers: Rc::new(RefCell::new(Vec::new())),
+        dbus_subscriptions: Rc::new(RefCell::new(Vec::new())),
+        copied_content: Rc::new(RefCell::new(None)),
+        clear_clipboard_secs: Rc::new(Cell::new(config.clear_clipboard_secs)),
+        clear_clipboard_on_lock: Rc::new(Cell::new(config.clear_clipboard_on_lock)),
         split_view: split_view.clone(),
         toast_overlay,
         toolbar: toolbar_scroll.clone(),
@@ -165,6 +232,7 @@ pub fn build_editor(
         source_toggle: source_toggle.clone(),
         sync_timeout_id: Rc::new(Cell::new(None)),
         search_timeout_id: Rc::new(Cell::new(None)),
+        secret_reveal_timeout_id: Rc::new(Cell::new(None)),
     };
 
     initialize_state(&ctx);
@@ -176,6 +244,12 @@ pub fn build_editor(
     wire_keyboard_shortcuts(&ctx, &rich_view, window);
     wire_close_request(&ctx);
     setup_auto_save(&ctx);
+    setup_auto_lock(&ctx);
+    if read_only {
+        disable_editing(&ctx);
+    } else {
+        open_crash_journal(&ctx);
+    }
 
     // Wire source toggle button in content header
     {
@@ -281,10 +355,11 @@ pub fn build_editor(
         });
     }
 
-    window.set_content(Some(&split_view));
+    window.set_content(Some(&breakpoint_bin));
+    ctx
 }
 
-pub fn build_content_pane() -> ContentPaneWidgets {
+pub fn build_content_pane(vault_folder: &str) -> ContentPaneWidgets {
     let content_toolbar_view = adw::ToolbarView::new();
 
     // --- Content header bar ---
@@ -331,11 +406,24 @@ pub fn build_content_pane() -> ContentPaneWidgets {
     content_menu_btn.set_tooltip_text(Some("Primary menu"));
     content_menu_btn.add_css_class("flat");
     set_accessible_label(&content_menu_btn, "Primary menu");
-    content_menu_btn.set_menu_model(Some(&build_content_menu()));
+    content_menu_btn.set_menu_model(Some(&build_content_menu(vault_folder)));
+    // Rebuilt on each open so the vault switcher lists vaults opened in other windows
+    {
+        let vault_folder = vault_folder.to_string();
+        content_menu_btn.set_create_popup_func(move |btn| {
+            btn.set_menu_model(Some(&build_content_menu(&vault_folder)));
+        });
+    }
     content_header.pack_end(&content_menu_btn);
 
     content_toolbar_view.add_top_bar(&content_header);
 
+    // Revealed for read-only sessions
+    let read_only_banner = adw::Banner::new("Read-only \u{2014} nothing in this vault can be changed or saved");
+    read_only_banner.set_button_label(Some("Close Vault"));
+    read_only_banner.set_action_name(Some("win.close-vault"));
+    content_toolbar_view.add_top_bar(&read_only_banner);
+
     // --- Formatting toolbar (scrollable so buttons are never clipped) ---
     let (toolbar, tb) = build_toolbar();
     let toolbar_scroll = gtk::ScrolledWindow::builder()
@@ -432,6 +520,7 @@ pub fn build_content_pane() -> ContentPaneWidgets {
         toast_overlay,
         content_toolbar_view,
         content_header,
+        read_only_banner,
         doc_label,
         dirty_label,
         toolbar_scroll,
@@ -481,6 +570,7 @@ pub fn build_rich_editor() -> (gtk::TextBuffer, gtk::TextView, gtk::ScrolledWind
         .vexpand(true)
         .child(&view)
         .build();
+    wysiwyg::install_secret_masks(&view);
 
     (buffer, view, scroll)
 }
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::vault;
+
+// ---------------------------------------------------------------------------
+// Failed unlock attempts  (<config>/unlock-attempts.json)
+// ---------------------------------------------------------------------------
+//
+// Every wrong passphrase or recovery code is counted per vault. After a few
+// in a row the unlock dialog makes the user wait, twice as long after each
+// further failure. The count lives in the config directory, so restarting the
+// app does not reset it, and the next successful unlock reports what happened
+// since the one before — a way to notice someone trying to guess their way in.
+//
+// This only slows guessing through this app on this computer. Someone with a
+// copy of the vault files is held back by the KDF, not by this.
+
+const ATTEMPTS_FILE: &str = "unlock-attempts.json";
+/// Failures in a row allowed before any waiting.
+const FREE_ATTEMPTS: u32 = 3;
+const FIRST_DELAY_SECS: i64 = 5;
+const MAX_DELAY_SECS: i64 = 15 * 60;
+
+pub fn attempts_path() -> PathBuf {
+    vault::config_dir().join(ATTEMPTS_FILE)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct VaultAttempts {
+    /// Failures since the last successful unlock.
+    #[serde(default)]
+    failed: u32,
+    /// Unix seconds of the first and the latest of them.
+    #[serde(default)]
+    first_failed: i64,
+    #[serde(default)]
+    last_failed: i64,
+    /// Unix seconds of the last successful unlock.
+    #[serde(default)]
+    last_unlocked: Option<i64>,
+}
+
+/// Failed attempts on one vault, by vault folder.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct UnlockAttempts {
+    #[serde(default)]
+    vaults: BTreeMap<String, VaultAttempts>,
+}
+
+/// What happened between two successful unlocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedAttempts {
+    pub count: u32,
+    /// The successful unlock before, if this device remembers one.
+    pub since: Option<i64>,
+    pub first: i64,
+    pub last: i64,
+}
+
+/// Seconds to wait after `failed` failures in a row.
+fn delay_after(failed: u32) -> i64 {
+    if failed < FREE_ATTEMPTS {
+        return 0;
+    }
+    let doublings = (failed - FREE_ATTEMPTS).min(16);
+    (FIRST_DELAY_SECS << doublings).min(MAX_DELAY_SECS)
+}
+
+impl UnlockAttempts {
+    /// The record on disk; empty if there is none or it can't be read.
+    pub fn load() -> Self {
+        match fs::read_to_string(attempts_path()) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Ignoring unreadable {ATTEMPTS_FILE}: {e}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(vault::config_dir())?;
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        vault::atomic_write(&attempts_path(), json.as_bytes())
+    }
+
+    /// Seconds until the vault may be tried again; 0 if it may be now.
+    pub fn wait_secs(&self, vault_folder: &str, now: i64) -> i64 {
+        let Some(attempts) = self.vaults.get(vault_folder) else { return 0 };
+        // A clock set back must not stretch the wait
+        let last = attempts.last_failed.min(now);
+        (last + delay_after(attempts.failed) - now).max(0)
+    }
+
+    pub fn record_failure(&mut self, vault_folder: &str, now: i64) {
+        let attempts = self.vaults.entry(vault_folder.to_string()).or_default();
+        if attempts.failed == 0 {
+            attempts.first_failed = now;
+        }
+        attempts.failed = attempts.failed.saturating_add(1);
+        attempts.last_failed = now;
+    }
+
+    /// Start over after a successful unlock. Returns the failures since the
+    /// previous one, if there were any.
+    pub fn record_success(&mut self, vault_folder: &str, now: i64) -> Option<FailedAttempts> {
+        let attempts = self.vaults.entry(vault_folder.to_string()).or_default();
+        let failed = (attempts.failed > 0).then_some(FailedAttempts {
+            count: attempts.failed,
+            since: attempts.last_unlocked,
+            first: attempts.first_failed,
+            last: attempts.last_failed,
+        });
+        *attempts = VaultAttempts { last_unlocked: Some(now), ..Default::default() };
+        failed
+    }
+}
+
+/// Count a failed unlock of the vault and return how long to wait before the
+/// next try.
+pub fn note_failure(vault_folder: &str, now: i64) -> i64 {
+    let mut attempts = UnlockAttempts::load();
+    attempts.record_failure(vault_folder, now);
+    if let Err(e) = attempts.save() {
+        eprintln!("Failed to save unlock attempts: {e}");
+    }
+    attempts.wait_secs(vault_folder, now)
+}
+
+/// Note a successful unlock of the vault; returns the failures before it.
+pub fn note_success(vault_folder: &str, now: i64) -> Option<FailedAttempts> {
+    let mut attempts = UnlockAttempts::load();
+    let failed = attempts.record_success(vault_folder, now);
+    if let Err(e) = attempts.save() {
+        eprintln!("Failed to save unlock attempts: {e}");
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_and_summary() {
+        let mut attempts = UnlockAttempts::default();
+        let vault = "/home/me/Vault";
+        assert_eq!(attempts.wait_secs(vault, 100), 0);
+        assert_eq!(attempts.record_success(vault, 100), None);
+
+        // A few mistakes are free, then the wait doubles each time
+        for now in 200..203 {
+            assert_eq!(attempts.wait_secs(vault, now), 0);
+            attempts.record_failure(vault, now);
+        }
+        assert_eq!(attempts.wait_secs(vault, 202), FIRST_DELAY_SECS);
+        assert_eq!(attempts.wait_secs(vault, 202 + FIRST_DELAY_SECS), 0);
+        attempts.record_failure(vault, 300);
+        assert_eq!(attempts.wait_secs(vault, 300), 2 * FIRST_DELAY_SECS);
+        for _ in 0..20 {
+            attempts.record_failure(vault, 400);
+        }
+        assert_eq!(attempts.wait_secs(vault, 400), MAX_DELAY_SECS);
+        // Turning the clock back doesn't lengthen it
+        assert_eq!(attempts.wait_secs(vault, 10), MAX_DELAY_SECS);
+        assert_eq!(attempts.wait_secs("/elsewhere", 400), 0);
+
+        // The record survives a restart
+        let json = serde_json::to_string(&attempts).unwrap();
+        let mut attempts: UnlockAttempts = serde_json::from_str(&json).unwrap();
+
+        let failed = attempts.record_success(vault, 500).unwrap();
+        assert_eq!(failed, FailedAttempts { count: 24, since: Some(100), first: 200, last: 400 });
+        assert_eq!(attempts.wait_secs(vault, 500), 0);
+        assert_eq!(attempts.record_success(vault, 600), None);
+    }
+}
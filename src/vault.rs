@@ -1,8 +1,14 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
+use crate::backup;
+use crate::crypto::{self, CachedKey, Keyfile};
+use crate::note_files::{self, NoteFiles};
+
 // ---------------------------------------------------------------------------
 // Vault data model (camelCase JSON — web-app compatible)
 // ---------------------------------------------------------------------------
@@ -44,6 +50,9 @@ pub struct AssetMeta {
     pub mime_type: String,
     pub size: u64,
     pub created_at: i64,
+    /// When asset collection first found no reference to this asset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orphaned_at: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -71,6 +80,10 @@ pub struct TreeItem {
     pub tags: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pinned: Option<bool>,
+    /// Note file under `notes/` holding the content, if not inline (see
+    /// `note_files.rs`). Only set in the manifest on disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -91,10 +104,165 @@ pub struct CustomTemplate {
 // App config  (~/.config/mdnotebook-native/config.json)
 // ---------------------------------------------------------------------------
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+/// A vault this device has opened, listed in the vault switcher.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KnownVault {
+    pub name: String,
+    pub path: String,
+    /// Unix seconds; 0 for a vault carried over from before the list existed.
+    #[serde(default)]
+    pub last_opened: i64,
+    /// The vault's key is kept in the keyring and it unlocks without asking.
+    #[serde(default)]
+    pub remembered_key: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AppConfig {
+    /// The vault opened last, offered for unlocking at startup.
     #[serde(default)]
     pub vault_path: Option<String>,
+    /// Every vault opened on this device, most recently opened first.
+    #[serde(default)]
+    pub vaults: Vec<KnownVault>,
+    /// Lock the vault after this many minutes without input (0 = never).
+    #[serde(default = "default_auto_lock_minutes")]
+    pub auto_lock_minutes: u32,
+    /// Lock the vault when the desktop session locks or the machine suspends.
+    #[serde(default = "default_true")]
+    pub lock_on_session_lock: bool,
+    /// Refuse vault and asset files that are not encrypted instead of reading them.
+    #[serde(default = "default_true")]
+    pub strict_encryption: bool,
+    /// Remove images no note refers to any more after emptying the trash.
+    #[serde(default)]
+    pub collect_assets_after_empty_trash: bool,
+    /// Compress notes and images before encrypting them.
+    #[serde(default = "default_true")]
+    pub compress_payloads: bool,
+    /// Pad notes and images to size buckets so file sizes don't reveal their
+    /// exact length.
+    #[serde(default)]
+    pub pad_payloads: bool,
+    /// Clear text copied from a note off the clipboard after this many
+    /// seconds (0 = leave it).
+    #[serde(default)]
+    pub clear_clipboard_secs: u32,
+    /// Clear text copied from a note off the clipboard when the vault locks.
+    #[serde(default = "default_true")]
+    pub clear_clipboard_on_lock: bool,
+    /// How many hourly, daily and weekly backups to keep.
+    #[serde(default)]
+    pub backup_retention: backup::Retention,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            vault_path: None,
+            vaults: Vec::new(),
+            auto_lock_minutes: default_auto_lock_minutes(),
+            lock_on_session_lock: true,
+            strict_encryption: true,
+            collect_assets_after_empty_trash: false,
+            compress_payloads: true,
+            pad_payloads: false,
+            clear_clipboard_secs: 0,
+            clear_clipboard_on_lock: true,
+            backup_retention: backup::Retention::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn plaintext_policy(&self) -> crypto::PlaintextPolicy {
+        if self.strict_encryption {
+            crypto::PlaintextPolicy::Refuse
+        } else {
+            crypto::PlaintextPolicy::Allow
+        }
+    }
+
+    pub fn known_vault(&self, path: &str) -> Option<&KnownVault> {
+        self.vaults.iter().find(|v| v.path == path)
+    }
+
+    /// The name shown for the vault at `path`.
+    pub fn vault_name(&self, path: &str) -> String {
+        self.known_vault(path).map_or_else(|| default_vault_name(path), |v| v.name.clone())
+    }
+
+    /// Record that the vault at `path` was just opened: it moves to the top of
+    /// the list and is the one offered at the next startup.
+    pub fn remember_vault(&mut self, path: &str, now: i64) {
+        let name = self.vault_name(path);
+        let remembered_key = self.is_key_remembered(path);
+        self.vaults.retain(|v| v.path != path);
+        self.vaults.insert(0, KnownVault { name, path: path.to_string(), last_opened: now, remembered_key });
+        self.vault_path = Some(path.to_string());
+    }
+
+    /// List the vault at `path` without opening it.
+    pub fn add_vault(&mut self, path: &str) {
+        if self.known_vault(path).is_none() {
+            self.vaults.push(KnownVault {
+                name: default_vault_name(path),
+                path: path.to_string(),
+                last_opened: 0,
+                remembered_key: false,
+            });
+        }
+    }
+
+    pub fn rename_vault(&mut self, path: &str, name: &str) {
+        let name = name.trim();
+        if let Some(vault) = self.vaults.iter_mut().find(|v| v.path == path) {
+            vault.name = if name.is_empty() { default_vault_name(path) } else { name.to_string() };
+        }
+    }
+
+    pub fn is_key_remembered(&self, path: &str) -> bool {
+        self.known_vault(path).is_some_and(|v| v.remembered_key)
+    }
+
+    /// Note whether the vault's key is in the keyring. Only listed vaults
+    /// can be remembered.
+    pub fn set_key_remembered(&mut self, path: &str, remembered: bool) {
+        if let Some(vault) = self.vaults.iter_mut().find(|v| v.path == path) {
+            vault.remembered_key = remembered;
+        }
+    }
+
+    /// Drop the vault from the list. Its files are left alone.
+    pub fn forget_vault(&mut self, path: &str) {
+        self.vaults.retain(|v| v.path != path);
+        if self.vault_path.as_deref() == Some(path) {
+            self.vault_path = None;
+        }
+    }
+
+    /// Configs written before the list existed only know `vault_path`.
+    fn adopt_vault_path(&mut self) {
+        if let Some(path) = self.vault_path.clone() {
+            self.add_vault(&path);
+        }
+    }
+}
+
+/// The folder name, which is what a vault is called until the user renames it.
+pub fn default_vault_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn default_auto_lock_minutes() -> u32 {
+    10
+}
+
+fn default_true() -> bool {
+    true
 }
 
 pub fn config_dir() -> PathBuf {
@@ -110,7 +278,11 @@ pub fn config_path() -> PathBuf {
 pub fn load_config() -> AppConfig {
     fs::read_to_string(config_path())
         .ok()
-        .and_then(|s| serde_json::from_str(&s).ok())
+        .and_then(|s| serde_json::from_str::<AppConfig>(&s).ok())
+        .map(|mut config| {
+            config.adopt_vault_path();
+            config
+        })
         .unwrap_or_default()
 }
 
@@ -122,6 +294,17 @@ pub fn save_config(config: &AppConfig) -> io::Result<()> {
     atomic_write(&config_path(), json.as_bytes())
 }
 
+/// Load the configuration, apply `change`, save it again and return it. A
+/// failed save is only reported: the setting still applies to this session.
+pub fn update_config(change: impl FnOnce(&mut AppConfig)) -> AppConfig {
+    let mut config = load_config();
+    change(&mut config);
+    if let Err(e) = save_config(&config) {
+        eprintln!("Failed to save config: {e}");
+    }
+    config
+}
+
 // ---------------------------------------------------------------------------
 // Vault file I/O
 // ---------------------------------------------------------------------------
@@ -131,6 +314,18 @@ pub fn vault_file_path(vault_folder: &str) -> PathBuf {
 }
 
 pub fn read_vault_raw(vault_folder: &str) -> io::Result<Option<String>> {
+    recover_interrupted_rekey(vault_folder)?;
+    read_vault_raw_untouched(vault_folder)
+}
+
+/// Like `read_vault_raw` but changes nothing on disk, for read-only sessions.
+/// A vault with an interrupted re-key can't be read this way.
+pub fn read_vault_raw_untouched(vault_folder: &str) -> io::Result<Option<String>> {
+    if rekey_dir(vault_folder).exists() {
+        return Err(io::Error::other(
+            "a passphrase change was interrupted; unlock the vault normally once to finish it",
+        ));
+    }
     let path = vault_file_path(vault_folder);
     if !path.exists() {
         return Ok(None);
@@ -138,18 +333,194 @@ pub fn read_vault_raw(vault_folder: &str) -> io::Result<Option<String>> {
     fs::read_to_string(&path).map(Some)
 }
 
+/// The vault file as it will be once an interrupted re-key is recovered,
+/// read without changing anything on disk. For unlocking before the writer
+/// lock is held; `recover_interrupted_rekey` runs once it is.
+pub fn read_vault_raw_pending(vault_folder: &str) -> io::Result<Option<String>> {
+    let staging = rekey_dir(vault_folder);
+    let staged_vault = staging.join("vault.json");
+    let path = if staging.join(REKEY_COMMIT_MARKER).exists() && staged_vault.exists() {
+        staged_vault
+    } else {
+        vault_file_path(vault_folder)
+    };
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(&path).map(Some)
+}
+
 pub fn write_vault_raw(vault_folder: &str, data: &str) -> io::Result<()> {
     fs::create_dir_all(vault_folder)?;
     atomic_write(&vault_file_path(vault_folder), data.as_bytes())
 }
 
-fn atomic_write(target: &Path, data: &[u8]) -> io::Result<()> {
+/// Encrypt a whole vault document as a single file, notes inline.
+pub fn encrypt_vault_data(data: &VaultData, key: &CachedKey) -> io::Result<String> {
+    let json = zeroize::Zeroizing::new(serde_json::to_string(data).map_err(io::Error::other)?);
+    crypto::encrypt_vault_fast(&json, key).map_err(io::Error::other)
+}
+
+/// The vault as it is on disk, notes inline, encrypted as a single file;
+/// `None` if there is no vault file yet.
+fn encrypt_vault_on_disk(
+    vault_folder: &str,
+    key: &CachedKey,
+    policy: crypto::PlaintextPolicy,
+) -> io::Result<Option<String>> {
+    let Some(raw) = read_vault_raw_untouched(vault_folder)? else {
+        return Ok(None);
+    };
+    let json = zeroize::Zeroizing::new(crypto::decrypt_vault_with_key(&raw, key, policy).map_err(io::Error::other)?);
+    let (data, _) = load_vault_json(vault_folder, &json, key)?;
+    encrypt_vault_data(&data, key).map(Some)
+}
+
+/// Parse a decrypted vault.json and read in the note files it refers to.
+pub fn load_vault_json(vault_folder: &str, json: &str, key: &CachedKey) -> io::Result<(VaultData, NoteFiles)> {
+    let mut data: VaultData = serde_json::from_str(json)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt vault: {e}")))?;
+    let files = note_files::load_note_files(vault_folder, &mut data, key)?;
+    Ok((data, files))
+}
+
+/// What vault.json looked like when this device last read or wrote it. A file
+/// with the same mtime and length is taken as unchanged; otherwise the hash
+/// decides, so a sync tool touching the file is not a conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultFingerprint {
+    pub sha256: [u8; 32],
+    pub modified: Option<std::time::SystemTime>,
+    pub len: u64,
+}
+
+impl VaultFingerprint {
+    fn of(data: &[u8], metadata: &fs::Metadata) -> Self {
+        VaultFingerprint {
+            sha256: Sha256::digest(data).into(),
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        }
+    }
+}
+
+/// The vault file and its fingerprint, if there is one.
+pub fn read_vault_fingerprinted(vault_folder: &str) -> io::Result<Option<(String, VaultFingerprint)>> {
+    let path = vault_file_path(vault_folder);
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let fingerprint = VaultFingerprint::of(data.as_bytes(), &fs::metadata(&path)?);
+    Ok(Some((data, fingerprint)))
+}
+
+pub fn vault_fingerprint(vault_folder: &str) -> io::Result<Option<VaultFingerprint>> {
+    Ok(read_vault_fingerprinted(vault_folder)?.map(|(_, fingerprint)| fingerprint))
+}
+
+/// If vault.json no longer matches `expected` (`None`: no file expected), the
+/// file as it is now.
+pub fn vault_changed_since(
+    vault_folder: &str,
+    expected: Option<&VaultFingerprint>,
+) -> io::Result<Option<(String, VaultFingerprint)>> {
+    if let Some(expected) = expected {
+        if let Ok(metadata) = fs::metadata(vault_file_path(vault_folder)) {
+            if metadata.modified().ok() == expected.modified && metadata.len() == expected.len {
+                return Ok(None);
+            }
+        }
+    }
+    Ok(read_vault_fingerprinted(vault_folder)?
+        .filter(|(_, current)| expected.is_none_or(|e| e.sha256 != current.sha256)))
+}
+
+pub enum VaultWrite {
+    Written(VaultFingerprint),
+    /// Someone else changed vault.json; nothing was written.
+    Changed { raw: String, fingerprint: VaultFingerprint },
+}
+
+/// Write vault.json unless it changed since `expected` was taken.
+pub fn write_vault_checked(
+    vault_folder: &str,
+    data: &str,
+    expected: Option<&VaultFingerprint>,
+) -> io::Result<VaultWrite> {
+    if let Some((raw, fingerprint)) = vault_changed_since(vault_folder, expected)? {
+        return Ok(VaultWrite::Changed { raw, fingerprint });
+    }
+    write_vault_raw(vault_folder, data)?;
+    let metadata = fs::metadata(vault_file_path(vault_folder))?;
+    Ok(VaultWrite::Written(VaultFingerprint::of(data.as_bytes(), &metadata)))
+}
+
+/// Save `data` as a manifest plus note files, unless vault.json changed since
+/// `expected`. `previous` are the note files of the manifest being replaced;
+/// returns the ones on disk afterwards. Keys from before wrapped data keys keep
+/// the single-file format. An hourly backup of the vault as it was is taken
+/// first, thinned by `retention`; `policy` is for reading that old version.
+pub fn write_vault_split(
+    vault_folder: &str,
+    mut data: VaultData,
+    key: &CachedKey,
+    expected: Option<&VaultFingerprint>,
+    previous: &NoteFiles,
+    retention: backup::Retention,
+    policy: crypto::PlaintextPolicy,
+) -> io::Result<(VaultWrite, NoteFiles)> {
+    if let Some((raw, fingerprint)) = vault_changed_since(vault_folder, expected)? {
+        return Ok((VaultWrite::Changed { raw, fingerprint }, previous.clone()));
+    }
+    fs::create_dir_all(vault_folder)?;
+    // A failed backup must not stop the save itself
+    let backup = backup::backup_before_save(vault_folder, retention, || {
+        encrypt_vault_on_disk(vault_folder, key, policy)
+    });
+    if let Err(e) = backup {
+        eprintln!("Warning: vault backup failed: {e}");
+    }
+
+    let files = if key.is_wrapped() {
+        note_files::store_note_files(vault_folder, &mut data, key, previous)?
+    } else {
+        NoteFiles::default()
+    };
+    let encrypted = encrypt_vault_data(&data, key)?;
+    let written = write_vault_checked(vault_folder, &encrypted, expected)?;
+    if let VaultWrite::Changed { .. } = written {
+        // Lost a race; the files just written belong to nothing
+        let _ = note_files::remove_replaced(vault_folder, &files, previous);
+        return Ok((written, previous.clone()));
+    }
+    if let Err(e) = note_files::remove_replaced(vault_folder, previous, &files) {
+        eprintln!("Warning: could not remove old note files: {e}");
+    }
+    if key.is_wrapped() {
+        let now = crate::state::unix_now();
+        if let Err(e) = note_files::sweep_unreferenced(vault_folder, &files, now, note_files::SWEEP_GRACE_SECS) {
+            eprintln!("Warning: could not remove unreferenced note files: {e}");
+        }
+    }
+    Ok((written, files))
+}
+
+/// Replace `target` so that after a crash it holds either the old or the new
+/// contents: the temp file is synced before the rename, and the directory
+/// after it so the rename itself is durable.
+pub fn atomic_write(target: &Path, data: &[u8]) -> io::Result<()> {
     let tmp = target.with_extension("tmp");
-    fs::write(&tmp, data)?;
+    write_synced(&tmp, data)?;
     fs::rename(&tmp, target).map_err(|e| {
         let _ = fs::remove_file(&tmp); // Best-effort cleanup of temp file
         e
-    })
+    })?;
+    match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => Ok(()),
+    }
 }
 
 pub fn assets_dir(vault_folder: &str) -> PathBuf {
@@ -162,6 +533,299 @@ pub fn write_asset(vault_folder: &str, asset_id: &str, data: &[u8]) -> io::Resul
     atomic_write(&dir.join(asset_id), data)
 }
 
+/// Write a file and flush it to stable storage before returning.
+pub fn write_synced(target: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create(target)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+// ---------------------------------------------------------------------------
+// Re-keying
+// ---------------------------------------------------------------------------
+//
+// Vaults with a wrapped data key change passphrase by re-wrapping the key and
+// rewriting vault.json alone. Legacy vaults, whose data is encrypted directly
+// with the passphrase-derived key, move to a fresh data key via `rekey_vault`.
+//
+// Every file is first re-encrypted into `.rekey/`. Once all of them are on disk a
+// commit marker is written; only then are the staged files renamed over the
+// originals. `recover_interrupted_rekey` runs once the writer lock is held, and
+// before every vault read under it, and either finishes a committed re-key or
+// discards an uncommitted one, so the vault never ends up with a mix of old-key
+// and new-key files. Until then `read_vault_raw_pending` reads the vault as the
+// recovery will leave it.
+
+const REKEY_DIR: &str = ".rekey";
+const REKEY_COMMIT_MARKER: &str = "COMMITTED";
+
+fn rekey_dir(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join(REKEY_DIR)
+}
+
+/// Re-encrypt the vault JSON and every asset under `new_key`.
+/// `old_key` must be the key the assets are currently encrypted with;
+/// `policy` decides whether unencrypted assets are taken in.
+pub fn rekey_vault(
+    vault_folder: &str,
+    vault_json: &str,
+    old_key: &CachedKey,
+    new_key: &CachedKey,
+    policy: crypto::PlaintextPolicy,
+) -> io::Result<()> {
+    recover_interrupted_rekey(vault_folder)?;
+
+    let staging = rekey_dir(vault_folder);
+    let staging_assets = staging.join("assets");
+    fs::create_dir_all(&staging_assets)?;
+
+    let result = stage_rekeyed_files(vault_folder, vault_json, old_key, new_key, &staging_assets, policy);
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&staging); // Nothing committed yet — roll back
+        return Err(e);
+    }
+
+    sync_dir(&staging_assets)?;
+    write_synced(&staging.join(REKEY_COMMIT_MARKER), b"")?;
+    sync_dir(&staging)?;
+
+    finish_rekey(vault_folder)
+}
+
+/// Make `passphrase` (plus `keyfile`, if given) the vault's unlock secret and bring
+/// the key up to the current format. Used both to change the passphrase and to
+/// upgrade old vaults on unlock.
+/// `key` must be the key the vault is currently encrypted with. The vault on
+/// disk keeps its contents: with a wrapped data key only the key slots in
+/// vault.json are replaced. Nothing is written if vault.json changed since
+/// `expected`. `policy` applies to the assets of a legacy vault being re-keyed.
+pub fn set_vault_passphrase(
+    vault_folder: &str,
+    key: &CachedKey,
+    passphrase: &str,
+    keyfile: Option<&Keyfile>,
+    expected: Option<&VaultFingerprint>,
+    policy: crypto::PlaintextPolicy,
+) -> io::Result<(CachedKey, VaultWrite)> {
+    if let Some((raw, fingerprint)) = vault_changed_since(vault_folder, expected)? {
+        return Ok((key.clone(), VaultWrite::Changed { raw, fingerprint }));
+    }
+    if key.is_wrapped() {
+        let new_key = key.with_passphrase(passphrase, keyfile).map_err(io::Error::other)?;
+        let written = write_key_slots(vault_folder, &new_key, expected)?;
+        return Ok((new_key, written));
+    }
+
+    // Legacy vaults are a single file encrypted with the passphrase itself
+    let raw = read_vault_raw(vault_folder)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "vault file not found"))?;
+    let new_key = CachedKey::generate(passphrase, keyfile).map_err(io::Error::other)?;
+    let json = zeroize::Zeroizing::new(
+        crypto::decrypt_vault_with_key(&raw, key, crypto::PlaintextPolicy::Refuse).map_err(io::Error::other)?,
+    );
+    rekey_vault(vault_folder, &json, key, &new_key, policy)?;
+    let fingerprint = vault_fingerprint(vault_folder)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "vault file not found"))?;
+    if let Err(e) = backup::rewrap_backups(vault_folder, &new_key) {
+        eprintln!("Warning: could not re-wrap backups: {e}");
+    }
+    Ok((new_key, VaultWrite::Written(fingerprint)))
+}
+
+/// Replace the key slots in vault.json with those of `key`, which must hold
+/// the same data key, and re-wrap the backups to match. The notes are left
+/// as they are. Nothing is written if vault.json changed since `expected`.
+fn write_key_slots(
+    vault_folder: &str,
+    key: &CachedKey,
+    expected: Option<&VaultFingerprint>,
+) -> io::Result<VaultWrite> {
+    if let Some((raw, fingerprint)) = vault_changed_since(vault_folder, expected)? {
+        return Ok(VaultWrite::Changed { raw, fingerprint });
+    }
+    let raw = read_vault_raw(vault_folder)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "vault file not found"))?;
+    let encrypted = crypto::rewrap_vault_envelope(&raw, key)
+        .map_err(io::Error::other)?
+        .ok_or_else(|| io::Error::other("the vault file is not encrypted with this key"))?;
+    let written = write_vault_checked(vault_folder, &encrypted, expected)?;
+    if let VaultWrite::Written(_) = written {
+        if let Err(e) = backup::rewrap_backups(vault_folder, key) {
+            eprintln!("Warning: could not re-wrap backups: {e}");
+        }
+    }
+    Ok(written)
+}
+
+fn stage_rekeyed_files(
+    vault_folder: &str,
+    vault_json: &str,
+    old_key: &CachedKey,
+    new_key: &CachedKey,
+    staging_assets: &Path,
+    policy: crypto::PlaintextPolicy,
+) -> io::Result<()> {
+    let assets = assets_dir(vault_folder);
+    if assets.is_dir() {
+        let meta = asset_metadata(vault_json);
+        for entry in fs::read_dir(&assets)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name();
+            if Path::new(&name).extension().is_some_and(|ext| ext == "tmp") {
+                continue;
+            }
+            let asset_id = name.to_string_lossy();
+            let raw = fs::read(entry.path())?;
+            let plain = crypto::decrypt_asset(&raw, &asset_id, old_key, policy)
+                .map_err(|e| io::Error::other(format!("{asset_id}: {e}")))?;
+            let mime_type = asset_mime_type(&raw, &asset_id, &meta);
+            let encrypted = crypto::encrypt_asset(&plain, &asset_id, &mime_type, new_key)
+                .map_err(io::Error::other)?;
+            write_synced(&staging_assets.join(&name), &encrypted)?;
+        }
+    }
+
+    let encrypted = crypto::encrypt_vault_fast(vault_json, new_key).map_err(io::Error::other)?;
+    write_synced(&rekey_dir(vault_folder).join("vault.json"), encrypted.as_bytes())
+}
+
+/// Asset metadata from a decrypted vault document, keyed by asset ID.
+fn asset_metadata(vault_json: &str) -> HashMap<String, AssetMeta> {
+    serde_json::from_str::<VaultData>(vault_json)
+        .map(|data| data.assets)
+        .unwrap_or_default()
+}
+
+/// MIME type to bind an asset to when (re-)encrypting it. Legacy and plaintext
+/// assets don't record one, so fall back to the vault's metadata.
+pub fn asset_mime_type(raw: &[u8], asset_id: &str, meta: &HashMap<String, AssetMeta>) -> String {
+    crypto::asset_mime_type(raw)
+        .or_else(|| meta.get(asset_id).map(|m| m.mime_type.clone()))
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+/// Move committed staged files into place. Idempotent: safe to re-run after a crash.
+fn finish_rekey(vault_folder: &str) -> io::Result<()> {
+    let staging = rekey_dir(vault_folder);
+    let staging_assets = staging.join("assets");
+    if staging_assets.is_dir() {
+        let assets = assets_dir(vault_folder);
+        fs::create_dir_all(&assets)?;
+        for entry in fs::read_dir(&staging_assets)? {
+            let entry = entry?;
+            fs::rename(entry.path(), assets.join(entry.file_name()))?;
+        }
+        sync_dir(&assets)?;
+    }
+
+    let staged_vault = staging.join("vault.json");
+    if staged_vault.exists() {
+        fs::rename(&staged_vault, vault_file_path(vault_folder))?;
+        sync_dir(Path::new(vault_folder))?;
+    }
+
+    fs::remove_dir_all(&staging)
+}
+
+/// Complete or discard a re-key that was interrupted by a crash.
+pub fn recover_interrupted_rekey(vault_folder: &str) -> io::Result<()> {
+    let staging = rekey_dir(vault_folder);
+    if !staging.exists() {
+        return Ok(());
+    }
+    if staging.join(REKEY_COMMIT_MARKER).exists() {
+        finish_rekey(vault_folder)
+    } else {
+        fs::remove_dir_all(&staging)
+    }
+}
+
+
+// ---------------------------------------------------------------------------
+// Plaintext migration
+// ---------------------------------------------------------------------------
+
+/// Files in a vault folder that are stored without encryption.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlaintextFiles {
+    pub vault: bool,
+    pub assets: Vec<String>,
+}
+
+impl PlaintextFiles {
+    pub fn is_empty(&self) -> bool {
+        !self.vault && self.assets.is_empty()
+    }
+
+    /// Paths relative to the vault folder, for showing to the user.
+    pub fn paths(&self) -> Vec<String> {
+        let vault = self.vault.then(|| "vault.json".to_string());
+        vault
+            .into_iter()
+            .chain(self.assets.iter().map(|id| format!("assets/{id}")))
+            .collect()
+    }
+}
+
+pub fn find_plaintext_files(vault_folder: &str) -> io::Result<PlaintextFiles> {
+    let mut found = PlaintextFiles {
+        // Runs while unlocking too, before the writer lock is held
+        vault: read_vault_raw_pending(vault_folder)?
+            .is_some_and(|raw| !crypto::is_encrypted_envelope(raw.as_bytes())),
+        assets: Vec::new(),
+    };
+    let assets = assets_dir(vault_folder);
+    if assets.is_dir() {
+        for entry in fs::read_dir(&assets)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !entry.file_type()?.is_file()
+                || Path::new(&name).extension().is_some_and(|ext| ext == "tmp")
+            {
+                continue;
+            }
+            if !crypto::is_encrypted_envelope(&fs::read(entry.path())?) {
+                found.assets.push(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    found.assets.sort();
+    Ok(found)
+}
+
+/// Encrypt the files listed in `files` with `key`. `vault_json` is the vault
+/// document; it is written out if `files.vault` is set and otherwise only used
+/// for asset metadata. Assets go first so the vault is never ahead of them.
+pub fn encrypt_plaintext_files(
+    vault_folder: &str,
+    vault_json: &str,
+    key: &CachedKey,
+    files: &PlaintextFiles,
+) -> io::Result<()> {
+    let meta = asset_metadata(vault_json);
+    for asset_id in &files.assets {
+        let raw = fs::read(assets_dir(vault_folder).join(asset_id))?;
+        if crypto::is_encrypted_envelope(&raw) {
+            continue;
+        }
+        let mime_type = asset_mime_type(&raw, asset_id, &meta);
+        let encrypted = crypto::encrypt_asset(&raw, asset_id, &mime_type, key)
+            .map_err(io::Error::other)?;
+        write_asset(vault_folder, asset_id, &encrypted)?;
+    }
+    if files.vault {
+        let encrypted = crypto::encrypt_vault_fast(vault_json, key).map_err(io::Error::other)?;
+        write_vault_raw(vault_folder, &encrypted)?;
+    }
+    Ok(())
+}
 
 // ---------------------------------------------------------------------------
 // Conversion helpers: internal flat model <-> tree vault format
@@ -234,6 +898,7 @@ pub fn doc_state_to_vault(state: &DocState) -> VaultData {
                 Some(t.tags.clone())
             },
             pinned: if t.pinned { Some(true) } else { None },
+            file: None,
         })
         .collect();
 
@@ -312,6 +977,7 @@ fn note_to_tree_item(note: &NoteItem) -> TreeItem {
             Some(note.tags.clone())
         },
         pinned: if note.pinned { Some(true) } else { None },
+        file: None,
     }
 }
 
@@ -341,6 +1007,7 @@ fn build_vault_tree_level(
                 deleted_at: None,
                 tags: None,
                 pinned: None,
+                file: None,
             });
         }
     }
@@ -542,12 +1209,64 @@ fn flatten_tree(
     }
 }
 
+/// Fixtures shared by the tests of the modules that read and write vaults.
+#[cfg(test)]
+pub mod test_fixtures {
+    use super::{AssetMeta, TreeItem};
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A new, empty directory for the test called `name`.
+    pub fn temp_vault_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "mdnotebook-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    pub fn note(id: &str, name: &str, content: &str) -> TreeItem {
+        TreeItem {
+            id: id.into(),
+            name: name.into(),
+            item_type: "note".into(),
+            content: Some(content.into()),
+            children: None,
+            expanded: None,
+            created_at: 0,
+            updated_at: 0,
+            deleted: None,
+            deleted_at: None,
+            tags: None,
+            pinned: None,
+            file: None,
+        }
+    }
+
+    /// Metadata of a three-byte PNG.
+    pub fn asset_meta(id: &str) -> AssetMeta {
+        AssetMeta {
+            id: id.into(),
+            original_name: format!("{id}.png"),
+            mime_type: "image/png".into(),
+            size: 3,
+            created_at: 0,
+            orphaned_at: None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::test_fixtures::temp_vault_dir;
     use super::*;
     use crate::{DocState, NoteItem, FolderItem, SortOrder};
     use std::collections::HashMap;
 
+    const STRICT: crypto::PlaintextPolicy = crypto::PlaintextPolicy::Refuse;
+
     #[test]
     fn test_doc_state_to_vault() {
         let mut state = DocState::default();
@@ -618,6 +1337,7 @@ mod tests {
                 deleted_at: None,
                 tags: Some(vec!["t1".to_string()]),
                 pinned: Some(true),
+                file: None,
             }]),
             expanded: Some(true),
             created_at: 100,
@@ -626,6 +1346,7 @@ mod tests {
             deleted_at: None,
             tags: None,
             pinned: None,
+            file: None,
         }];
 
         let trash = vec![TreeItem {
@@ -641,6 +1362,7 @@ mod tests {
             deleted_at: Some(800),
             tags: None,
             pinned: None,
+            file: None,
         }];
 
         let mut note_versions = HashMap::new();
@@ -684,4 +1406,243 @@ mod tests {
         assert_eq!(state.sidebar_width, 250);
         assert_eq!(state.next_note_seq, 10);
     }
+
+    #[test]
+    fn test_rekey_vault_reencrypts_vault_and_assets() {
+        let dir = temp_vault_dir("rekey");
+        let folder = dir.to_str().unwrap();
+        let old_key = CachedKey::generate("old-passphrase", None).unwrap();
+        let new_key = CachedKey::generate("new-passphrase", None).unwrap();
+
+        let vault_json = r#"{"tree":[]}"#;
+        write_vault_raw(folder, &crypto::encrypt_vault_fast(vault_json, &old_key).unwrap()).unwrap();
+        let asset = crypto::encrypt_asset(b"image-bytes", "abc123", "image/png", &old_key).unwrap();
+        write_asset(folder, "abc123", &asset).unwrap();
+
+        rekey_vault(folder, vault_json, &old_key, &new_key, STRICT).unwrap();
+
+        let raw = read_vault_raw(folder).unwrap().unwrap();
+        assert!(crypto::decrypt_vault_returning_key(&raw, "old-passphrase", None, STRICT).is_err());
+        let (json, _) = crypto::decrypt_vault_returning_key(&raw, "new-passphrase", None, STRICT).unwrap();
+        assert_eq!(json, vault_json);
+
+        let stored = fs::read(assets_dir(folder).join("abc123")).unwrap();
+        assert_eq!(crypto::decrypt_asset(&stored, "abc123", &new_key, STRICT).unwrap(), b"image-bytes");
+        assert!(!rekey_dir(folder).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_set_vault_passphrase_keeps_assets() {
+        let dir = temp_vault_dir("set-passphrase");
+        let folder = dir.to_str().unwrap();
+        let key = CachedKey::generate("old-passphrase", None).unwrap();
+        let vault_json = r#"{"tree":[]}"#;
+        let note = NoteItem::new("note-1".into(), "Note".into(), "on disk".into(), Vec::new());
+        let data = doc_state_to_vault(&DocState { notes: vec![note], ..DocState::default() });
+        let (VaultWrite::Written(fingerprint), _) =
+            write_vault_split(folder, data, &key, None, &NoteFiles::default(), backup::RETENTION, STRICT).unwrap()
+        else {
+            panic!("write refused");
+        };
+        let before = read_vault_raw(folder).unwrap().unwrap();
+        let asset = crypto::encrypt_asset(b"image-bytes", "abc123", "image/png", &key).unwrap();
+        write_asset(folder, "abc123", &asset).unwrap();
+        let backup = backup::create_backup(folder, &crypto::encrypt_vault_fast(vault_json, &key).unwrap()).unwrap();
+
+        // A vault changed since the fingerprint is left alone
+        let stale = VaultFingerprint { sha256: [0; 32], modified: None, len: 0 };
+        let (_, written) = set_vault_passphrase(folder, &key, "new-passphrase", None, Some(&stale), STRICT).unwrap();
+        assert!(matches!(written, VaultWrite::Changed { .. }));
+        assert_eq!(read_vault_raw(folder).unwrap().unwrap(), before);
+
+        let (new_key, written) =
+            set_vault_passphrase(folder, &key, "new-passphrase", None, Some(&fingerprint), STRICT).unwrap();
+        assert!(matches!(written, VaultWrite::Written(_)));
+
+        // Backups stop opening with the old passphrase too
+        let raw = fs::read_to_string(&backup.path).unwrap();
+        assert!(crypto::decrypt_vault_returning_key(&raw, "old-passphrase", None, STRICT).is_err());
+        crypto::decrypt_vault_returning_key(&raw, "new-passphrase", None, STRICT).unwrap();
+
+        let raw = read_vault_raw(folder).unwrap().unwrap();
+        assert!(crypto::decrypt_vault_returning_key(&raw, "old-passphrase", None, STRICT).is_err());
+        let (json, _) = crypto::decrypt_vault_returning_key(&raw, "new-passphrase", None, STRICT).unwrap();
+        // The manifest and its note files are kept as they were
+        let payload = |raw: &str| serde_json::from_str::<serde_json::Value>(raw).unwrap()["data"].clone();
+        assert_eq!(payload(&raw), payload(&before));
+        let (data, files) = load_vault_json(folder, &json, &new_key).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(vault_to_doc_state(data).notes[0].content, "on disk");
+        // Only the wrapping changed: the asset file is untouched and still decrypts
+        let stored = fs::read(assets_dir(folder).join("abc123")).unwrap();
+        assert_eq!(stored, asset);
+        assert_eq!(crypto::decrypt_asset(&stored, "abc123", &new_key, STRICT).unwrap(), b"image-bytes");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_recover_interrupted_rekey() {
+        let dir = temp_vault_dir("rekey-recover");
+        let folder = dir.to_str().unwrap();
+        write_vault_raw(folder, "old").unwrap();
+        write_asset(folder, "a1", b"old-asset").unwrap();
+
+        // Uncommitted staging is discarded
+        fs::create_dir_all(rekey_dir(folder).join("assets")).unwrap();
+        fs::write(rekey_dir(folder).join("vault.json"), "new").unwrap();
+        fs::write(rekey_dir(folder).join("assets").join("a1"), "new-asset").unwrap();
+        assert_eq!(read_vault_raw_pending(folder).unwrap().as_deref(), Some("old"));
+        assert!(rekey_dir(folder).exists());
+        assert_eq!(read_vault_raw(folder).unwrap().as_deref(), Some("old"));
+        assert!(!rekey_dir(folder).exists());
+        assert_eq!(fs::read(assets_dir(folder).join("a1")).unwrap(), b"old-asset");
+
+        // Committed staging is rolled forward
+        fs::create_dir_all(rekey_dir(folder).join("assets")).unwrap();
+        fs::write(rekey_dir(folder).join("vault.json"), "new").unwrap();
+        fs::write(rekey_dir(folder).join("assets").join("a1"), "new-asset").unwrap();
+        fs::write(rekey_dir(folder).join(REKEY_COMMIT_MARKER), "").unwrap();
+        // A read-only read leaves it alone
+        assert!(read_vault_raw_untouched(folder).is_err());
+        assert_eq!(read_vault_raw_pending(folder).unwrap().as_deref(), Some("new"));
+        assert!(rekey_dir(folder).exists());
+        assert_eq!(read_vault_raw(folder).unwrap().as_deref(), Some("new"));
+        assert!(!rekey_dir(folder).exists());
+        assert_eq!(fs::read(assets_dir(folder).join("a1")).unwrap(), b"new-asset");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_vault_checked() {
+        let dir = temp_vault_dir("checked");
+        let folder = dir.to_str().unwrap();
+
+        let VaultWrite::Written(first) = write_vault_checked(folder, "one", None).unwrap() else {
+            panic!("first write refused");
+        };
+        let VaultWrite::Written(second) = write_vault_checked(folder, "two", Some(&first)).unwrap() else {
+            panic!("unchanged file refused");
+        };
+        // Expecting no file while one exists is a change too
+        assert!(matches!(write_vault_checked(folder, "x", None).unwrap(), VaultWrite::Changed { .. }));
+
+        // Another device replaces the file
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(vault_file_path(folder), "foreign").unwrap();
+        match write_vault_checked(folder, "three", Some(&second)).unwrap() {
+            VaultWrite::Changed { raw, fingerprint } => {
+                assert_eq!(raw, "foreign");
+                assert!(matches!(
+                    write_vault_checked(folder, "merged", Some(&fingerprint)).unwrap(),
+                    VaultWrite::Written(_)
+                ));
+            }
+            VaultWrite::Written(_) => panic!("foreign change overwritten"),
+        }
+        assert_eq!(read_vault_raw(folder).unwrap().as_deref(), Some("merged"));
+
+        // Same contents with a new mtime is not a change
+        let current = vault_fingerprint(folder).unwrap().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(vault_file_path(folder), "merged").unwrap();
+        assert!(vault_changed_since(folder, Some(&current)).unwrap().is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_vault_split_backs_up_previous_version() {
+        let dir = temp_vault_dir("split-backup");
+        let folder = dir.to_str().unwrap();
+        let key = CachedKey::generate("passphrase", None).unwrap();
+        let with_note = |content: &str| {
+            let note = NoteItem::new("note-1".into(), "Note".into(), content.into(), Vec::new());
+            doc_state_to_vault(&DocState { notes: vec![note], ..DocState::default() })
+        };
+
+        // Nothing to back up before the first save
+        let (VaultWrite::Written(first), files) =
+            write_vault_split(folder, with_note("first"), &key, None, &NoteFiles::default(), backup::RETENTION, STRICT)
+                .unwrap()
+        else {
+            panic!("first write refused");
+        };
+        assert!(backup::list_backups(folder).unwrap().is_empty());
+
+        // The next save backs up what was on disk, not what it writes
+        write_vault_split(folder, with_note("second"), &key, Some(&first), &files, backup::RETENTION, STRICT).unwrap();
+        let backups = backup::list_backups(folder).unwrap();
+        assert_eq!(backups.len(), 1);
+        let raw = fs::read_to_string(&backups[0].path).unwrap();
+        let json = crypto::decrypt_vault_with_key(&raw, &key, STRICT).unwrap();
+        let state = vault_to_doc_state(serde_json::from_str(&json).unwrap());
+        assert_eq!(state.notes[0].content, "first");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_find_and_encrypt_plaintext_files() {
+        let dir = temp_vault_dir("plaintext");
+        let folder = dir.to_str().unwrap();
+        let key = CachedKey::generate("passphrase", None).unwrap();
+        let vault_json = r#"{"tree":[],"assets":{"plain1":{"id":"plain1","originalName":"a.png","mimeType":"image/png","size":3,"createdAt":0}}}"#;
+        write_vault_raw(folder, &format!(r#"{{"encrypted":false,"data":{}}}"#, serde_json::to_string(vault_json).unwrap())).unwrap();
+        write_asset(folder, "plain1", b"png").unwrap();
+        let sealed = crypto::encrypt_asset(b"gif", "sealed1", "image/gif", &key).unwrap();
+        write_asset(folder, "sealed1", &sealed).unwrap();
+
+        let found = find_plaintext_files(folder).unwrap();
+        assert_eq!(found, PlaintextFiles { vault: true, assets: vec!["plain1".to_string()] });
+        assert_eq!(found.paths(), vec!["vault.json", "assets/plain1"]);
+
+        encrypt_plaintext_files(folder, vault_json, &key, &found).unwrap();
+        assert!(find_plaintext_files(folder).unwrap().is_empty());
+        let raw = read_vault_raw(folder).unwrap().unwrap();
+        let (json, _) = crypto::decrypt_vault_returning_key(&raw, "passphrase", None, STRICT).unwrap();
+        assert_eq!(json, vault_json);
+        let stored = fs::read(assets_dir(folder).join("plain1")).unwrap();
+        assert_eq!(crypto::asset_mime_type(&stored).as_deref(), Some("image/png"));
+        assert_eq!(crypto::decrypt_asset(&stored, "plain1", &key, STRICT).unwrap(), b"png");
+        // Already-encrypted assets are left alone
+        assert_eq!(fs::read(assets_dir(folder).join("sealed1")).unwrap(), sealed);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_known_vaults() {
+        // A config from before the vault list carries its one vault over
+        let mut config: AppConfig = serde_json::from_str(r#"{"vault_path": "/home/me/Work"}"#).unwrap();
+        config.adopt_vault_path();
+        assert_eq!(config.vaults.len(), 1);
+        assert_eq!(config.vault_name("/home/me/Work"), "Work");
+
+        config.remember_vault("/home/me/Personal", 10);
+        config.rename_vault("/home/me/Personal", "  Diary ");
+        assert_eq!(config.vault_path.as_deref(), Some("/home/me/Personal"));
+        assert_eq!(config.vaults[0].name, "Diary");
+
+        // Reopening moves a vault to the top and keeps its name
+        config.remember_vault("/home/me/Work", 20);
+        config.remember_vault("/home/me/Personal", 30);
+        let order: Vec<_> = config.vaults.iter().map(|v| (v.name.as_str(), v.last_opened)).collect();
+        assert_eq!(order, [("Diary", 30), ("Work", 20)]);
+
+        // A remembered key survives reopening
+        config.set_key_remembered("/home/me/Work", true);
+        config.remember_vault("/home/me/Work", 25);
+        assert!(config.is_key_remembered("/home/me/Work"));
+        config.remember_vault("/home/me/Personal", 30);
+        assert!(!config.is_key_remembered("/home/me/Personal"));
+
+        // Adding lists a vault last without making it the current one
+        config.add_vault("/home/me/Imported");
+        config.add_vault("/home/me/Imported");
+        assert_eq!(config.vaults.len(), 3);
+        assert_eq!(config.vaults[2].last_opened, 0);
+        assert_eq!(config.vault_path.as_deref(), Some("/home/me/Personal"));
+
+        config.forget_vault("/home/me/Personal");
+        assert_eq!(config.vault_path, None);
+        assert_eq!(config.vaults.len(), 2);
+    }
 }
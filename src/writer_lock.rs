@@ -0,0 +1,368 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::state::unix_now;
+use crate::vault;
+
+// ---------------------------------------------------------------------------
+// Single-writer lock  (<vault>/vault.lock)
+// ---------------------------------------------------------------------------
+//
+// An unlocked, writable vault holds a lock file naming the host and process
+// that opened it. A heartbeat thread refreshes its timestamp; a lock whose
+// heartbeat stopped, or whose process is gone on this host, is stale and may be
+// taken over. The process is recognised by its start time and the boot it ran
+// in, so a PID reused after a crash or a reboot doesn't keep the lock alive.
+// The lock is advisory: it keeps two MDNotebook instances (on one machine or
+// on two sharing a synced folder) from both saving, nothing more.
+//
+// Each acquisition writes a random token, so an instance whose lock was taken
+// over notices on its next heartbeat or save and stops writing.
+
+const LOCK_FILE: &str = "vault.lock";
+const HEARTBEAT_SECS: u64 = 60;
+/// Five missed heartbeats; generous because synced folders lag.
+const STALE_AFTER_SECS: i64 = 5 * 60;
+
+pub fn lock_path(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join(LOCK_FILE)
+}
+
+/// Contents of the lock file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LockInfo {
+    pub host: String,
+    pub pid: u32,
+    /// Unix seconds when the lock was taken.
+    pub since: i64,
+    /// Unix seconds of the last heartbeat.
+    pub heartbeat: i64,
+    token: String,
+    /// The holder's boot and its start time in that boot (clock ticks), to
+    /// tell it apart from a later process given the same PID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    boot_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    process_start: Option<u64>,
+}
+
+impl LockInfo {
+    fn new() -> Self {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let now = unix_now();
+        let pid = std::process::id();
+        LockInfo {
+            host: host_name(),
+            pid,
+            since: now,
+            heartbeat: now,
+            token: bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            boot_id: boot_id(),
+            process_start: process_start_time(pid),
+        }
+    }
+
+    pub fn is_this_host(&self) -> bool {
+        self.host == host_name()
+    }
+
+    /// The holder stopped its heartbeat, or is known to be gone.
+    pub fn is_stale(&self) -> bool {
+        self.is_stale_at(unix_now())
+    }
+
+    fn is_stale_at(&self, now: i64) -> bool {
+        if now - self.heartbeat > STALE_AFTER_SECS {
+            return true;
+        }
+        // Only this host's processes can be checked directly
+        if !self.is_this_host() {
+            return false;
+        }
+        if self.boot_id.is_some() && self.boot_id != boot_id() {
+            return true; // Rebooted since
+        }
+        match process_start_time(self.pid) {
+            None => true,
+            // Locks written before start times were recorded go by the PID alone
+            Some(start) => self.process_start.is_some_and(|recorded| recorded != start),
+        }
+    }
+}
+
+fn boot_id() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// When process `pid` started, in clock ticks since boot; `None` if there is
+/// no such process.
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name in parentheses may itself contain spaces
+    let fields = &stat[stat.rfind(')')? + 1..];
+    // Field 22 of the file, counting the PID and command name
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+fn host_name() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .ok()
+        .filter(|h| !h.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "unknown host".to_string())
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Another instance holds the lock.
+    Held(LockInfo),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Held(info) => write!(f, "The vault is open on {} (process {})", info.host, info.pid),
+            Self::Io(e) => write!(f, "Lock file: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The current lock holder, if any. An unreadable lock file counts as a
+/// stale lock with unknown owner.
+pub fn read_lock(vault_folder: &str) -> io::Result<Option<LockInfo>> {
+    match fs::read_to_string(lock_path(vault_folder)) {
+        Ok(text) => Ok(Some(serde_json::from_str(&text).unwrap_or_else(|_| LockInfo {
+            host: "unknown host".to_string(),
+            pid: 0,
+            since: 0,
+            heartbeat: 0,
+            token: String::new(),
+            boot_id: None,
+            process_start: None,
+        }))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether another window of this very process has the vault open.
+pub fn held_by_this_process(vault_folder: &str) -> bool {
+    read_lock(vault_folder)
+        .ok()
+        .flatten()
+        .is_some_and(|info| info.is_this_host() && info.pid == std::process::id())
+}
+
+fn write_lock(path: &Path, info: &LockInfo) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(info).map_err(io::Error::other)?;
+    vault::atomic_write(path, &json)
+}
+
+/// Held lock on a vault folder. Dropping it stops the heartbeat and removes
+/// the lock file, unless another instance has taken it over.
+pub struct WriterLock {
+    path: PathBuf,
+    token: String,
+    lost: Arc<AtomicBool>,
+    stop: Option<mpsc::Sender<()>>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl WriterLock {
+    /// Take the lock. Fails with `Held` if another instance has it, unless
+    /// `take_over` is set — meant for stale locks the user chose to break.
+    pub fn acquire(vault_folder: &str, take_over: bool) -> Result<WriterLock, LockError> {
+        let path = lock_path(vault_folder);
+        let info = LockInfo::new();
+        if take_over {
+            write_lock(&path, &info)?;
+        } else {
+            let json = serde_json::to_vec_pretty(&info).map_err(io::Error::other)?;
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(&json)?;
+                    file.sync_all()?;
+                    vault::sync_dir(Path::new(vault_folder))?;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let holder = read_lock(vault_folder)?;
+                    return Err(match holder {
+                        Some(holder) => LockError::Held(holder),
+                        None => LockError::Io(e), // Released in between; let the user retry
+                    });
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let token = info.token.clone();
+        let lost = Arc::new(AtomicBool::new(false));
+        let (stop, stopped) = mpsc::channel();
+        let heartbeat = {
+            let path = path.clone();
+            let lost = lost.clone();
+            std::thread::spawn(move || run_heartbeat(&path, info, &stopped, &lost))
+        };
+        Ok(WriterLock { path, token, lost, stop: Some(stop), heartbeat: Some(heartbeat) })
+    }
+
+    /// Whether this instance still owns the lock file. Checks the disk, so a
+    /// takeover since the last heartbeat is noticed too.
+    pub fn still_held(&self) -> bool {
+        if self.lost.load(Ordering::Relaxed) {
+            return false;
+        }
+        let held = holds(&self.path, &self.token);
+        if !held {
+            self.lost.store(true, Ordering::Relaxed);
+        }
+        held
+    }
+}
+
+fn holds(path: &Path, token: &str) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<LockInfo>(&text).ok())
+        .is_some_and(|info| info.token == token)
+}
+
+fn run_heartbeat(path: &Path, mut info: LockInfo, stopped: &mpsc::Receiver<()>, lost: &AtomicBool) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(Duration::from_secs(HEARTBEAT_SECS)) {
+        if !holds(path, &info.token) {
+            lost.store(true, Ordering::Relaxed);
+            return;
+        }
+        info.heartbeat = unix_now();
+        match refresh_lock(path, &info) {
+            Ok(true) => {}
+            Ok(false) => {
+                lost.store(true, Ordering::Relaxed);
+                return;
+            }
+            Err(e) => eprintln!("Warning: vault lock heartbeat failed: {e}"),
+        }
+    }
+}
+
+/// Rewrite our lock file with a new heartbeat, unless it was taken over
+/// meanwhile; returns whether it was still ours. The token is checked again
+/// after the new contents are on disk, right before the rename, so a
+/// takeover is never overwritten by a heartbeat that started before it.
+fn refresh_lock(path: &Path, info: &LockInfo) -> io::Result<bool> {
+    let json = serde_json::to_vec_pretty(info).map_err(io::Error::other)?;
+    let tmp = path.with_file_name(format!("{LOCK_FILE}.{}.tmp", info.token));
+    vault::write_synced(&tmp, &json)?;
+    if !holds(path, &info.token) {
+        let _ = fs::remove_file(&tmp);
+        return Ok(false);
+    }
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        vault::sync_dir(dir)?;
+    }
+    Ok(true)
+}
+
+impl Drop for WriterLock {
+    fn drop(&mut self) {
+        self.stop.take(); // Ends the heartbeat
+        if let Some(heartbeat) = self.heartbeat.take() {
+            let _ = heartbeat.join();
+        }
+        if holds(&self.path, &self.token) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                eprintln!("Warning: could not remove the vault lock: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::test_fixtures::temp_vault_dir;
+
+    #[test]
+    fn test_lock_is_stale() {
+        let mut info = LockInfo::new();
+        let now = info.heartbeat;
+        assert!(!info.is_stale_at(now + 60));
+        assert!(info.is_stale_at(now + STALE_AFTER_SECS + 1));
+
+        // Another process that got the same PID later, or after a reboot
+        assert!(info.process_start.is_some());
+        info.process_start = info.process_start.map(|start| start + 1);
+        assert!(info.is_stale_at(now));
+        info.process_start = process_start_time(info.pid);
+        info.boot_id = Some("an earlier boot".to_string());
+        assert!(info.is_stale_at(now));
+        info.boot_id = boot_id();
+        assert!(!info.is_stale_at(now));
+
+        // A process that no longer exists on this host
+        info.pid = u32::MAX;
+        assert!(info.is_stale_at(now));
+        // Other hosts are judged by their heartbeat only
+        info.host = "elsewhere".to_string();
+        assert!(!info.is_stale_at(now));
+    }
+
+    #[test]
+    fn test_acquire_and_take_over() {
+        let dir = temp_vault_dir("lock");
+        let folder = dir.to_str().unwrap();
+
+        let first = WriterLock::acquire(folder, false).unwrap();
+        assert!(first.still_held());
+        let holder = match WriterLock::acquire(folder, false) {
+            Err(LockError::Held(info)) => info,
+            _ => panic!("second acquire should fail"),
+        };
+        assert_eq!(holder.pid, std::process::id());
+        assert!(!holder.is_stale());
+        assert!(held_by_this_process(folder));
+        let first_info: LockInfo = serde_json::from_str(&fs::read_to_string(lock_path(folder)).unwrap()).unwrap();
+        assert!(refresh_lock(&lock_path(folder), &first_info).unwrap());
+
+        // A takeover leaves the first holder without the lock; dropping it
+        // must not remove the new holder's file
+        let second = WriterLock::acquire(folder, true).unwrap();
+        assert!(!first.still_held());
+        // A heartbeat from before the takeover leaves it in place
+        assert!(!refresh_lock(&lock_path(folder), &first_info).unwrap());
+        assert!(second.still_held());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        drop(first);
+        assert!(second.still_held());
+        drop(second);
+        assert!(read_lock(folder).unwrap().is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
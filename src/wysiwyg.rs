@@ -1,7 +1,11 @@
 
 use base64::Engine as _;
+use gtk::glib;
 use gtk::pango;
 use gtk::prelude::*;
+use std::{cell::Cell, cell::RefCell, rc::Rc};
+
+use crate::state::{SECRET_CLOSE, SECRET_OPEN};
 
 pub const TAG_H1: &str = "h1";
 pub const TAG_H2: &str = "h2";
@@ -30,6 +34,10 @@ pub const TAG_TABLE_SEP: &str = "table-sep";
 pub const TAG_LINK: &str = "link";
 pub const TAG_IMAGE: &str = "image";
 pub const IMAGE_ALT_TAG_PREFIX: &str = "image-alt-||-";
+pub const TAG_SECRET: &str = "secret";
+/// On secret text while it is masked: drawn transparent and covered with
+/// dots by `install_secret_masks`. Never serialized.
+const TAG_SECRET_HIDDEN: &str = "secret-hidden";
 const TAG_TASK_MARKER: &str = "task-marker";
 const BLOCK_PLACEHOLDER: char = '\u{200b}';
 
@@ -91,6 +99,7 @@ struct InlineState {
     underline: bool,
     strike: bool,
     code: bool,
+    secret: bool,
 }
 
 
@@ -110,6 +119,7 @@ struct SerializeCtx {
     underline: Option<gtk::TextTag>,
     strike: Option<gtk::TextTag>,
     code: Option<gtk::TextTag>,
+    secret: Option<gtk::TextTag>,
 }
 
 pub fn install_tags(buffer: &gtk::TextBuffer) {
@@ -232,6 +242,15 @@ pub fn install_tags(buffer: &gtk::TextBuffer) {
             .family("monospace")
             .scale(1.50)
             .build(),
+        gtk::TextTag::builder()
+            .name(TAG_SECRET)
+            .background("rgba(127, 127, 127, 0.16)")
+            .build(),
+        gtk::TextTag::builder()
+            .name(TAG_SECRET_HIDDEN)
+            .foreground("rgba(0, 0, 0, 0)")
+            .editable(false)
+            .build(),
     ];
 
     for tag in tags {
@@ -505,6 +524,7 @@ pub fn load_markdown(buffer: &gtk::TextBuffer, markdown: &str) {
                     if state.italic { current_tags.push(TAG_ITALIC); }
                     if state.strike { current_tags.push(TAG_STRIKE); }
                     if state.code { current_tags.push(TAG_CODE); }
+                    if state.secret { current_tags.push(TAG_SECRET); }
 
                     let text_str = text.as_ref();
                     buffer.insert_with_tags_by_name(&mut iter, text_str, &current_tags);
@@ -532,10 +552,16 @@ pub fn load_markdown(buffer: &gtk::TextBuffer, markdown: &str) {
             pulldown_cmark::Event::InlineHtml(html) => {
                 let tag = html.trim();
                 let tag_lower = tag.to_ascii_lowercase();
+                // Table rows and raw blocks are saved verbatim, without tags
+                let verbatim = block_tags.contains(&TAG_TABLE_CELL) || block_tags.contains(&TAG_RAW_BLOCK);
                 if tag_lower == "<u>" || tag_lower.starts_with("<u ") {
                     state.underline = true;
                 } else if tag_lower == "</u>" {
                     state.underline = false;
+                } else if tag_lower == SECRET_OPEN && !verbatim {
+                    state.secret = true;
+                } else if tag_lower == SECRET_CLOSE && !verbatim {
+                    state.secret = false;
                 } else {
                     if let Some(prefix) = pending_item_prefix.take() {
                         let mut t = block_tags.clone();
@@ -553,6 +579,10 @@ pub fn load_markdown(buffer: &gtk::TextBuffer, markdown: &str) {
                     state.underline = true;
                 } else if trimmed.eq_ignore_ascii_case("</u>") {
                     state.underline = false;
+                } else if trimmed.eq_ignore_ascii_case(SECRET_OPEN) {
+                    state.secret = true;
+                } else if trimmed.eq_ignore_ascii_case(SECRET_CLOSE) {
+                    state.secret = false;
                 } else {
                     if let Some(prefix) = pending_item_prefix.take() {
                         let mut t = block_tags.clone();
@@ -619,6 +649,7 @@ pub fn load_markdown(buffer: &gtk::TextBuffer, markdown: &str) {
         }
     }
 
+    mask_secrets(buffer);
 }
 
 
@@ -645,6 +676,7 @@ pub fn to_markdown(buffer: &gtk::TextBuffer) -> String {
         underline: table.lookup(TAG_UNDERLINE),
         strike: table.lookup(TAG_STRIKE),
         code: table.lookup(TAG_CODE),
+        secret: table.lookup(TAG_SECRET),
     };
 
     let mut lines: Vec<String> = Vec::new();
@@ -1263,6 +1295,153 @@ pub fn renumber_ordered_list(buffer: &gtk::TextBuffer) {
     }
 }
 
+/// Mark the selection (or the word at the cursor) as secret, or unmark it
+/// if it already is, and mask again.
+pub fn toggle_secret(buffer: &gtk::TextBuffer) {
+    toggle_inline_tag(buffer, TAG_SECRET, "secret");
+    mask_secrets(buffer);
+}
+
+/// Hide the text of every secret span, including revealed ones. Only tags
+/// change: the text, the undo history and the dirty state stay as they are.
+pub fn mask_secrets(buffer: &gtk::TextBuffer) {
+    let table = buffer.tag_table();
+    let (Some(secret), Some(hidden)) = (table.lookup(TAG_SECRET), table.lookup(TAG_SECRET_HIDDEN)) else {
+        return;
+    };
+    // Text no longer secret keeps no mask
+    buffer.remove_tag(&hidden, &buffer.start_iter(), &buffer.end_iter());
+    let mut iter = buffer.start_iter();
+    while let Some((start, end)) = next_tag_range(&iter, &secret) {
+        buffer.apply_tag(&hidden, &start, &end);
+        iter = end;
+    }
+}
+
+/// If `iter` is on a masked secret span, show its text and return true.
+pub fn reveal_secret_at(buffer: &gtk::TextBuffer, iter: &gtk::TextIter) -> bool {
+    let Some(hidden) = buffer.tag_table().lookup(TAG_SECRET_HIDDEN) else {
+        return false;
+    };
+    match next_tag_range(iter, &hidden) {
+        Some((start, end)) if iter.has_tag(&hidden) => {
+            buffer.remove_tag(&hidden, &start, &end);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Cover the masked secret spans of `view` with dots. The dots are overlay
+/// widgets laid over the transparent text, rebuilt whenever the text, the
+/// masks or the layout change, so the buffer itself is never edited.
+pub fn install_secret_masks(view: &gtk::TextView) {
+    let masks: Rc<RefCell<Vec<gtk::Widget>>> = Rc::new(RefCell::new(Vec::new()));
+    let pending: Rc<Cell<Option<glib::SourceId>>> = Rc::new(Cell::new(None));
+    let schedule = {
+        let view = view.downgrade();
+        move || {
+            if let Some(source_id) = pending.take() {
+                source_id.remove();
+            }
+            let (view, masks, cell) = (view.clone(), masks.clone(), pending.clone());
+            let source_id = glib::idle_add_local_once(move || {
+                cell.set(None);
+                if let Some(view) = view.upgrade() {
+                    layout_secret_masks(&view, &masks);
+                }
+            });
+            pending.set(Some(source_id));
+        }
+    };
+    let schedule = Rc::new(schedule);
+
+    let buffer = view.buffer();
+    {
+        let schedule = schedule.clone();
+        buffer.connect_changed(move |_| schedule());
+    }
+    for signal in ["apply-tag", "remove-tag"] {
+        let schedule = schedule.clone();
+        buffer.connect_local(signal, true, move |args| {
+            let tag = args[1].get::<gtk::TextTag>().ok()?;
+            if tag.name().as_deref() == Some(TAG_SECRET_HIDDEN) {
+                schedule();
+            }
+            None
+        });
+    }
+    // Rewrapping moves the spans; the adjustments change with the layout
+    for adjustment in [view.hadjustment(), view.vadjustment()].into_iter().flatten() {
+        let schedule = schedule.clone();
+        adjustment.connect_changed(move |_| schedule());
+    }
+}
+
+/// Put one mask over each display line of each masked span in `view`,
+/// replacing the previous ones.
+fn layout_secret_masks(view: &gtk::TextView, masks: &RefCell<Vec<gtk::Widget>>) {
+    for mask in masks.borrow_mut().drain(..) {
+        view.remove(&mask);
+    }
+    let buffer = view.buffer();
+    let Some(hidden) = buffer.tag_table().lookup(TAG_SECRET_HIDDEN) else {
+        return;
+    };
+    let mut iter = buffer.start_iter();
+    while let Some((start, end)) = next_tag_range(&iter, &hidden) {
+        let mut line: Option<gtk::gdk::Rectangle> = None;
+        let mut at = start;
+        while at < end {
+            let rect = view.iter_location(&at);
+            line = match line {
+                Some(l) if l.y() == rect.y() => {
+                    Some(gtk::gdk::Rectangle::new(l.x(), l.y(), rect.x() + rect.width() - l.x(), l.height().max(rect.height())))
+                }
+                previous => {
+                    if let Some(previous) = previous {
+                        add_secret_mask(view, masks, &previous);
+                    }
+                    Some(rect)
+                }
+            };
+            at.forward_char();
+        }
+        if let Some(last) = line {
+            add_secret_mask(view, masks, &last);
+        }
+        iter = end;
+    }
+}
+
+fn add_secret_mask(view: &gtk::TextView, masks: &RefCell<Vec<gtk::Widget>>, rect: &gtk::gdk::Rectangle) {
+    if rect.width() <= 0 {
+        return;
+    }
+    let mask = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    mask.add_css_class("secret-mask");
+    mask.set_size_request(rect.width(), rect.height());
+    // Clicks go through to the text underneath, which reveals the span
+    mask.set_can_target(false);
+    view.add_overlay(&mask, rect.x(), rect.y());
+    masks.borrow_mut().push(mask.upcast());
+}
+
+/// The first range tagged `tag` from `from` on. Starts at `from` if it is
+/// inside one.
+fn next_tag_range(from: &gtk::TextIter, tag: &gtk::TextTag) -> Option<(gtk::TextIter, gtk::TextIter)> {
+    let mut start = *from;
+    if !start.has_tag(tag) {
+        start.forward_to_tag_toggle(Some(tag));
+        if !start.has_tag(tag) {
+            return None;
+        }
+    }
+    let mut end = start;
+    end.forward_to_tag_toggle(Some(tag));
+    Some((start, end))
+}
+
 pub fn insert_horizontal_rule(buffer: &gtk::TextBuffer) {
     let mut cursor = buffer.iter_at_offset(buffer.cursor_position());
     buffer.insert(&mut cursor, "\n");
@@ -1488,6 +1667,9 @@ fn insert_chunk(
     if state.code {
         tags.push(TAG_CODE);
     }
+    if state.secret {
+        tags.push(TAG_SECRET);
+    }
 
     if tags.is_empty() {
         buffer.insert(iter, text);
@@ -1600,13 +1782,7 @@ fn serialize_line(
             break;
         }
 
-        let next_state = InlineState {
-            bold: has_resolved_tag(&iter, ctx.bold.as_ref()),
-            italic: has_resolved_tag(&iter, ctx.italic.as_ref()),
-            underline: has_resolved_tag(&iter, ctx.underline.as_ref()),
-            strike: has_resolved_tag(&iter, ctx.strike.as_ref()),
-            code: has_resolved_tag(&iter, ctx.code.as_ref()),
-        };
+        let next_state = inline_state_at(&iter, ctx);
 
         transition_inline_state(&mut output, state, next_state);
 
@@ -1645,13 +1821,7 @@ fn serialize_inline_range(
         let next_offset = (iter.offset() + 1).min(end.offset());
         let next = buffer.iter_at_offset(next_offset);
 
-        let next_state = InlineState {
-            bold: has_resolved_tag(&iter, ctx.bold.as_ref()),
-            italic: has_resolved_tag(&iter, ctx.italic.as_ref()),
-            underline: has_resolved_tag(&iter, ctx.underline.as_ref()),
-            strike: has_resolved_tag(&iter, ctx.strike.as_ref()),
-            code: has_resolved_tag(&iter, ctx.code.as_ref()),
-        };
+        let next_state = inline_state_at(&iter, ctx);
 
         transition_inline_state(&mut out, state, next_state);
 
@@ -1669,6 +1839,17 @@ fn serialize_inline_range(
     out
 }
 
+fn inline_state_at(iter: &gtk::TextIter, ctx: &SerializeCtx) -> InlineState {
+    InlineState {
+        bold: has_resolved_tag(iter, ctx.bold.as_ref()),
+        italic: has_resolved_tag(iter, ctx.italic.as_ref()),
+        underline: has_resolved_tag(iter, ctx.underline.as_ref()),
+        strike: has_resolved_tag(iter, ctx.strike.as_ref()),
+        code: has_resolved_tag(iter, ctx.code.as_ref()),
+        secret: has_resolved_tag(iter, ctx.secret.as_ref()),
+    }
+}
+
 fn transition_inline_state(out: &mut String, from: InlineState, to: InlineState) {
     if from.code && !to.code {
         out.push('`');
@@ -1685,7 +1866,13 @@ fn transition_inline_state(out: &mut String, from: InlineState, to: InlineState)
     if from.underline && !to.underline {
         out.push_str("</u>");
     }
+    if from.secret && !to.secret {
+        out.push_str(SECRET_CLOSE);
+    }
 
+    if !from.secret && to.secret {
+        out.push_str(SECRET_OPEN);
+    }
     if !from.underline && to.underline {
         out.push_str("<u>");
     }
@@ -2297,4 +2484,41 @@ Link test: [My link](https://example.com)";
 
         assert_eq!(input.trim(), output.trim());
     }
+
+    #[test]
+    fn test_secret_spans() {
+        if !setup() {
+            eprintln!("Skipping GTK-dependent test_secret_spans (no display/GTK init)");
+            return;
+        }
+        let buffer = gtk::TextBuffer::new(None::<&gtk::TextTagTable>);
+        install_tags(&buffer);
+        let shown = |buffer: &gtk::TextBuffer| buffer.text(&buffer.start_iter(), &buffer.end_iter(), false).to_string();
+
+        let hidden = buffer.tag_table().lookup(TAG_SECRET_HIDDEN).unwrap();
+        let changes = Rc::new(Cell::new(0));
+        let counter = changes.clone();
+        buffer.connect_changed(move |_| counter.set(counter.get() + 1));
+
+        let input = "Token: <secret>abc **123**</secret> and **bold <secret>zz</secret> text**";
+        load_markdown(&buffer, input);
+        assert_eq!(to_markdown(&buffer), input);
+        assert_eq!(shown(&buffer), "Token: abc 123 and bold zz text");
+        let secret = buffer.iter_at_offset("Token: ".len() as i32);
+        assert!(secret.has_tag(&hidden));
+        assert!(buffer.iter_at_offset("Token: abc 123 and bold ".len() as i32).has_tag(&hidden));
+
+        // Masking and revealing only touch tags
+        changes.set(0);
+        assert!(reveal_secret_at(&buffer, &secret));
+        assert!(!secret.has_tag(&hidden));
+        assert!(buffer.iter_at_offset("Token: abc 123 and bold ".len() as i32).has_tag(&hidden));
+        assert_eq!(to_markdown(&buffer), input);
+
+        mask_secrets(&buffer);
+        assert!(secret.has_tag(&hidden));
+        assert!(!reveal_secret_at(&buffer, &buffer.start_iter()));
+        assert_eq!(changes.get(), 0);
+        assert_eq!(shown(&buffer), "Token: abc 123 and bold zz text");
+    }
 }